@@ -1055,8 +1055,11 @@ function convertFromProxyEndpoint(proxyEndpoint: grpcTypes.ProxyEndpoint.AsObjec
 function convertFromObfuscationEndpoint(
   obfuscationEndpoint: grpcTypes.ObfuscationEndpoint.AsObject,
 ): IObfuscationEndpoint {
-  const obfuscationTypes: Record<grpcTypes.ObfuscationType, EndpointObfuscationType> = {
-    [grpcTypes.ObfuscationType.UDP2TCP]: 'udp2tcp',
+  const obfuscationTypes: Record<
+    grpcTypes.ObfuscationEndpoint.ObfuscationType,
+    EndpointObfuscationType
+  > = {
+    [grpcTypes.ObfuscationEndpoint.ObfuscationType.UDP2TCP]: 'udp2tcp',
   };
 
   return {
@@ -1176,7 +1179,21 @@ function convertFromBridgeSettings(
     return { custom: settings };
   };
 
-  const localSettings = bridgeSettings.local;
+  // The GUI only shows one custom bridge. Prefer the first one that the daemon may select.
+  const bridges = bridgeSettings.custom?.bridgesList ?? [];
+  const bridge = bridges.find((bridge) => bridge.enabled) ?? bridges[0];
+  const proxy = bridge?.proxy;
+  if (!proxy) {
+    return {
+      normal: {
+        location: 'any',
+        providers: [],
+        ownership: Ownership.any,
+      },
+    };
+  }
+
+  const localSettings = proxy.local;
   if (localSettings) {
     return customSettings({
       port: localSettings.port,
@@ -1184,7 +1201,7 @@ function convertFromBridgeSettings(
     });
   }
 
-  const remoteSettings = bridgeSettings.remote;
+  const remoteSettings = proxy.remote;
   if (remoteSettings) {
     return customSettings({
       address: remoteSettings.address,
@@ -1192,7 +1209,16 @@ function convertFromBridgeSettings(
     });
   }
 
-  const shadowsocksSettings = bridgeSettings.shadowsocks!;
+  // The GUI does not tell HTTP proxies apart from remote SOCKS5 proxies
+  const httpSettings = proxy.http;
+  if (httpSettings) {
+    return customSettings({
+      address: httpSettings.address,
+      auth: httpSettings.auth && { ...httpSettings.auth },
+    });
+  }
+
+  const shadowsocksSettings = proxy.shadowsocks!;
   return customSettings({
     peer: shadowsocksSettings.peer!,
     password: shadowsocksSettings.password!,
@@ -1,5 +1,6 @@
 use crate::{
     abortable_stream::{AbortableStream, AbortableStreamHandle},
+    http_proxy,
     proxy::{ApiConnection, ApiConnectionMode, ProxyConfig},
     tls_stream::TlsStream,
     AddressCache,
@@ -33,7 +34,7 @@ use std::{
     task::{Context, Poll},
     time::Duration,
 };
-use talpid_types::ErrorExt;
+use talpid_types::{net::openvpn::HttpProxySettings, ErrorExt};
 
 use tokio::{
     net::{TcpSocket, TcpStream},
@@ -73,8 +74,10 @@ enum HttpsConnectorRequest {
 enum InnerConnectionMode {
     /// Connect directly to the target.
     Direct,
-    /// Connect to the destination via a proxy.
-    Proxied(ParsedShadowsocksConfig),
+    /// Connect to the destination via a Shadowsocks proxy.
+    Shadowsocks(ParsedShadowsocksConfig),
+    /// Connect to the destination via an HTTP proxy.
+    Http(HttpProxySettings),
 }
 
 #[derive(Clone)]
@@ -103,13 +106,16 @@ impl TryFrom<ApiConnectionMode> for InnerConnectionMode {
         Ok(match config {
             ApiConnectionMode::Direct => InnerConnectionMode::Direct,
             ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(config)) => {
-                InnerConnectionMode::Proxied(ParsedShadowsocksConfig {
+                InnerConnectionMode::Shadowsocks(ParsedShadowsocksConfig {
                     peer: config.peer,
                     password: config.password,
                     cipher: CipherKind::from_str(&config.cipher)
                         .map_err(|_| ProxyConfigError::InvalidCipher(config.cipher))?,
                 })
             }
+            ApiConnectionMode::Proxied(ProxyConfig::Http(config)) => {
+                InnerConnectionMode::Http(config)
+            }
         })
     }
 }
@@ -319,7 +325,7 @@ impl Service<Uri> for HttpsConnectorWithSni {
                             let tls_stream = TlsStream::connect_https(socket, &hostname).await?;
                             Ok::<_, io::Error>(ApiConnection::new(Box::new(tls_stream)))
                         }
-                        InnerConnectionMode::Proxied(proxy_config) => {
+                        InnerConnectionMode::Shadowsocks(proxy_config) => {
                             let socket = Self::open_socket(
                                 proxy_config.peer,
                                 #[cfg(target_os = "android")]
@@ -340,6 +346,25 @@ impl Service<Uri> for HttpsConnectorWithSni {
                                 ))));
                             }
 
+                            let tls_stream = TlsStream::connect_https(proxy, &hostname).await?;
+                            Ok(ApiConnection::new(Box::new(tls_stream)))
+                        }
+                        InnerConnectionMode::Http(proxy_config) => {
+                            let socket = Self::open_socket(
+                                proxy_config.address,
+                                #[cfg(target_os = "android")]
+                                socket_bypass_tx.clone(),
+                            )
+                            .await?;
+                            let proxy =
+                                http_proxy::connect(socket, addr, proxy_config.auth.as_ref())
+                                    .await?;
+
+                            #[cfg(feature = "api-override")]
+                            if API.disable_tls {
+                                return Ok(ApiConnection::new(Box::new(proxy)));
+                            }
+
                             let tls_stream = TlsStream::connect_https(proxy, &hostname).await?;
                             Ok(ApiConnection::new(Box::new(tls_stream)))
                         }
@@ -26,6 +26,7 @@ use availability::{ApiAvailability, ApiAvailabilityHandle};
 pub mod rest;
 
 mod abortable_stream;
+mod http_proxy;
 mod https_client_with_sni;
 pub mod proxy;
 mod tls_stream;
@@ -8,7 +8,10 @@ use std::{
     pin::Pin,
     task::{self, Poll},
 };
-use talpid_types::{net::openvpn::ShadowsocksProxySettings, ErrorExt};
+use talpid_types::{
+    net::openvpn::{HttpProxySettings, ShadowsocksProxySettings},
+    ErrorExt,
+};
 use tokio::{
     fs,
     io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf},
@@ -36,6 +39,7 @@ impl fmt::Display for ApiConnectionMode {
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub enum ProxyConfig {
     Shadowsocks(ShadowsocksProxySettings),
+    Http(HttpProxySettings),
 }
 
 impl fmt::Display for ProxyConfig {
@@ -43,6 +47,7 @@ impl fmt::Display for ProxyConfig {
         match self {
             // TODO: Do not hardcode TCP
             ProxyConfig::Shadowsocks(ss) => write!(f, "Shadowsocks {}/TCP", ss.peer),
+            ProxyConfig::Http(http) => write!(f, "HTTP proxy {}/TCP", http.address),
         }
     }
 }
@@ -111,6 +116,7 @@ impl ApiConnectionMode {
     pub fn get_endpoint(&self) -> Option<SocketAddr> {
         match self {
             ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(ss)) => Some(ss.peer),
+            ApiConnectionMode::Proxied(ProxyConfig::Http(http)) => Some(http.address),
             ApiConnectionMode::Direct => None,
         }
     }
@@ -308,6 +308,8 @@ struct WireGuardRelay {
     #[serde(flatten)]
     relay: Relay,
     public_key: wireguard::PublicKey,
+    #[serde(default)]
+    tls_server_name: Option<String>,
 }
 
 impl WireGuardRelay {
@@ -317,6 +319,7 @@ impl WireGuardRelay {
             location,
             relay_list::RelayEndpointData::Wireguard(relay_list::WireguardRelayEndpointData {
                 public_key: self.public_key,
+                tls_server_name: self.tls_server_name,
             }),
         )
     }
@@ -2,7 +2,7 @@ use crate::{location, new_rpc_client, Command, Error, Result};
 
 use mullvad_management_interface::types;
 use mullvad_types::relay_constraints::{
-    BridgeConstraints, BridgeSettings, BridgeState, Constraint, LocationConstraint,
+    BridgeConstraints, BridgeSettings, BridgeState, Constraint, CustomBridge, LocationConstraint,
 };
 use talpid_types::net::openvpn::{self, SHADOWSOCKS_CIPHERS};
 
@@ -21,11 +21,14 @@ impl Command for Bridge {
             .about(
                 "Manage use of bridges, socks proxies and Shadowsocks for OpenVPN. \
                 Can make OpenVPN tunnels use Shadowsocks via one of the Mullvad bridge servers. \
-                Can also make OpenVPN connect through any custom SOCKS5 proxy. \
-                These settings also affect how the app reaches the API over Shadowsocks.",
+                Can also make OpenVPN connect through any custom SOCKS5 or HTTP proxy, \
+                and WireGuard through a custom SOCKS5 proxy that supports UDP ASSOCIATE. \
+                These settings also affect how the app reaches the API over Shadowsocks, \
+                or through a custom HTTP proxy.",
             )
             .setting(clap::AppSettings::SubcommandRequiredElseHelp)
             .subcommand(create_bridge_set_subcommand())
+            .subcommand(create_custom_subcommand())
             .subcommand(clap::App::new("get").about("Get current bridge settings and state"))
             .subcommand(clap::App::new("list").about("List bridge relays"))
     }
@@ -33,6 +36,7 @@ impl Command for Bridge {
     async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
         match matches.subcommand() {
             Some(("set", set_matches)) => Self::handle_set(set_matches).await,
+            Some(("custom", custom_matches)) => Self::handle_custom(custom_matches).await,
             Some(("get", _)) => Self::handle_get().await,
             Some(("list", _)) => Self::list_bridge_relays().await,
             _ => unreachable!("unhandled command"),
@@ -45,7 +49,6 @@ fn create_bridge_set_subcommand() -> clap::App<'static> {
         .about("Set bridge state and settings")
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(create_set_state_subcommand())
-        .subcommand(create_set_custom_settings_subcommand())
         .subcommand(
             clap::App::new("provider")
                 .about(
@@ -78,27 +81,71 @@ fn create_bridge_set_subcommand() -> clap::App<'static> {
         ))
 }
 
-fn create_set_custom_settings_subcommand() -> clap::App<'static> {
+fn create_custom_subcommand() -> clap::App<'static> {
+    let name_arg = clap::Arg::new("name")
+        .help("Name of the custom bridge")
+        .required(true)
+        .index(1);
+
+    clap::App::new("custom")
+        .about(
+            "Manage custom bridges. When any custom bridge is added, the enabled custom bridges \
+            are used in turn instead of the Mullvad bridge servers",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(create_add_custom_bridge_subcommand())
+        .subcommand(
+            clap::App::new("remove")
+                .about("Remove a custom bridge")
+                .arg(name_arg.clone()),
+        )
+        .subcommand(
+            clap::App::new("enable")
+                .about("Enable a custom bridge")
+                .arg(name_arg.clone()),
+        )
+        .subcommand(
+            clap::App::new("disable")
+                .about("Disable a custom bridge without removing it")
+                .arg(name_arg.clone()),
+        )
+        .subcommand(
+            clap::App::new("test")
+                .about(
+                    "Check whether a custom bridge accepts connections. Bridges that fail the \
+                    test are skipped as long as any other enabled bridge passes it",
+                )
+                .arg(name_arg),
+        )
+}
+
+fn create_add_custom_bridge_subcommand() -> clap::App<'static> {
+    let name_arg = clap::Arg::new("name")
+        .help("Unique name of the new custom bridge")
+        .required(true)
+        .index(1);
+
     #[allow(unused_mut)]
     let mut local_subcommand = clap::App::new("local")
         .about("Registers a local SOCKS5 proxy")
+        .arg(name_arg.clone())
         .arg(
             clap::Arg::new("local-port")
                 .help("Specifies the port the local proxy server is listening on")
                 .required(true)
-                .index(1),
+                .index(2),
         )
         .arg(
             clap::Arg::new("remote-ip")
                 .help("Specifies the IP of the proxy server peer")
                 .required(true)
-                .index(2),
+                .index(3),
         )
         .arg(
             clap::Arg::new("remote-port")
                 .help("Specifies the port of the proxy server peer")
                 .required(true)
-                .index(3),
+                .index(4),
         );
 
     #[cfg(target_os = "linux")]
@@ -117,65 +164,101 @@ fn create_set_custom_settings_subcommand() -> clap::App<'static> {
         );
     }
 
-    clap::App::new("custom")
-        .about("Configure a SOCKS5 proxy")
+    clap::App::new("add")
+        .about("Add a SOCKS5, Shadowsocks or HTTP proxy as a custom bridge")
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(local_subcommand)
         .subcommand(
             clap::App::new("remote")
-                .about("Registers a remote SOCKS5 proxy")
+                .about(
+                    "Registers a remote SOCKS5 proxy. WireGuard tunnels can use it if it \
+                    supports UDP ASSOCIATE and relays UDP from the same IP",
+                )
+                .arg(name_arg.clone())
                 .arg(
                     clap::Arg::new("remote-ip")
                         .help("Specifies the IP of the remote proxy server")
                         .required(true)
-                        .index(1),
+                        .index(2),
                 )
                 .arg(
                     clap::Arg::new("remote-port")
                         .help("Specifies the port the remote proxy server is listening on")
                         .required(true)
-                        .index(2),
+                        .index(3),
                 )
                 .arg(
                     clap::Arg::new("username")
                         .help("Specifies the username for remote authentication")
                         .required(true)
-                        .index(3),
+                        .index(4),
                 )
                 .arg(
                     clap::Arg::new("password")
                         .help("Specifies the password for remote authentication")
                         .required(true)
-                        .index(4),
+                        .index(5),
                 ),
         )
         .subcommand(
             clap::App::new("shadowsocks")
                 .about("Configure bundled Shadowsocks proxy")
+                .arg(name_arg.clone())
                 .arg(
                     clap::Arg::new("remote-ip")
                         .help("Specifies the IP of the remote Shadowsocks server")
                         .required(true)
-                        .index(1),
+                        .index(2),
                 )
                 .arg(
                     clap::Arg::new("remote-port")
                         .help("Specifies the port of the remote Shadowsocks server")
                         .default_value("443")
-                        .index(2),
+                        .index(3),
                 )
                 .arg(
                     clap::Arg::new("password")
                         .help("Specifies the password on the remote Shadowsocks server")
                         .default_value("mullvad")
-                        .index(3),
+                        .index(4),
                 )
                 .arg(
                     clap::Arg::new("cipher")
                         .help("Specifies the cipher to use")
                         .default_value("aes-256-gcm")
                         .possible_values(SHADOWSOCKS_CIPHERS)
+                        .index(5),
+                ),
+        )
+        .subcommand(
+            clap::App::new("http")
+                .about(
+                    "Registers an HTTP proxy that supports the CONNECT method. \
+                    Only OpenVPN over TCP can be used with it",
+                )
+                .arg(name_arg)
+                .arg(
+                    clap::Arg::new("remote-ip")
+                        .help("Specifies the IP of the HTTP proxy")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    clap::Arg::new("remote-port")
+                        .help("Specifies the port the HTTP proxy is listening on")
+                        .required(true)
+                        .index(3),
+                )
+                .arg(
+                    clap::Arg::new("username")
+                        .help("Specifies the username for basic authentication")
+                        .requires("password")
                         .index(4),
+                )
+                .arg(
+                    clap::Arg::new("password")
+                        .help("Specifies the password for basic authentication")
+                        .index(5),
                 ),
         )
 }
@@ -202,9 +285,6 @@ impl Bridge {
             Some(("ownership", ownership_matches)) => {
                 Self::handle_set_bridge_ownership(ownership_matches).await
             }
-            Some(("custom", custom_matches)) => {
-                Self::handle_bridge_set_custom_settings(custom_matches).await
-            }
             Some(("state", set_matches)) => Self::handle_set_bridge_state(set_matches).await,
             _ => unreachable!("unhandled command"),
         }
@@ -219,15 +299,33 @@ impl Bridge {
             BridgeState::try_from(settings.bridge_state.unwrap()).unwrap()
         );
         match bridge_settings {
-            BridgeSettings::Custom(proxy) => match proxy {
-                openvpn::ProxySettings::Local(local_proxy) => Self::print_local_proxy(&local_proxy),
-                openvpn::ProxySettings::Remote(remote_proxy) => {
-                    Self::print_remote_proxy(&remote_proxy)
+            BridgeSettings::Custom(bridges) => {
+                if bridges.is_empty() {
+                    println!("Custom bridges: none");
                 }
-                openvpn::ProxySettings::Shadowsocks(shadowsocks_proxy) => {
-                    Self::print_shadowsocks_proxy(&shadowsocks_proxy)
+                for bridge in &bridges {
+                    let state = if bridge.enabled {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    };
+                    println!("Custom bridge: {} ({state})", bridge.name);
+                    match &bridge.proxy {
+                        openvpn::ProxySettings::Local(local_proxy) => {
+                            Self::print_local_proxy(local_proxy)
+                        }
+                        openvpn::ProxySettings::Remote(remote_proxy) => {
+                            Self::print_remote_proxy(remote_proxy)
+                        }
+                        openvpn::ProxySettings::Shadowsocks(shadowsocks_proxy) => {
+                            Self::print_shadowsocks_proxy(shadowsocks_proxy)
+                        }
+                        openvpn::ProxySettings::Http(http_proxy) => {
+                            Self::print_http_proxy(http_proxy)
+                        }
+                    }
                 }
-            },
+            }
             BridgeSettings::Normal(constraints) => {
                 println!("Bridge constraints: {constraints}")
             }
@@ -326,8 +424,85 @@ impl Bridge {
         Ok(())
     }
 
-    async fn handle_bridge_set_custom_settings(matches: &clap::ArgMatches) -> Result<()> {
-        if let Some(args) = matches.subcommand_matches("local") {
+    async fn handle_custom(matches: &clap::ArgMatches) -> Result<()> {
+        match matches.subcommand() {
+            Some(("add", add_matches)) => Self::handle_add_custom_bridge(add_matches).await,
+            Some(("remove", remove_matches)) => {
+                let name = remove_matches.value_of("name").unwrap();
+                Self::update_custom_bridges(|bridges| {
+                    let index = find_custom_bridge(bridges, name)?;
+                    bridges.remove(index);
+                    Ok(())
+                })
+                .await?;
+                println!("Removed custom bridge {name}");
+                Ok(())
+            }
+            Some(("enable", enable_matches)) => {
+                Self::set_custom_bridge_enabled(enable_matches.value_of("name").unwrap(), true)
+                    .await
+            }
+            Some(("disable", disable_matches)) => {
+                Self::set_custom_bridge_enabled(disable_matches.value_of("name").unwrap(), false)
+                    .await
+            }
+            Some(("test", test_matches)) => {
+                let name = test_matches.value_of("name").unwrap();
+                let mut rpc = new_rpc_client().await?;
+                let reachable = rpc
+                    .test_custom_bridge(name.to_owned())
+                    .await
+                    .map_err(|error| Error::RpcFailedExt("Failed to test custom bridge", error))?
+                    .into_inner();
+                if reachable {
+                    println!("Custom bridge {name} is reachable");
+                } else {
+                    println!("Custom bridge {name} is unreachable");
+                }
+                Ok(())
+            }
+            _ => unreachable!("unhandled command"),
+        }
+    }
+
+    async fn set_custom_bridge_enabled(name: &str, enabled: bool) -> Result<()> {
+        Self::update_custom_bridges(|bridges| {
+            let index = find_custom_bridge(bridges, name)?;
+            bridges[index].enabled = enabled;
+            Ok(())
+        })
+        .await?;
+        if enabled {
+            println!("Enabled custom bridge {name}");
+        } else {
+            println!("Disabled custom bridge {name}");
+        }
+        Ok(())
+    }
+
+    /// Applies `update` to the custom bridges. If Mullvad bridges were used, this switches to
+    /// custom bridges.
+    async fn update_custom_bridges(
+        update: impl FnOnce(&mut Vec<CustomBridge>) -> Result<()>,
+    ) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        let bridge_settings = BridgeSettings::try_from(settings.bridge_settings.unwrap()).unwrap();
+        let mut bridges = match bridge_settings {
+            BridgeSettings::Custom(bridges) => bridges,
+            BridgeSettings::Normal(_) => vec![],
+        };
+        update(&mut bridges)?;
+        rpc.set_bridge_settings(types::BridgeSettings::from(BridgeSettings::Custom(bridges)))
+            .await?;
+        Ok(())
+    }
+
+    async fn handle_add_custom_bridge(matches: &clap::ArgMatches) -> Result<()> {
+        let (_, args) = matches.subcommand().unwrap();
+        let name = args.value_of("name").unwrap().to_owned();
+
+        let packed_proxy = if let Some(args) = matches.subcommand_matches("local") {
             let local_port = args.value_of_t_or_exit("local-port");
             let remote_ip = args.value_of_t_or_exit("remote-ip");
             let remote_port = args.value_of_t_or_exit("remote-port");
@@ -336,16 +511,7 @@ impl Bridge {
                 port: local_port,
                 peer: SocketAddr::new(remote_ip, remote_port),
             };
-            let packed_proxy = openvpn::ProxySettings::Local(local_proxy);
-            if let Err(error) = openvpn::validate_proxy_settings(&packed_proxy) {
-                panic!("{}", error);
-            }
-
-            let mut rpc = new_rpc_client().await?;
-            rpc.set_bridge_settings(types::BridgeSettings::from(BridgeSettings::Custom(
-                packed_proxy,
-            )))
-            .await?;
+            openvpn::ProxySettings::Local(local_proxy)
         } else if let Some(args) = matches.subcommand_matches("remote") {
             let remote_ip = args.value_of_t_or_exit("remote-ip");
             let remote_port = args.value_of_t_or_exit("remote-port");
@@ -363,16 +529,7 @@ impl Bridge {
                 address: SocketAddr::new(remote_ip, remote_port),
                 auth,
             };
-            let packed_proxy = openvpn::ProxySettings::Remote(proxy);
-            if let Err(error) = openvpn::validate_proxy_settings(&packed_proxy) {
-                panic!("{}", error);
-            }
-
-            let mut rpc = new_rpc_client().await?;
-            rpc.set_bridge_settings(types::BridgeSettings::from(BridgeSettings::Custom(
-                packed_proxy,
-            )))
-            .await?;
+            openvpn::ProxySettings::Remote(proxy)
         } else if let Some(args) = matches.subcommand_matches("shadowsocks") {
             let remote_ip = args.value_of_t_or_exit("remote-ip");
             let remote_port = args.value_of_t_or_exit("remote-port");
@@ -386,21 +543,47 @@ impl Bridge {
                 #[cfg(target_os = "linux")]
                 fwmark: None,
             };
-            let packed_proxy = openvpn::ProxySettings::Shadowsocks(proxy);
-            if let Err(error) = openvpn::validate_proxy_settings(&packed_proxy) {
-                panic!("{}", error);
-            }
+            openvpn::ProxySettings::Shadowsocks(proxy)
+        } else if let Some(args) = matches.subcommand_matches("http") {
+            let remote_ip = args.value_of_t_or_exit("remote-ip");
+            let remote_port = args.value_of_t_or_exit("remote-port");
+            let username = args.value_of("username");
+            let password = args.value_of("password");
 
-            let mut rpc = new_rpc_client().await?;
-            rpc.set_bridge_settings(types::BridgeSettings::from(BridgeSettings::Custom(
-                packed_proxy,
-            )))
-            .await?;
+            let auth = match (username, password) {
+                (Some(username), Some(password)) => Some(openvpn::ProxyAuth {
+                    username: username.to_string(),
+                    password: password.to_string(),
+                }),
+                _ => None,
+            };
+            let proxy = openvpn::HttpProxySettings {
+                address: SocketAddr::new(remote_ip, remote_port),
+                auth,
+            };
+            openvpn::ProxySettings::Http(proxy)
         } else {
             unreachable!("unhandled proxy type");
+        };
+        if let Err(error) = openvpn::validate_proxy_settings(&packed_proxy) {
+            panic!("{}", error);
         }
 
-        println!("proxy details have been updated");
+        Self::update_custom_bridges(|bridges| {
+            if bridges.iter().any(|bridge| bridge.name == name) {
+                return Err(Error::InvalidCommand(
+                    "A custom bridge with that name already exists",
+                ));
+            }
+            bridges.push(CustomBridge {
+                name: name.clone(),
+                enabled: true,
+                proxy: packed_proxy,
+            });
+            Ok(())
+        })
+        .await?;
+        println!("Added custom bridge {name}");
         Ok(())
     }
 
@@ -429,6 +612,18 @@ impl Bridge {
         println!("  cipher: {}", proxy.cipher);
     }
 
+    fn print_http_proxy(proxy: &openvpn::HttpProxySettings) {
+        println!("proxy: HTTP");
+        println!("  server address: {}", proxy.address);
+
+        if let Some(ref auth) = proxy.auth {
+            println!("  auth username: {}", auth.username);
+            println!("  auth password: {}", auth.password);
+        } else {
+            println!("  auth: none");
+        }
+    }
+
     async fn list_bridge_relays() -> Result<()> {
         let mut rpc = new_rpc_client().await?;
         let relay_list = rpc
@@ -490,3 +685,10 @@ impl Bridge {
         Ok(())
     }
 }
+
+fn find_custom_bridge(bridges: &[CustomBridge], name: &str) -> Result<usize> {
+    bridges
+        .iter()
+        .position(|bridge| bridge.name == name)
+        .ok_or(Error::InvalidCommand("No custom bridge has that name"))
+}
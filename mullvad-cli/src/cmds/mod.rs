@@ -31,12 +31,22 @@ pub use self::lan::Lan;
 mod obfuscation;
 pub use self::obfuscation::Obfuscation;
 
+#[cfg(target_os = "linux")]
+mod overlay;
+#[cfg(target_os = "linux")]
+pub use self::overlay::Overlay;
+
 mod reconnect;
 pub use self::reconnect::Reconnect;
 
 mod relay;
 pub use self::relay::Relay;
 
+#[cfg(target_os = "linux")]
+mod route;
+#[cfg(target_os = "linux")]
+pub use self::route::Route;
+
 mod reset;
 pub use self::reset::Reset;
 
@@ -48,6 +58,9 @@ pub use self::split_tunnel::SplitTunnel;
 mod status;
 pub use self::status::Status;
 
+mod traffic_usage;
+pub use self::traffic_usage::TrafficUsage;
+
 mod tunnel;
 pub use self::tunnel::Tunnel;
 
@@ -68,11 +81,16 @@ pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
         Box::new(Reconnect),
         Box::new(Lan),
         Box::new(Obfuscation),
+        #[cfg(target_os = "linux")]
+        Box::new(Overlay),
         Box::new(Relay),
+        #[cfg(target_os = "linux")]
+        Box::new(Route),
         Box::new(Reset),
         #[cfg(any(target_os = "linux", windows))]
         Box::new(SplitTunnel),
         Box::new(Status),
+        Box::new(TrafficUsage),
         Box::new(Tunnel),
         Box::new(Version),
     ];
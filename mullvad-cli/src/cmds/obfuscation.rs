@@ -46,6 +46,8 @@ impl Obfuscation {
                     "auto" => SelectedObfuscation::Auto,
                     "off" => SelectedObfuscation::Off,
                     "udp2tcp" => SelectedObfuscation::Udp2Tcp,
+                    "shadowsocks" => SelectedObfuscation::Shadowsocks,
+                    "tls" => SelectedObfuscation::Tls,
                     _ => unreachable!("Unhandled obfuscator mode"),
                 };
                 Self::set_obfuscation_settings(&mut rpc, &settings).await?;
@@ -63,6 +65,30 @@ impl Obfuscation {
                 };
                 Self::set_obfuscation_settings(&mut rpc, &settings).await?;
             }
+            Some(("shadowsocks", settings_matches)) => {
+                let port: String = settings_matches.value_of_t_or_exit("port");
+                let mut rpc = new_rpc_client().await?;
+                let mut settings = Self::get_obfuscation_settings(&mut rpc).await?;
+                settings.shadowsocks.port = if port == "any" {
+                    mullvad_types::relay_constraints::Constraint::Any
+                } else {
+                    mullvad_types::relay_constraints::Constraint::Only(
+                        port.parse::<u16>().expect("Invalid port number"),
+                    )
+                };
+                Self::set_obfuscation_settings(&mut rpc, &settings).await?;
+            }
+            Some(("tls", settings_matches)) => {
+                let server_name = settings_matches.value_of("server-name").unwrap();
+                let mut rpc = new_rpc_client().await?;
+                let mut settings = Self::get_obfuscation_settings(&mut rpc).await?;
+                settings.tls.server_name = if server_name == "default" {
+                    None
+                } else {
+                    Some(server_name.to_owned())
+                };
+                Self::set_obfuscation_settings(&mut rpc, &settings).await?;
+            }
             _ => unreachable!("unhandled command"),
         }
         Ok(())
@@ -76,6 +102,8 @@ impl Obfuscation {
             obfuscation_settings.selected_obfuscation
         );
         println!("udp2tcp settings: {}", obfuscation_settings.udp2tcp);
+        println!("shadowsocks settings: {}", obfuscation_settings.shadowsocks);
+        println!("tls settings: {}", obfuscation_settings.tls);
         Ok(())
     }
 
@@ -116,7 +144,7 @@ fn create_obfuscation_set_subcommand() -> clap::App<'static> {
                     )
                     .required(true)
                     .index(1)
-                    .possible_values(["auto", "off", "udp2tcp"]),
+                    .possible_values(["auto", "off", "udp2tcp", "shadowsocks", "tls"]),
             ),
         )
         .subcommand(
@@ -130,6 +158,46 @@ fn create_obfuscation_set_subcommand() -> clap::App<'static> {
                         .takes_value(true),
                 ),
         )
+        .subcommand(
+            clap::App::new("shadowsocks")
+                .about("Specifies the config for the Shadowsocks obfuscator")
+                .setting(clap::AppSettings::ArgRequiredElseHelp)
+                .arg(
+                    clap::Arg::new("port")
+                        .help("UDP port of the Shadowsocks server. Either 'any' or a specific port")
+                        .long("port")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            clap::App::new("tls")
+                .about("Specifies the config for the TLS obfuscator")
+                .setting(clap::AppSettings::ArgRequiredElseHelp)
+                .arg(
+                    clap::Arg::new("server-name")
+                        .help(
+                            "Server name to send in the TLS handshake. \
+                            Either 'default', to use the name given by the relay, or a hostname",
+                        )
+                        .long("server-name")
+                        .takes_value(true)
+                        .validator(|name| {
+                            if name == "default" || is_valid_hostname(name) {
+                                Ok(())
+                            } else {
+                                Err("invalid hostname")
+                            }
+                        }),
+                ),
+        )
+}
+
+fn is_valid_hostname(name: &str) -> bool {
+    name.split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
 }
 
 fn create_obfuscation_get_subcommand() -> clap::App<'static> {
@@ -0,0 +1,88 @@
+use crate::{new_rpc_client, Command, Result};
+use mullvad_management_interface::types;
+
+pub struct Overlay;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Overlay {
+    fn name(&self) -> &'static str {
+        "overlay"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static> {
+        clap::App::new(self.name())
+            .about("Configure trusted overlay networks that should bypass the tunnel")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(clap::App::new("get").about("Display the trusted overlay networks"))
+            .subcommand(
+                clap::App::new("set")
+                    .about("Set trusted overlay interfaces and networks")
+                    .arg(
+                        clap::Arg::new("interface")
+                            .long("interface")
+                            .short('i')
+                            .takes_value(true)
+                            .multiple_occurrences(true)
+                            .help("Name of an overlay interface, such as tailscale0"),
+                    )
+                    .arg(
+                        clap::Arg::new("network")
+                            .long("network")
+                            .short('n')
+                            .takes_value(true)
+                            .multiple_occurrences(true)
+                            .help("Network reachable through an overlay, in CIDR notation"),
+                    ),
+            )
+            .subcommand(clap::App::new("clear").about("Remove all trusted overlay networks"))
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        match matches.subcommand() {
+            Some(("set", matches)) => {
+                let values = |name| -> Vec<String> {
+                    matches
+                        .values_of(name)
+                        .map(|values| values.map(String::from).collect())
+                        .unwrap_or_default()
+                };
+                self.set(values("interface"), values("network")).await
+            }
+            Some(("clear", _)) => self.set(vec![], vec![]).await,
+            Some(("get", _)) => self.get().await,
+            _ => unreachable!("No overlay command given"),
+        }
+    }
+}
+
+impl Overlay {
+    async fn set(&self, interfaces: Vec<String>, networks: Vec<String>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_trusted_overlays(types::TrustedOverlays {
+            interfaces,
+            networks,
+        })
+        .await?;
+        println!("Updated trusted overlay networks");
+        Ok(())
+    }
+
+    async fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let overlays = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .trusted_overlays
+            .unwrap_or_default();
+        println!("Interfaces:");
+        for interface in &overlays.interfaces {
+            println!("    {interface}");
+        }
+        println!("Networks:");
+        for network in &overlays.networks {
+            println!("    {network}");
+        }
+        Ok(())
+    }
+}
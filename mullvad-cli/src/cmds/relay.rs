@@ -4,11 +4,16 @@ use std::{
     convert::TryFrom,
     io::{self, BufRead},
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
     str::FromStr,
 };
 
 use mullvad_management_interface::{types, ManagementServiceClient};
-use mullvad_types::relay_constraints::{Constraint, RelaySettings};
+use mullvad_types::{
+    ovpn::OvpnConfig,
+    relay_constraints::{Constraint, RelaySettings, RelaySettingsUpdate},
+    wg_quick::WgQuickConfig,
+};
 use talpid_types::net::all_of_the_internet;
 
 pub struct Relay;
@@ -37,27 +42,27 @@ impl Command for Relay {
                                 .arg(
                                     clap::Arg::new("host")
                                         .help("Hostname or IP")
-                                        .required(true),
+                                        .required_unless_present("from-file"),
                                 )
                                 .arg(
                                     clap::Arg::new("port")
                                         .help("Remote network port")
-                                        .required(true),
+                                        .required_unless_present("from-file"),
                                 )
                                 .arg(
                                     clap::Arg::new("peer-pubkey")
                                         .help("Base64 encoded peer public key")
-                                        .required(true),
+                                        .required_unless_present("from-file"),
                                 )
                                 .arg(
                                     clap::Arg::new("v4-gateway")
                                         .help("IPv4 gateway address")
-                                        .required(true),
+                                        .required_unless_present("from-file"),
                                 )
                                 .arg(
                                     clap::Arg::new("addr")
                                         .help("Local address of wireguard tunnel")
-                                        .required(true)
+                                        .required_unless_present("from-file")
                                         .multiple_values(true),
                                 )
                                 .arg(
@@ -66,27 +71,41 @@ impl Command for Relay {
                                         .long("v6-gateway")
                                         .takes_value(true),
                                 )
+                                .arg(
+                                    clap::Arg::new("from-file")
+                                        .help("Read the relay from a wg-quick configuration file")
+                                        .long("from-file")
+                                        .takes_value(true)
+                                        .value_name("PATH")
+                                        .conflicts_with_all(&["host", "port", "peer-pubkey", "v4-gateway", "addr", "v6-gateway"]),
+                                )
+                                .arg(
+                                    clap::Arg::new("apply-dns-and-mtu")
+                                        .help("Also replace the DNS and WireGuard MTU settings with those of the configuration file. These settings apply to all relays")
+                                        .long("apply-dns-and-mtu")
+                                        .requires("from-file"),
+                                )
                             )
                             .subcommand(clap::App::new("openvpn")
                                 .arg(
                                     clap::Arg::new("host")
                                         .help("Hostname or IP")
-                                        .required(true),
+                                        .required_unless_present("from-file"),
                                 )
                                 .arg(
                                     clap::Arg::new("port")
                                         .help("Remote network port")
-                                        .required(true),
+                                        .required_unless_present("from-file"),
                                 )
                                 .arg(
                                     clap::Arg::new("username")
                                         .help("Username to be used with the OpenVpn relay")
-                                        .required(true),
+                                        .required_unless_present("from-file"),
                                 )
                                 .arg(
                                     clap::Arg::new("password")
                                         .help("Password to be used with the OpenVpn relay")
-                                        .required(true),
+                                        .required_unless_present("from-file"),
                                 )
                                 .arg(
                                     clap::Arg::new("protocol")
@@ -95,6 +114,16 @@ impl Command for Relay {
                                         .default_value("udp")
                                         .possible_values(["udp", "tcp"]),
                                 )
+                                .arg(
+                                    clap::Arg::new("from-file")
+                                        .help("Read the server, certificates and keys from an OpenVPN profile. \
+                                              If the server requires a username and password, \
+                                              they are read from standard input")
+                                        .long("from-file")
+                                        .takes_value(true)
+                                        .value_name("PATH")
+                                        .conflicts_with_all(&["host", "port", "username", "password"]),
+                                )
                             )
                     )
                     .subcommand(
@@ -197,6 +226,20 @@ impl Command for Relay {
             .subcommand(
                 clap::App::new("list").about("List available countries and cities"),
             )
+            .subcommand(
+                clap::App::new("export-wireguard")
+                    .about("Print a wg-quick configuration for this device and a WireGuard relay. \
+                            This reveals the private key of the device and requires running as \
+                            root. Quantum-resistant configurations are not supported")
+                    .arg(
+                        clap::Arg::new("location")
+                            .help("A relay hostname, or a country code optionally followed by \
+                                  a city code and a hostname")
+                            .required(true)
+                            .multiple_values(true)
+                            .max_values(3),
+                    ),
+            )
             .subcommand(
                 clap::App::new("update")
                     .about("Update the list of available countries and cities"),
@@ -210,6 +253,8 @@ impl Command for Relay {
             self.get().await
         } else if matches.subcommand_matches("list").is_some() {
             self.list().await
+        } else if let Some(export_matches) = matches.subcommand_matches("export-wireguard") {
+            self.export_wireguard(export_matches).await
         } else if matches.subcommand_matches("update").is_some() {
             self.update().await
         } else {
@@ -256,7 +301,13 @@ impl Relay {
 
     async fn set_custom(&self, matches: &clap::ArgMatches) -> Result<()> {
         let custom_endpoint = match matches.subcommand() {
+            Some(("openvpn", openvpn_matches)) if openvpn_matches.is_present("from-file") => {
+                return self.set_custom_openvpn_from_file(openvpn_matches).await;
+            }
             Some(("openvpn", openvpn_matches)) => Self::read_custom_openvpn_relay(openvpn_matches),
+            Some(("wireguard", wg_matches)) if wg_matches.is_present("from-file") => {
+                return self.set_custom_wireguard_from_file(wg_matches).await;
+            }
             Some(("wireguard", wg_matches)) => Self::read_custom_wireguard_relay(wg_matches),
             _ => unreachable!("No set relay command given"),
         };
@@ -267,6 +318,76 @@ impl Relay {
         .await
     }
 
+    async fn set_custom_wireguard_from_file(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let path: PathBuf = matches.value_of_t_or_exit("from-file");
+        let config = WgQuickConfig::from_file(&path).map_err(Error::WgQuickConfig)?;
+
+        self.update_constraints(types::RelaySettingsUpdate::from(
+            RelaySettingsUpdate::CustomTunnelEndpoint(config.custom_tunnel_endpoint()),
+        ))
+        .await?;
+
+        if !matches.is_present("apply-dns-and-mtu") {
+            if !config.dns_servers.is_empty() || config.mtu.is_some() {
+                println!(
+                    "The DNS and MTU settings of the configuration file were not applied. Use \
+                     --apply-dns-and-mtu to replace the current settings with them"
+                );
+            }
+            return Ok(());
+        }
+
+        let mut rpc = new_rpc_client().await?;
+        if let Some(dns_options) = config.dns_options() {
+            let settings = rpc.get_settings(()).await?.into_inner();
+            rpc.set_dns_options(types::DnsOptions {
+                default_options: settings
+                    .tunnel_options
+                    .unwrap()
+                    .dns_options
+                    .unwrap()
+                    .default_options,
+                ..types::DnsOptions::from(&dns_options)
+            })
+            .await?;
+            println!("Updated DNS settings");
+        }
+        if let Some(mtu) = config.mtu {
+            rpc.set_wireguard_mtu(u32::from(mtu)).await?;
+            println!("Wireguard MTU has been updated");
+        }
+        Ok(())
+    }
+
+    async fn set_custom_openvpn_from_file(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let path: PathBuf = matches.value_of_t_or_exit("from-file");
+        let mut config = OvpnConfig::from_file(&path).map_err(Error::OvpnConfig)?;
+
+        if config.requires_credentials() {
+            config.connection.username = Self::read_line_from_stdin("username")?;
+            config.connection.password = Self::read_line_from_stdin("password")?;
+        }
+
+        self.update_constraints(types::RelaySettingsUpdate::from(
+            RelaySettingsUpdate::CustomTunnelEndpoint(config.custom_tunnel_endpoint()),
+        ))
+        .await
+    }
+
+    fn read_line_from_stdin(name: &'static str) -> Result<String> {
+        let mut line = String::new();
+        println!("Reading {name} from standard input");
+        io::stdin()
+            .lock()
+            .read_line(&mut line)
+            .map_err(Error::ReadStdin)?;
+        let line = line.trim();
+        if line.is_empty() {
+            return Err(Error::MissingInput(name));
+        }
+        Ok(line.to_owned())
+    }
+
     fn read_custom_openvpn_relay(matches: &clap::ArgMatches) -> types::CustomRelaySettings {
         let host = matches.value_of_t_or_exit("host");
         let port = matches.value_of_t_or_exit("port");
@@ -286,6 +407,7 @@ impl Relay {
                         protocol: protocol as i32,
                         username,
                         password,
+                        profile: None,
                     },
                 )),
             }),
@@ -336,6 +458,7 @@ impl Relay {
                                 .collect(),
                             endpoint: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port)
                                 .to_string(),
+                            psk: vec![],
                         }),
                         ipv4_gateway: ipv4_gateway.to_string(),
                         ipv6_gateway: ipv6_gateway
@@ -381,26 +504,8 @@ impl Relay {
 
     async fn set_hostname(&self, matches: &clap::ArgMatches) -> Result<()> {
         let hostname = matches.value_of("hostname").unwrap();
-        let countries = Self::get_filtered_relays().await?;
 
-        let find_relay = || {
-            for country in countries {
-                for city in country.cities {
-                    for relay in city.relays {
-                        if relay.hostname.to_lowercase() == hostname.to_lowercase() {
-                            return Some(types::RelayLocation {
-                                country: country.code,
-                                city: city.code,
-                                hostname: relay.hostname,
-                            });
-                        }
-                    }
-                }
-            }
-            None
-        };
-
-        if let Some(location) = find_relay() {
+        if let Some(location) = Self::find_relay_by_hostname(hostname).await? {
             println!(
                 "Setting location constraint to {} in {}, {}",
                 location.hostname, location.city, location.country
@@ -420,6 +525,24 @@ impl Relay {
         }
     }
 
+    async fn find_relay_by_hostname(hostname: &str) -> Result<Option<types::RelayLocation>> {
+        let countries = Self::get_filtered_relays().await?;
+        for country in countries {
+            for city in country.cities {
+                for relay in city.relays {
+                    if relay.hostname.to_lowercase() == hostname.to_lowercase() {
+                        return Ok(Some(types::RelayLocation {
+                            country: country.code,
+                            city: city.code,
+                            hostname: relay.hostname,
+                        }));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
     async fn set_location(&self, matches: &clap::ArgMatches) -> Result<()> {
         let location_constraint = location::get_constraint_from_args(matches);
         let mut found = false;
@@ -712,6 +835,36 @@ impl Relay {
         Ok(())
     }
 
+    async fn export_wireguard(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let args: Vec<&str> = matches.values_of("location").unwrap().collect();
+        let location = match args.as_slice() {
+            [hostname] if hostname.len() > 3 => Self::find_relay_by_hostname(hostname)
+                .await?
+                .unwrap_or_else(|| {
+                    clap::Error::raw(clap::ErrorKind::ValueValidation, "No matching server found")
+                        .exit()
+                }),
+            [country, rest @ ..] => {
+                if let Err(error) = location::country_code_validator(country) {
+                    clap::Error::raw(clap::ErrorKind::ValueValidation, error).exit();
+                }
+                location::get_constraint(*country, rest.first().copied(), rest.get(1).copied())
+            }
+            [] => unreachable!("No location given"),
+        };
+
+        let mut rpc = new_rpc_client().await?;
+        let config = rpc
+            .export_wireguard_config(types::WireguardConfigExport {
+                location: Some(location),
+            })
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to export WireGuard config", error))?
+            .into_inner();
+        print!("{config}");
+        Ok(())
+    }
+
     async fn update(&self) -> Result<()> {
         new_rpc_client().await?.update_relay_locations(()).await?;
         println!("Updating relay list in the background...");
@@ -0,0 +1,115 @@
+use crate::{new_rpc_client, Command, Result};
+use mullvad_management_interface::types;
+
+pub struct Route;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Route {
+    fn name(&self) -> &'static str {
+        "route"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static> {
+        clap::App::new(self.name())
+            .about("Route networks through the tunnel or via the default gateway")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(clap::App::new("list").about("List all user routes"))
+            .subcommand(
+                clap::App::new("add")
+                    .about("Add or replace a route")
+                    .arg(
+                        clap::Arg::new("network")
+                            .required(true)
+                            .help("Destination network in CIDR notation"),
+                    )
+                    .arg(
+                        clap::Arg::new("via")
+                            .long("via")
+                            .takes_value(true)
+                            .required(true)
+                            .possible_values(["tunnel", "gateway"])
+                            .help("Route through the tunnel or via the default gateway"),
+                    ),
+            )
+            .subcommand(
+                clap::App::new("remove")
+                    .about("Remove a route")
+                    .arg(clap::Arg::new("network").required(true)),
+            )
+            .subcommand(clap::App::new("clear").about("Remove all user routes"))
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        match matches.subcommand() {
+            Some(("list", _)) => self.list().await,
+            Some(("add", matches)) => {
+                let network = matches.value_of("network").unwrap().to_owned();
+                let target = match matches.value_of("via").unwrap() {
+                    "tunnel" => types::user_route::Target::Tunnel,
+                    "gateway" => types::user_route::Target::DefaultGateway,
+                    _ => unreachable!("invalid route target"),
+                };
+                self.add(network, target).await
+            }
+            Some(("remove", matches)) => {
+                let network = matches.value_of("network").unwrap().to_owned();
+                self.remove(network).await
+            }
+            Some(("clear", _)) => self.set(vec![]).await,
+            _ => unreachable!("No route command given"),
+        }
+    }
+}
+
+impl Route {
+    async fn list(&self) -> Result<()> {
+        for route in self.get().await? {
+            let target = match types::user_route::Target::from_i32(route.target) {
+                Some(types::user_route::Target::Tunnel) => "tunnel",
+                Some(types::user_route::Target::DefaultGateway) => "default gateway",
+                None => "unknown",
+            };
+            println!("{} via {}", route.network, target);
+        }
+        Ok(())
+    }
+
+    async fn add(&self, network: String, target: types::user_route::Target) -> Result<()> {
+        let mut routes = self.get().await?;
+        routes.retain(|route| route.network != network);
+        routes.push(types::UserRoute {
+            network,
+            target: target as i32,
+        });
+        self.set(routes).await
+    }
+
+    async fn remove(&self, network: String) -> Result<()> {
+        let mut routes = self.get().await?;
+        let num_routes = routes.len();
+        routes.retain(|route| route.network != network);
+        if routes.len() == num_routes {
+            println!("No route for {network} exists");
+            return Ok(());
+        }
+        self.set(routes).await
+    }
+
+    async fn get(&self) -> Result<Vec<types::UserRoute>> {
+        let mut rpc = new_rpc_client().await?;
+        Ok(rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .user_routes
+            .unwrap_or_default()
+            .routes)
+    }
+
+    async fn set(&self, routes: Vec<types::UserRoute>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_user_routes(types::UserRoutes { routes }).await?;
+        println!("Updated user routes");
+        Ok(())
+    }
+}
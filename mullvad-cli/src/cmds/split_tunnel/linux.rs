@@ -16,11 +16,13 @@ impl Command for SplitTunnel {
             )
             .setting(clap::AppSettings::SubcommandRequiredElseHelp)
             .subcommand(create_pid_subcommand())
+            .subcommand(create_netns_subcommand())
     }
 
     async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
         match matches.subcommand() {
             Some(("pid", pid_matches)) => Self::handle_pid_cmd(pid_matches).await,
+            Some(("netns", netns_matches)) => Self::handle_netns_cmd(netns_matches).await,
             _ => unreachable!("unhandled command"),
         }
     }
@@ -36,7 +38,56 @@ fn create_pid_subcommand() -> clap::App<'static> {
         .subcommand(clap::App::new("list"))
 }
 
+fn create_netns_subcommand() -> clap::App<'static> {
+    clap::App::new("netns")
+        .about(
+            "Manage the network namespace that 'mullvad-exclude --netns' launches \
+                applications in",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::App::new("set")
+                .about("Create or remove the network namespace")
+                .arg(
+                    clap::Arg::new("policy")
+                        .required(true)
+                        .possible_values(["on", "off"]),
+                ),
+        )
+        .subcommand(clap::App::new("get").about("Display whether the network namespace is enabled"))
+}
+
 impl SplitTunnel {
+    async fn handle_netns_cmd(matches: &clap::ArgMatches) -> Result<()> {
+        match matches.subcommand() {
+            Some(("set", matches)) => {
+                let enabled = matches.value_of("policy").expect("missing policy") == "on";
+                new_rpc_client()
+                    .await?
+                    .set_split_tunnel_network_namespace(enabled)
+                    .await?;
+                println!("Changed split tunnel network namespace setting");
+                Ok(())
+            }
+            Some(("get", _)) => {
+                let enabled = new_rpc_client()
+                    .await?
+                    .get_settings(())
+                    .await?
+                    .into_inner()
+                    .split_tunnel
+                    .map(|settings| settings.enable_network_namespace)
+                    .unwrap_or(false);
+                println!(
+                    "Split tunnel network namespace: {}",
+                    if enabled { "on" } else { "off" }
+                );
+                Ok(())
+            }
+            _ => unreachable!("unhandled command"),
+        }
+    }
+
     async fn handle_pid_cmd(matches: &clap::ArgMatches) -> Result<()> {
         match matches.subcommand() {
             Some(("add", matches)) => {
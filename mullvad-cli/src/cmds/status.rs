@@ -26,6 +26,11 @@ impl Command for Status {
                     .short('l')
                     .help("Prints the current location and IP. Based on GeoIP lookups"),
             )
+            .arg(
+                clap::Arg::new("stats")
+                    .long("stats")
+                    .help("Continuously prints traffic statistics while the tunnel is connected"),
+            )
             .arg(
                 clap::Arg::new("debug")
                     .long("debug")
@@ -54,7 +59,16 @@ impl Command for Status {
             print_location(&mut rpc).await?;
         }
 
-        if matches.subcommand_matches("listen").is_some() {
+        if matches.is_present("stats") {
+            let mut statistics = rpc.tunnel_statistics_listen(()).await?.into_inner();
+            while let Some(stats) = statistics.message().await? {
+                if debug {
+                    println!("Tunnel statistics: {stats:#?}");
+                } else {
+                    format::print_statistics(&stats);
+                }
+            }
+        } else if matches.subcommand_matches("listen").is_some() {
             let mut events = rpc.events_listen(()).await?.into_inner();
 
             while let Some(event) = events.message().await? {
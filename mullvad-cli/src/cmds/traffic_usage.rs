@@ -0,0 +1,92 @@
+use crate::{format, new_rpc_client, Command, Error, Result};
+use mullvad_management_interface::types;
+
+pub struct TrafficUsage;
+
+#[mullvad_management_interface::async_trait]
+impl Command for TrafficUsage {
+    fn name(&self) -> &'static str {
+        "traffic-usage"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static> {
+        clap::App::new(self.name())
+            .about("Show the amount of traffic sent through the tunnel, per day (UTC) and relay")
+            .arg(
+                clap::Arg::new("since")
+                    .help("Only include traffic from this date onwards (YYYY-MM-DD)")
+                    .long("since")
+                    .takes_value(true)
+                    .validator(date_validator),
+            )
+            .arg(
+                clap::Arg::new("until")
+                    .help("Only include traffic up to and including this date (YYYY-MM-DD)")
+                    .long("until")
+                    .takes_value(true)
+                    .validator(date_validator),
+            )
+            .arg(
+                clap::Arg::new("relay")
+                    .help("Only include traffic through the relay with this hostname")
+                    .long("relay")
+                    .takes_value(true),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let filter = types::TrafficUsageFilter {
+            since: matches.value_of("since").unwrap_or_default().to_owned(),
+            until: matches.value_of("until").unwrap_or_default().to_owned(),
+            relay: matches.value_of("relay").unwrap_or_default().to_owned(),
+        };
+
+        let mut rpc = new_rpc_client().await?;
+        let records = rpc
+            .get_traffic_usage(filter)
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to obtain traffic usage", error))?
+            .into_inner()
+            .records;
+
+        if records.is_empty() {
+            println!("No traffic has been recorded");
+            return Ok(());
+        }
+
+        let relay_width = records
+            .iter()
+            .map(|record| record.relay.len())
+            .max()
+            .unwrap_or(0)
+            .max("Relay".len());
+        println!(
+            "{:10}  {:relay_width$}  {:>10}  {:>10}",
+            "Date", "Relay", "Received", "Sent"
+        );
+        for record in &records {
+            println!(
+                "{:10}  {:relay_width$}  {:>10}  {:>10}",
+                record.date,
+                record.relay,
+                format::format_bytes(record.rx_bytes),
+                format::format_bytes(record.tx_bytes),
+            );
+        }
+        println!(
+            "{:10}  {:relay_width$}  {:>10}  {:>10}",
+            "Total",
+            "",
+            format::format_bytes(records.iter().map(|record| record.rx_bytes).sum()),
+            format::format_bytes(records.iter().map(|record| record.tx_bytes).sum()),
+        );
+
+        Ok(())
+    }
+}
+
+fn date_validator(date: &str) -> std::result::Result<(), String> {
+    chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map(|_| ())
+        .map_err(|_| String::from("Dates must be formatted as YYYY-MM-DD"))
+}
@@ -2,6 +2,7 @@ use crate::{new_rpc_client, Command, Error, Result};
 use mullvad_management_interface::types::{self, Timestamp, TunnelOptions};
 use mullvad_types::wireguard::DEFAULT_ROTATION_INTERVAL;
 use std::{convert::TryFrom, time::Duration};
+use talpid_types::net::wireguard::{ConnectivityProbe, ConnectivityProbes};
 
 pub struct Tunnel;
 
@@ -37,13 +38,19 @@ fn create_wireguard_subcommand() -> clap::App<'static> {
         .about("Manage options for Wireguard tunnels")
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(create_wireguard_mtu_subcommand())
+        .subcommand(create_wireguard_persistent_keepalive_subcommand())
         .subcommand(create_wireguard_quantum_resistant_tunnel_subcommand())
+        .subcommand(create_wireguard_connectivity_probes_subcommand())
         .subcommand(create_wireguard_keys_subcommand());
     #[cfg(windows)]
     {
         subcmd.subcommand(create_wireguard_use_wg_nt_subcommand())
     }
-    #[cfg(not(windows))]
+    #[cfg(target_os = "linux")]
+    {
+        subcmd.subcommand(create_wireguard_use_boringtun_subcommand())
+    }
+    #[cfg(not(any(windows, target_os = "linux")))]
     {
         subcmd
     }
@@ -55,7 +62,25 @@ fn create_wireguard_mtu_subcommand() -> clap::App<'static> {
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(clap::App::new("get"))
         .subcommand(clap::App::new("unset"))
-        .subcommand(clap::App::new("set").arg(clap::Arg::new("mtu").required(true)))
+        .subcommand(
+            clap::App::new("set").arg(
+                clap::Arg::new("mtu")
+                    .help(
+                        "The MTU, or 'auto' to probe the largest MTU that passes through the \
+                         tunnel after connecting (Linux only)",
+                    )
+                    .required(true),
+            ),
+        )
+}
+
+fn create_wireguard_persistent_keepalive_subcommand() -> clap::App<'static> {
+    clap::App::new("persistent-keepalive")
+        .about("Configure how often keepalive packets are sent to the relay (given in seconds)")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::App::new("get"))
+        .subcommand(clap::App::new("unset"))
+        .subcommand(clap::App::new("set").arg(clap::Arg::new("interval").required(true)))
 }
 
 fn create_wireguard_quantum_resistant_tunnel_subcommand() -> clap::App<'static> {
@@ -70,6 +95,50 @@ fn create_wireguard_quantum_resistant_tunnel_subcommand() -> clap::App<'static>
                     .possible_values(["on", "off", "auto"]),
             ),
         )
+        .subcommand(create_wireguard_quantum_resistant_rekey_interval_subcommand())
+}
+
+fn create_wireguard_quantum_resistant_rekey_interval_subcommand() -> clap::App<'static> {
+    clap::App::new("rekey-interval")
+        .about("Manage periodic renegotiation of the PSK (given in hours)")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::App::new("get"))
+        .subcommand(clap::App::new("reset").about("Only negotiate a PSK when connecting"))
+        .subcommand(clap::App::new("set").arg(clap::Arg::new("interval").required(true)))
+}
+
+fn create_wireguard_connectivity_probes_subcommand() -> clap::App<'static> {
+    clap::App::new("connectivity-probes")
+        .about("Configure how the connectivity of the tunnel is verified")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::App::new("get"))
+        .subcommand(clap::App::new("reset").about("Only ping the tunnel gateway"))
+        .subcommand(
+            clap::App::new("set")
+                .arg(
+                    clap::Arg::new("probes")
+                        .help(
+                            "Probes to send: 'icmp', 'tcp:<address>:<port>', \
+                             'dns:<hostname>@<resolver>' or 'http:<address>:<port>[/path]'. \
+                             The resolver must be one of the DNS servers in use",
+                        )
+                        .required(true)
+                        .multiple_values(true),
+                )
+                .arg(
+                    clap::Arg::new("required")
+                        .help("Number of probes that must be answered")
+                        .long("required")
+                        .takes_value(true)
+                        .default_value("1"),
+                )
+                .arg(
+                    clap::Arg::new("timeout")
+                        .help("Seconds without answers before the tunnel is considered broken")
+                        .long("timeout")
+                        .takes_value(true),
+                ),
+        )
 }
 
 fn create_wireguard_keys_subcommand() -> clap::App<'static> {
@@ -97,6 +166,22 @@ fn create_wireguard_use_wg_nt_subcommand() -> clap::App<'static> {
         )
 }
 
+#[cfg(target_os = "linux")]
+fn create_wireguard_use_boringtun_subcommand() -> clap::App<'static> {
+    clap::App::new("use-boringtun")
+        .about("Use the BoringTun userspace implementation instead of the kernel module")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::App::new("get"))
+        .subcommand(
+            clap::App::new("set").arg(
+                clap::Arg::new("policy")
+                    .required(true)
+                    .takes_value(true)
+                    .possible_values(&["on", "off"]),
+            ),
+        )
+}
+
 fn create_wireguard_keys_rotation_interval_subcommand() -> clap::App<'static> {
     clap::App::new("rotation-interval")
         .about("Manage automatic key rotation (given in hours)")
@@ -111,6 +196,39 @@ fn create_openvpn_subcommand() -> clap::App<'static> {
         .about("Manage options for OpenVPN tunnels")
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(create_openvpn_mssfix_subcommand())
+        .subcommand(clap::App::new("get").about("Show the data channel options"))
+        .subcommand(
+            clap::App::new("set")
+                .about("Configure the data channel options")
+                .setting(clap::AppSettings::ArgRequiredElseHelp)
+                .arg(
+                    clap::Arg::new("cipher")
+                        .long("cipher")
+                        .help(
+                            "Only use this data channel cipher. With 'any', the server picks \
+                            one of the supported ciphers",
+                        )
+                        .takes_value(true)
+                        .possible_values(["any", "aes-256-gcm", "chacha20-poly1305"]),
+                )
+                .arg(
+                    clap::Arg::new("fragment")
+                        .long("fragment")
+                        .help(
+                            "Fragment UDP packets larger than this many bytes. \
+                            Requires server support and is ignored over TCP",
+                        )
+                        .value_name("BYTES|default")
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::new("tun-mtu")
+                        .long("tun-mtu")
+                        .help("MTU of the tunnel device")
+                        .value_name("BYTES|default")
+                        .takes_value(true),
+                ),
+        )
 }
 
 fn create_openvpn_mssfix_subcommand() -> clap::App<'static> {
@@ -142,6 +260,8 @@ impl Tunnel {
             Some(("mssfix", mssfix_matches)) => {
                 Self::handle_openvpn_mssfix_cmd(mssfix_matches).await
             }
+            Some(("get", _)) => Self::process_openvpn_options_get().await,
+            Some(("set", set_matches)) => Self::process_openvpn_options_set(set_matches).await,
             _ => unreachable!("unhandled command"),
         }
     }
@@ -178,11 +298,37 @@ impl Tunnel {
                 _ => unreachable!("unhandled command"),
             },
 
+            Some(("persistent-keepalive", matches)) => match matches.subcommand() {
+                Some(("get", _)) => Self::process_wireguard_persistent_keepalive_get().await,
+                Some(("set", matches)) => {
+                    Self::process_wireguard_persistent_keepalive_set(matches).await
+                }
+                Some(("unset", _)) => Self::process_wireguard_persistent_keepalive_unset().await,
+                _ => unreachable!("unhandled command"),
+            },
+
             Some(("quantum-resistant-tunnel", matches)) => match matches.subcommand() {
                 Some(("get", _)) => Self::process_wireguard_quantum_resistant_tunnel_get().await,
                 Some(("set", matches)) => {
                     Self::process_wireguard_quantum_resistant_tunnel_set(matches).await
                 }
+                Some(("rekey-interval", matches)) => match matches.subcommand() {
+                    Some(("get", _)) => Self::process_wireguard_rekey_interval_get().await,
+                    Some(("set", matches)) => {
+                        Self::process_wireguard_rekey_interval_set(matches).await
+                    }
+                    Some(("reset", _)) => Self::process_wireguard_rekey_interval_reset().await,
+                    _ => unreachable!("unhandled command"),
+                },
+                _ => unreachable!("unhandled command"),
+            },
+
+            Some(("connectivity-probes", matches)) => match matches.subcommand() {
+                Some(("get", _)) => Self::process_wireguard_connectivity_probes_get().await,
+                Some(("set", matches)) => {
+                    Self::process_wireguard_connectivity_probes_set(matches).await
+                }
+                Some(("reset", _)) => Self::process_wireguard_connectivity_probes_reset().await,
                 _ => unreachable!("unhandled command"),
             },
 
@@ -193,17 +339,26 @@ impl Tunnel {
                 _ => unreachable!("unhandled command"),
             },
 
+            #[cfg(target_os = "linux")]
+            Some(("use-boringtun", matches)) => match matches.subcommand() {
+                Some(("get", _)) => Self::process_wireguard_use_boringtun_get().await,
+                Some(("set", matches)) => Self::process_wireguard_use_boringtun_set(matches).await,
+                _ => unreachable!("unhandled command"),
+            },
+
             _ => unreachable!("unhandled command"),
         }
     }
 
     async fn process_wireguard_mtu_get() -> Result<()> {
         let tunnel_options = Self::get_tunnel_options().await?;
-        let mtu = tunnel_options.wireguard.unwrap().mtu;
+        let wireguard = tunnel_options.wireguard.unwrap();
         println!(
             "mtu: {}",
-            if mtu != 0 {
-                mtu.to_string()
+            if wireguard.mtu != 0 {
+                wireguard.mtu.to_string()
+            } else if wireguard.mtu_discovery {
+                "auto".to_string()
             } else {
                 "unset".to_string()
             },
@@ -212,9 +367,13 @@ impl Tunnel {
     }
 
     async fn process_wireguard_mtu_set(matches: &clap::ArgMatches) -> Result<()> {
-        let mtu = matches.value_of_t_or_exit::<u16>("mtu");
         let mut rpc = new_rpc_client().await?;
-        rpc.set_wireguard_mtu(mtu as u32).await?;
+        if matches.value_of("mtu") == Some("auto") {
+            rpc.set_wireguard_mtu_discovery(true).await?;
+        } else {
+            let mtu = matches.value_of_t_or_exit::<u16>("mtu");
+            rpc.set_wireguard_mtu(mtu as u32).await?;
+        }
         println!("Wireguard MTU has been updated");
         Ok(())
     }
@@ -226,6 +385,36 @@ impl Tunnel {
         Ok(())
     }
 
+    async fn process_wireguard_persistent_keepalive_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        match tunnel_options.wireguard.unwrap().persistent_keepalive {
+            0 => println!("Persistent keepalive: off"),
+            interval => println!("Persistent keepalive: {interval} second(s)"),
+        }
+        Ok(())
+    }
+
+    async fn process_wireguard_persistent_keepalive_set(matches: &clap::ArgMatches) -> Result<()> {
+        let interval = matches.value_of_t_or_exit::<u16>("interval");
+        if interval == 0 {
+            return Err(Error::InvalidCommand(
+                "The keepalive interval must be at least 1 second",
+            ));
+        }
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_persistent_keepalive(u32::from(interval))
+            .await?;
+        println!("Set persistent keepalive: {interval} second(s)");
+        Ok(())
+    }
+
+    async fn process_wireguard_persistent_keepalive_unset() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_persistent_keepalive(0).await?;
+        println!("Persistent keepalive has been unset");
+        Ok(())
+    }
+
     async fn process_wireguard_quantum_resistant_tunnel_get() -> Result<()> {
         let tunnel_options = Self::get_tunnel_options().await?;
         match tunnel_options
@@ -259,6 +448,99 @@ impl Tunnel {
         Ok(())
     }
 
+    async fn process_wireguard_rekey_interval_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        match tunnel_options
+            .wireguard
+            .unwrap()
+            .quantum_resistant_rekey_interval
+        {
+            Some(interval) => {
+                let hours = duration_hours(&Duration::try_from(interval).unwrap());
+                println!("Rekey interval: {hours} hour(s)");
+            }
+            None => println!("Rekey interval: none"),
+        }
+        Ok(())
+    }
+
+    async fn process_wireguard_rekey_interval_set(matches: &clap::ArgMatches) -> Result<()> {
+        let rekey_interval = matches.value_of_t_or_exit::<u64>("interval");
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_quantum_resistant_rekey_interval(
+            types::Duration::try_from(Duration::from_secs(60 * 60 * rekey_interval))
+                .expect("Failed to convert rekey interval to prost_types::Duration"),
+        )
+        .await?;
+        println!("Set PSK rekey interval: {rekey_interval} hour(s)");
+        Ok(())
+    }
+
+    async fn process_wireguard_rekey_interval_reset() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.reset_quantum_resistant_rekey_interval(()).await?;
+        println!("Unset PSK rekey interval");
+        Ok(())
+    }
+
+    async fn process_wireguard_connectivity_probes_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        let probes = tunnel_options
+            .wireguard
+            .unwrap()
+            .connectivity_probes
+            .map(ConnectivityProbes::try_from)
+            .transpose()
+            .map_err(|_| Error::Other("Received invalid connectivity probes"))?
+            .unwrap_or_default();
+
+        let formatted_probes: Vec<_> = probes
+            .probes
+            .iter()
+            .map(format_connectivity_probe)
+            .collect();
+        println!("Probes: {}", formatted_probes.join(" "));
+        println!("Required successes: {}", probes.required_successes);
+        match probes.timeout {
+            Some(timeout) => println!("Timeout: {} seconds", timeout.as_secs()),
+            None => println!("Timeout: default"),
+        }
+        Ok(())
+    }
+
+    async fn process_wireguard_connectivity_probes_set(matches: &clap::ArgMatches) -> Result<()> {
+        let probes = ConnectivityProbes {
+            probes: matches
+                .values_of("probes")
+                .unwrap()
+                .map(parse_connectivity_probe)
+                .collect::<Result<Vec<_>>>()?,
+            required_successes: matches.value_of_t_or_exit("required"),
+            timeout: if matches.is_present("timeout") {
+                Some(Duration::from_secs(
+                    matches.value_of_t_or_exit::<u64>("timeout"),
+                ))
+            } else {
+                None
+            },
+        };
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_connectivity_probes(types::ConnectivityProbes::from(&probes))
+            .await?;
+        println!("Updated connectivity probes");
+        Ok(())
+    }
+
+    async fn process_wireguard_connectivity_probes_reset() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_connectivity_probes(types::ConnectivityProbes::from(
+            &ConnectivityProbes::default(),
+        ))
+        .await?;
+        println!("Reset connectivity probes");
+        Ok(())
+    }
+
     #[cfg(windows)]
     async fn process_wireguard_use_wg_nt_get() -> Result<()> {
         let tunnel_options = Self::get_tunnel_options().await?;
@@ -279,6 +561,26 @@ impl Tunnel {
         Ok(())
     }
 
+    #[cfg(target_os = "linux")]
+    async fn process_wireguard_use_boringtun_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        if tunnel_options.wireguard.unwrap().use_boringtun {
+            println!("enabled");
+        } else {
+            println!("disabled");
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn process_wireguard_use_boringtun_set(matches: &clap::ArgMatches) -> Result<()> {
+        let new_state = matches.value_of("policy").unwrap() == "on";
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_use_boringtun(new_state).await?;
+        println!("Updated BoringTun setting");
+        Ok(())
+    }
+
     async fn process_wireguard_key_check() -> Result<()> {
         let mut rpc = new_rpc_client().await?;
         let key = rpc.get_wireguard_key(()).await;
@@ -398,6 +700,69 @@ impl Tunnel {
         Ok(())
     }
 
+    async fn process_openvpn_options_get() -> Result<()> {
+        use types::tunnel_options::openvpn_options::DataCipher;
+
+        let options = Self::get_tunnel_options().await?.openvpn.unwrap();
+        let cipher = match options.data_cipher() {
+            DataCipher::Any => "any",
+            DataCipher::Aes256Gcm => "aes-256-gcm",
+            DataCipher::Chacha20Poly1305 => "chacha20-poly1305",
+        };
+        println!("Cipher: {}", cipher);
+        println!(
+            "Fragment: {}",
+            Self::format_optional_bytes(options.fragment)
+        );
+        println!("Tun MTU: {}", Self::format_optional_bytes(options.tun_mtu));
+        Ok(())
+    }
+
+    async fn process_openvpn_options_set(matches: &clap::ArgMatches) -> Result<()> {
+        use types::tunnel_options::openvpn_options::DataCipher;
+
+        let mut options = Self::get_tunnel_options().await?.openvpn.unwrap();
+        if let Some(cipher) = matches.value_of("cipher") {
+            options.set_data_cipher(match cipher {
+                "any" => DataCipher::Any,
+                "aes-256-gcm" => DataCipher::Aes256Gcm,
+                "chacha20-poly1305" => DataCipher::Chacha20Poly1305,
+                _ => unreachable!("invalid cipher"),
+            });
+        }
+        if let Some(fragment) = matches.value_of("fragment") {
+            options.fragment = Self::parse_optional_bytes(fragment)?;
+        }
+        if let Some(tun_mtu) = matches.value_of("tun-mtu") {
+            options.tun_mtu = Self::parse_optional_bytes(tun_mtu)?;
+        }
+
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_openvpn_options(options).await?;
+        println!("Updated OpenVPN options");
+        Ok(())
+    }
+
+    fn parse_optional_bytes(value: &str) -> Result<u32> {
+        if value == "default" {
+            return Ok(0);
+        }
+        match value.parse::<u16>() {
+            Ok(bytes) if bytes > 0 => Ok(u32::from(bytes)),
+            _ => Err(Error::InvalidCommand(
+                "expected a size in bytes or \"default\"",
+            )),
+        }
+    }
+
+    fn format_optional_bytes(value: u32) -> String {
+        if value != 0 {
+            value.to_string()
+        } else {
+            "default".to_string()
+        }
+    }
+
     async fn process_ipv6_get() -> Result<()> {
         let tunnel_options = Self::get_tunnel_options().await?;
         println!(
@@ -431,6 +796,50 @@ impl Tunnel {
     }
 }
 
+fn parse_connectivity_probe(probe: &str) -> Result<ConnectivityProbe> {
+    const INVALID_PROBE: Error = Error::InvalidCommand(
+        "Probes must be 'icmp', 'tcp:<address>:<port>', 'dns:<hostname>@<resolver>' or \
+         'http:<address>:<port>[/path]'",
+    );
+
+    if probe == "icmp" {
+        return Ok(ConnectivityProbe::IcmpGateway);
+    }
+    let (kind, target) = probe.split_once(':').ok_or(INVALID_PROBE)?;
+    match kind {
+        "tcp" => Ok(ConnectivityProbe::TcpConnect(
+            target.parse().map_err(|_| INVALID_PROBE)?,
+        )),
+        "dns" => {
+            let (hostname, resolver) = target.split_once('@').ok_or(INVALID_PROBE)?;
+            Ok(ConnectivityProbe::Dns {
+                resolver: resolver.parse().map_err(|_| INVALID_PROBE)?,
+                hostname: hostname.to_owned(),
+            })
+        }
+        "http" => {
+            let (address, path) = match target.find('/') {
+                Some(index) => target.split_at(index),
+                None => (target, "/"),
+            };
+            Ok(ConnectivityProbe::HttpHead {
+                address: address.parse().map_err(|_| INVALID_PROBE)?,
+                path: path.to_owned(),
+            })
+        }
+        _ => Err(INVALID_PROBE),
+    }
+}
+
+fn format_connectivity_probe(probe: &ConnectivityProbe) -> String {
+    match probe {
+        ConnectivityProbe::IcmpGateway => "icmp".to_owned(),
+        ConnectivityProbe::TcpConnect(address) => format!("tcp:{address}"),
+        ConnectivityProbe::Dns { resolver, hostname } => format!("dns:{hostname}@{resolver}"),
+        ConnectivityProbe::HttpHead { address, path } => format!("http:{address}{path}"),
+    }
+}
+
 fn duration_hours(duration: &Duration) -> u64 {
     duration.as_secs() / 60 / 60
 }
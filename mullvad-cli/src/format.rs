@@ -1,4 +1,6 @@
+use mullvad_management_interface::types;
 use mullvad_types::{auth_failed::AuthFailed, location::GeoIpLocation, states::TunnelState};
+use std::time::{Duration, SystemTime};
 use talpid_types::{
     net::{Endpoint, TunnelEndpoint},
     tunnel::ErrorState,
@@ -128,6 +130,7 @@ fn format_relay_connection(
 
     let mut bridge_type = String::new();
     let mut obfuscator_type = String::new();
+    let mut mtu = String::new();
     if verbose {
         if let Some(bridge) = &endpoint.proxy {
             bridge_type = format!("\nBridge type: {}", bridge.proxy_type);
@@ -135,10 +138,13 @@ fn format_relay_connection(
         if let Some(obfuscator) = &endpoint.obfuscation {
             obfuscator_type = format!("\nObfuscator: {}", obfuscator.obfuscation_type);
         }
+        if let Some(tunnel_mtu) = endpoint.mtu {
+            mtu = format!("\nMTU: {tunnel_mtu}");
+        }
     }
 
     format!(
-        "{exit_endpoint}{first_hop}{bridge}{obfuscator}{tunnel_type}{quantum_resistant}{bridge_type}{obfuscator_type}",
+        "{exit_endpoint}{first_hop}{bridge}{obfuscator}{tunnel_type}{quantum_resistant}{bridge_type}{obfuscator_type}{mtu}",
         first_hop = first_hop.unwrap_or_default(),
         bridge = bridge.unwrap_or_default(),
         obfuscator = obfuscator.unwrap_or_default(),
@@ -189,3 +195,83 @@ const fn get_auth_failed_message(auth_failed: AuthFailed) -> &'static str {
         AuthFailed::Unknown => UNKNOWN_MSG,
     }
 }
+
+pub fn print_statistics(stats: &types::TunnelStatistics) {
+    println!(
+        "Traffic: {} received ({}/s), {} sent ({}/s)",
+        format_bytes(stats.rx_bytes),
+        format_bytes(stats.rx_rate),
+        format_bytes(stats.tx_bytes),
+        format_bytes(stats.tx_rate),
+    );
+    for peer in &stats.peers {
+        println!("Peer {}:", base64::encode(&peer.public_key));
+        println!(
+            "\t{} received ({}/s), {} sent ({}/s)",
+            format_bytes(peer.rx_bytes),
+            format_bytes(peer.rx_rate),
+            format_bytes(peer.tx_bytes),
+            format_bytes(peer.tx_rate),
+        );
+        match peer
+            .last_handshake
+            .clone()
+            .and_then(|time| SystemTime::try_from(time).ok())
+        {
+            Some(time) => {
+                let age = SystemTime::now()
+                    .duration_since(time)
+                    .unwrap_or(Duration::ZERO);
+                println!("\tLast handshake: {} seconds ago", age.as_secs());
+            }
+            None => println!("\tLast handshake: never"),
+        }
+    }
+    if let Some(rtt) = stats
+        .ping_rtt
+        .clone()
+        .and_then(|rtt| Duration::try_from(rtt).ok())
+    {
+        match stats
+            .ping_rtt_age
+            .clone()
+            .and_then(|age| Duration::try_from(age).ok())
+        {
+            Some(age) => println!(
+                "Ping round-trip time: {} ms (measured {} seconds ago)",
+                rtt.as_millis(),
+                age.as_secs()
+            ),
+            None => println!("Ping round-trip time: {} ms", rtt.as_millis()),
+        }
+    }
+    if let Some(time) = stats
+        .last_tls_negotiation
+        .clone()
+        .and_then(|time| SystemTime::try_from(time).ok())
+    {
+        let age = SystemTime::now()
+            .duration_since(time)
+            .unwrap_or(Duration::ZERO);
+        println!(
+            "Last TLS negotiation: {} seconds ago ({} renegotiations)",
+            age.as_secs(),
+            stats.tls_renegotiations
+        );
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} {}", UNITS[unit])
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
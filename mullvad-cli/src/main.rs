@@ -42,6 +42,18 @@ pub enum Error {
     #[error(display = "Command failed: {}", _0)]
     CommandFailed(&'static str),
 
+    #[error(display = "Failed to import WireGuard configuration")]
+    WgQuickConfig(#[error(source)] mullvad_types::wg_quick::Error),
+
+    #[error(display = "Failed to import OpenVPN profile")]
+    OvpnConfig(#[error(source)] mullvad_types::ovpn::Error),
+
+    #[error(display = "Failed to read from standard input")]
+    ReadStdin(#[error(source, no_from)] io::Error),
+
+    #[error(display = "Expected to read {} from standard input", _0)]
+    MissingInput(&'static str),
+
     #[error(display = "Failed to listen for status updates")]
     StatusListenerFailed,
 
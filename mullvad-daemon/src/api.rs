@@ -34,6 +34,7 @@ use talpid_types::{
 /// * Every 3rd attempt returns [`ApiConnectionMode::Direct`].
 /// * Any other attempt returns a configuration for the bridge that is closest to the selected relay
 ///   location and matches all bridge constraints.
+/// * If a custom HTTP proxy is set as the bridge, it is used instead of a Mullvad bridge.
 /// * When no matching bridge is found, e.g. if the selected hosting providers don't match any
 ///   bridge, [`ApiConnectionMode::Direct`] is returned.
 pub struct ApiConnectionModeProvider {
@@ -71,6 +72,9 @@ impl Stream for ApiConnectionModeProvider {
                     ProxySettings::Shadowsocks(ss_settings) => {
                         ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(ss_settings))
                     }
+                    ProxySettings::Http(http_settings) => {
+                        ApiConnectionMode::Proxied(ProxyConfig::Http(http_settings))
+                    }
                     _ => {
                         log::error!("Received unexpected proxy settings type");
                         ApiConnectionMode::Direct
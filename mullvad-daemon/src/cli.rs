@@ -1,4 +1,9 @@
 use clap::{crate_authors, crate_description, crate_name, App, Arg};
+#[cfg(target_os = "linux")]
+use talpid_core::tunnel_state_machine::LinuxNetworkingIdentifiers;
+
+/// Routing tables reserved by the kernel: unspec, default, main and local.
+const RESERVED_ROUTING_TABLES: [u32; 4] = [0, 253, 254, 255];
 
 #[derive(Debug)]
 pub struct Config {
@@ -11,6 +16,8 @@ pub struct Config {
     pub launch_daemon_status: bool,
     #[cfg(target_os = "linux")]
     pub initialize_firewall_and_exit: bool,
+    #[cfg(target_os = "linux")]
+    pub linux_ids: LinuxNetworkingIdentifiers,
 }
 
 pub fn get_config() -> &'static Config {
@@ -40,9 +47,28 @@ pub fn create_config() -> Config {
     #[cfg(target_os = "macos")]
     let launch_daemon_status = matches.is_present("launch_daemon_status");
 
+    #[cfg(target_os = "linux")]
+    let linux_ids = {
+        let defaults = mullvad_daemon::default_linux_networking_ids();
+        let get_id = |name: &str, default: u32| {
+            matches
+                .value_of(name)
+                .map(|value| parse_id(value).unwrap())
+                .unwrap_or(default)
+        };
+        LinuxNetworkingIdentifiers {
+            fwmark: get_id("fwmark", defaults.fwmark),
+            table_id: get_id("routing_table", defaults.table_id),
+            split_tunnel_mark: get_id("split_tunnel_mark", defaults.split_tunnel_mark),
+            net_cls_classid: get_id("net_cls_classid", defaults.net_cls_classid),
+        }
+    };
+
     Config {
         #[cfg(target_os = "linux")]
         initialize_firewall_and_exit,
+        #[cfg(target_os = "linux")]
+        linux_ids,
         log_level,
         log_to_file,
         log_stdout_timestamps,
@@ -111,11 +137,48 @@ fn create_app() -> App<'static> {
     }
 
     if cfg!(target_os = "linux") {
-        app = app.arg(
-            Arg::new("initialize-early-boot-firewall")
-                .long("initialize-early-boot-firewall")
-                .help("Initialize firewall to be used during early boot and exit"),
-        )
+        app = app
+            .arg(
+                Arg::new("initialize-early-boot-firewall")
+                    .long("initialize-early-boot-firewall")
+                    .help("Initialize firewall to be used during early boot and exit"),
+            )
+            .arg(
+                Arg::new("fwmark")
+                    .long("fwmark")
+                    .env("MULLVAD_FWMARK")
+                    .takes_value(true)
+                    .value_name("MARK")
+                    .validator(parse_id)
+                    .help("Firewall mark used for traffic that bypasses the tunnel"),
+            )
+            .arg(
+                Arg::new("routing_table")
+                    .long("routing-table")
+                    .env("MULLVAD_ROUTING_TABLE")
+                    .takes_value(true)
+                    .value_name("ID")
+                    .validator(parse_routing_table)
+                    .help("ID of the routing table used to route traffic through the tunnel"),
+            )
+            .arg(
+                Arg::new("split_tunnel_mark")
+                    .long("split-tunnel-mark")
+                    .env("MULLVAD_SPLIT_TUNNEL_MARK")
+                    .takes_value(true)
+                    .value_name("MARK")
+                    .validator(parse_id)
+                    .help("Connection mark used for traffic from excluded processes"),
+            )
+            .arg(
+                Arg::new("net_cls_classid")
+                    .long("net-cls-classid")
+                    .env("MULLVAD_NET_CLS_CLASSID")
+                    .takes_value(true)
+                    .value_name("ID")
+                    .validator(parse_id)
+                    .help("Class ID of the net_cls cgroup containing excluded processes"),
+            )
     }
 
     if cfg!(target_os = "macos") {
@@ -125,3 +188,51 @@ fn create_app() -> App<'static> {
     }
     app
 }
+
+/// Parses a non-zero decimal or hexadecimal (`0x` prefixed) identifier.
+fn parse_id(value: &str) -> Result<u32, String> {
+    let id = match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|error| format!("Invalid identifier: {error}"))?;
+    if id == 0 {
+        return Err("The identifier must not be zero".to_owned());
+    }
+    Ok(id)
+}
+
+fn parse_routing_table(value: &str) -> Result<u32, String> {
+    let id = parse_id(value)?;
+    if RESERVED_ROUTING_TABLES.contains(&id) {
+        return Err(format!("Routing table {id} is reserved"));
+    }
+    Ok(id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_id() {
+        assert_eq!(parse_id("1"), Ok(1));
+        assert_eq!(parse_id("0x6d6f6c65"), Ok(0x6d6f6c65));
+        assert_eq!(parse_id("4294967295"), Ok(u32::MAX));
+        assert!(parse_id("0").is_err());
+        assert!(parse_id("0x0").is_err());
+        assert!(parse_id("-1").is_err());
+        assert!(parse_id("0x").is_err());
+        assert!(parse_id("4294967296").is_err());
+        assert!(parse_id("mark").is_err());
+    }
+
+    #[test]
+    fn test_parse_routing_table() {
+        assert_eq!(parse_routing_table("1836018789"), Ok(1836018789));
+        assert_eq!(parse_routing_table("0xfa"), Ok(0xfa));
+        for reserved in ["0", "253", "254", "255", "0xff"] {
+            assert!(parse_routing_table(reserved).is_err());
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use mullvad_daemon::settings::{self, SettingsPersister};
-use talpid_core::firewall::{self, Firewall, FirewallPolicy};
+use talpid_core::{
+    firewall::{self, Firewall, FirewallPolicy},
+    tunnel_state_machine::LinuxNetworkingIdentifiers,
+};
 
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -13,8 +16,8 @@ pub enum Error {
     Settings(#[error(source)] settings::Error),
 }
 
-pub async fn initialize_firewall() -> Result<(), Error> {
-    let mut firewall = Firewall::new(mullvad_types::TUNNEL_FWMARK)?;
+pub async fn initialize_firewall(linux_ids: LinuxNetworkingIdentifiers) -> Result<(), Error> {
+    let mut firewall = Firewall::new(linux_ids)?;
     let allow_lan = get_allow_lan().await.unwrap_or_else(|err| {
         log::info!(
             "Not allowing LAN traffic due to failing to read settings: {}",
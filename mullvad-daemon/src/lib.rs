@@ -18,12 +18,14 @@ mod macos;
 #[cfg(not(target_os = "android"))]
 pub mod management_interface;
 mod migrations;
+mod network_memory;
 #[cfg(not(target_os = "android"))]
 pub mod rpc_uniqueness_check;
 pub mod runtime;
 pub mod settings;
 pub mod shutdown;
 mod target_state;
+mod traffic_usage;
 mod tunnel;
 pub mod version;
 mod version_check;
@@ -44,11 +46,16 @@ use mullvad_types::{
     auth_failed::AuthFailed,
     device::{Device, DeviceEvent, DeviceEventCause, DeviceId, DeviceState, RemoveDeviceEvent},
     location::GeoIpLocation,
-    relay_constraints::{BridgeSettings, BridgeState, ObfuscationSettings, RelaySettingsUpdate},
+    relay_constraints::{
+        BridgeSettings, BridgeState, Constraint, LocationConstraint, ObfuscationSettings,
+        RelaySettingsUpdate,
+    },
     relay_list::RelayList,
     settings::{DnsOptions, Settings},
     states::{TargetState, TunnelState},
+    traffic_usage::{TrafficUsageFilter, TrafficUsageRecord},
     version::{AppVersion, AppVersionInfo},
+    wg_quick::WgQuickConfig,
     wireguard::{PublicKey, QuantumResistantState, RotationInterval},
 };
 use settings::SettingsPersister;
@@ -59,6 +66,7 @@ use std::{collections::HashSet, ffi::OsString};
 use std::{
     marker::PhantomData,
     mem,
+    net::IpAddr,
     path::PathBuf,
     pin::Pin,
     sync::{Arc, Weak},
@@ -72,9 +80,11 @@ use talpid_core::{
 };
 #[cfg(target_os = "android")]
 use talpid_types::android::AndroidContext;
+#[cfg(target_os = "linux")]
+use talpid_types::net::{TrustedOverlays, UserRoute};
 use talpid_types::{
-    net::{TunnelEndpoint, TunnelType},
-    tunnel::{ErrorStateCause, TunnelStateTransition},
+    net::{openvpn, wireguard, TunnelEndpoint, TunnelType},
+    tunnel::{ErrorStateCause, TunnelStateTransition, TunnelStatistics},
     ErrorExt,
 };
 #[cfg(any(target_os = "macos", target_os = "linux"))]
@@ -84,8 +94,23 @@ use tokio::io;
 /// Delay between generating a new WireGuard key and reconnecting
 const WG_RECONNECT_DELAY: Duration = Duration::from_secs(4 * 60);
 
+/// Timeout for connecting to a custom bridge when testing whether it is reachable
+const CUSTOM_BRIDGE_TEST_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub type ResponseTx<T, E> = oneshot::Sender<Result<T, E>>;
 
+/// Returns the firewall marks, routing table and cgroup class ID used unless they are overridden
+/// when starting the daemon.
+#[cfg(target_os = "linux")]
+pub fn default_linux_networking_ids() -> tunnel_state_machine::LinuxNetworkingIdentifiers {
+    tunnel_state_machine::LinuxNetworkingIdentifiers {
+        fwmark: mullvad_types::TUNNEL_FWMARK,
+        table_id: mullvad_types::TUNNEL_TABLE_ID,
+        split_tunnel_mark: split_tunnel::MARK,
+        net_cls_classid: split_tunnel::NET_CLS_CLASSID,
+    }
+}
+
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
 pub enum Error {
@@ -135,7 +160,7 @@ pub enum Error {
     #[error(display = "Unable to initialize split tunneling")]
     InitSplitTunneling(#[error(source)] split_tunnel::Error),
 
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
     #[error(display = "Split tunneling error")]
     SplitTunnelError(#[error(source)] split_tunnel::Error),
 
@@ -154,6 +179,9 @@ pub enum Error {
     #[error(display = "Account history error")]
     AccountHistory(#[error(source)] account_history::Error),
 
+    #[error(display = "Traffic usage error")]
+    TrafficUsage(#[error(source)] traffic_usage::Error),
+
     #[cfg(not(target_os = "android"))]
     #[error(display = "Factory reset partially failed: {}", _0)]
     FactoryResetError(&'static str),
@@ -161,6 +189,12 @@ pub enum Error {
     #[error(display = "Tunnel state machine error")]
     TunnelError(#[error(source)] tunnel_state_machine::Error),
 
+    #[error(display = "No WireGuard relay matches the location")]
+    NoMatchingWireguardRelay,
+
+    #[error(display = "No custom bridge is named \"{}\"", _0)]
+    NoSuchCustomBridge(String),
+
     #[cfg(target_os = "macos")]
     #[error(display = "Failed to set exclusion group")]
     GroupIdError(#[error(source)] io::Error),
@@ -174,6 +208,14 @@ pub enum DaemonCommand {
     Reconnect(oneshot::Sender<bool>),
     /// Request the current state.
     GetState(oneshot::Sender<TunnelState>),
+    /// Request traffic statistics for the current tunnel. The sender is dropped if the tunnel is
+    /// not connected.
+    GetTunnelStatistics(oneshot::Sender<TunnelStatistics>),
+    /// Get the traffic sent through the tunnel, per day and relay.
+    GetTrafficUsage(
+        ResponseTx<Vec<TrafficUsageRecord>, Error>,
+        TrafficUsageFilter,
+    ),
     /// Get the current geographical location.
     GetCurrentLocation(oneshot::Sender<Option<GeoIpLocation>>),
     CreateNewAccount(ResponseTx<String, Error>),
@@ -219,19 +261,40 @@ pub enum DaemonCommand {
     SetAutoConnect(ResponseTx<(), settings::Error>, bool),
     /// Set the mssfix argument for OpenVPN
     SetOpenVpnMssfix(ResponseTx<(), settings::Error>, Option<u16>),
+    /// Set the OpenVPN tunnel options
+    SetOpenVpnOptions(ResponseTx<(), settings::Error>, openvpn::TunnelOptions),
     /// Set proxy details for OpenVPN
     SetBridgeSettings(ResponseTx<(), settings::Error>, BridgeSettings),
     /// Set proxy state
     SetBridgeState(ResponseTx<(), settings::Error>, BridgeState),
+    /// Check whether a custom bridge, identified by its name, accepts connections
+    TestCustomBridge(ResponseTx<bool, Error>, String),
     /// Set if IPv6 should be enabled in the tunnel
     SetEnableIpv6(ResponseTx<(), settings::Error>, bool),
     /// Set whether to enable PQ PSK exchange in the tunnel
     SetQuantumResistantTunnel(ResponseTx<(), settings::Error>, QuantumResistantState),
+    /// Set how often to renegotiate the PSK in a quantum-resistant tunnel
+    SetQuantumResistantRekeyInterval(ResponseTx<(), settings::Error>, Option<Duration>),
+    /// Set the probes used to verify that a WireGuard tunnel works
+    SetConnectivityProbes(
+        ResponseTx<(), settings::Error>,
+        wireguard::ConnectivityProbes,
+    ),
     /// Set DNS options or servers to use
     SetDnsOptions(ResponseTx<(), settings::Error>, DnsOptions),
+    /// Set overlay interfaces and networks that should be allowed outside the tunnel
+    #[cfg(target_os = "linux")]
+    SetTrustedOverlays(ResponseTx<(), settings::Error>, TrustedOverlays),
+    /// Set networks that should always be routed through the tunnel or via the default gateway
+    #[cfg(target_os = "linux")]
+    SetUserRoutes(ResponseTx<(), settings::Error>, Vec<UserRoute>),
     /// Toggle macOS network check leak
     /// Set MTU for wireguard tunnels
     SetWireguardMtu(ResponseTx<(), settings::Error>, Option<u16>),
+    /// Enable or disable WireGuard MTU discovery
+    SetWireguardMtuDiscovery(ResponseTx<(), settings::Error>, bool),
+    /// Set the interval between WireGuard keepalive packets
+    SetWireguardPersistentKeepalive(ResponseTx<(), settings::Error>, Option<u16>),
     /// Set automatic key rotation interval for wireguard tunnels
     SetWireguardRotationInterval(ResponseTx<(), settings::Error>, Option<RotationInterval>),
     /// Get the daemon settings
@@ -240,6 +303,8 @@ pub enum DaemonCommand {
     RotateWireguardKey(ResponseTx<(), Error>),
     /// Return a public key of the currently set wireguard private key, if there is one
     GetWireguardKey(ResponseTx<Option<PublicKey>, Error>),
+    /// Return a wg-quick config for the current device and a relay matching the location.
+    ExportWireguardConfig(ResponseTx<String, Error>, Constraint<LocationConstraint>),
     /// Get information about the currently running and latest app versions
     GetVersionInfo(oneshot::Sender<Option<AppVersionInfo>>),
     /// Return whether the daemon is performing post-upgrade tasks
@@ -261,6 +326,9 @@ pub enum DaemonCommand {
     /// Clear list of processes excluded from the tunnel
     #[cfg(target_os = "linux")]
     ClearSplitTunnelProcesses(ResponseTx<(), split_tunnel::Error>),
+    /// Create or remove the network namespace used by `mullvad-exclude --netns`
+    #[cfg(target_os = "linux")]
+    SetSplitTunnelNetworkNamespace(ResponseTx<(), Error>, bool),
     /// Exclude traffic of an application from the tunnel
     #[cfg(windows)]
     AddSplitTunnelApp(ResponseTx<(), Error>, PathBuf),
@@ -279,6 +347,9 @@ pub enum DaemonCommand {
     /// Toggle wireguard-nt on or off
     #[cfg(target_os = "windows")]
     UseWireGuardNt(ResponseTx<(), Error>, bool),
+    /// Toggle the BoringTun userspace WireGuard implementation on or off
+    #[cfg(target_os = "linux")]
+    UseBoringtun(ResponseTx<(), Error>, bool),
     /// Notify the split tunnel monitor that a volume was mounted or dismounted
     #[cfg(target_os = "windows")]
     CheckVolumes(ResponseTx<(), Error>),
@@ -533,6 +604,8 @@ pub struct Daemon<L: EventListener> {
     state: DaemonExecutionState,
     #[cfg(target_os = "linux")]
     exclude_pids: split_tunnel::PidManager,
+    #[cfg(target_os = "linux")]
+    exclusion_namespace: Option<split_tunnel::ExclusionNamespace>,
     rx: mpsc::UnboundedReceiver<InternalDaemonEvent>,
     tx: DaemonEventSender,
     reconnection_job: Option<AbortHandle>,
@@ -540,6 +613,7 @@ pub struct Daemon<L: EventListener> {
     migration_complete: migrations::MigrationComplete,
     settings: SettingsPersister,
     account_history: account_history::AccountHistory,
+    traffic_usage: traffic_usage::TrafficUsageHandle,
     device_checker: device::TunnelStateChangeHandler,
     account_manager: device::AccountManagerHandle,
     api_runtime: mullvad_api::Runtime,
@@ -567,6 +641,7 @@ where
         event_listener: L,
         command_channel: DaemonCommandChannel,
         #[cfg(target_os = "android")] android_context: AndroidContext,
+        #[cfg(target_os = "linux")] linux_ids: tunnel_state_machine::LinuxNetworkingIdentifiers,
     ) -> Result<Self, Error> {
         #[cfg(target_os = "macos")]
         let exclusion_gid = {
@@ -668,6 +743,9 @@ where
             account_manager.clone(),
             relay_selector.clone(),
             settings.tunnel_options.clone(),
+            network_memory::NetworkMemory::load(&cache_dir).await,
+            #[cfg(target_os = "linux")]
+            linux_ids.fwmark,
         );
         let (offline_state_tx, offline_state_rx) = mpsc::unbounded();
         #[cfg(target_os = "windows")]
@@ -681,6 +759,10 @@ where
                 reset_firewall: *target_state != TargetState::Secured,
                 #[cfg(windows)]
                 exclude_paths,
+                #[cfg(target_os = "linux")]
+                trusted_overlays: settings.trusted_overlays.clone(),
+                #[cfg(target_os = "linux")]
+                user_routes: settings.user_routes.clone(),
             },
             parameters_generator.clone(),
             log_dir,
@@ -694,10 +776,7 @@ where
             #[cfg(target_os = "android")]
             android_context,
             #[cfg(target_os = "linux")]
-            tunnel_state_machine::LinuxNetworkingIdentifiers {
-                fwmark: mullvad_types::TUNNEL_FWMARK,
-                table_id: mullvad_types::TUNNEL_TABLE_ID,
-            },
+            linux_ids,
         )
         .await
         .map_err(Error::TunnelError)?;
@@ -705,6 +784,12 @@ where
         endpoint_updater
             .set_tunnel_command_tx(Arc::downgrade(tunnel_state_machine_handle.command_tx()));
 
+        let traffic_usage = traffic_usage::TrafficUsageAccountant::spawn(
+            &cache_dir,
+            Arc::downgrade(tunnel_state_machine_handle.command_tx()),
+        )
+        .await;
+
         api::forward_offline_state(api_availability.clone(), offline_state_rx);
 
         let relay_list_listener = event_listener.clone();
@@ -732,12 +817,32 @@ where
         // Attempt to download a fresh relay list
         relay_list_updater.update().await;
 
+        #[cfg(target_os = "linux")]
+        let exclusion_namespace = if settings.split_tunnel.enable_network_namespace {
+            split_tunnel::ExclusionNamespace::new()
+                .map_err(|error| {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg(
+                            "Failed to create network namespace for excluded processes"
+                        )
+                    );
+                })
+                .ok()
+        } else {
+            split_tunnel::ExclusionNamespace::remove_stale();
+            None
+        };
+
         let daemon = Daemon {
             tunnel_state: TunnelState::Disconnected,
             target_state,
             state: DaemonExecutionState::Running,
             #[cfg(target_os = "linux")]
-            exclude_pids: split_tunnel::PidManager::new().map_err(Error::InitSplitTunneling)?,
+            exclude_pids: split_tunnel::PidManager::new(linux_ids.net_cls_classid)
+                .map_err(Error::InitSplitTunneling)?,
+            #[cfg(target_os = "linux")]
+            exclusion_namespace,
             rx: internal_event_rx,
             tx: internal_event_tx,
             reconnection_job: None,
@@ -745,6 +850,7 @@ where
             migration_complete,
             settings,
             account_history,
+            traffic_usage,
             device_checker: device::TunnelStateChangeHandler::new(account_manager.clone()),
             account_manager,
             api_runtime,
@@ -885,6 +991,26 @@ where
 
         log::debug!("New tunnel state: {:?}", tunnel_state);
 
+        match &tunnel_state {
+            TunnelState::Connected { endpoint, location } => {
+                let relay = location
+                    .as_ref()
+                    .and_then(|location| location.hostname.clone())
+                    .unwrap_or_else(|| endpoint.endpoint.address.to_string());
+                self.traffic_usage.start_session(relay);
+
+                let parameters_generator = self.parameters_generator.clone();
+                let endpoint = *endpoint;
+                tokio::spawn(async move {
+                    parameters_generator
+                        .remember_connection_method(&endpoint)
+                        .await;
+                });
+            }
+            _ if self.tunnel_state.is_connected() => self.traffic_usage.end_session(),
+            _ => (),
+        }
+
         match tunnel_state {
             TunnelState::Disconnected => {
                 self.api_handle.availability.reset_inactivity_timer();
@@ -973,6 +1099,8 @@ where
             SetTargetState(tx, state) => self.on_set_target_state(tx, state).await,
             Reconnect(tx) => self.on_reconnect(tx),
             GetState(tx) => self.on_get_state(tx),
+            GetTunnelStatistics(tx) => self.on_get_tunnel_statistics(tx),
+            GetTrafficUsage(tx, filter) => self.on_get_traffic_usage(tx, filter),
             GetCurrentLocation(tx) => self.on_get_current_location(tx).await,
             CreateNewAccount(tx) => self.on_create_new_account(tx).await,
             GetAccountData(tx, account_token) => self.on_get_account_data(tx, account_token).await,
@@ -999,23 +1127,44 @@ where
             }
             SetAutoConnect(tx, auto_connect) => self.on_set_auto_connect(tx, auto_connect).await,
             SetOpenVpnMssfix(tx, mssfix_arg) => self.on_set_openvpn_mssfix(tx, mssfix_arg).await,
+            SetOpenVpnOptions(tx, options) => self.on_set_openvpn_options(tx, options).await,
             SetBridgeSettings(tx, bridge_settings) => {
                 self.on_set_bridge_settings(tx, bridge_settings).await
             }
             SetBridgeState(tx, bridge_state) => self.on_set_bridge_state(tx, bridge_state).await,
+            TestCustomBridge(tx, name) => self.on_test_custom_bridge(tx, name),
             SetEnableIpv6(tx, enable_ipv6) => self.on_set_enable_ipv6(tx, enable_ipv6).await,
             SetQuantumResistantTunnel(tx, quantum_resistant_state) => {
                 self.on_set_quantum_resistant_tunnel(tx, quantum_resistant_state)
                     .await
             }
+            SetQuantumResistantRekeyInterval(tx, interval) => {
+                self.on_set_quantum_resistant_rekey_interval(tx, interval)
+                    .await
+            }
+            SetConnectivityProbes(tx, probes) => self.on_set_connectivity_probes(tx, probes).await,
             SetDnsOptions(tx, dns_servers) => self.on_set_dns_options(tx, dns_servers).await,
+            #[cfg(target_os = "linux")]
+            SetTrustedOverlays(tx, overlays) => self.on_set_trusted_overlays(tx, overlays).await,
+            #[cfg(target_os = "linux")]
+            SetUserRoutes(tx, routes) => self.on_set_user_routes(tx, routes).await,
             SetWireguardMtu(tx, mtu) => self.on_set_wireguard_mtu(tx, mtu).await,
+            SetWireguardMtuDiscovery(tx, enabled) => {
+                self.on_set_wireguard_mtu_discovery(tx, enabled).await
+            }
+            SetWireguardPersistentKeepalive(tx, interval) => {
+                self.on_set_wireguard_persistent_keepalive(tx, interval)
+                    .await
+            }
             SetWireguardRotationInterval(tx, interval) => {
                 self.on_set_wireguard_rotation_interval(tx, interval).await
             }
             GetSettings(tx) => self.on_get_settings(tx),
             RotateWireguardKey(tx) => self.on_rotate_wireguard_key(tx).await,
             GetWireguardKey(tx) => self.on_get_wireguard_key(tx).await,
+            ExportWireguardConfig(tx, location) => {
+                self.on_export_wireguard_config(tx, location).await
+            }
             GetVersionInfo(tx) => self.on_get_version_info(tx).await,
             IsPerformingPostUpgrade(tx) => self.on_is_performing_post_upgrade(tx),
             GetCurrentVersion(tx) => self.on_get_current_version(tx),
@@ -1029,6 +1178,11 @@ where
             RemoveSplitTunnelProcess(tx, pid) => self.on_remove_split_tunnel_process(tx, pid),
             #[cfg(target_os = "linux")]
             ClearSplitTunnelProcesses(tx) => self.on_clear_split_tunnel_processes(tx),
+            #[cfg(target_os = "linux")]
+            SetSplitTunnelNetworkNamespace(tx, enabled) => {
+                self.on_set_split_tunnel_network_namespace(tx, enabled)
+                    .await
+            }
             #[cfg(windows)]
             AddSplitTunnelApp(tx, path) => self.on_add_split_tunnel_app(tx, path).await,
             #[cfg(windows)]
@@ -1041,6 +1195,8 @@ where
             GetSplitTunnelProcesses(tx) => self.on_get_split_tunnel_processes(tx),
             #[cfg(target_os = "windows")]
             UseWireGuardNt(tx, state) => self.on_use_wireguard_nt(tx, state).await,
+            #[cfg(target_os = "linux")]
+            UseBoringtun(tx, state) => self.on_use_boringtun(tx, state).await,
             #[cfg(target_os = "windows")]
             CheckVolumes(tx) => self.on_check_volumes(tx).await,
             SetObfuscationSettings(tx, settings) => {
@@ -1204,6 +1360,25 @@ where
         Self::oneshot_send(tx, self.tunnel_state.clone(), "current state");
     }
 
+    fn on_get_tunnel_statistics(&self, tx: oneshot::Sender<TunnelStatistics>) {
+        self.send_tunnel_command(TunnelCommand::GetStatistics(tx));
+    }
+
+    fn on_get_traffic_usage(
+        &self,
+        tx: ResponseTx<Vec<TrafficUsageRecord>, Error>,
+        filter: TrafficUsageFilter,
+    ) {
+        let traffic_usage = self.traffic_usage.clone();
+        tokio::spawn(async move {
+            let result = traffic_usage
+                .query(filter)
+                .await
+                .map_err(Error::TrafficUsage);
+            Self::oneshot_send(tx, result, "get_traffic_usage response");
+        });
+    }
+
     fn on_is_performing_post_upgrade(&self, tx: oneshot::Sender<bool>) {
         let performing_post_upgrade = !self.migration_complete.is_complete();
         Self::oneshot_send(tx, performing_post_upgrade, "performing post upgrade");
@@ -1528,6 +1703,14 @@ where
             last_error = Err(Error::FactoryResetError("Failed to clear account history"));
         }
 
+        if let Err(error) = self.traffic_usage.clear().await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to clear traffic usage")
+            );
+            last_error = Err(Error::FactoryResetError("Failed to clear traffic usage"));
+        }
+
         if let Err(e) = self.settings.reset().await {
             log::error!("Failed to reset settings: {}", e);
             last_error = Err(Error::FactoryResetError("Failed to reset settings"));
@@ -1590,6 +1773,60 @@ where
         Self::oneshot_send(tx, result, "clear_split_tunnel_processes response");
     }
 
+    #[cfg(target_os = "linux")]
+    async fn on_set_split_tunnel_network_namespace(
+        &mut self,
+        tx: ResponseTx<(), Error>,
+        enabled: bool,
+    ) {
+        if !enabled {
+            self.exclusion_namespace = None;
+        } else if self.exclusion_namespace.is_none() {
+            match split_tunnel::ExclusionNamespace::new() {
+                Ok(namespace) => self.exclusion_namespace = Some(namespace),
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg(
+                            "Failed to create network namespace for excluded processes"
+                        )
+                    );
+                    Self::oneshot_send(
+                        tx,
+                        Err(Error::SplitTunnelError(error)),
+                        "set_split_tunnel_network_namespace response",
+                    );
+                    return;
+                }
+            }
+        }
+
+        match self
+            .settings
+            .update(move |settings| settings.split_tunnel.enable_network_namespace = enabled)
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_split_tunnel_network_namespace response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Unable to save settings")
+                );
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::SettingsError(error)),
+                    "set_split_tunnel_network_namespace response",
+                );
+            }
+        }
+    }
+
     /// Update the split app paths in both the settings and tunnel
     #[cfg(windows)]
     async fn set_split_tunnel_paths(
@@ -1763,6 +2000,41 @@ where
         }
     }
 
+    #[cfg(target_os = "linux")]
+    async fn on_use_boringtun(&mut self, tx: ResponseTx<(), Error>, state: bool) {
+        match self
+            .settings
+            .update(move |settings| settings.tunnel_options.wireguard.use_boringtun = state)
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "use_boringtun response");
+                if settings_changed {
+                    self.parameters_generator
+                        .set_tunnel_options(&self.settings.tunnel_options)
+                        .await;
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_target_tunnel_type() {
+                        log::info!("Initiating tunnel restart");
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Unable to save settings")
+                );
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::SettingsError(error)),
+                    "use_boringtun response",
+                );
+            }
+        }
+    }
+
     #[cfg(windows)]
     async fn on_check_volumes(&mut self, tx: ResponseTx<(), Error>) {
         if self.volume_update_tx.unbounded_send(()).is_ok() {
@@ -1819,6 +2091,60 @@ where
         }
     }
 
+    #[cfg(target_os = "linux")]
+    async fn on_set_trusted_overlays(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        overlays: TrustedOverlays,
+    ) {
+        let overlays_copy = overlays.clone();
+        match self
+            .settings
+            .update(move |settings| settings.trusted_overlays = overlays_copy)
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_trusted_overlays response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::TrustedOverlays(overlays));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_trusted_overlays response");
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_set_user_routes(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        routes: Vec<UserRoute>,
+    ) {
+        let routes_copy = routes.clone();
+        match self
+            .settings
+            .update(move |settings| settings.user_routes = routes_copy)
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_user_routes response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::UserRoutes(routes));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_user_routes response");
+            }
+        }
+    }
+
     async fn on_set_show_beta_releases(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -1929,6 +2255,37 @@ where
         }
     }
 
+    async fn on_set_openvpn_options(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        options: openvpn::TunnelOptions,
+    ) {
+        match self
+            .settings
+            .update(move |settings| settings.tunnel_options.openvpn = options)
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_openvpn_options response");
+                if settings_changed {
+                    self.parameters_generator
+                        .set_tunnel_options(&self.settings.tunnel_options)
+                        .await;
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if self.get_target_tunnel_type() == Some(TunnelType::OpenVpn) {
+                        log::info!("Initiating tunnel restart because the OpenVPN options changed");
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_openvpn_options response");
+            }
+        }
+    }
+
     async fn on_set_bridge_settings(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -1963,6 +2320,62 @@ where
         }
     }
 
+    fn on_test_custom_bridge(&mut self, tx: ResponseTx<bool, Error>, name: String) {
+        let bridge = match &self.settings.bridge_settings {
+            BridgeSettings::Custom(bridges) => {
+                bridges.iter().find(|bridge| bridge.name == name).cloned()
+            }
+            BridgeSettings::Normal(_) => None,
+        };
+        let bridge = match bridge {
+            Some(bridge) => bridge,
+            None => {
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::NoSuchCustomBridge(name)),
+                    "test_custom_bridge response",
+                );
+                return;
+            }
+        };
+
+        // The test connection is not exempt from the firewall or the tunnel, so the result only
+        // says something about reaching the bridge directly when neither is in the way. Through
+        // the tunnel, a bridge may be reachable only from the relay, or unreachable only from it.
+        let result_is_conclusive =
+            self.tunnel_state.is_disconnected() && !self.settings.block_when_disconnected;
+        let relay_selector = self.relay_selector.clone();
+        tokio::spawn(async move {
+            let address = bridge.proxy.get_endpoint().endpoint.address;
+            let reachable = match tokio::time::timeout(
+                CUSTOM_BRIDGE_TEST_TIMEOUT,
+                tokio::net::TcpStream::connect(address),
+            )
+            .await
+            {
+                Ok(Ok(_stream)) => true,
+                Ok(Err(error)) => {
+                    log::warn!(
+                        "{}",
+                        error.display_chain_with_msg(&format!(
+                            "Custom bridge {} is unreachable",
+                            bridge.name
+                        ))
+                    );
+                    false
+                }
+                Err(_) => {
+                    log::warn!("Timed out connecting to custom bridge {}", bridge.name);
+                    false
+                }
+            };
+            if result_is_conclusive {
+                relay_selector.set_custom_bridge_reachable(&bridge.name, reachable);
+            }
+            Self::oneshot_send(tx, Ok(reachable), "test_custom_bridge response");
+        });
+    }
+
     async fn on_set_obfuscation_settings(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -2083,6 +2496,70 @@ where
         }
     }
 
+    async fn on_set_quantum_resistant_rekey_interval(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        interval: Option<Duration>,
+    ) {
+        match self
+            .settings
+            .update(move |settings| {
+                settings
+                    .tunnel_options
+                    .wireguard
+                    .quantum_resistant_rekey_interval = interval
+            })
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_quantum_resistant_rekey_interval response");
+                if settings_changed {
+                    self.parameters_generator
+                        .set_tunnel_options(&self.settings.tunnel_options)
+                        .await;
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::PskRekeyInterval(interval));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_quantum_resistant_rekey_interval response");
+            }
+        }
+    }
+
+    async fn on_set_connectivity_probes(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        probes: wireguard::ConnectivityProbes,
+    ) {
+        match self
+            .settings
+            .update(move |settings| settings.tunnel_options.wireguard.connectivity_probes = probes)
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_connectivity_probes response");
+                if settings_changed {
+                    self.parameters_generator
+                        .set_tunnel_options(&self.settings.tunnel_options)
+                        .await;
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if self.get_target_tunnel_type() == Some(TunnelType::Wireguard) {
+                        log::info!("Reconnecting because the connectivity probes changed");
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_connectivity_probes response");
+            }
+        }
+    }
+
     async fn on_set_dns_options(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -2120,7 +2597,11 @@ where
     ) {
         match self
             .settings
-            .update(move |settings| settings.tunnel_options.wireguard.mtu = mtu)
+            .update(move |settings| {
+                // Setting the MTU replaces any automatic MTU
+                settings.tunnel_options.wireguard.mtu = mtu;
+                settings.tunnel_options.wireguard.mtu_discovery = false;
+            })
             .await
         {
             Ok(settings_changed) => {
@@ -2146,6 +2627,84 @@ where
         }
     }
 
+    async fn on_set_wireguard_persistent_keepalive(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        interval: Option<u16>,
+    ) {
+        match self
+            .settings
+            .update(move |settings| {
+                settings.tunnel_options.wireguard.persistent_keepalive = interval
+            })
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_persistent_keepalive response");
+                if settings_changed {
+                    self.parameters_generator
+                        .set_tunnel_options(&self.settings.tunnel_options)
+                        .await;
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        log::info!(
+                            "Initiating tunnel restart because the WireGuard keepalive changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_persistent_keepalive response");
+            }
+        }
+    }
+
+    async fn on_set_wireguard_mtu_discovery(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        enabled: bool,
+    ) {
+        match self
+            .settings
+            .update(move |settings| {
+                settings.tunnel_options.wireguard.mtu_discovery = enabled;
+                // Discovery replaces any fixed MTU
+                if enabled {
+                    settings.tunnel_options.wireguard.mtu = None;
+                }
+            })
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_mtu_discovery response");
+                if settings_changed {
+                    self.parameters_generator
+                        .set_tunnel_options(&self.settings.tunnel_options)
+                        .await;
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    // A fixed MTU takes precedence over discovery
+                    if self.settings.tunnel_options.wireguard.mtu.is_none()
+                        && self.get_connected_tunnel_type() == Some(TunnelType::Wireguard)
+                    {
+                        log::info!(
+                            "Initiating tunnel restart because the WireGuard MTU discovery \
+                             setting changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_mtu_discovery response");
+            }
+        }
+    }
+
     async fn on_set_wireguard_rotation_interval(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -2205,6 +2764,67 @@ where
         Self::oneshot_send(tx, result, "get_wireguard_key response");
     }
 
+    async fn on_export_wireguard_config(
+        &self,
+        tx: ResponseTx<String, Error>,
+        location: Constraint<LocationConstraint>,
+    ) {
+        let device = match self.account_manager.data().await.map(|s| s.into_device()) {
+            Ok(Some(config)) => config.device,
+            _ => {
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::NoAccountToken),
+                    "export_wireguard_config response",
+                );
+                return;
+            }
+        };
+        let (relay, endpoint) = match self
+            .relay_selector
+            .get_wireguard_relay_by_location(location)
+        {
+            Ok(selected) => selected,
+            Err(_) => {
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::NoMatchingWireguardRelay),
+                    "export_wireguard_config response",
+                );
+                return;
+            }
+        };
+
+        log::debug!("Exporting WireGuard config for {}", relay.hostname);
+
+        let config = WgQuickConfig {
+            host: endpoint.peer.endpoint.ip().to_string(),
+            connection: wireguard::ConnectionConfig {
+                tunnel: wireguard::TunnelConfig {
+                    private_key: device.wg_data.private_key,
+                    addresses: vec![
+                        device.wg_data.addresses.ipv4_address.ip().into(),
+                        device.wg_data.addresses.ipv6_address.ip().into(),
+                    ],
+                },
+                peer: endpoint.peer,
+                exit_peer: None,
+                ipv4_gateway: endpoint.ipv4_gateway,
+                ipv6_gateway: Some(endpoint.ipv6_gateway),
+                #[cfg(target_os = "linux")]
+                fwmark: None,
+            },
+            dns_servers: vec![IpAddr::V4(endpoint.ipv4_gateway)],
+            mtu: None,
+        };
+
+        Self::oneshot_send(
+            tx,
+            Ok(config.to_string()),
+            "export_wireguard_config response",
+        );
+    }
+
     fn on_get_settings(&self, tx: oneshot::Sender<Settings>) {
         Self::oneshot_send(tx, self.settings.to_settings(), "get_settings response");
     }
@@ -2274,13 +2894,25 @@ where
 
     fn connect_tunnel(&mut self) {
         self.api_runtime.availability_handle().resume_background();
+        self.request_final_traffic_sample();
         self.send_tunnel_command(TunnelCommand::Connect);
     }
 
     fn disconnect_tunnel(&mut self) {
+        self.request_final_traffic_sample();
         self.send_tunnel_command(TunnelCommand::Disconnect);
     }
 
+    /// Read the tunnel counters before a command that tears down a connected tunnel. Tunnel
+    /// commands are handled in order, so the request is answered while the tunnel is still up.
+    fn request_final_traffic_sample(&self) {
+        if self.tunnel_state.is_connected() {
+            let (tx, rx) = oneshot::channel();
+            self.send_tunnel_command(TunnelCommand::GetStatistics(tx));
+            self.traffic_usage.final_sample(rx);
+        }
+    }
+
     fn reconnect_tunnel(&mut self) {
         if *self.target_state == TargetState::Secured {
             self.connect_tunnel();
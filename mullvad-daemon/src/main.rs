@@ -119,7 +119,7 @@ async fn run_platform(config: &cli::Config, log_dir: Option<PathBuf>) -> Result<
             }
             install_result
         } else {
-            run_standalone(log_dir).await
+            run_standalone(config, log_dir).await
         }
     }
 }
@@ -127,11 +127,11 @@ async fn run_platform(config: &cli::Config, log_dir: Option<PathBuf>) -> Result<
 #[cfg(target_os = "linux")]
 async fn run_platform(config: &cli::Config, log_dir: Option<PathBuf>) -> Result<(), String> {
     if config.initialize_firewall_and_exit {
-        return crate::early_boot_firewall::initialize_firewall()
+        return crate::early_boot_firewall::initialize_firewall(config.linux_ids)
             .await
             .map_err(|err| format!("{err}"));
     }
-    run_standalone(log_dir).await
+    run_standalone(config, log_dir).await
 }
 
 #[cfg(target_os = "macos")]
@@ -139,15 +139,15 @@ async fn run_platform(config: &cli::Config, log_dir: Option<PathBuf>) -> Result<
     if config.launch_daemon_status {
         std::process::exit(macos_launch_daemon::get_status() as i32);
     }
-    run_standalone(log_dir).await
+    run_standalone(config, log_dir).await
 }
 
 #[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
-async fn run_platform(_config: &cli::Config, log_dir: Option<PathBuf>) -> Result<(), String> {
-    run_standalone(log_dir).await
+async fn run_platform(config: &cli::Config, log_dir: Option<PathBuf>) -> Result<(), String> {
+    run_standalone(config, log_dir).await
 }
 
-async fn run_standalone(log_dir: Option<PathBuf>) -> Result<(), String> {
+async fn run_standalone(config: &cli::Config, log_dir: Option<PathBuf>) -> Result<(), String> {
     if rpc_uniqueness_check::is_another_instance_running().await {
         return Err("Another instance of the daemon is already running".to_owned());
     }
@@ -163,7 +163,7 @@ async fn run_standalone(log_dir: Option<PathBuf>) -> Result<(), String> {
         log::warn!("Running daemon as a non-administrator user, clients might refuse to connect");
     }
 
-    let daemon = create_daemon(log_dir).await?;
+    let daemon = create_daemon(config, log_dir).await?;
 
     let shutdown_handle = daemon.shutdown_handle();
     #[cfg(any(target_os = "linux", target_os = "macos"))]
@@ -183,7 +183,9 @@ async fn run_standalone(log_dir: Option<PathBuf>) -> Result<(), String> {
     Ok(())
 }
 
+#[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
 async fn create_daemon(
+    config: &cli::Config,
     log_dir: Option<PathBuf>,
 ) -> Result<Daemon<ManagementInterfaceEventBroadcaster>, String> {
     let resource_dir = mullvad_paths::get_resource_dir();
@@ -202,6 +204,8 @@ async fn create_daemon(
         cache_dir,
         event_listener,
         command_channel,
+        #[cfg(target_os = "linux")]
+        config.linux_ids,
     )
     .await
     .map_err(|e| e.display_chain_with_msg("Unable to initialize daemon"))
@@ -13,10 +13,14 @@ use mullvad_paths;
 use mullvad_types::settings::DnsOptions;
 use mullvad_types::{
     account::AccountToken,
-    relay_constraints::{BridgeSettings, BridgeState, ObfuscationSettings, RelaySettingsUpdate},
+    relay_constraints::{
+        BridgeSettings, BridgeState, Constraint, LocationConstraint, ObfuscationSettings,
+        RelaySettingsUpdate,
+    },
     relay_list::RelayList,
     settings::Settings,
     states::{TargetState, TunnelState},
+    traffic_usage::TrafficUsageFilter,
     version,
     wireguard::{RotationInterval, RotationIntervalError},
 };
@@ -26,7 +30,7 @@ use std::path::PathBuf;
 use std::{
     convert::{TryFrom, TryInto},
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 use talpid_types::ErrorExt;
 use tokio_stream::wrappers::UnboundedReceiverStream;
@@ -48,6 +52,9 @@ pub type ServiceResult<T> = std::result::Result<Response<T>, Status>;
 type EventsListenerReceiver = UnboundedReceiverStream<Result<types::DaemonEvent, Status>>;
 type EventsListenerSender = tokio::sync::mpsc::UnboundedSender<Result<types::DaemonEvent, Status>>;
 
+/// How often tunnel statistics are sent to listening clients.
+const TUNNEL_STATISTICS_INTERVAL: Duration = Duration::from_secs(1);
+
 const INVALID_VOUCHER_MESSAGE: &str = "This voucher code is invalid";
 const USED_VOUCHER_MESSAGE: &str = "This voucher code has already been used";
 
@@ -55,6 +62,8 @@ const USED_VOUCHER_MESSAGE: &str = "This voucher code has already been used";
 impl ManagementService for ManagementServiceImpl {
     type GetSplitTunnelProcessesStream = UnboundedReceiverStream<Result<i32, Status>>;
     type EventsListenStream = EventsListenerReceiver;
+    type TunnelStatisticsListenStream =
+        UnboundedReceiverStream<Result<types::TunnelStatistics, Status>>;
 
     // Control and get the tunnel state
     //
@@ -93,6 +102,64 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(types::TunnelState::from(state)))
     }
 
+    async fn tunnel_statistics_listen(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<Self::TunnelStatisticsListenStream> {
+        log::debug!("tunnel_statistics_listen");
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let daemon_tx = self.daemon_tx.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(TUNNEL_STATISTICS_INTERVAL);
+            let mut previous: Option<(Instant, types::TunnelStatistics)> = None;
+            while !tx.is_closed() {
+                interval.tick().await;
+
+                let (stats_tx, stats_rx) = oneshot::channel();
+                if daemon_tx
+                    .send(DaemonCommand::GetTunnelStatistics(stats_tx))
+                    .is_err()
+                {
+                    break;
+                }
+                // The tunnel is not connected
+                let stats = match stats_rx.await {
+                    Ok(stats) => stats,
+                    Err(_) => {
+                        previous = None;
+                        continue;
+                    }
+                };
+
+                let now = Instant::now();
+                let mut stats = types::TunnelStatistics::from(stats);
+                if let Some((previous_time, previous_stats)) = &previous {
+                    set_statistics_rates(&mut stats, previous_stats, now - *previous_time);
+                }
+                if tx.send(Ok(stats.clone())).is_err() {
+                    break;
+                }
+                previous = Some((now, stats));
+            }
+        });
+
+        Ok(Response::new(UnboundedReceiverStream::new(rx)))
+    }
+
+    async fn get_traffic_usage(
+        &self,
+        request: Request<types::TrafficUsageFilter>,
+    ) -> ServiceResult<types::TrafficUsage> {
+        log::debug!("get_traffic_usage");
+        let filter =
+            TrafficUsageFilter::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetTrafficUsage(tx, filter))?;
+        let records = self.wait_for_result(rx).await?.map_err(map_daemon_error)?;
+        Ok(Response::new(types::TrafficUsage::from(records)))
+    }
+
     // Control the daemon and receive events
     //
 
@@ -247,6 +314,17 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn test_custom_bridge(&self, request: Request<String>) -> ServiceResult<bool> {
+        let name = request.into_inner();
+        log::debug!("test_custom_bridge({})", name);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::TestCustomBridge(tx, name))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
     // Settings
     //
 
@@ -322,6 +400,25 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_openvpn_options(
+        &self,
+        request: Request<types::tunnel_options::OpenvpnOptions>,
+    ) -> ServiceResult<()> {
+        let options = talpid_types::net::openvpn::TunnelOptions::try_from(request.into_inner())
+            .map_err(map_protobuf_type_err)?;
+        options
+            .validate()
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
+
+        log::debug!("set_openvpn_options({:?})", options);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetOpenVpnOptions(tx, options))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn set_wireguard_mtu(&self, request: Request<u32>) -> ServiceResult<()> {
         let mtu = request.into_inner();
         let mtu = if mtu != 0 { Some(mtu as u16) } else { None };
@@ -334,6 +431,35 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_wireguard_persistent_keepalive(&self, request: Request<u32>) -> ServiceResult<()> {
+        let interval = request.into_inner();
+        let interval = if interval != 0 {
+            Some(u16::try_from(interval).map_err(|_| {
+                Status::invalid_argument("keepalive interval must be at most 65535 seconds")
+            })?)
+        } else {
+            None
+        };
+        log::debug!("set_wireguard_persistent_keepalive({:?})", interval);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardPersistentKeepalive(tx, interval))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_wireguard_mtu_discovery(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_wireguard_mtu_discovery({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardMtuDiscovery(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn set_enable_ipv6(&self, request: Request<bool>) -> ServiceResult<()> {
         let enable_ipv6 = request.into_inner();
         log::debug!("set_enable_ipv6({})", enable_ipv6);
@@ -361,6 +487,61 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_quantum_resistant_rekey_interval(
+        &self,
+        request: Request<types::Duration>,
+    ) -> ServiceResult<()> {
+        let interval = Duration::try_from(request.into_inner())
+            .map_err(|_| Status::invalid_argument("unexpected negative rekey interval"))?;
+        if interval < mullvad_types::wireguard::MIN_QUANTUM_RESISTANT_REKEY_INTERVAL {
+            return Err(Status::invalid_argument(format!(
+                "Rekey interval must be at least {} seconds",
+                mullvad_types::wireguard::MIN_QUANTUM_RESISTANT_REKEY_INTERVAL.as_secs()
+            )));
+        }
+
+        log::debug!("set_quantum_resistant_rekey_interval({:?})", interval);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetQuantumResistantRekeyInterval(
+            tx,
+            Some(interval),
+        ))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_connectivity_probes(
+        &self,
+        request: Request<types::ConnectivityProbes>,
+    ) -> ServiceResult<()> {
+        let probes =
+            talpid_types::net::wireguard::ConnectivityProbes::try_from(request.into_inner())
+                .map_err(map_protobuf_type_err)?;
+        probes
+            .validate()
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
+
+        log::debug!("set_connectivity_probes({:?})", probes);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetConnectivityProbes(tx, probes))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn reset_quantum_resistant_rekey_interval(&self, _: Request<()>) -> ServiceResult<()> {
+        log::debug!("reset_quantum_resistant_rekey_interval");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetQuantumResistantRekeyInterval(tx, None))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     #[cfg(not(target_os = "android"))]
     async fn set_dns_options(&self, request: Request<types::DnsOptions>) -> ServiceResult<()> {
         let options = DnsOptions::try_from(request.into_inner()).map_err(map_protobuf_type_err)?;
@@ -379,6 +560,59 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(()))
     }
 
+    #[cfg(target_os = "linux")]
+    async fn set_trusted_overlays(
+        &self,
+        request: Request<types::TrustedOverlays>,
+    ) -> ServiceResult<()> {
+        let overlays = talpid_types::net::TrustedOverlays::try_from(request.into_inner())
+            .map_err(map_protobuf_type_err)?;
+        log::debug!("set_trusted_overlays({:?})", overlays);
+
+        let default_interfaces =
+            talpid_routing::default_route_interfaces().unwrap_or_else(|error| {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to read default route interfaces")
+                );
+                vec![]
+            });
+        overlays
+            .validate(&default_interfaces)
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
+
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetTrustedOverlays(tx, overlays))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn set_trusted_overlays(&self, _: Request<types::TrustedOverlays>) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn set_user_routes(&self, request: Request<types::UserRoutes>) -> ServiceResult<()> {
+        let routes = Vec::<talpid_types::net::UserRoute>::try_from(request.into_inner())
+            .map_err(map_protobuf_type_err)?;
+        log::debug!("set_user_routes({:?})", routes);
+
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetUserRoutes(tx, routes))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn set_user_routes(&self, _: Request<types::UserRoutes>) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
     // Account management
     //
 
@@ -596,6 +830,30 @@ impl ManagementService for ManagementServiceImpl {
         }
     }
 
+    async fn export_wireguard_config(
+        &self,
+        request: Request<types::WireguardConfigExport>,
+    ) -> ServiceResult<String> {
+        log::debug!("export_wireguard_config");
+        // The config contains the private key of the device
+        if !mullvad_management_interface::is_privileged_client(&request) {
+            return Err(Status::permission_denied(
+                "exporting the WireGuard config requires running as root",
+            ));
+        }
+        let request = request.into_inner();
+        let location = request
+            .location
+            .map(Constraint::<LocationConstraint>::from)
+            .unwrap_or(Constraint::Any);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ExportWireguardConfig(tx, location))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
     // Split tunneling
     //
 
@@ -678,6 +936,25 @@ impl ManagementService for ManagementServiceImpl {
         }
     }
 
+    #[cfg(target_os = "linux")]
+    async fn set_split_tunnel_network_namespace(
+        &self,
+        request: Request<bool>,
+    ) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_split_tunnel_network_namespace({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetSplitTunnelNetworkNamespace(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map_err(map_daemon_error)
+            .map(Response::new)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn set_split_tunnel_network_namespace(&self, _: Request<bool>) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
     #[cfg(windows)]
     async fn add_split_tunnel_app(&self, request: Request<String>) -> ServiceResult<()> {
         log::debug!("add_split_tunnel_app");
@@ -793,6 +1070,22 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(()))
     }
 
+    #[cfg(target_os = "linux")]
+    async fn set_use_boringtun(&self, request: Request<bool>) -> ServiceResult<()> {
+        log::debug!("set_use_boringtun");
+        let state = request.into_inner();
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::UseBoringtun(tx, state))?;
+        self.wait_for_result(rx)
+            .await?
+            .map_err(map_daemon_error)
+            .map(Response::new)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn set_use_boringtun(&self, _: Request<bool>) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
     #[cfg(windows)]
     async fn check_volumes(&self, _: Request<()>) -> ServiceResult<()> {
         log::debug!("check_volumes");
@@ -936,6 +1229,30 @@ impl ManagementInterfaceEventBroadcaster {
     }
 }
 
+/// Fills in the transfer rates of `stats` from the counters of a sample taken `elapsed` earlier.
+fn set_statistics_rates(
+    stats: &mut types::TunnelStatistics,
+    previous: &types::TunnelStatistics,
+    elapsed: Duration,
+) {
+    let rate = |new: u64, old: u64| {
+        let millis = elapsed.as_millis().max(1);
+        u64::try_from(u128::from(new.saturating_sub(old)) * 1000 / millis).unwrap_or(u64::MAX)
+    };
+    stats.rx_rate = rate(stats.rx_bytes, previous.rx_bytes);
+    stats.tx_rate = rate(stats.tx_bytes, previous.tx_bytes);
+    for peer in &mut stats.peers {
+        if let Some(previous_peer) = previous
+            .peers
+            .iter()
+            .find(|previous_peer| previous_peer.public_key == peer.public_key)
+        {
+            peer.rx_rate = rate(peer.rx_bytes, previous_peer.rx_bytes);
+            peer.tx_rate = rate(peer.tx_bytes, previous_peer.tx_bytes);
+        }
+    }
+}
+
 /// Converts [`mullvad_daemon::Error`] into a tonic status.
 fn map_daemon_error(error: crate::Error) -> Status {
     use crate::Error as DaemonError;
@@ -953,10 +1270,14 @@ fn map_daemon_error(error: crate::Error) -> Status {
         DaemonError::VoucherSubmission(error) => map_device_error(&error),
         #[cfg(windows)]
         DaemonError::SplitTunnelError(error) => map_split_tunnel_error(error),
+        #[cfg(target_os = "linux")]
+        DaemonError::SplitTunnelError(error) => Status::failed_precondition(error.to_string()),
         DaemonError::AccountHistory(error) => map_account_history_error(error),
         DaemonError::NoAccountToken | DaemonError::NoAccountTokenHistory => {
             Status::unauthenticated(error.to_string())
         }
+        DaemonError::NoMatchingWireguardRelay => Status::not_found(error.to_string()),
+        DaemonError::NoSuchCustomBridge(_) => Status::not_found(error.to_string()),
         error => Status::unknown(error.to_string()),
     }
 }
@@ -14,20 +14,22 @@ pub enum QuantumResistantState {
     Off,
 }
 
+/// Name given to the custom bridge that existed before the migration.
+const CUSTOM_BRIDGE_NAME: &str = "custom";
+
 // ======================================================
 
-/// This is an open ended migration. There is no v7 yet!
-/// The migrations performed by this function are still backwards compatible.
-/// The JSON coming out of this migration can be read by any v6 compatible daemon.
-///
-/// When further migrations are needed, add them here and if they are not backwards
-/// compatible then create v7 and "close" this migration for further modification.
+/// # Changes to the format
 ///
 /// The `use_pq_safe_psk` tunnel option is replaced by `quantum_resistant`, which
 /// is optional. `false` is mapped to `None`. `true` is mapped to `Some(true)`.
 ///
 /// Migrate WireGuard over TCP port setting away from Only(443) (to auto),
 /// since it's no longer a valid port.
+///
+/// A custom bridge used to be a single set of proxy settings. Now any number of named custom
+/// bridges can be configured, so an existing custom bridge is moved into a list, under the name
+/// `custom`.
 pub fn migrate(settings: &mut serde_json::Value) -> Result<()> {
     if !version_matches(settings) {
         return Ok(());
@@ -37,12 +39,11 @@ pub fn migrate(settings: &mut serde_json::Value) -> Result<()> {
 
     migrate_udp2tcp_port_443(settings);
 
-    // TODO
-    // log::info!("Migrating settings format to V7");
+    log::info!("Migrating settings format to V7");
+
+    migrate_custom_bridge(settings);
 
-    // Note: Not incrementing the version number yet, since this migration is still open
-    // for future modification.
-    // settings["settings_version"] = serde_json::json!(SettingsVersion::V7);
+    settings["settings_version"] = serde_json::json!(SettingsVersion::V7);
 
     Ok(())
 }
@@ -81,6 +82,20 @@ fn migrate_udp2tcp_port_443(settings: &mut serde_json::Value) -> Option<()> {
     None
 }
 
+/// Move a custom bridge into a list of named custom bridges
+fn migrate_custom_bridge(settings: &mut serde_json::Value) -> Option<()> {
+    let custom_bridge = settings.get_mut("bridge_settings")?.get_mut("custom")?;
+    if custom_bridge.is_array() {
+        return None;
+    }
+    *custom_bridge = serde_json::json!([{
+        "name": CUSTOM_BRIDGE_NAME,
+        "enabled": true,
+        "proxy": custom_bridge.take(),
+    }]);
+    None
+}
+
 fn version_matches(settings: &mut serde_json::Value) -> bool {
     settings
         .get("settings_version")
@@ -90,7 +105,7 @@ fn version_matches(settings: &mut serde_json::Value) -> bool {
 
 #[cfg(test)]
 mod test {
-    use super::{migrate, migrate_pq_setting, version_matches};
+    use super::{migrate, migrate_custom_bridge, migrate_pq_setting, version_matches};
     use serde_json;
 
     pub const V6_SETTINGS: &str = r#"
@@ -243,7 +258,7 @@ mod test {
       }
     }
   },
-  "settings_version": 6
+  "settings_version": 7
 }
 "#;
 
@@ -323,4 +338,48 @@ mod test {
 
         assert_eq!(migrated_settings, expected_settings);
     }
+
+    #[test]
+    fn test_custom_bridge() {
+        let mut migrated_settings: serde_json::Value = serde_json::from_str(
+            r#"
+        {
+            "bridge_settings": {
+                "custom": {
+                    "remote": {
+                        "address": "192.0.2.1:1080",
+                        "auth": null
+                    }
+                }
+            }
+        }
+        "#,
+        )
+        .unwrap();
+        migrate_custom_bridge(&mut migrated_settings);
+
+        let expected_settings: serde_json::Value = serde_json::from_str(
+            r#"
+        {
+            "bridge_settings": {
+                "custom": [
+                    {
+                        "name": "custom",
+                        "enabled": true,
+                        "proxy": {
+                            "remote": {
+                                "address": "192.0.2.1:1080",
+                                "auth": null
+                            }
+                        }
+                    }
+                ]
+            }
+        }
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(migrated_settings, expected_settings);
+    }
 }
@@ -0,0 +1,61 @@
+use super::Result;
+use mullvad_types::settings::SettingsVersion;
+
+// ======================================================
+// Section for vendoring types and values that
+// this settings version depend on. See `mod.rs`.
+
+// ======================================================
+
+/// This is an open ended migration. There is no v8 yet!
+/// The migrations performed by this function are still backwards compatible.
+/// The JSON coming out of this migration can be read by any v7 compatible daemon.
+///
+/// When further migrations are needed, add them here and if they are not backwards
+/// compatible then create v8 and "close" this migration for further modification.
+pub fn migrate(settings: &mut serde_json::Value) -> Result<()> {
+    if !version_matches(settings) {
+        return Ok(());
+    }
+
+    // TODO
+    // log::info!("Migrating settings format to V8");
+
+    // Note: Not incrementing the version number yet, since this migration is still open
+    // for future modification.
+    // settings["settings_version"] = serde_json::json!(SettingsVersion::V8);
+
+    Ok(())
+}
+
+fn version_matches(settings: &mut serde_json::Value) -> bool {
+    settings
+        .get("settings_version")
+        .map(|version| version == SettingsVersion::V7 as u64)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{migrate, version_matches};
+
+    #[test]
+    fn test_v7_migration() {
+        let mut old_settings: serde_json::Value =
+            serde_json::from_str(r#"{ "settings_version": 7 }"#).unwrap();
+
+        assert!(version_matches(&mut old_settings));
+
+        let mut new_settings = old_settings.clone();
+        migrate(&mut new_settings).unwrap();
+        assert_eq!(new_settings, old_settings);
+    }
+
+    #[test]
+    fn test_other_versions_are_ignored() {
+        let mut old_settings: serde_json::Value =
+            serde_json::from_str(r#"{ "settings_version": 6 }"#).unwrap();
+
+        assert!(!version_matches(&mut old_settings));
+    }
+}
@@ -0,0 +1,427 @@
+//! Remembers which connection method last worked on each network, so that it can be tried first
+//! the next time a tunnel is set up on that network. Networks are identified by the MAC address
+//! of the default gateway, which is only stored as an HMAC keyed with a random per-install key.
+//! Networks are not identified on Windows.
+
+use mullvad_relay_selector::ConnectionMethod;
+use ring::{
+    hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+use talpid_types::ErrorExt;
+use tokio::{fs, io};
+
+const NETWORK_MEMORY_FILE: &str = "network-memory.json";
+const NETWORK_MEMORY_KEY_FILE: &str = "network-memory.key";
+
+/// Length of the key used to derive network identifiers.
+const KEY_LEN: usize = 32;
+
+/// Maximum number of networks to remember. The least recently used network is forgotten first.
+const MAX_NETWORKS: usize = 64;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to serialize network memory")]
+    Serialize(#[error(source)] serde_json::Error),
+
+    #[error(display = "Unable to write network memory file")]
+    Write(#[error(source)] io::Error),
+
+    #[error(display = "Failed to generate network memory key")]
+    GenerateKey,
+
+    #[error(display = "Unable to write network memory key file")]
+    WriteKey(#[error(source)] io::Error),
+}
+
+/// Opaque identifier of a network.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct NetworkId(String);
+
+impl NetworkId {
+    fn from_mac_address(key: &hmac::Key, mac: &str) -> Self {
+        let tag = hmac::sign(key, mac.to_lowercase().as_bytes());
+        let mut id = String::with_capacity(2 * tag.as_ref().len());
+        for byte in tag.as_ref() {
+            let _ = write!(id, "{byte:02x}");
+        }
+        NetworkId(id)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RememberedNetwork {
+    network: NetworkId,
+    method: ConnectionMethod,
+}
+
+/// Connection methods that have worked, per network. Persisted in the cache directory.
+pub struct NetworkMemory {
+    cache_path: PathBuf,
+    /// Key that network identifiers are derived with. Networks are not identified without it.
+    key: Option<hmac::Key>,
+    /// Most recently used network first.
+    networks: Vec<RememberedNetwork>,
+}
+
+impl NetworkMemory {
+    pub async fn load(cache_dir: &Path) -> Self {
+        let cache_path = cache_dir.join(NETWORK_MEMORY_FILE);
+        let key = Self::load_key(cache_dir).await;
+        let networks = match fs::read_to_string(&cache_path).await {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|error| {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to parse network memory")
+                );
+                vec![]
+            }),
+            Err(error) => {
+                if error.kind() != io::ErrorKind::NotFound {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to read network memory")
+                    );
+                }
+                vec![]
+            }
+        };
+        NetworkMemory {
+            cache_path,
+            key,
+            networks,
+        }
+    }
+
+    /// Loads the key that network identifiers are derived with, or creates a new one. Remembered
+    /// networks are forgotten when a new key is created, since they could never be recognized.
+    async fn load_key(cache_dir: &Path) -> Option<hmac::Key> {
+        let key_path = cache_dir.join(NETWORK_MEMORY_KEY_FILE);
+        let key = match fs::read(&key_path).await {
+            Ok(key) if key.len() == KEY_LEN => Some(key),
+            Ok(_) => {
+                log::error!("Ignoring network memory key with invalid length");
+                None
+            }
+            Err(error) => {
+                if error.kind() != io::ErrorKind::NotFound {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to read network memory key")
+                    );
+                }
+                None
+            }
+        };
+        let key = match key {
+            Some(key) => key,
+            None => {
+                let cache_path = cache_dir.join(NETWORK_MEMORY_FILE);
+                if let Err(error) = fs::remove_file(&cache_path).await {
+                    if error.kind() != io::ErrorKind::NotFound {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg("Failed to remove network memory")
+                        );
+                    }
+                }
+                match Self::generate_key(&key_path).await {
+                    Ok(key) => key,
+                    Err(error) => {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg("Failed to create network memory key")
+                        );
+                        return None;
+                    }
+                }
+            }
+        };
+        Some(hmac::Key::new(hmac::HMAC_SHA256, &key))
+    }
+
+    async fn generate_key(key_path: &Path) -> Result<Vec<u8>> {
+        let mut key = vec![0u8; KEY_LEN];
+        SystemRandom::new()
+            .fill(&mut key)
+            .map_err(|_| Error::GenerateKey)?;
+        fs::write(key_path, &key).await.map_err(Error::WriteKey)?;
+        Ok(key)
+    }
+
+    /// Identifies the network that the default route currently goes through. Returns `None` if
+    /// there is no default gateway, or if its MAC address is not known.
+    pub async fn current_network(&self) -> Option<NetworkId> {
+        let key = self.key.as_ref()?;
+        imp::default_gateway_mac_address()
+            .await
+            .map(|mac| NetworkId::from_mac_address(key, &mac))
+    }
+
+    /// Returns the connection method that last worked on `network`.
+    pub fn get(&self, network: &NetworkId) -> Option<ConnectionMethod> {
+        self.networks
+            .iter()
+            .find(|remembered| &remembered.network == network)
+            .map(|remembered| remembered.method)
+    }
+
+    /// Records that `method` worked on `network`.
+    pub async fn remember(&mut self, network: NetworkId, method: ConnectionMethod) {
+        let index = self
+            .networks
+            .iter()
+            .position(|remembered| remembered.network == network);
+        if let Some(index) = index {
+            if index == 0 && self.networks[0].method == method {
+                return;
+            }
+            self.networks.remove(index);
+        }
+        self.networks
+            .insert(0, RememberedNetwork { network, method });
+        self.networks.truncate(MAX_NETWORKS);
+
+        if let Err(error) = self.write_to_disk().await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to save network memory")
+            );
+        }
+    }
+
+    async fn write_to_disk(&self) -> Result<()> {
+        log::trace!("Saving network memory to {}", self.cache_path.display());
+        let data = serde_json::to_string(&self.networks).map_err(Error::Serialize)?;
+        fs::write(&self.cache_path, data)
+            .await
+            .map_err(Error::Write)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_network_id_from_mac_address() {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &[1u8; KEY_LEN]);
+        let other_key = hmac::Key::new(hmac::HMAC_SHA256, &[2u8; KEY_LEN]);
+
+        let id = NetworkId::from_mac_address(&key, "a4:91:b1:00:00:01");
+        assert_eq!(id, NetworkId::from_mac_address(&key, "A4:91:B1:00:00:01"));
+        assert_ne!(id, NetworkId::from_mac_address(&key, "a4:91:b1:00:00:02"));
+        assert_ne!(
+            id,
+            NetworkId::from_mac_address(&other_key, "a4:91:b1:00:00:01")
+        );
+        assert_eq!(id.0.len(), 64);
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::net::Ipv4Addr;
+    use tokio::fs;
+
+    /// Route flags from `linux/route.h`.
+    const RTF_UP: u16 = 0x0001;
+    const RTF_GATEWAY: u16 = 0x0002;
+    /// ARP flag from `linux/if_arp.h`. Set when the entry is resolved.
+    const ATF_COM: u16 = 0x02;
+
+    pub async fn default_gateway_mac_address() -> Option<String> {
+        let routes = fs::read_to_string("/proc/net/route").await.ok()?;
+        let (interface, gateway) = parse_default_gateway(&routes)?;
+        let arp_table = fs::read_to_string("/proc/net/arp").await.ok()?;
+        parse_arp_table(&arp_table, &interface, gateway)
+    }
+
+    /// Returns the interface and gateway of the IPv4 default route with the lowest metric.
+    fn parse_default_gateway(routes: &str) -> Option<(String, Ipv4Addr)> {
+        routes
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                let fields: Vec<_> = line.split_whitespace().collect();
+                if fields.len() < 8 {
+                    return None;
+                }
+                let destination = u32::from_str_radix(fields[1], 16).ok()?;
+                let gateway = u32::from_str_radix(fields[2], 16).ok()?;
+                let flags = u16::from_str_radix(fields[3], 16).ok()?;
+                let metric: u32 = fields[6].parse().ok()?;
+                let mask = u32::from_str_radix(fields[7], 16).ok()?;
+
+                let is_default_route = destination == 0 && mask == 0;
+                let flags_set = flags & RTF_UP != 0 && flags & RTF_GATEWAY != 0;
+                (is_default_route && flags_set).then(|| {
+                    // Addresses are printed in network byte order
+                    let gateway = Ipv4Addr::from(gateway.to_ne_bytes());
+                    (metric, fields[0].to_owned(), gateway)
+                })
+            })
+            .min_by_key(|(metric, ..)| *metric)
+            .map(|(_, interface, gateway)| (interface, gateway))
+    }
+
+    fn parse_arp_table(arp_table: &str, interface: &str, gateway: Ipv4Addr) -> Option<String> {
+        arp_table.lines().skip(1).find_map(|line| {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            if fields.len() < 6 || fields[5] != interface {
+                return None;
+            }
+            let address: Ipv4Addr = fields[0].parse().ok()?;
+            let flags = u16::from_str_radix(fields[2].trim_start_matches("0x"), 16).ok()?;
+            let mac = fields[3];
+            (address == gateway && flags & ATF_COM != 0 && mac != "00:00:00:00:00:00")
+                .then(|| mac.to_owned())
+        })
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        const ROUTES: &str = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlan0\t00000000\t0102A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0
+eth0\t0001A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0
+";
+
+        const ARP_TABLE: &str = "\
+IP address       HW type     Flags       HW address            Mask     Device
+192.168.2.1      0x1         0x2         a4:91:b1:00:00:02     *        wlan0
+192.168.1.1      0x1         0x2         a4:91:b1:00:00:01     *        eth0
+";
+
+        #[test]
+        fn test_parse_default_gateway() {
+            assert_eq!(
+                parse_default_gateway(ROUTES),
+                Some(("eth0".to_owned(), Ipv4Addr::new(192, 168, 1, 1)))
+            );
+        }
+
+        #[test]
+        fn test_parse_default_gateway_without_default_route() {
+            let routes = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+eth0\t0001A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0
+";
+            assert_eq!(parse_default_gateway(routes), None);
+        }
+
+        #[test]
+        fn test_parse_arp_table() {
+            assert_eq!(
+                parse_arp_table(ARP_TABLE, "eth0", Ipv4Addr::new(192, 168, 1, 1)),
+                Some("a4:91:b1:00:00:01".to_owned())
+            );
+            assert_eq!(
+                parse_arp_table(ARP_TABLE, "wlan0", Ipv4Addr::new(192, 168, 1, 1)),
+                None
+            );
+        }
+
+        #[test]
+        fn test_parse_arp_table_incomplete_entry() {
+            let arp_table = ARP_TABLE.replace(
+                "0x2         a4:91:b1:00:00:01",
+                "0x0         00:00:00:00:00:00",
+            );
+            assert_eq!(
+                parse_arp_table(&arp_table, "eth0", Ipv4Addr::new(192, 168, 1, 1)),
+                None
+            );
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use std::net::Ipv4Addr;
+    use tokio::process::Command;
+
+    pub async fn default_gateway_mac_address() -> Option<String> {
+        let output = Command::new("/sbin/route")
+            .args(["-n", "get", "default"])
+            .output()
+            .await
+            .ok()?;
+        let gateway = parse_route_gateway(&String::from_utf8_lossy(&output.stdout))?;
+        let output = Command::new("/usr/sbin/arp")
+            .args(["-n", &gateway.to_string()])
+            .output()
+            .await
+            .ok()?;
+        parse_arp_output(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// Parses the gateway from the output of `route -n get default`.
+    fn parse_route_gateway(output: &str) -> Option<Ipv4Addr> {
+        output
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("gateway:"))
+            .and_then(|gateway| gateway.trim().parse().ok())
+    }
+
+    /// Parses the MAC address from the output of `arp -n <address>`, which looks like
+    /// `? (192.168.1.1) at a4:91:b1:0:0:1 on en0 ifscope [ethernet]`.
+    fn parse_arp_output(output: &str) -> Option<String> {
+        let mut words = output.split_whitespace();
+        words.find(|word| *word == "at")?;
+        let mac = words.next()?;
+        (mac.split(':').count() == 6).then(|| mac.to_owned())
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn test_parse_route_gateway() {
+            let output = "   route to: default
+destination: default
+       mask: default
+    gateway: 192.168.1.1
+  interface: en0
+";
+            assert_eq!(
+                parse_route_gateway(output),
+                Some(Ipv4Addr::new(192, 168, 1, 1))
+            );
+        }
+
+        #[test]
+        fn test_parse_arp_output() {
+            assert_eq!(
+                parse_arp_output("? (192.168.1.1) at a4:91:b1:0:0:1 on en0 ifscope [ethernet]\n"),
+                Some("a4:91:b1:0:0:1".to_owned())
+            );
+            assert_eq!(
+                parse_arp_output("? (192.168.1.1) at (incomplete) on en0 ifscope [ethernet]\n"),
+                None
+            );
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+mod imp {
+    /// Networks are not identified on this platform, so nothing is remembered.
+    pub async fn default_gateway_mac_address() -> Option<String> {
+        None
+    }
+}
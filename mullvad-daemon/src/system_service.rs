@@ -110,7 +110,7 @@ pub fn handle_service_main(_arguments: Vec<OsString>) {
         Ok(runtime) => runtime,
     };
 
-    let result = runtime.block_on(crate::create_daemon(log_dir));
+    let result = runtime.block_on(crate::create_daemon(crate::cli::get_config(), log_dir));
     let result = if let Ok(daemon) = result {
         let shutdown_handle = daemon.shutdown_handle();
 
@@ -0,0 +1,358 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use futures::{
+    channel::{mpsc, oneshot},
+    FutureExt, StreamExt,
+};
+use mullvad_types::traffic_usage::{TrafficUsage, TrafficUsageFilter, TrafficUsageRecord};
+use std::{
+    path::{Path, PathBuf},
+    sync::Weak,
+    time::Duration,
+};
+use talpid_core::tunnel_state_machine::TunnelCommand;
+use talpid_types::{tunnel::TunnelStatistics, ErrorExt};
+use tokio::{fs, io};
+
+const TRAFFIC_USAGE_FILE: &str = "traffic-usage.json";
+
+/// How often to sample the tunnel counters while connected.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+/// How many samples to take between each write to disk.
+const SAMPLES_PER_SAVE: usize = 6;
+/// Time to wait for the tunnel to respond to a statistics request.
+const SAMPLE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Records older than this are removed.
+const RETENTION_DAYS: i64 = 366;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to serialize traffic usage")]
+    Serialize(#[error(source)] serde_json::Error),
+
+    #[error(display = "Unable to write traffic usage file")]
+    Write(#[error(source)] io::Error),
+
+    #[error(display = "Unable to remove traffic usage file")]
+    Remove(#[error(source)] io::Error),
+
+    #[error(display = "Traffic usage accountant is not running")]
+    AccountantDown,
+}
+
+enum TrafficUsageCommand {
+    StartSession(String),
+    FinalSample(oneshot::Receiver<TunnelStatistics>),
+    EndSession,
+    Query(TrafficUsageFilter, oneshot::Sender<Vec<TrafficUsageRecord>>),
+    Clear(oneshot::Sender<Result<()>>),
+}
+
+#[derive(Clone)]
+pub struct TrafficUsageHandle {
+    tx: mpsc::UnboundedSender<TrafficUsageCommand>,
+}
+
+impl TrafficUsageHandle {
+    /// Begin attributing tunnel traffic to `relay`.
+    pub fn start_session(&self, relay: String) {
+        let _ = self
+            .tx
+            .unbounded_send(TrafficUsageCommand::StartSession(relay));
+    }
+
+    /// Add the traffic reported by a statistics request that was issued right before the tunnel
+    /// was told to disconnect. Once the tunnel is down, its counters can no longer be read, so
+    /// this is the only way to account for traffic since the last periodic sample.
+    pub fn final_sample(&self, statistics_rx: oneshot::Receiver<TunnelStatistics>) {
+        let _ = self
+            .tx
+            .unbounded_send(TrafficUsageCommand::FinalSample(statistics_rx));
+    }
+
+    /// Record the remaining traffic of the current session, if any, and stop sampling.
+    pub fn end_session(&self) {
+        let _ = self.tx.unbounded_send(TrafficUsageCommand::EndSession);
+    }
+
+    pub async fn query(&self, filter: TrafficUsageFilter) -> Result<Vec<TrafficUsageRecord>> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(TrafficUsageCommand::Query(filter, tx))
+            .map_err(|_| Error::AccountantDown)?;
+        rx.await.map_err(|_| Error::AccountantDown)
+    }
+
+    /// Remove all recorded traffic usage, both in memory and on disk.
+    pub async fn clear(&self) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(TrafficUsageCommand::Clear(tx))
+            .map_err(|_| Error::AccountantDown)?;
+        rx.await.map_err(|_| Error::AccountantDown)?
+    }
+}
+
+struct Session {
+    relay: String,
+    /// Counters reported by the tunnel at the last sample.
+    last_rx_bytes: u64,
+    last_tx_bytes: u64,
+}
+
+/// Accumulates tunnel traffic per day and relay, and persists it in the cache directory.
+pub struct TrafficUsageAccountant {
+    cache_path: PathBuf,
+    usage: TrafficUsage,
+    session: Option<Session>,
+    tunnel_command_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    unsaved_samples: usize,
+}
+
+impl TrafficUsageAccountant {
+    pub async fn spawn(
+        cache_dir: &Path,
+        tunnel_command_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    ) -> TrafficUsageHandle {
+        let cache_path = cache_dir.join(TRAFFIC_USAGE_FILE);
+        let usage = Self::load(&cache_path).await;
+        let (tx, rx) = mpsc::unbounded();
+
+        let accountant = TrafficUsageAccountant {
+            cache_path,
+            usage,
+            session: None,
+            tunnel_command_tx,
+            unsaved_samples: 0,
+        };
+        tokio::spawn(accountant.run(rx));
+
+        TrafficUsageHandle { tx }
+    }
+
+    async fn load(cache_path: &Path) -> TrafficUsage {
+        match fs::read_to_string(cache_path).await {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|error| {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to parse cached traffic usage")
+                );
+                TrafficUsage::default()
+            }),
+            Err(error) => {
+                if error.kind() != io::ErrorKind::NotFound {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to read cached traffic usage")
+                    );
+                }
+                TrafficUsage::default()
+            }
+        }
+    }
+
+    async fn run(mut self, rx: mpsc::UnboundedReceiver<TrafficUsageCommand>) {
+        let mut rx = rx.fuse();
+        let next_sample = || Box::pin(talpid_time::sleep(SAMPLE_INTERVAL)).fuse();
+        let mut sample_delay = futures::future::Fuse::terminated();
+
+        loop {
+            futures::select! {
+                command = rx.next() => match command {
+                    Some(TrafficUsageCommand::StartSession(relay)) => {
+                        self.end_session().await;
+                        self.session = Some(Session {
+                            relay,
+                            last_rx_bytes: 0,
+                            last_tx_bytes: 0,
+                        });
+                        sample_delay = next_sample();
+                    }
+                    Some(TrafficUsageCommand::FinalSample(statistics_rx)) => {
+                        if let Ok(Ok(statistics)) =
+                            tokio::time::timeout(SAMPLE_TIMEOUT, statistics_rx).await
+                        {
+                            self.add_sample(&statistics);
+                        }
+                    }
+                    Some(TrafficUsageCommand::EndSession) => {
+                        self.end_session().await;
+                        sample_delay = futures::future::Fuse::terminated();
+                    }
+                    Some(TrafficUsageCommand::Query(filter, tx)) => {
+                        let _ = tx.send(self.usage.query(&filter));
+                    }
+                    Some(TrafficUsageCommand::Clear(tx)) => {
+                        let _ = tx.send(self.clear().await);
+                    }
+                    None => break,
+                },
+                _ = sample_delay => {
+                    self.sample().await;
+                    self.unsaved_samples += 1;
+                    if self.unsaved_samples >= SAMPLES_PER_SAVE {
+                        self.save().await;
+                    }
+                    sample_delay = next_sample();
+                }
+            }
+        }
+
+        self.end_session().await;
+    }
+
+    async fn end_session(&mut self) {
+        if self.session.is_some() {
+            self.sample().await;
+            self.session = None;
+            self.save().await;
+        }
+    }
+
+    /// Fetch the current tunnel counters and add the traffic since the last sample.
+    async fn sample(&mut self) {
+        if self.session.is_none() {
+            return;
+        }
+        let tunnel_tx = match self.tunnel_command_tx.upgrade() {
+            Some(tunnel_tx) => tunnel_tx,
+            None => return,
+        };
+
+        let (tx, rx) = oneshot::channel();
+        if tunnel_tx
+            .unbounded_send(TunnelCommand::GetStatistics(tx))
+            .is_err()
+        {
+            return;
+        }
+        let statistics = match tokio::time::timeout(SAMPLE_TIMEOUT, rx).await {
+            Ok(Ok(statistics)) => statistics,
+            // The tunnel is not up, or went down before responding.
+            _ => return,
+        };
+        self.add_sample(&statistics);
+    }
+
+    /// Add the traffic since the last sample to the current session, if any.
+    fn add_sample(&mut self, statistics: &TunnelStatistics) {
+        let session = match self.session.as_mut() {
+            Some(session) => session,
+            None => return,
+        };
+
+        let (rx_bytes, tx_bytes) = total_bytes(statistics);
+        // Counters start over when the tunnel is recreated.
+        let rx_delta = rx_bytes
+            .checked_sub(session.last_rx_bytes)
+            .unwrap_or(rx_bytes);
+        let tx_delta = tx_bytes
+            .checked_sub(session.last_tx_bytes)
+            .unwrap_or(tx_bytes);
+        session.last_rx_bytes = rx_bytes;
+        session.last_tx_bytes = tx_bytes;
+
+        if rx_delta > 0 || tx_delta > 0 {
+            self.usage
+                .add(Utc::today().naive_utc(), &session.relay, rx_delta, tx_delta);
+        }
+    }
+
+    async fn save(&mut self) {
+        self.unsaved_samples = 0;
+        self.usage
+            .prune(Utc::today().naive_utc() - ChronoDuration::days(RETENTION_DAYS));
+
+        if let Err(error) = self.write_to_disk().await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to save traffic usage")
+            );
+        }
+    }
+
+    async fn write_to_disk(&self) -> Result<()> {
+        log::trace!("Saving traffic usage to {}", self.cache_path.display());
+        let data = serde_json::to_string(&self.usage).map_err(Error::Serialize)?;
+        fs::write(&self.cache_path, data)
+            .await
+            .map_err(Error::Write)
+    }
+
+    async fn clear(&mut self) -> Result<()> {
+        // Catch up with the tunnel counters so that traffic from before the reset is not counted.
+        self.sample().await;
+        self.usage.clear();
+        self.unsaved_samples = 0;
+        match fs::remove_file(&self.cache_path).await {
+            Ok(()) => Ok(()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(Error::Remove(error)),
+        }
+    }
+}
+
+fn total_bytes(statistics: &TunnelStatistics) -> (u64, u64) {
+    match statistics {
+        TunnelStatistics::Wireguard { peers, .. } => peers.iter().fold((0, 0), |(rx, tx), peer| {
+            (rx + peer.rx_bytes, tx + peer.tx_bytes)
+        }),
+        TunnelStatistics::OpenVpn {
+            rx_bytes, tx_bytes, ..
+        } => (*rx_bytes, *tx_bytes),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    fn openvpn_statistics(rx_bytes: u64, tx_bytes: u64) -> TunnelStatistics {
+        TunnelStatistics::OpenVpn {
+            rx_bytes,
+            tx_bytes,
+            tls_renegotiations: 0,
+            last_tls_negotiation: None,
+        }
+    }
+
+    /// Verify that traffic reported right before disconnecting is recorded, even though the
+    /// tunnel can no longer be sampled when the session ends.
+    #[test]
+    fn test_final_sample_before_disconnect() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let cache_dir =
+            std::env::temp_dir().join(format!("traffic-usage-test-{}", std::process::id()));
+        std::fs::create_dir_all(&cache_dir).unwrap();
+
+        runtime.block_on(async {
+            let (tunnel_tx, mut tunnel_rx) = mpsc::unbounded();
+            let tunnel_tx = Arc::new(tunnel_tx);
+            // Behave like a disconnected tunnel, which drops statistics requests
+            tokio::spawn(async move { while tunnel_rx.next().await.is_some() {} });
+
+            let handle =
+                TrafficUsageAccountant::spawn(&cache_dir, Arc::downgrade(&tunnel_tx)).await;
+            handle.start_session("se-got-wg-001".to_owned());
+
+            let (statistics_tx, statistics_rx) = oneshot::channel();
+            handle.final_sample(statistics_rx);
+            statistics_tx.send(openvpn_statistics(300, 150)).unwrap();
+            handle.end_session();
+
+            let records = handle.query(TrafficUsageFilter::default()).await.unwrap();
+            assert_eq!(records.len(), 1);
+            assert_eq!(records[0].relay, "se-got-wg-001");
+            assert_eq!(records[0].rx_bytes, 300);
+            assert_eq!(records[0].tx_bytes, 150);
+        });
+
+        let _ = std::fs::remove_dir_all(&cache_dir);
+    }
+}
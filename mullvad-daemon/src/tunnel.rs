@@ -2,13 +2,15 @@ use std::{future::Future, pin::Pin, sync::Arc};
 
 use tokio::sync::Mutex;
 
-use mullvad_relay_selector::{RelaySelector, SelectedBridge, SelectedObfuscator, SelectedRelay};
+use mullvad_relay_selector::{
+    ConnectionMethod, RelaySelector, SelectedBridge, SelectedObfuscator, SelectedRelay,
+};
 use mullvad_types::{
     endpoint::MullvadEndpoint, location::GeoIpLocation, relay_list::Relay, settings::TunnelOptions,
 };
 use talpid_core::tunnel_state_machine::TunnelParametersGenerator;
 use talpid_types::{
-    net::{wireguard, TunnelParameters},
+    net::{wireguard, TunnelEndpoint, TunnelParameters},
     tunnel::ParameterGenerationError,
     ErrorExt,
 };
@@ -16,7 +18,10 @@ use talpid_types::{
 #[cfg(not(target_os = "android"))]
 use talpid_types::net::openvpn;
 
-use crate::device::{AccountManagerHandle, PrivateAccountAndDevice};
+use crate::{
+    device::{AccountManagerHandle, PrivateAccountAndDevice},
+    network_memory::{NetworkId, NetworkMemory},
+};
 
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -40,8 +45,15 @@ struct InnerParametersGenerator {
     relay_selector: RelaySelector,
     tunnel_options: TunnelOptions,
     account_manager: AccountManagerHandle,
+    /// Firewall mark applied to all generated tunnel parameters.
+    #[cfg(target_os = "linux")]
+    fwmark: u32,
 
     last_generated_relays: Option<LastSelectedRelays>,
+    /// Connection methods that have worked on previously seen networks.
+    network_memory: NetworkMemory,
+    /// Network that the last tunnel parameters were generated on.
+    current_network: Option<NetworkId>,
 }
 
 impl ParametersGenerator {
@@ -50,14 +62,20 @@ impl ParametersGenerator {
         account_manager: AccountManagerHandle,
         relay_selector: RelaySelector,
         tunnel_options: TunnelOptions,
+        network_memory: NetworkMemory,
+        #[cfg(target_os = "linux")] fwmark: u32,
     ) -> Self {
         Self(Arc::new(Mutex::new(InnerParametersGenerator {
             tunnel_options,
             relay_selector,
 
             account_manager,
+            #[cfg(target_os = "linux")]
+            fwmark,
 
             last_generated_relays: None,
+            network_memory,
+            current_network: None,
         })))
     }
 
@@ -66,6 +84,22 @@ impl ParametersGenerator {
         self.0.lock().await.tunnel_options = tunnel_options.clone();
     }
 
+    /// Remembers how the tunnel to `endpoint` was established, so that the same method is tried
+    /// first the next time a tunnel is set up on the current network.
+    pub async fn remember_connection_method(&self, endpoint: &TunnelEndpoint) {
+        let mut inner = self.0.lock().await;
+        // Custom relays are not chosen by the relay selector
+        if inner.last_generated_relays.is_none() {
+            return;
+        }
+        if let Some(network) = inner.current_network.clone() {
+            inner
+                .network_memory
+                .remember(network, ConnectionMethod::from(endpoint))
+                .await;
+        }
+    }
+
     /// Gets the location associated with the last generated tunnel parameters.
     pub async fn get_last_location(&self) -> Option<GeoIpLocation> {
         let inner = self.0.lock().await;
@@ -120,8 +154,34 @@ impl ParametersGenerator {
 
 impl InnerParametersGenerator {
     async fn generate(&mut self, retry_attempt: u32) -> Result<TunnelParameters, Error> {
+        #[cfg_attr(not(target_os = "linux"), allow(unused_mut))]
+        let mut parameters = self.generate_inner(retry_attempt).await?;
+        // Custom relays and bridges may have been stored with a different firewall mark
+        #[cfg(target_os = "linux")]
+        set_fwmark(&mut parameters, self.fwmark);
+        Ok(parameters)
+    }
+
+    async fn generate_inner(&mut self, retry_attempt: u32) -> Result<TunnelParameters, Error> {
         let _data = self.device().await?;
-        match self.relay_selector.get_relay(retry_attempt) {
+
+        self.current_network = self.network_memory.current_network().await;
+        let remembered_method = self
+            .current_network
+            .as_ref()
+            .and_then(|network| self.network_memory.get(network));
+        let selection = match remembered_method {
+            Some(method) => {
+                if retry_attempt == 0 {
+                    log::debug!("Preferring connection method that last worked: {method:?}");
+                }
+                self.relay_selector
+                    .get_relay_preferring(retry_attempt, &method)
+            }
+            None => self.relay_selector.get_relay(retry_attempt),
+        };
+
+        match selection {
             Ok((SelectedRelay::Custom(custom_relay), _bridge, _obfsucator)) => {
                 self.last_generated_relays = None;
                 custom_relay
@@ -183,7 +243,7 @@ impl InnerParametersGenerator {
                     generic_options: self.tunnel_options.generic.clone(),
                     proxy: bridge_settings,
                     #[cfg(target_os = "linux")]
-                    fwmark: mullvad_types::TUNNEL_FWMARK,
+                    fwmark: self.fwmark,
                 }
                 .into())
             }
@@ -201,7 +261,7 @@ impl InnerParametersGenerator {
                 };
 
                 let (obfuscator_relay, obfuscator_config) = match obfuscator {
-                    Some(obfuscator) => (Some(obfuscator.relay), Some(obfuscator.config)),
+                    Some(obfuscator) => (obfuscator.relay, Some(obfuscator.config)),
                     None => (None, None),
                 };
 
@@ -219,7 +279,7 @@ impl InnerParametersGenerator {
                         ipv4_gateway: endpoint.ipv4_gateway,
                         ipv6_gateway: Some(endpoint.ipv6_gateway),
                         #[cfg(target_os = "linux")]
-                        fwmark: Some(mullvad_types::TUNNEL_FWMARK),
+                        fwmark: Some(self.fwmark),
                     },
                     options: self
                         .tunnel_options
@@ -245,6 +305,21 @@ impl InnerParametersGenerator {
     }
 }
 
+#[cfg(target_os = "linux")]
+fn set_fwmark(parameters: &mut TunnelParameters, fwmark: u32) {
+    match parameters {
+        TunnelParameters::OpenVpn(parameters) => {
+            parameters.fwmark = fwmark;
+            if let Some(openvpn::ProxySettings::Shadowsocks(proxy)) = &mut parameters.proxy {
+                proxy.fwmark = Some(fwmark);
+            }
+        }
+        TunnelParameters::Wireguard(parameters) => {
+            parameters.connection.fwmark = Some(fwmark);
+        }
+    }
+}
+
 impl TunnelParametersGenerator for ParametersGenerator {
     fn generate(
         &mut self,
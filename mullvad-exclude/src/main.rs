@@ -1,5 +1,9 @@
 #[cfg(target_os = "linux")]
-use nix::unistd::{execvp, getgid, getpid, getuid, setgid, setuid};
+use nix::{
+    mount::{mount, MsFlags},
+    sched::{setns, unshare, CloneFlags},
+    unistd::{execvp, getgid, getpid, getuid, setgid, setuid},
+};
 #[cfg(target_os = "linux")]
 use std::fmt::Write as _;
 #[cfg(target_os = "linux")]
@@ -7,18 +11,25 @@ use std::{
     convert::Infallible,
     env,
     error::Error as StdError,
-    ffi::{CString, NulError},
+    ffi::{CString, NulError, OsString},
     fs,
     io::{self, BufWriter, Write},
-    os::unix::ffi::OsStrExt,
+    os::unix::{ffi::OsStrExt, io::AsRawFd},
 };
 
 #[cfg(target_os = "linux")]
-use talpid_types::cgroup::{find_net_cls_mount, SPLIT_TUNNEL_CGROUP_NAME};
+use talpid_types::{
+    cgroup::{find_net_cls_mount, SPLIT_TUNNEL_CGROUP_NAME},
+    net_namespace::{netns_path, netns_resolv_conf_path},
+};
 
 #[cfg(target_os = "linux")]
 const PROGRAM_NAME: &str = "mullvad-exclude";
 
+/// Run the command in the exclusion network namespace instead of the net_cls cgroup.
+#[cfg(target_os = "linux")]
+const NETNS_FLAG: &str = "--netns";
+
 #[cfg(target_os = "linux")]
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
@@ -46,6 +57,17 @@ enum Error {
 
     #[error(display = "No net_cls controller")]
     NoNetClsController,
+
+    #[error(
+        display = "Failed to open the network namespace. Enable it using 'mullvad split-tunnel netns set on'"
+    )]
+    OpenNetNamespace(#[error(source)] io::Error),
+
+    #[error(display = "Failed to enter the network namespace")]
+    EnterNetNamespace(#[error(source)] nix::Error),
+
+    #[error(display = "Failed to set up resolv.conf for the network namespace")]
+    SetResolvConf(#[error(source)] nix::Error),
 }
 
 fn main() {
@@ -54,7 +76,7 @@ fn main() {
         Err(Error::InvalidArguments) => {
             let mut args = env::args();
             let program = args.next().unwrap_or_else(|| PROGRAM_NAME.to_string());
-            eprintln!("Usage: {program} COMMAND [ARGS]");
+            eprintln!("Usage: {program} [{NETNS_FLAG}] COMMAND [ARGS]");
             std::process::exit(1);
         }
         Err(e) => {
@@ -74,16 +96,42 @@ fn main() {
 
 #[cfg(target_os = "linux")]
 fn run() -> Result<Infallible, Error> {
-    let mut args_iter = env::args_os().skip(1);
-    let program = args_iter.next().ok_or(Error::InvalidArguments)?;
-    let program = CString::new(program.as_bytes()).map_err(Error::ArgumentNulError)?;
+    let (use_netns, args) = parse_args(env::args_os().skip(1))?;
+    let program = args.first().ok_or(Error::InvalidArguments)?.clone();
+
+    if use_netns {
+        enter_net_namespace()?;
+    } else {
+        add_to_cgroup()?;
+    }
+
+    // Drop root privileges
+    let real_uid = getuid();
+    setuid(real_uid).map_err(Error::DropRootUid)?;
+    let real_gid = getgid();
+    setgid(real_gid).map_err(Error::DropRootGid)?;
 
-    let args: Vec<CString> = env::args_os()
-        .skip(1)
+    // Launch the process
+    execvp(&program, &args).map_err(Error::Exec)
+}
+
+/// Splits the arguments into whether `--netns` was given and the command to run.
+#[cfg(target_os = "linux")]
+fn parse_args(args: impl Iterator<Item = OsString>) -> Result<(bool, Vec<CString>), Error> {
+    let mut args_iter = args.peekable();
+    let use_netns = args_iter
+        .next_if(|arg| arg.as_bytes() == NETNS_FLAG.as_bytes())
+        .is_some();
+
+    let args = args_iter
         .map(|arg| CString::new(arg.as_bytes()))
         .collect::<Result<Vec<CString>, NulError>>()
         .map_err(Error::ArgumentNulError)?;
+    Ok((use_netns, args))
+}
 
+#[cfg(target_os = "linux")]
+fn add_to_cgroup() -> Result<(), Error> {
     let cgroup_dir = find_net_cls_mount()
         .map_err(Error::FindNetClsController)?
         .ok_or(Error::NoNetClsController)?;
@@ -100,14 +148,78 @@ fn run() -> Result<Infallible, Error> {
 
     BufWriter::new(file)
         .write_all(getpid().to_string().as_bytes())
-        .map_err(Error::AddProcToCGroup)?;
+        .map_err(Error::AddProcToCGroup)
+}
 
-    // Drop root privileges
-    let real_uid = getuid();
-    setuid(real_uid).map_err(Error::DropRootUid)?;
-    let real_gid = getgid();
-    setgid(real_gid).map_err(Error::DropRootGid)?;
+#[cfg(target_os = "linux")]
+fn enter_net_namespace() -> Result<(), Error> {
+    let netns = fs::File::open(netns_path()).map_err(Error::OpenNetNamespace)?;
+    setns(netns.as_raw_fd(), CloneFlags::CLONE_NEWNET).map_err(Error::EnterNetNamespace)?;
+
+    // Like `ip netns exec`, use a namespace specific resolv.conf if one exists, since the host's
+    // resolver may listen on an address that is unreachable from the namespace.
+    let resolv_conf = netns_resolv_conf_path();
+    if resolv_conf.exists() {
+        unshare(CloneFlags::CLONE_NEWNS).map_err(Error::SetResolvConf)?;
+        mount(
+            None::<&str>,
+            "/",
+            None::<&str>,
+            MsFlags::MS_SLAVE | MsFlags::MS_REC,
+            None::<&str>,
+        )
+        .map_err(Error::SetResolvConf)?;
+        mount(
+            Some(resolv_conf.as_path()),
+            "/etc/resolv.conf",
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>,
+        )
+        .map_err(Error::SetResolvConf)?;
+    }
 
-    // Launch the process
-    execvp(&program, &args).map_err(Error::Exec)
+    Ok(())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+
+    fn os_args(args: &[&str]) -> impl Iterator<Item = OsString> {
+        args.iter()
+            .map(OsString::from)
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn test_parse_args_cgroup() {
+        let (use_netns, args) = parse_args(os_args(&["curl", "--netns"])).unwrap();
+        assert!(!use_netns);
+        assert_eq!(
+            args,
+            vec![
+                CString::new("curl").unwrap(),
+                CString::new("--netns").unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_args_netns() {
+        let (use_netns, args) = parse_args(os_args(&["--netns", "curl", "-v"])).unwrap();
+        assert!(use_netns);
+        assert_eq!(
+            args,
+            vec![CString::new("curl").unwrap(), CString::new("-v").unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_parse_args_netns_without_command() {
+        let (use_netns, args) = parse_args(os_args(&["--netns"])).unwrap();
+        assert!(use_netns);
+        assert!(args.is_empty());
+    }
 }
@@ -3,14 +3,17 @@ pub mod types;
 use parity_tokio_ipc::Endpoint as IpcEndpoint;
 #[cfg(unix)]
 use std::{env, fs, os::unix::fs::PermissionsExt};
+use std::{future::Future, io};
+#[cfg(windows)]
 use std::{
-    future::Future,
-    io,
     pin::Pin,
     task::{Context, Poll},
 };
+#[cfg(windows)]
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
-use tonic::transport::{server::Connected, Endpoint, Server, Uri};
+#[cfg(windows)]
+use tonic::transport::server::Connected;
+use tonic::transport::{Endpoint, Server, Uri};
 use tower::service_fn;
 
 pub use tonic::{async_trait, transport::Channel, Code, Request, Response, Status};
@@ -69,23 +72,66 @@ pub async fn new_rpc_client() -> Result<ManagementServiceClient, Error> {
 
 pub type ServerJoinHandle = tokio::task::JoinHandle<Result<(), Error>>;
 
+/// Returns whether the client that sent the request runs as root. Anyone may connect to the
+/// management interface, so requests that reveal secrets must only be served to such clients.
+/// The identity of clients cannot be determined on Windows, where this always returns `false`.
+pub fn is_privileged_client<T>(request: &Request<T>) -> bool {
+    #[cfg(unix)]
+    {
+        use tonic::transport::server::UdsConnectInfo;
+
+        request
+            .extensions()
+            .get::<UdsConnectInfo>()
+            .and_then(|info| info.peer_cred)
+            .map(|cred| cred.uid() == 0)
+            .unwrap_or(false)
+    }
+    #[cfg(windows)]
+    {
+        let _ = request;
+        false
+    }
+}
+
 pub async fn spawn_rpc_server<T: ManagementService, F: Future<Output = ()> + Send + 'static>(
     service: T,
     abort_rx: F,
 ) -> std::result::Result<ServerJoinHandle, Error> {
-    use futures::stream::TryStreamExt;
-    use parity_tokio_ipc::SecurityAttributes;
-
     let socket_path = mullvad_paths::get_rpc_socket_path();
 
-    let mut endpoint = IpcEndpoint::new(socket_path.to_string_lossy().to_string());
-    endpoint.set_security_attributes(
-        SecurityAttributes::allow_everyone_create()
-            .map_err(Error::SecurityAttributes)?
-            .set_mode(0o766)
-            .map_err(Error::SecurityAttributes)?,
-    );
-    let incoming = endpoint.incoming().map_err(Error::StartServerError)?;
+    // Accept the connections directly so that tonic can attach the credentials of each client
+    // to its requests.
+    #[cfg(unix)]
+    let incoming = {
+        let listener =
+            tokio::net::UnixListener::bind(&socket_path).map_err(Error::StartServerError)?;
+        fs::set_permissions(&socket_path, PermissionsExt::from_mode(0o766))
+            .map_err(Error::PermissionsError)?;
+        futures::stream::poll_fn(move |cx| {
+            listener
+                .poll_accept(cx)
+                .map(|result| Some(result.map(|(stream, _addr)| stream)))
+        })
+    };
+
+    #[cfg(windows)]
+    let incoming = {
+        use futures::stream::TryStreamExt;
+        use parity_tokio_ipc::SecurityAttributes;
+
+        let mut endpoint = IpcEndpoint::new(socket_path.to_string_lossy().to_string());
+        endpoint.set_security_attributes(
+            SecurityAttributes::allow_everyone_create()
+                .map_err(Error::SecurityAttributes)?
+                .set_mode(0o766)
+                .map_err(Error::SecurityAttributes)?,
+        );
+        endpoint
+            .incoming()
+            .map_err(Error::StartServerError)?
+            .map_ok(StreamBox)
+    };
 
     #[cfg(unix)]
     if let Some(group_name) = &*MULLVAD_MANAGEMENT_SOCKET_GROUP {
@@ -100,14 +146,16 @@ pub async fn spawn_rpc_server<T: ManagementService, F: Future<Output = ()> + Sen
     Ok(tokio::spawn(async move {
         Server::builder()
             .add_service(ManagementServiceServer::new(service))
-            .serve_with_incoming_shutdown(incoming.map_ok(StreamBox), abort_rx)
+            .serve_with_incoming_shutdown(incoming, abort_rx)
             .await
             .map_err(Error::GrpcTransportError)
     }))
 }
 
+#[cfg(windows)]
 #[derive(Debug)]
 struct StreamBox<T: AsyncRead + AsyncWrite>(pub T);
+#[cfg(windows)]
 impl<T: AsyncRead + AsyncWrite> Connected for StreamBox<T> {
     type ConnectInfo = Option<()>;
 
@@ -115,6 +163,7 @@ impl<T: AsyncRead + AsyncWrite> Connected for StreamBox<T> {
         None
     }
 }
+#[cfg(windows)]
 impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for StreamBox<T> {
     fn poll_read(
         mut self: Pin<&mut Self>,
@@ -124,6 +173,7 @@ impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for StreamBox<T> {
         Pin::new(&mut self.0).poll_read(cx, buf)
     }
 }
+#[cfg(windows)]
 impl<T: AsyncRead + AsyncWrite + Unpin> AsyncWrite for StreamBox<T> {
     fn poll_write(
         mut self: Pin<&mut Self>,
@@ -1,8 +1,8 @@
 use crate::types::{
-    conversions::{bytes_to_privkey, bytes_to_pubkey, option_from_proto_string},
+    conversions::{bytes_to_privkey, bytes_to_psk, bytes_to_pubkey, option_from_proto_string},
     proto, FromProtobufTypeError,
 };
-use talpid_types::net::wireguard;
+use talpid_types::net::{openvpn, wireguard};
 
 impl TryFrom<proto::ConnectionConfig> for mullvad_types::ConnectionConfig {
     type Error = FromProtobufTypeError;
@@ -10,7 +10,7 @@ impl TryFrom<proto::ConnectionConfig> for mullvad_types::ConnectionConfig {
     fn try_from(
         config: proto::ConnectionConfig,
     ) -> Result<mullvad_types::ConnectionConfig, Self::Error> {
-        use talpid_types::net::{self, openvpn};
+        use talpid_types::net;
 
         let config = config.config.ok_or(FromProtobufTypeError::InvalidArgument(
             "missing connection config",
@@ -32,6 +32,7 @@ impl TryFrom<proto::ConnectionConfig> for mullvad_types::ConnectionConfig {
                         },
                         username: config.username,
                         password: config.password,
+                        profile: config.profile.map(try_profile_from_proto).transpose()?,
                     },
                 ))
             }
@@ -47,6 +48,11 @@ impl TryFrom<proto::ConnectionConfig> for mullvad_types::ConnectionConfig {
                 ))?;
 
                 let public_key = bytes_to_pubkey(&peer.public_key)?;
+                let psk = if peer.psk.is_empty() {
+                    None
+                } else {
+                    Some(bytes_to_psk(&peer.psk)?)
+                };
 
                 let ipv4_gateway = config.ipv4_gateway.parse().map_err(|_err| {
                     FromProtobufTypeError::InvalidArgument("invalid IPv4 gateway")
@@ -89,7 +95,7 @@ impl TryFrom<proto::ConnectionConfig> for mullvad_types::ConnectionConfig {
                             public_key,
                             allowed_ips,
                             endpoint,
-                            psk: None,
+                            psk,
                         },
                         exit_peer: None,
                         ipv4_gateway,
@@ -117,6 +123,7 @@ impl From<mullvad_types::ConnectionConfig> for proto::ConnectionConfig {
                         )),
                         username: config.username,
                         password: config.password,
+                        profile: config.profile.map(profile_to_proto),
                     })
                 }
                 mullvad_types::ConnectionConfig::Wireguard(config) => {
@@ -139,6 +146,12 @@ impl From<mullvad_types::ConnectionConfig> for proto::ConnectionConfig {
                                 .map(|address| address.to_string())
                                 .collect(),
                             endpoint: config.peer.endpoint.to_string(),
+                            psk: config
+                                .peer
+                                .psk
+                                .as_ref()
+                                .map(|psk| psk.as_bytes().to_vec())
+                                .unwrap_or_default(),
                         }),
                         ipv4_gateway: config.ipv4_gateway.to_string(),
                         ipv6_gateway: config
@@ -152,3 +165,56 @@ impl From<mullvad_types::ConnectionConfig> for proto::ConnectionConfig {
         }
     }
 }
+
+fn try_profile_from_proto(
+    profile: proto::connection_config::openvpn_config::Profile,
+) -> Result<openvpn::ProfileConfig, FromProtobufTypeError> {
+    let key_direction = profile
+        .key_direction
+        .map(|direction| match direction {
+            0 | 1 => Ok(direction as u8),
+            _ => Err(FromProtobufTypeError::InvalidArgument(
+                "invalid key direction",
+            )),
+        })
+        .transpose()?;
+    Ok(openvpn::ProfileConfig {
+        ca: profile.ca,
+        cert: option_from_proto_string(profile.cert),
+        key: option_from_proto_string(profile.key),
+        tls_auth: option_from_proto_string(profile.tls_auth),
+        key_direction,
+        cipher: option_from_proto_string(profile.cipher),
+        auth: option_from_proto_string(profile.auth),
+        verify_x509_name: profile.verify_x509_name.map(|verify_x509_name| {
+            openvpn::VerifyX509Name {
+                name: verify_x509_name.name,
+                name_type: option_from_proto_string(verify_x509_name.name_type),
+            }
+        }),
+        auth_user_pass: profile.auth_user_pass,
+    })
+}
+
+fn profile_to_proto(
+    profile: openvpn::ProfileConfig,
+) -> proto::connection_config::openvpn_config::Profile {
+    use proto::connection_config::openvpn_config::{profile::VerifyX509Name, Profile};
+
+    Profile {
+        ca: profile.ca,
+        cert: profile.cert.unwrap_or_default(),
+        key: profile.key.unwrap_or_default(),
+        tls_auth: profile.tls_auth.unwrap_or_default(),
+        key_direction: profile.key_direction.map(u32::from),
+        cipher: profile.cipher.unwrap_or_default(),
+        auth_user_pass: profile.auth_user_pass,
+        auth: profile.auth.unwrap_or_default(),
+        verify_x509_name: profile
+            .verify_x509_name
+            .map(|verify_x509_name| VerifyX509Name {
+                name: verify_x509_name.name,
+                name_type: verify_x509_name.name_type.unwrap_or_default(),
+            }),
+    }
+}
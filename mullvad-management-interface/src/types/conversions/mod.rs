@@ -8,6 +8,7 @@ pub mod relay_constraints;
 mod relay_list;
 mod settings;
 mod states;
+mod statistics;
 mod version;
 mod wireguard;
 
@@ -32,6 +33,14 @@ fn bytes_to_privkey(
     ))
 }
 
+fn bytes_to_psk(
+    bytes: &[u8],
+) -> Result<talpid_types::net::wireguard::PresharedKey, FromProtobufTypeError> {
+    Ok(talpid_types::net::wireguard::PresharedKey::from(
+        *bytes_to_wg_key(bytes, "invalid preshared key")?,
+    ))
+}
+
 fn bytes_to_wg_key<'a>(
     bytes: &'a [u8],
     error_msg: &'static str,
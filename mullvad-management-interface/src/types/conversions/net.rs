@@ -30,7 +30,18 @@ impl From<talpid_types::net::TunnelEndpoint> for proto::TunnelEndpoint {
                         obfuscation_endpoint.endpoint.protocol,
                     )),
                     obfuscation_type: match obfuscation_endpoint.obfuscation_type {
-                        net::ObfuscationType::Udp2Tcp => i32::from(proto::ObfuscationType::Udp2tcp),
+                        net::ObfuscationType::Udp2Tcp => {
+                            i32::from(proto::obfuscation_endpoint::ObfuscationType::Udp2tcp)
+                        }
+                        net::ObfuscationType::Shadowsocks => {
+                            i32::from(proto::obfuscation_endpoint::ObfuscationType::Shadowsocks)
+                        }
+                        net::ObfuscationType::Tls => {
+                            i32::from(proto::obfuscation_endpoint::ObfuscationType::Tls)
+                        }
+                        net::ObfuscationType::Socks5 => {
+                            i32::from(proto::obfuscation_endpoint::ObfuscationType::Socks5)
+                        }
                     },
                 }
             }),
@@ -38,6 +49,7 @@ impl From<talpid_types::net::TunnelEndpoint> for proto::TunnelEndpoint {
                 address: entry.address.to_string(),
                 protocol: i32::from(proto::TransportProtocol::from(entry.protocol)),
             }),
+            mtu: u32::from(endpoint.mtu.unwrap_or_default()),
         }
     }
 }
@@ -94,18 +106,28 @@ impl TryFrom<proto::TunnelEndpoint> for talpid_types::net::TunnelEndpoint {
                             ),
                             protocol: try_transport_protocol_from_i32(obfs_ep.protocol)?,
                         },
-                        obfuscation_type: match proto::ObfuscationType::from_i32(
-                            obfs_ep.obfuscation_type,
-                        ) {
-                            Some(proto::ObfuscationType::Udp2tcp) => {
-                                talpid_net::ObfuscationType::Udp2Tcp
-                            }
-                            None => {
-                                return Err(FromProtobufTypeError::InvalidArgument(
-                                    "unknown obfuscation type",
-                                ))
-                            }
-                        },
+                        obfuscation_type:
+                            match proto::obfuscation_endpoint::ObfuscationType::from_i32(
+                                obfs_ep.obfuscation_type,
+                            ) {
+                                Some(proto::obfuscation_endpoint::ObfuscationType::Udp2tcp) => {
+                                    talpid_net::ObfuscationType::Udp2Tcp
+                                }
+                                Some(proto::obfuscation_endpoint::ObfuscationType::Shadowsocks) => {
+                                    talpid_net::ObfuscationType::Shadowsocks
+                                }
+                                Some(proto::obfuscation_endpoint::ObfuscationType::Tls) => {
+                                    talpid_net::ObfuscationType::Tls
+                                }
+                                Some(proto::obfuscation_endpoint::ObfuscationType::Socks5) => {
+                                    talpid_net::ObfuscationType::Socks5
+                                }
+                                None => {
+                                    return Err(FromProtobufTypeError::InvalidArgument(
+                                        "unknown obfuscation type",
+                                    ))
+                                }
+                            },
                     })
                 })
                 .transpose()?,
@@ -118,6 +140,7 @@ impl TryFrom<proto::TunnelEndpoint> for talpid_types::net::TunnelEndpoint {
                     })
                 })
                 .transpose()?,
+            mtu: u16::try_from(endpoint.mtu).ok().filter(|mtu| *mtu != 0),
         })
     }
 }
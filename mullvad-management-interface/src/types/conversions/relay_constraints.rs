@@ -320,10 +320,18 @@ impl From<&mullvad_types::relay_constraints::ObfuscationSettings> for proto::Obf
             SelectedObfuscation::Udp2Tcp => {
                 proto::obfuscation_settings::SelectedObfuscation::Udp2tcp
             }
+            SelectedObfuscation::Shadowsocks => {
+                proto::obfuscation_settings::SelectedObfuscation::Shadowsocks
+            }
+            SelectedObfuscation::Tls => proto::obfuscation_settings::SelectedObfuscation::Tls,
         });
         Self {
             selected_obfuscation,
             udp2tcp: Some(proto::Udp2TcpObfuscationSettings::from(&settings.udp2tcp)),
+            shadowsocks: Some(proto::ShadowsocksObfuscationSettings::from(
+                &settings.shadowsocks,
+            )),
+            tls: Some(proto::TlsObfuscationSettings::from(&settings.tls)),
         }
     }
 }
@@ -338,11 +346,30 @@ impl From<&mullvad_types::relay_constraints::Udp2TcpObfuscationSettings>
     }
 }
 
+impl From<&mullvad_types::relay_constraints::ShadowsocksObfuscationSettings>
+    for proto::ShadowsocksObfuscationSettings
+{
+    fn from(settings: &mullvad_types::relay_constraints::ShadowsocksObfuscationSettings) -> Self {
+        Self {
+            port: u32::from(settings.port.unwrap_or(0)),
+        }
+    }
+}
+
+impl From<&mullvad_types::relay_constraints::TlsObfuscationSettings>
+    for proto::TlsObfuscationSettings
+{
+    fn from(settings: &mullvad_types::relay_constraints::TlsObfuscationSettings) -> Self {
+        Self {
+            server_name: settings.server_name.clone().unwrap_or_default(),
+        }
+    }
+}
+
 impl From<mullvad_types::relay_constraints::BridgeSettings> for proto::BridgeSettings {
     fn from(settings: mullvad_types::relay_constraints::BridgeSettings) -> Self {
         use mullvad_types::relay_constraints::BridgeSettings as MullvadBridgeSettings;
         use proto::bridge_settings;
-        use talpid_types::net as talpid_net;
 
         let settings = match settings {
             MullvadBridgeSettings::Normal(constraints) => {
@@ -356,32 +383,14 @@ impl From<mullvad_types::relay_constraints::BridgeSettings> for proto::BridgeSet
                     ownership: convert_ownership_constraint(&constraints.ownership) as i32,
                 })
             }
-            MullvadBridgeSettings::Custom(proxy_settings) => match proxy_settings {
-                talpid_net::openvpn::ProxySettings::Local(proxy_settings) => {
-                    bridge_settings::Type::Local(bridge_settings::LocalProxySettings {
-                        port: u32::from(proxy_settings.port),
-                        peer: proxy_settings.peer.to_string(),
-                    })
-                }
-                talpid_net::openvpn::ProxySettings::Remote(proxy_settings) => {
-                    bridge_settings::Type::Remote(bridge_settings::RemoteProxySettings {
-                        address: proxy_settings.address.to_string(),
-                        auth: proxy_settings.auth.as_ref().map(|auth| {
-                            bridge_settings::RemoteProxyAuth {
-                                username: auth.username.clone(),
-                                password: auth.password.clone(),
-                            }
-                        }),
-                    })
-                }
-                talpid_net::openvpn::ProxySettings::Shadowsocks(proxy_settings) => {
-                    bridge_settings::Type::Shadowsocks(bridge_settings::ShadowsocksProxySettings {
-                        peer: proxy_settings.peer.to_string(),
-                        password: proxy_settings.password.clone(),
-                        cipher: proxy_settings.cipher,
-                    })
-                }
-            },
+            MullvadBridgeSettings::Custom(bridges) => {
+                bridge_settings::Type::Custom(bridge_settings::CustomBridges {
+                    bridges: bridges
+                        .into_iter()
+                        .map(bridge_settings::CustomBridge::from)
+                        .collect(),
+                })
+            }
         };
 
         proto::BridgeSettings {
@@ -390,6 +399,65 @@ impl From<mullvad_types::relay_constraints::BridgeSettings> for proto::BridgeSet
     }
 }
 
+impl From<mullvad_types::relay_constraints::CustomBridge> for proto::bridge_settings::CustomBridge {
+    fn from(bridge: mullvad_types::relay_constraints::CustomBridge) -> Self {
+        Self {
+            name: bridge.name,
+            enabled: bridge.enabled,
+            proxy: Some(proto::bridge_settings::CustomProxy::from(bridge.proxy)),
+        }
+    }
+}
+
+impl From<talpid_types::net::openvpn::ProxySettings> for proto::bridge_settings::CustomProxy {
+    fn from(proxy_settings: talpid_types::net::openvpn::ProxySettings) -> Self {
+        use proto::bridge_settings::{self, custom_proxy};
+        use talpid_types::net as talpid_net;
+
+        let proxy = match proxy_settings {
+            talpid_net::openvpn::ProxySettings::Local(proxy_settings) => {
+                custom_proxy::Type::Local(bridge_settings::LocalProxySettings {
+                    port: u32::from(proxy_settings.port),
+                    peer: proxy_settings.peer.to_string(),
+                })
+            }
+            talpid_net::openvpn::ProxySettings::Remote(proxy_settings) => {
+                custom_proxy::Type::Remote(bridge_settings::RemoteProxySettings {
+                    address: proxy_settings.address.to_string(),
+                    auth: proxy_settings
+                        .auth
+                        .map(|auth| bridge_settings::RemoteProxyAuth {
+                            username: auth.username,
+                            password: auth.password,
+                        }),
+                })
+            }
+            talpid_net::openvpn::ProxySettings::Shadowsocks(proxy_settings) => {
+                custom_proxy::Type::Shadowsocks(bridge_settings::ShadowsocksProxySettings {
+                    peer: proxy_settings.peer.to_string(),
+                    password: proxy_settings.password,
+                    cipher: proxy_settings.cipher,
+                })
+            }
+            talpid_net::openvpn::ProxySettings::Http(proxy_settings) => {
+                custom_proxy::Type::Http(bridge_settings::HttpProxySettings {
+                    address: proxy_settings.address.to_string(),
+                    auth: proxy_settings
+                        .auth
+                        .map(|auth| bridge_settings::RemoteProxyAuth {
+                            username: auth.username,
+                            password: auth.password,
+                        }),
+                })
+            }
+        };
+
+        Self {
+            r#type: Some(proxy),
+        }
+    }
+}
+
 impl From<mullvad_types::relay_constraints::RelaySettings> for proto::RelaySettings {
     fn from(settings: mullvad_types::relay_constraints::RelaySettings) -> Self {
         use mullvad_types::relay_constraints::RelaySettings as MullvadRelaySettings;
@@ -397,7 +465,13 @@ impl From<mullvad_types::relay_constraints::RelaySettings> for proto::RelaySetti
         use talpid_types::net as talpid_net;
 
         let endpoint = match settings {
-            MullvadRelaySettings::CustomTunnelEndpoint(endpoint) => {
+            MullvadRelaySettings::CustomTunnelEndpoint(mut endpoint) => {
+                // The private key of an imported OpenVPN profile is never sent to clients
+                if let mullvad_types::ConnectionConfig::OpenVpn(config) = &mut endpoint.config {
+                    if let Some(profile) = &mut config.profile {
+                        profile.key = None;
+                    }
+                }
                 relay_settings::Endpoint::Custom(proto::CustomRelaySettings {
                     host: endpoint.host,
                     config: Some(proto::ConnectionConfig::from(endpoint.config)),
@@ -535,7 +609,6 @@ impl TryFrom<proto::BridgeSettings> for mullvad_types::relay_constraints::Bridge
 
     fn try_from(settings: proto::BridgeSettings) -> Result<Self, Self::Error> {
         use mullvad_types::relay_constraints as mullvad_constraints;
-        use talpid_types::net as talpid_net;
 
         match settings
             .r#type
@@ -560,19 +633,57 @@ impl TryFrom<proto::BridgeSettings> for mullvad_types::relay_constraints::Bridge
                     },
                 ))
             }
-            proto::bridge_settings::Type::Local(proxy_settings) => {
+            proto::bridge_settings::Type::Custom(custom_bridges) => {
+                let bridges = custom_bridges
+                    .bridges
+                    .into_iter()
+                    .map(mullvad_constraints::CustomBridge::try_from)
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(mullvad_constraints::BridgeSettings::Custom(bridges))
+            }
+        }
+    }
+}
+
+impl TryFrom<proto::bridge_settings::CustomBridge>
+    for mullvad_types::relay_constraints::CustomBridge
+{
+    type Error = FromProtobufTypeError;
+
+    fn try_from(bridge: proto::bridge_settings::CustomBridge) -> Result<Self, Self::Error> {
+        let proxy = bridge.proxy.ok_or(FromProtobufTypeError::InvalidArgument(
+            "missing custom bridge proxy",
+        ))?;
+        Ok(Self {
+            name: bridge.name,
+            enabled: bridge.enabled,
+            proxy: talpid_types::net::openvpn::ProxySettings::try_from(proxy)?,
+        })
+    }
+}
+
+impl TryFrom<proto::bridge_settings::CustomProxy> for talpid_types::net::openvpn::ProxySettings {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(proxy: proto::bridge_settings::CustomProxy) -> Result<Self, Self::Error> {
+        use proto::bridge_settings::custom_proxy;
+        use talpid_types::net as talpid_net;
+
+        match proxy.r#type.ok_or(FromProtobufTypeError::InvalidArgument(
+            "no proxy settings provided",
+        ))? {
+            custom_proxy::Type::Local(proxy_settings) => {
                 let peer = proxy_settings.peer.parse().map_err(|_| {
                     FromProtobufTypeError::InvalidArgument("failed to parse peer address")
                 })?;
-                let proxy_settings = talpid_net::openvpn::ProxySettings::Local(
+                Ok(talpid_net::openvpn::ProxySettings::Local(
                     talpid_net::openvpn::LocalProxySettings {
                         port: proxy_settings.port as u16,
                         peer,
                     },
-                );
-                Ok(mullvad_constraints::BridgeSettings::Custom(proxy_settings))
+                ))
             }
-            proto::bridge_settings::Type::Remote(proxy_settings) => {
+            custom_proxy::Type::Remote(proxy_settings) => {
                 let address = proxy_settings.address.parse().map_err(|_| {
                     FromProtobufTypeError::InvalidArgument("failed to parse IP address")
                 })?;
@@ -582,16 +693,15 @@ impl TryFrom<proto::BridgeSettings> for mullvad_types::relay_constraints::Bridge
                         username: auth.username,
                         password: auth.password,
                     });
-                let proxy_settings = talpid_net::openvpn::ProxySettings::Remote(
+                Ok(talpid_net::openvpn::ProxySettings::Remote(
                     talpid_net::openvpn::RemoteProxySettings { address, auth },
-                );
-                Ok(mullvad_constraints::BridgeSettings::Custom(proxy_settings))
+                ))
             }
-            proto::bridge_settings::Type::Shadowsocks(proxy_settings) => {
+            custom_proxy::Type::Shadowsocks(proxy_settings) => {
                 let peer = proxy_settings.peer.parse().map_err(|_| {
                     FromProtobufTypeError::InvalidArgument("failed to parse peer address")
                 })?;
-                let proxy_settings = talpid_net::openvpn::ProxySettings::Shadowsocks(
+                Ok(talpid_net::openvpn::ProxySettings::Shadowsocks(
                     talpid_net::openvpn::ShadowsocksProxySettings {
                         #[cfg(target_os = "linux")]
                         fwmark: Some(mullvad_types::TUNNEL_FWMARK),
@@ -599,8 +709,21 @@ impl TryFrom<proto::BridgeSettings> for mullvad_types::relay_constraints::Bridge
                         password: proxy_settings.password,
                         cipher: proxy_settings.cipher,
                     },
-                );
-                Ok(mullvad_constraints::BridgeSettings::Custom(proxy_settings))
+                ))
+            }
+            custom_proxy::Type::Http(proxy_settings) => {
+                let address = proxy_settings.address.parse().map_err(|_| {
+                    FromProtobufTypeError::InvalidArgument("failed to parse IP address")
+                })?;
+                let auth = proxy_settings
+                    .auth
+                    .map(|auth| talpid_net::openvpn::ProxyAuth {
+                        username: auth.username,
+                        password: auth.password,
+                    });
+                Ok(talpid_net::openvpn::ProxySettings::Http(
+                    talpid_net::openvpn::HttpProxySettings { address, auth },
+                ))
             }
         }
     }
@@ -617,6 +740,8 @@ impl TryFrom<proto::ObfuscationSettings> for mullvad_types::relay_constraints::O
                 Some(IpcSelectedObfuscation::Auto) => SelectedObfuscation::Auto,
                 Some(IpcSelectedObfuscation::Off) => SelectedObfuscation::Off,
                 Some(IpcSelectedObfuscation::Udp2tcp) => SelectedObfuscation::Udp2Tcp,
+                Some(IpcSelectedObfuscation::Shadowsocks) => SelectedObfuscation::Shadowsocks,
+                Some(IpcSelectedObfuscation::Tls) => SelectedObfuscation::Tls,
                 None => {
                     return Err(FromProtobufTypeError::InvalidArgument(
                         "invalid selected obfuscator",
@@ -635,9 +760,25 @@ impl TryFrom<proto::ObfuscationSettings> for mullvad_types::relay_constraints::O
             }
         };
 
+        // Older clients do not know about Shadowsocks or TLS obfuscation
+        let shadowsocks = match settings.shadowsocks {
+            Some(settings) => {
+                mullvad_types::relay_constraints::ShadowsocksObfuscationSettings::try_from(
+                    &settings,
+                )?
+            }
+            None => Default::default(),
+        };
+        let tls = settings
+            .tls
+            .map(mullvad_types::relay_constraints::TlsObfuscationSettings::from)
+            .unwrap_or_default();
+
         Ok(Self {
             selected_obfuscation,
             udp2tcp,
+            shadowsocks,
+            tls,
         })
     }
 }
@@ -658,6 +799,35 @@ impl TryFrom<&proto::Udp2TcpObfuscationSettings>
     }
 }
 
+impl TryFrom<&proto::ShadowsocksObfuscationSettings>
+    for mullvad_types::relay_constraints::ShadowsocksObfuscationSettings
+{
+    type Error = FromProtobufTypeError;
+
+    fn try_from(settings: &proto::ShadowsocksObfuscationSettings) -> Result<Self, Self::Error> {
+        Ok(Self {
+            port: if settings.port == 0 {
+                Constraint::Any
+            } else {
+                Constraint::Only(
+                    u16::try_from(settings.port)
+                        .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid port"))?,
+                )
+            },
+        })
+    }
+}
+
+impl From<proto::TlsObfuscationSettings>
+    for mullvad_types::relay_constraints::TlsObfuscationSettings
+{
+    fn from(settings: proto::TlsObfuscationSettings) -> Self {
+        Self {
+            server_name: Some(settings.server_name).filter(|name| !name.is_empty()),
+        }
+    }
+}
+
 impl TryFrom<proto::BridgeState> for mullvad_types::relay_constraints::BridgeState {
     type Error = FromProtobufTypeError;
 
@@ -118,6 +118,7 @@ impl From<mullvad_types::relay_list::Relay> for proto::Relay {
                     "mullvad_daemon.management_interface/WireguardRelayEndpointData",
                     proto::WireguardRelayEndpointData {
                         public_key: data.public_key.as_bytes().to_vec(),
+                        tls_server_name: data.tls_server_name.unwrap_or_default(),
                     },
                 )),
                 _ => None,
@@ -162,6 +163,7 @@ impl TryFrom<proto::Relay> for mullvad_types::relay_list::Relay {
                 MullvadEndpointData::Wireguard(
                     mullvad_types::relay_list::WireguardRelayEndpointData {
                         public_key: bytes_to_pubkey(&data.public_key)?,
+                        tls_server_name: option_from_proto_string(data.tls_server_name),
                     },
                 )
             }
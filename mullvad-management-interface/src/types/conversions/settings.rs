@@ -18,9 +18,16 @@ impl From<&mullvad_types::settings::Settings> for proto::Settings {
             Some(proto::SplitTunnelSettings {
                 enable_exclusions: settings.split_tunnel.enable_exclusions,
                 apps: converted_list,
+                enable_network_namespace: false,
             })
         };
-        #[cfg(not(windows))]
+        #[cfg(target_os = "linux")]
+        let split_tunnel = Some(proto::SplitTunnelSettings {
+            enable_exclusions: false,
+            apps: vec![],
+            enable_network_namespace: settings.split_tunnel.enable_network_namespace,
+        });
+        #[cfg(not(any(windows, target_os = "linux")))]
         let split_tunnel = None;
 
         Self {
@@ -38,10 +45,119 @@ impl From<&mullvad_types::settings::Settings> for proto::Settings {
                 &settings.obfuscation_settings,
             )),
             split_tunnel,
+            #[cfg(target_os = "linux")]
+            trusted_overlays: Some(proto::TrustedOverlays::from(&settings.trusted_overlays)),
+            #[cfg(not(target_os = "linux"))]
+            trusted_overlays: None,
+            #[cfg(target_os = "linux")]
+            user_routes: Some(proto::UserRoutes::from(settings.user_routes.as_slice())),
+            #[cfg(not(target_os = "linux"))]
+            user_routes: None,
         }
     }
 }
 
+#[cfg(target_os = "linux")]
+impl From<&talpid_types::net::TrustedOverlays> for proto::TrustedOverlays {
+    fn from(overlays: &talpid_types::net::TrustedOverlays) -> Self {
+        Self {
+            interfaces: overlays.interfaces.clone(),
+            networks: overlays
+                .networks
+                .iter()
+                .map(|network| network.to_string())
+                .collect(),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl TryFrom<proto::TrustedOverlays> for talpid_types::net::TrustedOverlays {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(overlays: proto::TrustedOverlays) -> Result<Self, Self::Error> {
+        let overlays = Self {
+            interfaces: overlays.interfaces,
+            networks: overlays
+                .networks
+                .iter()
+                .map(|network| {
+                    network.parse().map_err(|_| {
+                        FromProtobufTypeError::InvalidArgument("invalid overlay network")
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+
+        // The interfaces are checked by the daemon, which knows the default routes
+        overlays.validate_networks().map_err(|_| {
+            FromProtobufTypeError::InvalidArgument("overlay networks must not match all addresses")
+        })?;
+
+        Ok(overlays)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<&[talpid_types::net::UserRoute]> for proto::UserRoutes {
+    fn from(routes: &[talpid_types::net::UserRoute]) -> Self {
+        use talpid_types::net::UserRouteTarget;
+
+        Self {
+            routes: routes
+                .iter()
+                .map(|route| proto::UserRoute {
+                    network: route.network.to_string(),
+                    target: match route.target {
+                        UserRouteTarget::Tunnel => proto::user_route::Target::Tunnel,
+                        UserRouteTarget::DefaultGateway => {
+                            proto::user_route::Target::DefaultGateway
+                        }
+                    } as i32,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl TryFrom<proto::UserRoutes> for Vec<talpid_types::net::UserRoute> {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(routes: proto::UserRoutes) -> Result<Self, Self::Error> {
+        use talpid_types::net::{validate_user_routes, UserRoute, UserRouteTarget};
+
+        let routes = routes
+            .routes
+            .into_iter()
+            .map(|route| {
+                let network = route
+                    .network
+                    .parse()
+                    .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid route network"))?;
+                let target = match proto::user_route::Target::from_i32(route.target) {
+                    Some(proto::user_route::Target::Tunnel) => UserRouteTarget::Tunnel,
+                    Some(proto::user_route::Target::DefaultGateway) => {
+                        UserRouteTarget::DefaultGateway
+                    }
+                    None => {
+                        return Err(FromProtobufTypeError::InvalidArgument(
+                            "invalid route target",
+                        ))
+                    }
+                };
+                Ok(UserRoute { network, target })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        validate_user_routes(&routes).map_err(|_| {
+            FromProtobufTypeError::InvalidArgument(
+                "routes must not match all addresses or send them all via the default gateway",
+            )
+        })?;
+        Ok(routes)
+    }
+}
+
 impl From<&mullvad_types::settings::DnsOptions> for proto::DnsOptions {
     fn from(options: &mullvad_types::settings::DnsOptions) -> Self {
         use proto::dns_options;
@@ -73,11 +189,11 @@ impl From<&mullvad_types::settings::DnsOptions> for proto::DnsOptions {
 impl From<&mullvad_types::settings::TunnelOptions> for proto::TunnelOptions {
     fn from(options: &mullvad_types::settings::TunnelOptions) -> Self {
         Self {
-            openvpn: Some(proto::tunnel_options::OpenvpnOptions {
-                mssfix: u32::from(options.openvpn.mssfix.unwrap_or_default()),
-            }),
+            openvpn: Some(proto::tunnel_options::OpenvpnOptions::from(&options.openvpn)),
             wireguard: Some(proto::tunnel_options::WireguardOptions {
                 mtu: u32::from(options.wireguard.mtu.unwrap_or_default()),
+                mtu_discovery: options.wireguard.mtu_discovery,
+                persistent_keepalive: u32::from(options.wireguard.persistent_keepalive.unwrap_or_default()),
                 rotation_interval: options.wireguard.rotation_interval.map(|ivl| {
                     prost_types::Duration::try_from(std::time::Duration::from(ivl))
                         .expect("Failed to convert std::time::Duration to prost_types::Duration for tunnel_options.wireguard.rotation_interval")
@@ -86,7 +202,16 @@ impl From<&mullvad_types::settings::TunnelOptions> for proto::TunnelOptions {
                 use_wireguard_nt: options.wireguard.use_wireguard_nt,
                 #[cfg(not(windows))]
                 use_wireguard_nt: false,
+                #[cfg(target_os = "linux")]
+                use_boringtun: options.wireguard.use_boringtun,
+                #[cfg(not(target_os = "linux"))]
+                use_boringtun: false,
                 quantum_resistant: Some(proto::QuantumResistantState::from(options.wireguard.quantum_resistant)),
+                quantum_resistant_rekey_interval: options.wireguard.quantum_resistant_rekey_interval.map(|ivl| {
+                    prost_types::Duration::try_from(ivl)
+                        .expect("Failed to convert std::time::Duration to prost_types::Duration for tunnel_options.wireguard.quantum_resistant_rekey_interval")
+                }),
+                connectivity_probes: Some(proto::ConnectivityProbes::from(&options.wireguard.connectivity_probes)),
             }),
             generic: Some(proto::tunnel_options::GenericOptions {
                 enable_ipv6: options.generic.enable_ipv6,
@@ -127,21 +252,27 @@ impl TryFrom<proto::TunnelOptions> for mullvad_types::settings::TunnelOptions {
             ))?;
 
         Ok(Self {
-            openvpn: net::openvpn::TunnelOptions {
-                mssfix: if openvpn_options.mssfix != 0 {
-                    Some(openvpn_options.mssfix as u16)
-                } else {
-                    None
-                },
-            },
+            openvpn: net::openvpn::TunnelOptions::try_from(openvpn_options)?,
             wireguard: mullvad_types::wireguard::TunnelOptions {
                 mtu: if wireguard_options.mtu != 0 {
                     Some(wireguard_options.mtu as u16)
                 } else {
                     None
                 },
+                mtu_discovery: wireguard_options.mtu_discovery,
+                persistent_keepalive: if wireguard_options.persistent_keepalive != 0 {
+                    Some(
+                        u16::try_from(wireguard_options.persistent_keepalive).map_err(|_| {
+                            FromProtobufTypeError::InvalidArgument("invalid keepalive interval")
+                        })?,
+                    )
+                } else {
+                    None
+                },
                 #[cfg(windows)]
                 use_wireguard_nt: wireguard_options.use_wireguard_nt,
+                #[cfg(target_os = "linux")]
+                use_boringtun: wireguard_options.use_boringtun,
                 rotation_interval: wireguard_options
                     .rotation_interval
                     .map(std::time::Duration::try_from)
@@ -162,6 +293,16 @@ impl TryFrom<proto::TunnelOptions> for mullvad_types::settings::TunnelOptions {
                     .ok_or(FromProtobufTypeError::InvalidArgument(
                         "missing quantum resistant state",
                     ))??,
+                quantum_resistant_rekey_interval: wireguard_options
+                    .quantum_resistant_rekey_interval
+                    .map(std::time::Duration::try_from)
+                    .transpose()
+                    .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid duration"))?,
+                connectivity_probes: wireguard_options
+                    .connectivity_probes
+                    .map(net::wireguard::ConnectivityProbes::try_from)
+                    .transpose()?
+                    .unwrap_or_default(),
             },
             generic: net::GenericTunnelOptions {
                 enable_ipv6: generic_options.enable_ipv6,
@@ -228,3 +369,148 @@ impl TryFrom<proto::DnsOptions> for mullvad_types::settings::DnsOptions {
         })
     }
 }
+
+impl From<&talpid_types::net::openvpn::TunnelOptions> for proto::tunnel_options::OpenvpnOptions {
+    fn from(options: &talpid_types::net::openvpn::TunnelOptions) -> Self {
+        use proto::tunnel_options::openvpn_options::DataCipher;
+        use talpid_types::net::openvpn;
+
+        Self {
+            mssfix: u32::from(options.mssfix.unwrap_or_default()),
+            data_cipher: i32::from(match options.data_cipher {
+                None => DataCipher::Any,
+                Some(openvpn::DataCipher::Aes256Gcm) => DataCipher::Aes256Gcm,
+                Some(openvpn::DataCipher::Chacha20Poly1305) => DataCipher::Chacha20Poly1305,
+            }),
+            fragment: u32::from(options.fragment.unwrap_or_default()),
+            tun_mtu: u32::from(options.tun_mtu.unwrap_or_default()),
+        }
+    }
+}
+
+impl TryFrom<proto::tunnel_options::OpenvpnOptions> for talpid_types::net::openvpn::TunnelOptions {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(options: proto::tunnel_options::OpenvpnOptions) -> Result<Self, Self::Error> {
+        use proto::tunnel_options::openvpn_options::DataCipher;
+        use talpid_types::net::openvpn;
+
+        let size = |value: u32, error| match value {
+            0 => Ok(None),
+            value => u16::try_from(value)
+                .map(Some)
+                .map_err(|_| FromProtobufTypeError::InvalidArgument(error)),
+        };
+
+        Ok(Self {
+            mssfix: size(options.mssfix, "invalid mssfix")?,
+            data_cipher: match DataCipher::from_i32(options.data_cipher) {
+                Some(DataCipher::Any) => None,
+                Some(DataCipher::Aes256Gcm) => Some(openvpn::DataCipher::Aes256Gcm),
+                Some(DataCipher::Chacha20Poly1305) => Some(openvpn::DataCipher::Chacha20Poly1305),
+                None => {
+                    return Err(FromProtobufTypeError::InvalidArgument(
+                        "invalid data cipher",
+                    ))
+                }
+            },
+            fragment: size(options.fragment, "invalid fragment size")?,
+            tun_mtu: size(options.tun_mtu, "invalid tun-mtu")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::net::openvpn::{DataCipher, TunnelOptions};
+    #[cfg(target_os = "linux")]
+    use talpid_types::net::{TrustedOverlays, UserRoute};
+
+    #[test]
+    fn test_openvpn_options_round_trip() {
+        for options in [
+            TunnelOptions::default(),
+            TunnelOptions {
+                mssfix: Some(1300),
+                data_cipher: Some(DataCipher::Chacha20Poly1305),
+                fragment: Some(1200),
+                tun_mtu: Some(1400),
+            },
+        ] {
+            let converted = proto::tunnel_options::OpenvpnOptions::from(&options);
+            assert_eq!(TunnelOptions::try_from(converted).unwrap(), options);
+        }
+
+        let options = proto::tunnel_options::OpenvpnOptions {
+            tun_mtu: u32::from(u16::MAX) + 1,
+            ..Default::default()
+        };
+        assert!(matches!(
+            TunnelOptions::try_from(options),
+            Err(FromProtobufTypeError::InvalidArgument(_))
+        ));
+
+        let options = proto::tunnel_options::OpenvpnOptions {
+            data_cipher: -1,
+            ..Default::default()
+        };
+        assert!(matches!(
+            TunnelOptions::try_from(options),
+            Err(FromProtobufTypeError::InvalidArgument(_))
+        ));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_user_routes_reject_default_network() {
+        let route = |network: &str, target: proto::user_route::Target| proto::UserRoute {
+            network: network.to_owned(),
+            target: target as i32,
+        };
+        let routes = proto::UserRoutes {
+            routes: vec![
+                route("10.0.0.0/8", proto::user_route::Target::Tunnel),
+                route("0.0.0.0/1", proto::user_route::Target::DefaultGateway),
+            ],
+        };
+        let converted = Vec::<UserRoute>::try_from(routes.clone()).unwrap();
+        assert_eq!(proto::UserRoutes::from(converted.as_slice()), routes);
+
+        for routes in [
+            vec![route("0.0.0.0/0", proto::user_route::Target::Tunnel)],
+            vec![route("::/0", proto::user_route::Target::DefaultGateway)],
+            vec![
+                route("0.0.0.0/1", proto::user_route::Target::DefaultGateway),
+                route("128.0.0.0/1", proto::user_route::Target::DefaultGateway),
+            ],
+        ] {
+            assert!(matches!(
+                Vec::<UserRoute>::try_from(proto::UserRoutes { routes }),
+                Err(FromProtobufTypeError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_trusted_overlays_reject_default_network() {
+        let overlays = proto::TrustedOverlays {
+            interfaces: vec!["tailscale0".to_owned()],
+            networks: vec!["100.64.0.0/10".to_owned(), "fd7a:115c:a1e0::/48".to_owned()],
+        };
+        let converted = TrustedOverlays::try_from(overlays.clone()).unwrap();
+        assert_eq!(proto::TrustedOverlays::from(&converted), overlays);
+
+        for networks in [&["0.0.0.0/0"][..], &["::/0"], &["0.0.0.0/1", "128.0.0.0/1"]] {
+            let overlays = proto::TrustedOverlays {
+                interfaces: vec![],
+                networks: networks.iter().map(|n| n.to_string()).collect(),
+            };
+            assert!(matches!(
+                TrustedOverlays::try_from(overlays),
+                Err(FromProtobufTypeError::InvalidArgument(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use super::{arg_from_str, option_from_proto_string, FromProtobufTypeError};
+use crate::types::proto;
+use mullvad_types::traffic_usage::{TrafficUsageFilter, TrafficUsageRecord};
+use talpid_types::tunnel::TunnelStatistics;
+
+impl From<TunnelStatistics> for proto::TunnelStatistics {
+    fn from(statistics: TunnelStatistics) -> Self {
+        match statistics {
+            TunnelStatistics::Wireguard {
+                peers,
+                ping_rtt,
+                ping_rtt_age,
+            } => {
+                let peers: Vec<_> = peers
+                    .into_iter()
+                    .map(|peer| proto::tunnel_statistics::WireguardPeer {
+                        public_key: peer.public_key.as_bytes().to_vec(),
+                        rx_bytes: peer.rx_bytes,
+                        tx_bytes: peer.tx_bytes,
+                        rx_rate: 0,
+                        tx_rate: 0,
+                        last_handshake: peer.last_handshake.map(prost_types::Timestamp::from),
+                    })
+                    .collect();
+                proto::TunnelStatistics {
+                    tunnel_type: i32::from(proto::TunnelType::Wireguard),
+                    rx_bytes: peers.iter().map(|peer| peer.rx_bytes).sum(),
+                    tx_bytes: peers.iter().map(|peer| peer.tx_bytes).sum(),
+                    rx_rate: 0,
+                    tx_rate: 0,
+                    peers,
+                    ping_rtt: ping_rtt.and_then(|rtt| prost_types::Duration::try_from(rtt).ok()),
+                    ping_rtt_age: ping_rtt_age
+                        .and_then(|age| prost_types::Duration::try_from(age).ok()),
+                    tls_renegotiations: 0,
+                    last_tls_negotiation: None,
+                }
+            }
+            TunnelStatistics::OpenVpn {
+                rx_bytes,
+                tx_bytes,
+                tls_renegotiations,
+                last_tls_negotiation,
+            } => proto::TunnelStatistics {
+                tunnel_type: i32::from(proto::TunnelType::Openvpn),
+                rx_bytes,
+                tx_bytes,
+                rx_rate: 0,
+                tx_rate: 0,
+                peers: vec![],
+                ping_rtt: None,
+                ping_rtt_age: None,
+                tls_renegotiations,
+                last_tls_negotiation: last_tls_negotiation.map(prost_types::Timestamp::from),
+            },
+        }
+    }
+}
+
+impl From<Vec<TrafficUsageRecord>> for proto::TrafficUsage {
+    fn from(records: Vec<TrafficUsageRecord>) -> Self {
+        proto::TrafficUsage {
+            records: records
+                .into_iter()
+                .map(|record| proto::traffic_usage::Record {
+                    date: record.date.to_string(),
+                    relay: record.relay,
+                    rx_bytes: record.rx_bytes,
+                    tx_bytes: record.tx_bytes,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::TrafficUsageFilter> for TrafficUsageFilter {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(filter: proto::TrafficUsageFilter) -> Result<Self, Self::Error> {
+        let parse_date = |date: String| {
+            option_from_proto_string(date)
+                .map(|date| arg_from_str(&date, "invalid date"))
+                .transpose()
+        };
+        Ok(TrafficUsageFilter {
+            since: parse_date(filter.since)?,
+            until: parse_date(filter.until)?,
+            relay: option_from_proto_string(filter.relay),
+        })
+    }
+}
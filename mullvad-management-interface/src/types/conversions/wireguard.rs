@@ -50,3 +50,93 @@ impl TryFrom<proto::QuantumResistantState> for mullvad_types::wireguard::Quantum
         }
     }
 }
+
+impl From<&talpid_types::net::wireguard::ConnectivityProbes> for proto::ConnectivityProbes {
+    fn from(probes: &talpid_types::net::wireguard::ConnectivityProbes) -> Self {
+        use proto::connectivity_probe::{self, Probe};
+        use talpid_types::net::wireguard::ConnectivityProbe;
+
+        proto::ConnectivityProbes {
+            probes: probes
+                .probes
+                .iter()
+                .map(|probe| {
+                    let probe = match probe {
+                        ConnectivityProbe::IcmpGateway => {
+                            Probe::IcmpGateway(connectivity_probe::IcmpGateway {})
+                        }
+                        ConnectivityProbe::TcpConnect(address) => {
+                            Probe::TcpConnect(connectivity_probe::TcpConnect {
+                                address: address.to_string(),
+                            })
+                        }
+                        ConnectivityProbe::Dns { resolver, hostname } => {
+                            Probe::Dns(connectivity_probe::Dns {
+                                resolver: resolver.to_string(),
+                                hostname: hostname.clone(),
+                            })
+                        }
+                        ConnectivityProbe::HttpHead { address, path } => {
+                            Probe::HttpHead(connectivity_probe::HttpHead {
+                                address: address.to_string(),
+                                path: path.clone(),
+                            })
+                        }
+                    };
+                    proto::ConnectivityProbe { probe: Some(probe) }
+                })
+                .collect(),
+            required_successes: probes.required_successes,
+            timeout: probes.timeout.map(|timeout| {
+                prost_types::Duration::try_from(timeout)
+                    .expect("Failed to convert std::time::Duration to prost_types::Duration for connectivity_probes.timeout")
+            }),
+        }
+    }
+}
+
+impl TryFrom<proto::ConnectivityProbes> for talpid_types::net::wireguard::ConnectivityProbes {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(probes: proto::ConnectivityProbes) -> Result<Self, Self::Error> {
+        use proto::connectivity_probe::Probe;
+        use talpid_types::net::wireguard::ConnectivityProbe;
+
+        Ok(talpid_types::net::wireguard::ConnectivityProbes {
+            probes: probes
+                .probes
+                .into_iter()
+                .map(|probe| {
+                    match probe.probe.ok_or(FromProtobufTypeError::InvalidArgument(
+                        "missing connectivity probe",
+                    ))? {
+                        Probe::IcmpGateway(_) => Ok(ConnectivityProbe::IcmpGateway),
+                        Probe::TcpConnect(probe) => Ok(ConnectivityProbe::TcpConnect(
+                            probe.address.parse().map_err(|_| {
+                                FromProtobufTypeError::InvalidArgument("invalid probe address")
+                            })?,
+                        )),
+                        Probe::Dns(probe) => Ok(ConnectivityProbe::Dns {
+                            resolver: probe.resolver.parse().map_err(|_| {
+                                FromProtobufTypeError::InvalidArgument("invalid probe resolver")
+                            })?,
+                            hostname: probe.hostname,
+                        }),
+                        Probe::HttpHead(probe) => Ok(ConnectivityProbe::HttpHead {
+                            address: probe.address.parse().map_err(|_| {
+                                FromProtobufTypeError::InvalidArgument("invalid probe address")
+                            })?,
+                            path: probe.path,
+                        }),
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            required_successes: probes.required_successes,
+            timeout: probes
+                .timeout
+                .map(std::time::Duration::try_from)
+                .transpose()
+                .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid probe timeout"))?,
+        })
+    }
+}
@@ -0,0 +1,113 @@
+use crate::SelectorConfig;
+use mullvad_types::relay_constraints::{
+    Constraint, RelaySettings, SelectedObfuscation, TransportPort,
+};
+use serde::{Deserialize, Serialize};
+use talpid_types::net::{ObfuscationType, TransportProtocol, TunnelEndpoint, TunnelType};
+
+/// The tunnel protocol, port and obfuscation of a connection that was established successfully.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionMethod {
+    pub tunnel_type: TunnelType,
+    pub port: u16,
+    pub transport_protocol: TransportProtocol,
+    pub obfuscation: Option<ObfuscationMethod>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObfuscationMethod {
+    pub obfuscation_type: ObfuscationType,
+    pub port: u16,
+}
+
+impl From<&TunnelEndpoint> for ConnectionMethod {
+    fn from(endpoint: &TunnelEndpoint) -> Self {
+        // With multihop, the entry relay is the one that the client connects to
+        let first_hop = endpoint.entry_endpoint.unwrap_or(endpoint.endpoint);
+        ConnectionMethod {
+            tunnel_type: endpoint.tunnel_type,
+            port: first_hop.address.port(),
+            transport_protocol: first_hop.protocol,
+            obfuscation: endpoint
+                .obfuscation
+                .as_ref()
+                .map(|obfuscation| ObfuscationMethod {
+                    obfuscation_type: obfuscation.obfuscation_type,
+                    port: obfuscation.endpoint.address.port(),
+                }),
+        }
+    }
+}
+
+impl ConnectionMethod {
+    /// Restrict `config` to this method, but only where it leaves the choice to the relay
+    /// selector. Explicit settings are never overridden.
+    pub(crate) fn apply(&self, config: &mut SelectorConfig) {
+        let constraints = match &mut config.relay_settings {
+            RelaySettings::Normal(constraints) => constraints,
+            RelaySettings::CustomTunnelEndpoint(_) => return,
+        };
+
+        if constraints.tunnel_protocol.is_any() {
+            constraints.tunnel_protocol = Constraint::Only(self.tunnel_type);
+        }
+        if constraints.tunnel_protocol != Constraint::Only(self.tunnel_type) {
+            return;
+        }
+
+        match self.tunnel_type {
+            TunnelType::OpenVpn => {
+                if constraints.openvpn_constraints.port.is_any() {
+                    constraints.openvpn_constraints.port = Constraint::Only(TransportPort {
+                        protocol: self.transport_protocol,
+                        port: Constraint::Only(self.port),
+                    });
+                }
+            }
+            TunnelType::Wireguard => {
+                let obfuscation_settings = &mut config.obfuscation_settings;
+                if obfuscation_settings.selected_obfuscation != SelectedObfuscation::Auto {
+                    return;
+                }
+                match self.obfuscation {
+                    None => {
+                        obfuscation_settings.selected_obfuscation = SelectedObfuscation::Off;
+                        if constraints.wireguard_constraints.port.is_any() {
+                            constraints.wireguard_constraints.port = Constraint::Only(self.port);
+                        }
+                    }
+                    Some(ObfuscationMethod {
+                        obfuscation_type: ObfuscationType::Udp2Tcp,
+                        port,
+                    }) => {
+                        obfuscation_settings.selected_obfuscation = SelectedObfuscation::Udp2Tcp;
+                        if obfuscation_settings.udp2tcp.port.is_any() {
+                            obfuscation_settings.udp2tcp.port = Constraint::Only(port);
+                        }
+                    }
+                    Some(ObfuscationMethod {
+                        obfuscation_type: ObfuscationType::Shadowsocks,
+                        port,
+                    }) => {
+                        obfuscation_settings.selected_obfuscation =
+                            SelectedObfuscation::Shadowsocks;
+                        if obfuscation_settings.shadowsocks.port.is_any() {
+                            obfuscation_settings.shadowsocks.port = Constraint::Only(port);
+                        }
+                    }
+                    Some(ObfuscationMethod {
+                        obfuscation_type: ObfuscationType::Tls,
+                        ..
+                    }) => {
+                        obfuscation_settings.selected_obfuscation = SelectedObfuscation::Tls;
+                    }
+                    // Custom SOCKS5 bridges are picked using the bridge settings
+                    Some(ObfuscationMethod {
+                        obfuscation_type: ObfuscationType::Socks5,
+                        ..
+                    }) => (),
+                }
+            }
+        }
+    }
+}
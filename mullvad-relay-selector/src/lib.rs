@@ -7,17 +7,18 @@ use mullvad_types::{
     endpoint::{MullvadEndpoint, MullvadWireguardEndpoint},
     location::{Coordinates, Location},
     relay_constraints::{
-        BridgeSettings, BridgeState, Constraint, InternalBridgeConstraints, LocationConstraint,
-        Match, ObfuscationSettings, OpenVpnConstraints, Ownership, Providers, RelayConstraints,
-        RelaySettings, SelectedObfuscation, Set, TransportPort, Udp2TcpObfuscationSettings,
-        WireguardConstraints,
+        BridgeSettings, BridgeState, Constraint, CustomBridge, InternalBridgeConstraints,
+        LocationConstraint, Match, ObfuscationSettings, OpenVpnConstraints, Ownership, Providers,
+        RelayConstraints, RelaySettings, SelectedObfuscation, Set, ShadowsocksObfuscationSettings,
+        TlsObfuscationSettings, TransportPort, Udp2TcpObfuscationSettings, WireguardConstraints,
     },
     relay_list::{BridgeEndpointData, Relay, RelayEndpointData, RelayList},
     CustomTunnelEndpoint,
 };
-use parking_lot::{Mutex, MutexGuard};
+use parking_lot::Mutex;
 use rand::{seq::SliceRandom, Rng};
 use std::{
+    collections::HashSet,
     io,
     net::{IpAddr, SocketAddr},
     path::Path,
@@ -34,6 +35,9 @@ use talpid_types::{
 
 use matcher::{BridgeMatcher, EndpointMatcher, OpenVpnMatcher, RelayMatcher, WireguardMatcher};
 
+pub use connection_method::{ConnectionMethod, ObfuscationMethod};
+
+mod connection_method;
 mod matcher;
 pub mod updater;
 
@@ -45,6 +49,9 @@ const WIREGUARD_EXIT_IP_VERSION: Constraint<IpVersion> = Constraint::Only(IpVers
 
 const UDP2TCP_PORTS: [u16; 2] = [80, 5001];
 
+/// Port of the TLS obfuscation server on WireGuard relays.
+const TLS_PORT: u16 = 443;
+
 /// Minimum number of bridges to keep for selection when filtering by distance.
 const MIN_BRIDGE_COUNT: usize = 5;
 
@@ -177,6 +184,8 @@ pub struct SelectorConfig {
 pub struct RelaySelector {
     config: Arc<Mutex<SelectorConfig>>,
     parsed_relays: Arc<Mutex<ParsedRelays>>,
+    /// Names of the custom bridges that failed their most recent reachability test.
+    unreachable_custom_bridges: Arc<Mutex<HashSet<String>>>,
 }
 
 impl RelaySelector {
@@ -202,6 +211,7 @@ impl RelaySelector {
         RelaySelector {
             config: Arc::new(Mutex::new(config)),
             parsed_relays: Arc::new(Mutex::new(unsynchronized_parsed_relays)),
+            unreachable_custom_bridges: Arc::new(Mutex::new(HashSet::new())),
         }
     }
 
@@ -209,6 +219,17 @@ impl RelaySelector {
         *self.config.lock() = config;
     }
 
+    /// Records the result of a reachability test of the custom bridge called `name`. Unreachable
+    /// custom bridges are skipped as long as any other enabled custom bridge is reachable.
+    pub fn set_custom_bridge_reachable(&self, name: &str, reachable: bool) {
+        let mut unreachable = self.unreachable_custom_bridges.lock();
+        if reachable {
+            unreachable.remove(name);
+        } else {
+            unreachable.insert(name.to_owned());
+        }
+    }
+
     /// Returns all countries and cities. The cities in the object returned does not have any
     /// relays in them.
     pub fn get_locations(&mut self) -> RelayList {
@@ -227,7 +248,50 @@ impl RelaySelector {
         ),
         Error,
     > {
-        let config = self.config.lock();
+        self.get_relay_inner(&self.config.lock(), retry_attempt)
+    }
+
+    /// Like [`Self::get_relay`], but the first attempt uses `method` where the settings leave the
+    /// tunnel protocol, port or obfuscation up to the relay selector. Falls back on the regular
+    /// selection if no relay can be used with `method`.
+    pub fn get_relay_preferring(
+        &self,
+        retry_attempt: u32,
+        method: &ConnectionMethod,
+    ) -> Result<
+        (
+            SelectedRelay,
+            Option<SelectedBridge>,
+            Option<SelectedObfuscator>,
+        ),
+        Error,
+    > {
+        if retry_attempt == 0 {
+            let mut config = self.config.lock().clone();
+            method.apply(&mut config);
+            match self.get_relay_inner(&config, retry_attempt) {
+                Ok(selection) => return Ok(selection),
+                Err(error) => log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to select relay using preferred method")
+                ),
+            }
+        }
+        self.get_relay(retry_attempt)
+    }
+
+    fn get_relay_inner(
+        &self,
+        config: &SelectorConfig,
+        retry_attempt: u32,
+    ) -> Result<
+        (
+            SelectedRelay,
+            Option<SelectedBridge>,
+            Option<SelectedObfuscator>,
+        ),
+        Error,
+    > {
         match &config.relay_settings {
             RelaySettings::CustomTunnelEndpoint(custom_relay) => {
                 Ok((SelectedRelay::Custom(custom_relay.clone()), None, None))
@@ -248,20 +312,25 @@ impl RelaySelector {
                             .location
                             .as_ref()
                             .expect("Relay has no location set");
-                        self.get_bridge_for(&config, location, retry_attempt)?
+                        self.get_bridge_for(config, location, retry_attempt)?
                     }
                     _ => None,
                 };
                 let obfuscator = match relay.endpoint {
                     MullvadEndpoint::Wireguard(ref endpoint) => {
-                        let obfuscator_relay =
-                            relay.entry_relay.as_ref().unwrap_or(&relay.exit_relay);
-                        self.get_obfuscator_inner(
-                            &config,
-                            obfuscator_relay,
-                            endpoint,
-                            retry_attempt,
-                        )?
+                        match self.get_custom_bridge_obfuscator(config, endpoint, retry_attempt)? {
+                            Some(obfuscator) => Some(obfuscator),
+                            None => {
+                                let obfuscator_relay =
+                                    relay.entry_relay.as_ref().unwrap_or(&relay.exit_relay);
+                                self.get_obfuscator_inner(
+                                    config,
+                                    obfuscator_relay,
+                                    endpoint,
+                                    retry_attempt,
+                                )?
+                            }
+                        }
                     }
                     _ => None,
                 };
@@ -336,6 +405,29 @@ impl RelaySelector {
         Some(Coordinates::midpoint(&matching_locations))
     }
 
+    /// Returns a random WireGuard relay matching the given location, along with a single-hop
+    /// endpoint for it. The current relay constraints are ignored, since this is used to
+    /// configure WireGuard clients other than the daemon itself.
+    pub fn get_wireguard_relay_by_location(
+        &self,
+        location: Constraint<LocationConstraint>,
+    ) -> Result<(Relay, MullvadWireguardEndpoint), Error> {
+        let matcher = RelayMatcher {
+            location,
+            providers: Constraint::Any,
+            ownership: Constraint::Any,
+            endpoint_matcher: WireguardMatcher::new(
+                WireguardConstraints::default(),
+                self.parsed_relays.lock().locations.wireguard.clone(),
+            ),
+        };
+        let selected = self.get_tunnel_endpoint_internal(&matcher)?;
+        match selected.endpoint {
+            MullvadEndpoint::Wireguard(endpoint) => Ok((selected.exit_relay, endpoint)),
+            MullvadEndpoint::OpenVpn(_) => Err(Error::NoRelay),
+        }
+    }
+
     /// Returns an OpenVpn endpoint, should only ever be used when the user has specified the tunnel
     /// protocol as only OpenVPN.
     fn get_openvpn_endpoint(
@@ -723,7 +815,7 @@ impl RelaySelector {
 
     fn get_bridge_for(
         &self,
-        config: &MutexGuard<'_, SelectorConfig>,
+        config: &SelectorConfig,
         location: &mullvad_types::location::Location,
         retry_attempt: u32,
     ) -> Result<Option<SelectedBridge>, Error> {
@@ -754,20 +846,115 @@ impl RelaySelector {
                     BridgeState::Auto | BridgeState::Off => Ok(None),
                 }
             }
-            BridgeSettings::Custom(bridge_settings) => match config.bridge_state {
-                BridgeState::On => Ok(Some(SelectedBridge::Custom(bridge_settings.clone()))),
-                BridgeState::Auto if Self::should_use_bridge(retry_attempt) => {
-                    Ok(Some(SelectedBridge::Custom(bridge_settings.clone())))
-                }
-                BridgeState::Auto | BridgeState::Off => Ok(None),
-            },
+            BridgeSettings::Custom(bridges) => {
+                self.select_custom_bridge(bridges, config.bridge_state, retry_attempt)
+            }
+        }
+    }
+
+    fn select_custom_bridge(
+        &self,
+        bridges: &[CustomBridge],
+        bridge_state: BridgeState,
+        retry_attempt: u32,
+    ) -> Result<Option<SelectedBridge>, Error> {
+        match bridge_state {
+            BridgeState::On => self
+                .pick_custom_bridge(bridges, retry_attempt)
+                .map(Some)
+                .ok_or(Error::NoBridge),
+            BridgeState::Auto if Self::should_use_bridge(retry_attempt) => {
+                // Only count the attempts that use a bridge, so that none is skipped
+                let bridge_attempt = (retry_attempt / 4 - 1) * 2 + retry_attempt % 4;
+                Ok(self.pick_custom_bridge(bridges, bridge_attempt))
+            }
+            BridgeState::Auto | BridgeState::Off => Ok(None),
+        }
+    }
+
+    /// Returns a custom SOCKS5 bridge to relay WireGuard through, using UDP ASSOCIATE. Custom
+    /// bridges of other kinds cannot carry UDP, so they are only used for OpenVPN.
+    fn get_custom_bridge_obfuscator(
+        &self,
+        config: &SelectorConfig,
+        endpoint: &MullvadWireguardEndpoint,
+        retry_attempt: u32,
+    ) -> Result<Option<SelectedObfuscator>, Error> {
+        // The firewall can only allow the proxy's UDP relay on these platforms
+        if cfg!(not(any(target_os = "linux", target_os = "macos"))) {
+            return Ok(None);
+        }
+        let socks5_bridges: Vec<_> = match &config.bridge_settings {
+            BridgeSettings::Custom(bridges) => bridges
+                .iter()
+                .filter(|bridge| matches!(bridge.proxy, ProxySettings::Remote(_)))
+                .cloned()
+                .collect(),
+            BridgeSettings::Normal(_) => return Ok(None),
+        };
+        if socks5_bridges.is_empty() {
+            return Ok(None);
+        }
+        let bridge =
+            self.select_custom_bridge(&socks5_bridges, config.bridge_state, retry_attempt)?;
+        Ok(match bridge {
+            Some(SelectedBridge::Custom(ProxySettings::Remote(proxy))) => {
+                Some(SelectedObfuscator {
+                    config: ObfuscatorConfig::Socks5 {
+                        endpoint: proxy.address,
+                        auth: proxy.auth,
+                        wireguard_endpoint: endpoint.peer.endpoint,
+                    },
+                    relay: None,
+                })
+            }
+            _ => None,
+        })
+    }
+
+    /// Rotates through the usable custom bridges, picking a different one for each attempt.
+    fn pick_custom_bridge(&self, bridges: &[CustomBridge], attempt: u32) -> Option<SelectedBridge> {
+        let bridges = self.usable_custom_bridges(bridges);
+        if bridges.is_empty() {
+            return None;
+        }
+        let bridge = bridges[attempt as usize % bridges.len()];
+        log::debug!("Selected custom bridge {}", bridge.name);
+        Some(SelectedBridge::Custom(bridge.proxy.clone()))
+    }
+
+    /// Returns the enabled custom bridges that are not known to be unreachable. If all of them
+    /// are, all enabled bridges are returned, since they may have become reachable again.
+    fn usable_custom_bridges<'a>(&self, bridges: &'a [CustomBridge]) -> Vec<&'a CustomBridge> {
+        let enabled: Vec<_> = bridges.iter().filter(|bridge| bridge.enabled).collect();
+        let unreachable = self.unreachable_custom_bridges.lock();
+        let reachable: Vec<_> = enabled
+            .iter()
+            .copied()
+            .filter(|bridge| !unreachable.contains(&bridge.name))
+            .collect();
+        if reachable.is_empty() {
+            enabled
+        } else {
+            reachable
         }
     }
 
     /// Returns a bridge based on the relay and bridge constraints, ignoring the bridge state.
+    /// A custom HTTP proxy is returned as is, since it may be the only way out of the network.
     pub fn get_bridge_forced(&self) -> Option<ProxySettings> {
         let config = self.config.lock();
 
+        if let BridgeSettings::Custom(bridges) = &config.bridge_settings {
+            let http_proxy = self
+                .usable_custom_bridges(bridges)
+                .into_iter()
+                .find(|bridge| matches!(bridge.proxy, ProxySettings::Http(_)));
+            if let Some(bridge) = http_proxy {
+                return Some(bridge.proxy.clone());
+            }
+        }
+
         let near_location = match &config.relay_settings {
             RelaySettings::Normal(settings) => self.get_relay_midpoint(settings),
             _ => None,
@@ -780,7 +967,7 @@ impl RelaySelector {
                 ownership: settings.ownership,
                 transport_protocol: Constraint::Only(TransportProtocol::Tcp),
             },
-            BridgeSettings::Custom(_bridge_settings) => InternalBridgeConstraints {
+            BridgeSettings::Custom(_bridges) => InternalBridgeConstraints {
                 location: Constraint::Any,
                 providers: Constraint::Any,
                 ownership: Constraint::Any,
@@ -807,6 +994,19 @@ impl RelaySelector {
         constraints: &InternalBridgeConstraints,
         location: Option<T>,
     ) -> Option<(ProxySettings, Relay)> {
+        self.get_proximate_bridge(constraints, location)
+            .and_then(|relay| {
+                self.pick_random_bridge(&self.parsed_relays.lock().locations.bridge, &relay)
+                    .map(|bridge| (bridge, relay))
+            })
+    }
+
+    /// Returns a random bridge server matching `constraints`, preferring ones close to `location`.
+    fn get_proximate_bridge<T: Into<Coordinates>>(
+        &self,
+        constraints: &InternalBridgeConstraints,
+        location: Option<T>,
+    ) -> Option<Relay> {
         let matcher = RelayMatcher {
             location: constraints.location.clone(),
             providers: constraints.providers.clone(),
@@ -820,7 +1020,7 @@ impl RelaySelector {
             return None;
         }
 
-        let relay = if let Some(location) = location {
+        if let Some(location) = location {
             let location = location.into();
 
             #[derive(Debug, Clone)]
@@ -862,11 +1062,7 @@ impl RelaySelector {
                 .map(|relay_with_distance| relay_with_distance.relay)
         } else {
             self.pick_random_relay(&matching_relays).cloned()
-        };
-        relay.and_then(|relay| {
-            self.pick_random_bridge(&self.parsed_relays.lock().locations.bridge, &relay)
-                .map(|bridge| (bridge, relay.clone()))
-        })
+        }
     }
 
     pub fn get_obfuscator(
@@ -880,7 +1076,7 @@ impl RelaySelector {
 
     fn get_obfuscator_inner(
         &self,
-        config: &MutexGuard<'_, SelectorConfig>,
+        config: &SelectorConfig,
         relay: &Relay,
         endpoint: &MullvadWireguardEndpoint,
         retry_attempt: u32,
@@ -902,6 +1098,19 @@ impl RelaySelector {
                 )
                 .ok_or(Error::NoObfuscator)?,
             )),
+            SelectedObfuscation::Tls => Ok(Some(
+                self.get_tls_obfuscator(&config.obfuscation_settings.tls, relay, endpoint)
+                    .ok_or(Error::NoObfuscator)?,
+            )),
+            SelectedObfuscation::Shadowsocks => Ok(Some(
+                self.get_shadowsocks_obfuscator(
+                    &config.obfuscation_settings.shadowsocks,
+                    relay,
+                    endpoint,
+                    retry_attempt,
+                )
+                .ok_or(Error::NoObfuscator)?,
+            )),
         }
     }
 
@@ -912,24 +1121,22 @@ impl RelaySelector {
         endpoint: &MullvadWireguardEndpoint,
         retry_attempt: u32,
     ) -> Option<SelectedObfuscator> {
-        if !self.should_use_auto_obfuscator(retry_attempt) {
-            return None;
+        // Try udp2tcp first and TLS second, if the relay supports it. The udp2tcp port changes
+        // every round, so that all of them are eventually tried.
+        let udp2tcp = || {
+            self.get_udp2tcp_obfuscator(
+                &obfuscation_settings.udp2tcp,
+                relay,
+                endpoint,
+                retry_attempt / 4,
+            )
+        };
+        match self.get_auto_obfuscator_retry_attempt(retry_attempt)? {
+            0 => udp2tcp(),
+            _ => self
+                .get_tls_obfuscator(&obfuscation_settings.tls, relay, endpoint)
+                .or_else(udp2tcp),
         }
-        // TODO FIX: The third obfuscator entry will never be chosen
-        // Because get_auto_obfuscator_retry_attempt() returns [0, 1]
-        // And the udp2tcp endpoints are defined in a vector with entries [0, 1, 2]
-        self.get_udp2tcp_obfuscator(
-            &obfuscation_settings.udp2tcp,
-            relay,
-            endpoint,
-            self.get_auto_obfuscator_retry_attempt(retry_attempt)
-                .unwrap(),
-        )
-    }
-
-    fn should_use_auto_obfuscator(&self, retry_attempt: u32) -> bool {
-        self.get_auto_obfuscator_retry_attempt(retry_attempt)
-            .is_some()
     }
 
     fn get_auto_obfuscator_retry_attempt(&self, retry_attempt: u32) -> Option<u32> {
@@ -960,10 +1167,87 @@ impl RelaySelector {
             })
             .map(|config| SelectedObfuscator {
                 config,
-                relay: relay.clone(),
+                relay: Some(relay.clone()),
             })
     }
 
+    /// Returns a TLS obfuscator for `relay`, or `None` if the relay list does not advertise TLS
+    /// support for it.
+    fn get_tls_obfuscator(
+        &self,
+        obfuscation_settings: &TlsObfuscationSettings,
+        relay: &Relay,
+        endpoint: &MullvadWireguardEndpoint,
+    ) -> Option<SelectedObfuscator> {
+        let relay_server_name = match &relay.endpoint_data {
+            RelayEndpointData::Wireguard(data) => data.tls_server_name.as_ref()?,
+            _ => return None,
+        };
+        let server_name = obfuscation_settings
+            .server_name
+            .as_ref()
+            .unwrap_or(relay_server_name);
+        Some(SelectedObfuscator {
+            config: ObfuscatorConfig::Tls {
+                endpoint: SocketAddr::new(endpoint.peer.endpoint.ip(), TLS_PORT),
+                server_name: server_name.clone(),
+            },
+            relay: Some(relay.clone()),
+        })
+    }
+
+    /// Returns a Shadowsocks obfuscator running on a bridge server close to `relay`. Only
+    /// Shadowsocks endpoints that relay UDP are considered.
+    fn get_shadowsocks_obfuscator(
+        &self,
+        obfuscation_settings: &ShadowsocksObfuscationSettings,
+        relay: &Relay,
+        endpoint: &MullvadWireguardEndpoint,
+        retry_attempt: u32,
+    ) -> Option<SelectedObfuscator> {
+        let bridge_constraints = InternalBridgeConstraints {
+            location: Constraint::Any,
+            providers: Constraint::Any,
+            ownership: Constraint::Any,
+            transport_protocol: Constraint::Only(TransportProtocol::Udp),
+        };
+        let bridge = self.get_proximate_bridge(&bridge_constraints, relay.location.as_ref())?;
+
+        let parsed_relays = self.parsed_relays.lock();
+        let shadowsocks_endpoints: Vec<_> = parsed_relays
+            .locations
+            .bridge
+            .shadowsocks
+            .iter()
+            .filter(|endpoint| endpoint.protocol == TransportProtocol::Udp)
+            .collect();
+        let shadowsocks_endpoint = if obfuscation_settings.port.is_only() {
+            shadowsocks_endpoints
+                .into_iter()
+                .find(|candidate| obfuscation_settings.port == Constraint::Only(candidate.port))
+        } else if shadowsocks_endpoints.is_empty() {
+            None
+        } else {
+            Some(shadowsocks_endpoints[retry_attempt as usize % shadowsocks_endpoints.len()])
+        }?;
+
+        log::info!(
+            "Selected Shadowsocks obfuscator {} at {}:{}",
+            bridge.hostname,
+            bridge.ipv4_addr_in,
+            shadowsocks_endpoint.port,
+        );
+        Some(SelectedObfuscator {
+            config: ObfuscatorConfig::Shadowsocks {
+                endpoint: SocketAddr::new(bridge.ipv4_addr_in.into(), shadowsocks_endpoint.port),
+                password: shadowsocks_endpoint.password.clone(),
+                cipher: shadowsocks_endpoint.cipher.clone(),
+                wireguard_endpoint: endpoint.peer.endpoint,
+            },
+            relay: Some(bridge),
+        })
+    }
+
     /// Returns preferred constraints
     #[allow(unused_variables)]
     fn preferred_tunnel_constraints(
@@ -1210,7 +1494,8 @@ pub struct NormalSelectedRelay {
 #[derive(Debug)]
 pub struct SelectedObfuscator {
     pub config: ObfuscatorConfig,
-    pub relay: Relay,
+    /// The relay that runs the obfuscation server. `None` for custom bridges.
+    pub relay: Option<Relay>,
 }
 
 impl NormalSelectedRelay {
@@ -1248,7 +1533,7 @@ mod test {
         },
     };
     use std::collections::HashSet;
-    use talpid_types::net::{wireguard::PublicKey, Endpoint};
+    use talpid_types::net::{openvpn, wireguard::PublicKey, Endpoint, ObfuscationType};
 
     lazy_static::lazy_static! {
         static ref RELAYS: RelayList = RelayList {
@@ -1275,6 +1560,7 @@ mod test {
                                     weight: 1,
                                     endpoint_data: RelayEndpointData::Wireguard(WireguardRelayEndpointData {
                                         public_key: PublicKey::from_base64("BLNHNoGO88LjV/wDBa7CUUwUzPq/fO2UwcGLy56hKy4=").unwrap(),
+                                        tls_server_name: None,
                                     }),
                                     location: None,
                                 },
@@ -1289,6 +1575,7 @@ mod test {
                                     weight: 1,
                                     endpoint_data: RelayEndpointData::Wireguard(WireguardRelayEndpointData {
                                         public_key: PublicKey::from_base64("BLNHNoGO88LjV/wDBa7CUUwUzPq/fO2UwcGLy56hKy4=").unwrap(),
+                                        tls_server_name: None,
                                     }),
                                     location: None,
                                 },
@@ -1407,6 +1694,7 @@ mod test {
                 bridge_state: BridgeState::Auto,
                 default_tunnel_type: default_tunnel_type(),
             })),
+            unreachable_custom_bridges: Arc::new(Mutex::new(HashSet::new())),
         }
     }
 
@@ -1984,6 +2272,126 @@ mod test {
             .is_some());
     }
 
+    #[test]
+    fn test_auto_obfuscation_order() {
+        let relay_selector = new_relay_selector();
+
+        let result = relay_selector.get_tunnel_endpoint(&WIREGUARD_SINGLEHOP_CONSTRAINTS, BridgeState::Off, 0, default_tunnel_type())
+            .expect("Failed to get relay when tunnel constraints are set to default WireGuard constraints");
+
+        relay_selector.config.lock().obfuscation_settings = ObfuscationSettings {
+            selected_obfuscation: SelectedObfuscation::Auto,
+            ..ObfuscationSettings::default()
+        };
+
+        let mut tls_relay = result.exit_relay.clone();
+        match &mut tls_relay.endpoint_data {
+            RelayEndpointData::Wireguard(data) => {
+                data.tls_server_name = Some("relay.example".to_owned())
+            }
+            data => panic!("Expected WireGuard relay, got {data:?}"),
+        }
+        let get_obfuscator = |relay, attempt| {
+            relay_selector
+                .get_obfuscator(relay, result.endpoint.unwrap_wireguard(), attempt)
+                .unwrap()
+                .map(|obfuscator| obfuscator.config)
+        };
+
+        let mut udp2tcp_ports = HashSet::new();
+        for round in 0..4 {
+            assert!(get_obfuscator(&tls_relay, round * 4).is_none());
+            assert!(get_obfuscator(&tls_relay, round * 4 + 1).is_none());
+            match get_obfuscator(&tls_relay, round * 4 + 2) {
+                Some(ObfuscatorConfig::Udp2Tcp { endpoint }) => {
+                    udp2tcp_ports.insert(endpoint.port());
+                }
+                config => panic!("Expected udp2tcp, got {config:?}"),
+            }
+            match get_obfuscator(&tls_relay, round * 4 + 3) {
+                Some(ObfuscatorConfig::Tls {
+                    endpoint,
+                    server_name,
+                }) => {
+                    assert_eq!(endpoint.port(), TLS_PORT);
+                    assert_eq!(server_name, "relay.example");
+                }
+                config => panic!("Expected TLS, got {config:?}"),
+            }
+            // TLS is only tried on relays that support it
+            assert!(matches!(
+                get_obfuscator(&result.exit_relay, round * 4 + 3),
+                Some(ObfuscatorConfig::Udp2Tcp { .. })
+            ));
+        }
+        // All udp2tcp ports are tried
+        assert_eq!(udp2tcp_ports.len(), UDP2TCP_PORTS.len());
+
+        relay_selector.config.lock().obfuscation_settings = ObfuscationSettings {
+            selected_obfuscation: SelectedObfuscation::Tls,
+            tls: TlsObfuscationSettings {
+                server_name: Some("configured.example".to_owned()),
+            },
+            ..ObfuscationSettings::default()
+        };
+        assert!(matches!(
+            get_obfuscator(&tls_relay, 0),
+            Some(ObfuscatorConfig::Tls { server_name, .. }) if server_name == "configured.example"
+        ));
+        assert!(matches!(
+            relay_selector.get_obfuscator(
+                &result.exit_relay,
+                result.endpoint.unwrap_wireguard(),
+                0
+            ),
+            Err(Error::NoObfuscator)
+        ));
+    }
+
+    #[test]
+    fn test_get_relay_preferring_connection_method() {
+        let relay_selector = new_relay_selector();
+        relay_selector.config.lock().obfuscation_settings = ObfuscationSettings {
+            selected_obfuscation: SelectedObfuscation::Auto,
+            ..ObfuscationSettings::default()
+        };
+
+        let method = ConnectionMethod {
+            tunnel_type: TunnelType::Wireguard,
+            port: 51820,
+            transport_protocol: TransportProtocol::Udp,
+            obfuscation: Some(ObfuscationMethod {
+                obfuscation_type: ObfuscationType::Udp2Tcp,
+                port: 5001,
+            }),
+        };
+
+        // The first attempt uses the remembered method
+        let (relay, _, obfuscator) = relay_selector.get_relay_preferring(0, &method).unwrap();
+        match relay {
+            SelectedRelay::Normal(relay) => {
+                assert!(matches!(relay.endpoint, MullvadEndpoint::Wireguard { .. }))
+            }
+            SelectedRelay::Custom(_) => unreachable!("not using custom relay"),
+        }
+        match obfuscator.map(|obfuscator| obfuscator.config) {
+            Some(ObfuscatorConfig::Udp2Tcp { endpoint }) => assert_eq!(endpoint.port(), 5001),
+            config => panic!("Expected udp2tcp, got {config:?}"),
+        }
+
+        // Later attempts are unaffected
+        let (.., obfuscator) = relay_selector.get_relay_preferring(1, &method).unwrap();
+        assert!(obfuscator.is_none());
+
+        // Explicit settings take precedence
+        relay_selector.config.lock().obfuscation_settings = ObfuscationSettings {
+            selected_obfuscation: SelectedObfuscation::Off,
+            ..ObfuscationSettings::default()
+        };
+        let (.., obfuscator) = relay_selector.get_relay_preferring(0, &method).unwrap();
+        assert!(obfuscator.is_none());
+    }
+
     #[test]
     fn test_selected_endpoints_use_correct_port_ranges() {
         let relay_selector = new_relay_selector();
@@ -2023,14 +2431,89 @@ mod test {
                 }
             ));
 
-            let SelectedObfuscator {
-                config: ObfuscatorConfig::Udp2Tcp { endpoint },
-                ..
-            } = obfs_config;
+            let endpoint = match obfs_config.config {
+                ObfuscatorConfig::Udp2Tcp { endpoint } => endpoint,
+                _ => unreachable!(),
+            };
             assert!(TCP2UDP_PORTS.contains(&endpoint.port()));
         }
     }
 
+    #[test]
+    fn test_selecting_wg_endpoint_with_shadowsocks_obfuscation() {
+        let relay_selector = new_relay_selector();
+
+        let result = relay_selector.get_tunnel_endpoint(&WIREGUARD_SINGLEHOP_CONSTRAINTS, BridgeState::Off, 0, default_tunnel_type())
+            .expect("Failed to get relay when tunnel constraints are set to default WireGuard constraints");
+
+        relay_selector.config.lock().obfuscation_settings = ObfuscationSettings {
+            selected_obfuscation: SelectedObfuscation::Shadowsocks,
+            ..ObfuscationSettings::default()
+        };
+
+        for attempt in 0..10 {
+            let obfs_config = relay_selector
+                .get_obfuscator(
+                    &result.exit_relay,
+                    result.endpoint.unwrap_wireguard(),
+                    attempt,
+                )
+                .unwrap()
+                .expect("Failed to get Shadowsocks endpoint");
+
+            assert_eq!(obfs_config.relay.unwrap().hostname, "se-got-br-001");
+            match obfs_config.config {
+                ObfuscatorConfig::Shadowsocks {
+                    endpoint,
+                    wireguard_endpoint,
+                    ..
+                } => {
+                    assert_eq!(endpoint.ip(), "1.3.3.7".parse::<IpAddr>().unwrap());
+                    assert_eq!(
+                        wireguard_endpoint,
+                        result.endpoint.unwrap_wireguard().peer.endpoint
+                    );
+                    // Only endpoints that relay UDP may be used
+                    assert!([1234, 1236].contains(&endpoint.port()));
+                }
+                config => panic!("Unexpected obfuscator: {config:?}"),
+            }
+        }
+
+        relay_selector.config.lock().obfuscation_settings = ObfuscationSettings {
+            selected_obfuscation: SelectedObfuscation::Shadowsocks,
+            shadowsocks: ShadowsocksObfuscationSettings {
+                port: Constraint::Only(1236),
+            },
+            ..ObfuscationSettings::default()
+        };
+        let obfs_config = relay_selector
+            .get_obfuscator(&result.exit_relay, result.endpoint.unwrap_wireguard(), 0)
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            obfs_config.config,
+            ObfuscatorConfig::Shadowsocks { endpoint, ref cipher, .. }
+                if endpoint.port() == 1236 && cipher == "aes-256-gcm"
+        ));
+
+        relay_selector.config.lock().obfuscation_settings = ObfuscationSettings {
+            selected_obfuscation: SelectedObfuscation::Shadowsocks,
+            shadowsocks: ShadowsocksObfuscationSettings {
+                port: Constraint::Only(443),
+            },
+            ..ObfuscationSettings::default()
+        };
+        assert!(matches!(
+            relay_selector.get_obfuscator(
+                &result.exit_relay,
+                result.endpoint.unwrap_wireguard(),
+                0
+            ),
+            Err(Error::NoObfuscator)
+        ));
+    }
+
     #[test]
     fn test_ownership() {
         let relay_selector = new_relay_selector();
@@ -2169,6 +2652,194 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_forced_bridge_uses_custom_http_proxy() {
+        let relay_selector = new_relay_selector();
+
+        // A Mullvad bridge is used even if a custom SOCKS5 proxy is set
+        let mut bridges = vec![custom_bridge("socks", "192.0.2.1:1080")];
+        relay_selector.config.lock().bridge_settings = BridgeSettings::Custom(bridges.clone());
+        assert!(matches!(
+            relay_selector.get_bridge_forced(),
+            Some(ProxySettings::Shadowsocks(_))
+        ));
+
+        let http_proxy = ProxySettings::Http(openvpn::HttpProxySettings {
+            address: "192.0.2.1:3128".parse().unwrap(),
+            auth: None,
+        });
+        bridges.push(CustomBridge {
+            name: "http".to_owned(),
+            enabled: true,
+            proxy: http_proxy.clone(),
+        });
+        relay_selector.config.lock().bridge_settings = BridgeSettings::Custom(bridges);
+        assert_eq!(relay_selector.get_bridge_forced(), Some(http_proxy));
+    }
+
+    fn custom_bridge(name: &str, address: &str) -> CustomBridge {
+        CustomBridge {
+            name: name.to_owned(),
+            enabled: true,
+            proxy: ProxySettings::Remote(openvpn::RemoteProxySettings {
+                address: address.parse().unwrap(),
+                auth: None,
+            }),
+        }
+    }
+
+    fn custom_bridge_for(
+        relay_selector: &RelaySelector,
+        retry_attempt: u32,
+    ) -> Result<Option<SelectedBridge>, Error> {
+        let config = relay_selector.config.lock().clone();
+        let location = Location {
+            country: "Sweden".to_owned(),
+            country_code: "se".to_owned(),
+            city: "Gothenburg".to_owned(),
+            city_code: "got".to_owned(),
+            latitude: 57.70887,
+            longitude: 11.97456,
+        };
+        relay_selector.get_bridge_for(&config, &location, retry_attempt)
+    }
+
+    fn selected_custom_bridge(relay_selector: &RelaySelector, retry_attempt: u32) -> SocketAddr {
+        match custom_bridge_for(relay_selector, retry_attempt) {
+            Ok(Some(SelectedBridge::Custom(proxy))) => proxy.get_endpoint().endpoint.address,
+            bridge => panic!("Expected custom bridge, got {bridge:?}"),
+        }
+    }
+
+    #[test]
+    fn test_custom_bridge_rotation() {
+        let relay_selector = new_relay_selector();
+        let mut bridges = vec![
+            custom_bridge("first", "192.0.2.1:1080"),
+            custom_bridge("second", "192.0.2.2:1080"),
+            custom_bridge("third", "192.0.2.3:1080"),
+        ];
+        bridges[1].enabled = false;
+        {
+            let mut config = relay_selector.config.lock();
+            config.bridge_settings = BridgeSettings::Custom(bridges);
+            config.bridge_state = BridgeState::On;
+        }
+
+        // Disabled bridges are skipped
+        let first: SocketAddr = "192.0.2.1:1080".parse().unwrap();
+        let third: SocketAddr = "192.0.2.3:1080".parse().unwrap();
+        assert_eq!(selected_custom_bridge(&relay_selector, 0), first);
+        assert_eq!(selected_custom_bridge(&relay_selector, 1), third);
+        assert_eq!(selected_custom_bridge(&relay_selector, 2), first);
+
+        // Automatic bridge mode rotates among the attempts that use a bridge
+        relay_selector.config.lock().bridge_state = BridgeState::Auto;
+        assert_eq!(selected_custom_bridge(&relay_selector, 4), first);
+        assert_eq!(selected_custom_bridge(&relay_selector, 5), third);
+        assert_eq!(selected_custom_bridge(&relay_selector, 8), first);
+    }
+
+    #[test]
+    fn test_unreachable_custom_bridges_are_skipped() {
+        let relay_selector = new_relay_selector();
+        {
+            let mut config = relay_selector.config.lock();
+            config.bridge_settings = BridgeSettings::Custom(vec![
+                custom_bridge("first", "192.0.2.1:1080"),
+                custom_bridge("second", "192.0.2.2:1080"),
+            ]);
+            config.bridge_state = BridgeState::On;
+        }
+        let second: SocketAddr = "192.0.2.2:1080".parse().unwrap();
+
+        relay_selector.set_custom_bridge_reachable("first", false);
+        for attempt in 0..4 {
+            assert_eq!(selected_custom_bridge(&relay_selector, attempt), second);
+        }
+
+        // If no bridge is reachable, all of them are tried
+        relay_selector.set_custom_bridge_reachable("second", false);
+        assert_ne!(selected_custom_bridge(&relay_selector, 0), second);
+
+        relay_selector.set_custom_bridge_reachable("first", true);
+        relay_selector.set_custom_bridge_reachable("second", true);
+        assert_ne!(
+            selected_custom_bridge(&relay_selector, 0),
+            selected_custom_bridge(&relay_selector, 1)
+        );
+    }
+
+    #[test]
+    fn test_no_enabled_custom_bridge() {
+        let relay_selector = new_relay_selector();
+        let mut bridge = custom_bridge("first", "192.0.2.1:1080");
+        bridge.enabled = false;
+        {
+            let mut config = relay_selector.config.lock();
+            config.bridge_settings = BridgeSettings::Custom(vec![bridge]);
+            config.bridge_state = BridgeState::On;
+        }
+        assert!(matches!(
+            custom_bridge_for(&relay_selector, 0),
+            Err(Error::NoBridge)
+        ));
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn test_custom_socks5_bridge_for_wireguard() {
+        let relay_selector = new_relay_selector();
+        let http_proxy = CustomBridge {
+            name: "http".to_owned(),
+            enabled: true,
+            proxy: ProxySettings::Http(openvpn::HttpProxySettings {
+                address: "192.0.2.2:3128".parse().unwrap(),
+                auth: None,
+            }),
+        };
+        {
+            let mut config = relay_selector.config.lock();
+            config.relay_settings =
+                config
+                    .relay_settings
+                    .merge(RelaySettingsUpdate::Normal(RelayConstraintsUpdate {
+                        tunnel_protocol: Some(Constraint::Only(TunnelType::Wireguard)),
+                        ..Default::default()
+                    }));
+            config.bridge_settings = BridgeSettings::Custom(vec![
+                http_proxy.clone(),
+                custom_bridge("socks", "192.0.2.1:1080"),
+            ]);
+            config.bridge_state = BridgeState::On;
+        }
+
+        // HTTP proxies cannot carry WireGuard, so the SOCKS5 proxy is always used
+        for attempt in 0..4 {
+            let (relay, bridge, obfuscator) = relay_selector.get_relay(attempt).unwrap();
+            let wireguard_endpoint = match relay {
+                SelectedRelay::Normal(relay) => relay.endpoint.unwrap_wireguard().peer.endpoint,
+                SelectedRelay::Custom(_) => unreachable!("not using custom relay"),
+            };
+            assert!(bridge.is_none());
+            let obfuscator = obfuscator.expect("Expected SOCKS5 obfuscator");
+            assert!(obfuscator.relay.is_none());
+            assert_eq!(
+                obfuscator.config,
+                ObfuscatorConfig::Socks5 {
+                    endpoint: "192.0.2.1:1080".parse().unwrap(),
+                    auth: None,
+                    wireguard_endpoint,
+                }
+            );
+        }
+
+        // Without a SOCKS5 proxy, the obfuscation settings are used as usual
+        relay_selector.config.lock().bridge_settings = BridgeSettings::Custom(vec![http_proxy]);
+        let (_relay, _bridge, obfuscator) = relay_selector.get_relay(0).unwrap();
+        assert!(obfuscator.is_none());
+    }
+
     /// Ensure that `include_in_country` is ignored if all relays have it set to false (i.e., some
     /// relay is returned). Also ensure that `include_in_country` is respected if some relays
     /// have it set to true (i.e., that relay is never returned)
@@ -2200,6 +2871,7 @@ mod test {
                                         "BLNHNoGO88LjV/wDBa7CUUwUzPq/fO2UwcGLy56hKy4=",
                                     )
                                     .unwrap(),
+                                    tls_server_name: None,
                                 },
                             ),
                             location: None,
@@ -2219,6 +2891,7 @@ mod test {
                                         "BLNHNoGO88LjV/wDBa7CUUwUzPq/fO2UwcGLy56hKy4=",
                                     )
                                     .unwrap(),
+                                    tls_server_name: None,
                                 },
                             ),
                             location: None,
@@ -147,7 +147,7 @@ async fn reset_firewall() -> Result<(), Error> {
 
     Firewall::new(
         #[cfg(target_os = "linux")]
-        mullvad_types::TUNNEL_FWMARK,
+        mullvad_daemon::default_linux_networking_ids(),
     )
     .map_err(Error::FirewallError)?
     .reset_policy()
@@ -5,17 +5,21 @@ pub mod auth_failed;
 pub mod device;
 pub mod endpoint;
 pub mod location;
+pub mod ovpn;
 pub mod relay_constraints;
 pub mod relay_list;
 pub mod settings;
 pub mod states;
+pub mod traffic_usage;
 pub mod version;
+pub mod wg_quick;
 pub mod wireguard;
 
 mod custom_tunnel;
 pub use crate::custom_tunnel::*;
 
 // b"mole" is [ 0x6d, 0x6f 0x6c, 0x65 ]
+// These are the defaults. They can be overridden using `mullvad-daemon --routing-table/--fwmark`.
 #[cfg(target_os = "linux")]
 pub const TUNNEL_TABLE_ID: u32 = 0x6d6f6c65;
 #[cfg(target_os = "linux")]
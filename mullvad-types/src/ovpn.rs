@@ -0,0 +1,446 @@
+//! Parser for OpenVPN client profiles, usually distributed as `.ovpn` files.
+//!
+//! Only the options that describe the server are read: where to connect, how to authenticate
+//! it, and how the client authenticates itself. Options that change routing, DNS or run
+//! scripts are ignored, since those are managed by the daemon.
+
+use crate::{ConnectionConfig, CustomTunnelEndpoint};
+use std::{
+    fs, io,
+    net::{IpAddr, Ipv4Addr},
+    path::Path,
+    str::FromStr,
+};
+use talpid_types::net::{openvpn, Endpoint, TransportProtocol};
+
+const DEFAULT_PORT: u16 = 1194;
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Failed to read OpenVPN profile")]
+    Read(#[error(source)] io::Error),
+
+    #[error(display = "Line {}: Missing closing tag for <{}>", _0, _1)]
+    UnterminatedBlock(usize, String),
+
+    #[error(display = "Line {}: Invalid value for {}", _0, _1)]
+    InvalidValue(usize, &'static str),
+
+    #[error(display = "Line {}: Missing closing quote", _0)]
+    UnterminatedQuote(usize),
+
+    #[error(
+        display = "Line {}: {} must be included inline in the profile, such as <{}>...</{}>",
+        _0,
+        _1,
+        _1,
+        _1
+    )]
+    NotInline(usize, &'static str),
+
+    #[error(display = "Line {}: {} is not supported", _0, _1)]
+    Unsupported(usize, &'static str),
+
+    #[error(display = "Missing remote")]
+    MissingRemote,
+
+    #[error(display = "Missing CA certificate")]
+    MissingCa,
+
+    #[error(display = "A client certificate and key must be given together")]
+    IncompleteCertificate,
+
+    #[error(display = "The profile does not authenticate the client")]
+    MissingClientAuth,
+}
+
+/// A custom OpenVPN server read from an OpenVPN profile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OvpnConfig {
+    /// Hostname or IP address of the server.
+    pub host: String,
+    /// Connection config. The IP of the endpoint is unspecified until `host` is resolved, and
+    /// the username and password are empty.
+    pub connection: openvpn::ConnectionConfig,
+}
+
+impl OvpnConfig {
+    /// Reads and parses an OpenVPN profile.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        fs::read_to_string(path).map_err(Error::Read)?.parse()
+    }
+
+    /// Whether the server expects a username and password.
+    pub fn requires_credentials(&self) -> bool {
+        self.connection
+            .profile
+            .as_ref()
+            .map(|profile| profile.auth_user_pass)
+            .unwrap_or(true)
+    }
+
+    /// Returns a custom relay that connects to the server of the profile.
+    pub fn custom_tunnel_endpoint(&self) -> CustomTunnelEndpoint {
+        CustomTunnelEndpoint::new(
+            self.host.clone(),
+            ConnectionConfig::OpenVpn(self.connection.clone()),
+        )
+    }
+}
+
+impl FromStr for OvpnConfig {
+    type Err = Error;
+
+    fn from_str(config: &str) -> Result<Self, Self::Err> {
+        let mut remote: Option<(String, Option<u16>, Option<TransportProtocol>)> = None;
+        let mut port = None;
+        let mut protocol = None;
+
+        let mut ca = None;
+        let mut cert = None;
+        let mut key = None;
+        let mut tls_auth = None;
+        let mut key_direction = None;
+        let mut cipher = None;
+        let mut auth = None;
+        let mut verify_x509_name = None;
+        let mut auth_user_pass = false;
+
+        let mut lines = config.lines().enumerate();
+        while let Some((index, line)) = lines.next() {
+            let line_nr = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(tag) = line.strip_prefix('<').and_then(|l| l.strip_suffix('>')) {
+                let closing_tag = format!("</{tag}>");
+                let mut block = String::new();
+                loop {
+                    let (_, line) = lines
+                        .next()
+                        .ok_or_else(|| Error::UnterminatedBlock(line_nr, tag.to_owned()))?;
+                    if line.trim() == closing_tag {
+                        break;
+                    }
+                    block.push_str(line.trim());
+                    block.push('\n');
+                }
+                match tag {
+                    "ca" => ca = Some(block),
+                    "cert" => cert = Some(block),
+                    "key" => key = Some(block),
+                    "tls-auth" => tls_auth = Some(block),
+                    "tls-crypt" | "tls-crypt-v2" => {
+                        return Err(Error::Unsupported(line_nr, "tls-crypt"))
+                    }
+                    "pkcs12" => return Err(Error::Unsupported(line_nr, "pkcs12")),
+                    _ => log::debug!("Ignoring unsupported OpenVPN block <{}>", tag),
+                }
+                continue;
+            }
+
+            let mut words = split_args(line)
+                .ok_or(Error::UnterminatedQuote(line_nr))?
+                .into_iter();
+            let directive = words.next().unwrap_or_default();
+            let args: Vec<String> = words.collect();
+
+            match directive.as_str() {
+                "remote" => {
+                    // Only the first server is used
+                    if remote.is_some() {
+                        log::debug!("Ignoring additional OpenVPN remote on line {}", line_nr);
+                        continue;
+                    }
+                    let host = args.first().ok_or(Error::InvalidValue(line_nr, "remote"))?;
+                    let port = args
+                        .get(1)
+                        .map(|port| port.parse())
+                        .transpose()
+                        .map_err(|_| Error::InvalidValue(line_nr, "remote"))?;
+                    let protocol = match args.get(2) {
+                        Some(protocol) => Some(
+                            parse_protocol(protocol)
+                                .ok_or(Error::InvalidValue(line_nr, "remote"))?,
+                        ),
+                        None => None,
+                    };
+                    remote = Some((host.to_owned(), port, protocol));
+                }
+                "port" | "rport" => {
+                    port = Some(
+                        args.first()
+                            .and_then(|port| port.parse().ok())
+                            .ok_or(Error::InvalidValue(line_nr, "port"))?,
+                    );
+                }
+                "proto" => {
+                    protocol = Some(
+                        args.first()
+                            .and_then(|protocol| parse_protocol(protocol))
+                            .ok_or(Error::InvalidValue(line_nr, "proto"))?,
+                    );
+                }
+                "ca" => check_inline(line_nr, "ca", &args)?,
+                "cert" => check_inline(line_nr, "cert", &args)?,
+                "key" => check_inline(line_nr, "key", &args)?,
+                "tls-auth" => {
+                    check_inline(line_nr, "tls-auth", &args)?;
+                    if let Some(direction) = args.get(1) {
+                        key_direction = Some(parse_key_direction(line_nr, direction)?);
+                    }
+                }
+                "key-direction" => {
+                    let direction = args
+                        .first()
+                        .ok_or(Error::InvalidValue(line_nr, "key-direction"))?;
+                    key_direction = Some(parse_key_direction(line_nr, direction)?);
+                }
+                "tls-crypt" | "tls-crypt-v2" => {
+                    return Err(Error::Unsupported(line_nr, "tls-crypt"))
+                }
+                "pkcs12" => return Err(Error::Unsupported(line_nr, "pkcs12")),
+                "cipher" => {
+                    cipher = Some(
+                        args.first()
+                            .ok_or(Error::InvalidValue(line_nr, "cipher"))?
+                            .to_owned(),
+                    );
+                }
+                "auth" => {
+                    auth = Some(
+                        args.first()
+                            .ok_or(Error::InvalidValue(line_nr, "auth"))?
+                            .to_owned(),
+                    );
+                }
+                "verify-x509-name" => {
+                    let name = args
+                        .first()
+                        .ok_or(Error::InvalidValue(line_nr, "verify-x509-name"))?;
+                    let name_type = match args.get(1).map(String::as_str) {
+                        Some(name_type @ ("subject" | "name" | "name-prefix")) => {
+                            Some(name_type.to_owned())
+                        }
+                        Some(_) => return Err(Error::InvalidValue(line_nr, "verify-x509-name")),
+                        None => None,
+                    };
+                    verify_x509_name = Some(openvpn::VerifyX509Name {
+                        name: name.to_owned(),
+                        name_type,
+                    });
+                }
+                "auth-user-pass" => {
+                    // A file with credentials may be given, but they are read from the settings
+                    // instead
+                    auth_user_pass = true;
+                }
+                _ => {
+                    log::debug!("Ignoring unsupported OpenVPN option \"{}\"", directive);
+                }
+            }
+        }
+
+        let (host, remote_port, remote_protocol) = remote.ok_or(Error::MissingRemote)?;
+        let ca = ca.ok_or(Error::MissingCa)?;
+        if cert.is_some() != key.is_some() {
+            return Err(Error::IncompleteCertificate);
+        }
+        if cert.is_none() && !auth_user_pass {
+            return Err(Error::MissingClientAuth);
+        }
+
+        let endpoint = Endpoint::new(
+            // The host is resolved when connecting
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            remote_port.or(port).unwrap_or(DEFAULT_PORT),
+            remote_protocol
+                .or(protocol)
+                .unwrap_or(TransportProtocol::Udp),
+        );
+        let mut connection = openvpn::ConnectionConfig::new(endpoint, String::new(), String::new());
+        connection.profile = Some(openvpn::ProfileConfig {
+            ca,
+            cert,
+            key,
+            tls_auth,
+            key_direction,
+            cipher,
+            auth,
+            verify_x509_name,
+            auth_user_pass,
+        });
+
+        Ok(OvpnConfig { host, connection })
+    }
+}
+
+/// Splits a line into its directive and arguments. Arguments may be enclosed in single or double
+/// quotes to include whitespace. Returns `None` if a quote is not closed.
+fn split_args(line: &str) -> Option<Vec<String>> {
+    let mut args = vec![];
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let first = match chars.next() {
+            Some(first) => first,
+            None => return Some(args),
+        };
+        let mut arg = String::new();
+        if first == '"' || first == '\'' {
+            loop {
+                match chars.next()? {
+                    c if c == first => break,
+                    c => arg.push(c),
+                }
+            }
+        } else {
+            arg.push(first);
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                arg.push(c);
+            }
+        }
+        args.push(arg);
+    }
+}
+
+/// Parses the protocol of `proto` or `remote`. The client variants of TCP, and variants that only
+/// allow one IP version, are treated like plain TCP or UDP.
+fn parse_protocol(protocol: &str) -> Option<TransportProtocol> {
+    match protocol {
+        "udp" | "udp4" | "udp6" => Some(TransportProtocol::Udp),
+        "tcp" | "tcp4" | "tcp6" | "tcp-client" | "tcp4-client" | "tcp6-client" => {
+            Some(TransportProtocol::Tcp)
+        }
+        _ => None,
+    }
+}
+
+fn parse_key_direction(line_nr: usize, direction: &str) -> Result<u8, Error> {
+    match direction {
+        "0" => Ok(0),
+        "1" => Ok(1),
+        _ => Err(Error::InvalidValue(line_nr, "key-direction")),
+    }
+}
+
+/// Files referenced by a profile are not read, since the profile may be moved or deleted after
+/// being imported. Only the `[inline]` placeholder is accepted.
+fn check_inline(line_nr: usize, directive: &'static str, args: &[String]) -> Result<(), Error> {
+    match args.first() {
+        Some(arg) if arg == "[inline]" => Ok(()),
+        _ => Err(Error::NotInline(line_nr, directive)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CA: &str =
+        "-----BEGIN CERTIFICATE-----\nMIIBszCCAVmgAwIBAgIUCA==\n-----END CERTIFICATE-----\n";
+    const TLS_AUTH: &str = "-----BEGIN OpenVPN Static key V1-----\n6acef03f62675b4b1bbd03e53b187727\n-----END OpenVPN Static key V1-----\n";
+
+    #[test]
+    fn test_parse_profile() {
+        let config: OvpnConfig = format!(
+            "
+            # Generated profile
+            client
+            dev tun
+            proto tcp-client
+            remote vpn.example.com 443
+            remote backup.example.com 1194 udp
+            cipher AES-256-CBC
+            auth-user-pass
+            dhcp-option DNS 10.8.0.1
+            key-direction 1
+            <ca>
+            {CA}</ca>
+            <tls-auth>
+            {TLS_AUTH}</tls-auth>
+            "
+        )
+        .parse()
+        .unwrap();
+
+        assert_eq!(config.host, "vpn.example.com");
+        assert!(config.requires_credentials());
+        let connection = config.connection;
+        assert_eq!(connection.endpoint.address.port(), 443);
+        assert_eq!(connection.endpoint.protocol, TransportProtocol::Tcp);
+
+        let profile = connection.profile.unwrap();
+        assert_eq!(profile.ca, CA);
+        assert_eq!(profile.tls_auth.as_deref(), Some(TLS_AUTH));
+        assert_eq!(profile.key_direction, Some(1));
+        assert_eq!(profile.cipher.as_deref(), Some("AES-256-CBC"));
+        assert_eq!(profile.cert, None);
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config: OvpnConfig = format!("remote 192.0.2.1\nauth-user-pass\n<ca>\n{CA}</ca>\n")
+            .parse()
+            .unwrap();
+        assert_eq!(config.host, "192.0.2.1");
+        assert_eq!(config.connection.endpoint.address.port(), DEFAULT_PORT);
+        assert_eq!(config.connection.endpoint.protocol, TransportProtocol::Udp);
+    }
+
+    #[test]
+    fn test_client_certificate() {
+        let config: OvpnConfig =
+            format!("remote 192.0.2.1\n<ca>\n{CA}</ca>\n<cert>\n{CA}</cert>\n<key>\nkey\n</key>\n")
+                .parse()
+                .unwrap();
+        assert!(!config.requires_credentials());
+
+        let result: Result<OvpnConfig, _> =
+            format!("remote 192.0.2.1\n<ca>\n{CA}</ca>\n<cert>\n{CA}</cert>\n").parse();
+        assert!(matches!(result, Err(Error::IncompleteCertificate)));
+    }
+
+    #[test]
+    fn test_file_references_are_rejected() {
+        let result: Result<OvpnConfig, _> = "remote 192.0.2.1\nauth-user-pass\nca ca.crt\n".parse();
+        assert!(matches!(result, Err(Error::NotInline(3, "ca"))));
+    }
+
+    #[test]
+    fn test_unterminated_block() {
+        let result: Result<OvpnConfig, _> = format!("remote 192.0.2.1\n<ca>\n{CA}").parse();
+        assert!(matches!(result, Err(Error::UnterminatedBlock(2, _))));
+    }
+
+    #[test]
+    fn test_server_verification() {
+        let config: OvpnConfig = format!(
+            "remote 192.0.2.1\nauth-user-pass\nauth SHA512\n\
+            verify-x509-name 'C=SE, CN=server' subject\n<ca>\n{CA}</ca>\n"
+        )
+        .parse()
+        .unwrap();
+        let profile = config.connection.profile.unwrap();
+        assert_eq!(profile.auth.as_deref(), Some("SHA512"));
+        assert_eq!(
+            profile.verify_x509_name,
+            Some(openvpn::VerifyX509Name {
+                name: "C=SE, CN=server".to_owned(),
+                name_type: Some("subject".to_owned()),
+            })
+        );
+
+        let result: Result<OvpnConfig, _> =
+            "remote 192.0.2.1\nverify-x509-name server issuer\n".parse();
+        assert!(matches!(
+            result,
+            Err(Error::InvalidValue(2, "verify-x509-name"))
+        ));
+
+        let result: Result<OvpnConfig, _> = "remote 192.0.2.1\nverify-x509-name \"server\n".parse();
+        assert!(matches!(result, Err(Error::UnterminatedQuote(2))));
+    }
+}
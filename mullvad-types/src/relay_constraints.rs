@@ -496,14 +496,25 @@ impl fmt::Display for WireguardConstraints {
     }
 }
 
-/// Specifies a specific endpoint or [`BridgeConstraints`] to use when `mullvad-daemon` selects a
+/// Specifies custom bridges or [`BridgeConstraints`] to use when `mullvad-daemon` selects a
 /// bridge server.
 #[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum BridgeSettings {
     /// Let the relay selection algorithm decide on bridges, based on the relay list.
     Normal(BridgeConstraints),
-    Custom(ProxySettings),
+    /// Rotate among the enabled custom bridges.
+    Custom(Vec<CustomBridge>),
+}
+
+/// A user-provided proxy server that is used as a bridge.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub struct CustomBridge {
+    /// Unique name of the bridge.
+    pub name: String,
+    /// Disabled bridges are kept in the settings, but never selected.
+    pub enabled: bool,
+    pub proxy: ProxySettings,
 }
 
 #[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
@@ -513,6 +524,8 @@ pub enum SelectedObfuscation {
     #[default]
     Off,
     Udp2Tcp,
+    Shadowsocks,
+    Tls,
 }
 
 impl fmt::Display for SelectedObfuscation {
@@ -521,6 +534,8 @@ impl fmt::Display for SelectedObfuscation {
             SelectedObfuscation::Auto => "auto".fmt(f),
             SelectedObfuscation::Off => "off".fmt(f),
             SelectedObfuscation::Udp2Tcp => "udp2tcp".fmt(f),
+            SelectedObfuscation::Shadowsocks => "shadowsocks".fmt(f),
+            SelectedObfuscation::Tls => "tls".fmt(f),
         }
     }
 }
@@ -540,6 +555,40 @@ impl fmt::Display for Udp2TcpObfuscationSettings {
     }
 }
 
+/// Settings for Shadowsocks obfuscation. The servers are the same ones that are used as bridges.
+#[derive(Default, Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ShadowsocksObfuscationSettings {
+    pub port: Constraint<u16>,
+}
+
+impl fmt::Display for ShadowsocksObfuscationSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.port {
+            Constraint::Any => write!(f, "any port"),
+            Constraint::Only(port) => write!(f, "port {port}"),
+        }
+    }
+}
+
+/// Settings for TLS obfuscation.
+#[derive(Default, Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct TlsObfuscationSettings {
+    /// Server name to send in the TLS handshake. The server name advertised by the relay is used
+    /// if this is not set.
+    pub server_name: Option<String>,
+}
+
+impl fmt::Display for TlsObfuscationSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.server_name {
+            None => write!(f, "server name of the relay"),
+            Some(ref server_name) => write!(f, "server name {server_name}"),
+        }
+    }
+}
+
 /// Contains obfuscation settings
 #[derive(Default, Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
@@ -547,6 +596,8 @@ impl fmt::Display for Udp2TcpObfuscationSettings {
 pub struct ObfuscationSettings {
     pub selected_obfuscation: SelectedObfuscation,
     pub udp2tcp: Udp2TcpObfuscationSettings,
+    pub shadowsocks: ShadowsocksObfuscationSettings,
+    pub tls: TlsObfuscationSettings,
 }
 
 /// Limits the set of bridge servers to use in `mullvad-daemon`.
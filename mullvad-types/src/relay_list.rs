@@ -147,6 +147,10 @@ impl Default for WireguardEndpointData {
 pub struct WireguardRelayEndpointData {
     /// Public key used by the relay peer
     pub public_key: wireguard::PublicKey,
+    /// Server name of the TLS obfuscation server on the relay. Not set for relays that do not
+    /// run one.
+    #[serde(default)]
+    pub tls_server_name: Option<String>,
 }
 
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
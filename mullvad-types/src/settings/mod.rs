@@ -13,6 +13,8 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 #[cfg(target_os = "windows")]
 use std::{collections::HashSet, path::PathBuf};
 use talpid_types::net::{openvpn, GenericTunnelOptions};
+#[cfg(target_os = "linux")]
+use talpid_types::net::{TrustedOverlays, UserRoute};
 
 mod dns;
 
@@ -20,7 +22,7 @@ mod dns;
 /// latest version that exists in `SettingsVersion`.
 /// This should be bumped when a new version is introduced along with a migration
 /// being added to `mullvad-daemon`.
-pub const CURRENT_SETTINGS_VERSION: SettingsVersion = SettingsVersion::V6;
+pub const CURRENT_SETTINGS_VERSION: SettingsVersion = SettingsVersion::V7;
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Clone, Copy)]
 #[repr(u32)]
@@ -30,6 +32,7 @@ pub enum SettingsVersion {
     V4 = 4,
     V5 = 5,
     V6 = 6,
+    V7 = 7,
 }
 
 impl<'de> Deserialize<'de> for SettingsVersion {
@@ -43,6 +46,7 @@ impl<'de> Deserialize<'de> for SettingsVersion {
             v if v == SettingsVersion::V4 as u32 => Ok(SettingsVersion::V4),
             v if v == SettingsVersion::V5 as u32 => Ok(SettingsVersion::V5),
             v if v == SettingsVersion::V6 as u32 => Ok(SettingsVersion::V6),
+            v if v == SettingsVersion::V7 as u32 => Ok(SettingsVersion::V7),
             v => Err(serde::de::Error::custom(format!(
                 "{v} is not a valid SettingsVersion"
             ))),
@@ -86,8 +90,15 @@ pub struct Settings {
     /// Whether to notify users of beta updates.
     pub show_beta_releases: bool,
     /// Split tunneling settings
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
     pub split_tunnel: SplitTunnelSettings,
+    /// Interfaces and networks of other VPNs or overlay networks that should be allowed in every
+    /// tunnel state and routed outside the tunnel.
+    #[cfg(target_os = "linux")]
+    pub trusted_overlays: TrustedOverlays,
+    /// Networks that should always be routed through the tunnel or via the default gateway.
+    #[cfg(target_os = "linux")]
+    pub user_routes: Vec<UserRoute>,
     /// Temporary variable for a random number between 0 and 1 that determines if the user should
     /// use wireguard or openvpn when the automatic feature is set. This variable will be removed
     /// in future versions.
@@ -114,6 +125,14 @@ pub struct SplitTunnelSettings {
     pub apps: HashSet<PathBuf>,
 }
 
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct SplitTunnelSettings {
+    /// Whether to create a network namespace that `mullvad-exclude` can launch processes in, as
+    /// an alternative to the net_cls cgroup.
+    pub enable_network_namespace: bool,
+}
+
 impl Default for Settings {
     fn default() -> Self {
         Settings {
@@ -137,8 +156,12 @@ impl Default for Settings {
             tunnel_options: TunnelOptions::default(),
             show_beta_releases: false,
             wg_migration_rand_num: rand::thread_rng().gen_range(0.0..=1.0),
-            #[cfg(windows)]
+            #[cfg(any(windows, target_os = "linux"))]
             split_tunnel: SplitTunnelSettings::default(),
+            #[cfg(target_os = "linux")]
+            trusted_overlays: TrustedOverlays::default(),
+            #[cfg(target_os = "linux")]
+            user_routes: vec![],
             settings_version: CURRENT_SETTINGS_VERSION,
         }
     }
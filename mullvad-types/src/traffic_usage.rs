@@ -0,0 +1,124 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+/// Number of bytes transferred through the tunnel to a single relay during a single day (UTC).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TrafficUsageRecord {
+    pub date: NaiveDate,
+    pub relay: String,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
+
+/// Selects a subset of the recorded traffic usage. Unset fields match everything.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TrafficUsageFilter {
+    /// First day to include.
+    pub since: Option<NaiveDate>,
+    /// Last day to include.
+    pub until: Option<NaiveDate>,
+    pub relay: Option<String>,
+}
+
+impl TrafficUsageFilter {
+    pub fn matches(&self, record: &TrafficUsageRecord) -> bool {
+        self.since.map(|since| record.date >= since).unwrap_or(true)
+            && self.until.map(|until| record.date <= until).unwrap_or(true)
+            && self
+                .relay
+                .as_ref()
+                .map(|relay| relay.eq_ignore_ascii_case(&record.relay))
+                .unwrap_or(true)
+    }
+}
+
+/// Traffic usage accumulated per day and relay.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct TrafficUsage {
+    records: Vec<TrafficUsageRecord>,
+}
+
+impl TrafficUsage {
+    /// Adds the given number of bytes to the record for `date` and `relay`, creating it if
+    /// necessary.
+    pub fn add(&mut self, date: NaiveDate, relay: &str, rx_bytes: u64, tx_bytes: u64) {
+        match self
+            .records
+            .iter_mut()
+            .find(|record| record.date == date && record.relay == relay)
+        {
+            Some(record) => {
+                record.rx_bytes = record.rx_bytes.saturating_add(rx_bytes);
+                record.tx_bytes = record.tx_bytes.saturating_add(tx_bytes);
+            }
+            None => self.records.push(TrafficUsageRecord {
+                date,
+                relay: relay.to_owned(),
+                rx_bytes,
+                tx_bytes,
+            }),
+        }
+    }
+
+    /// Returns all records matching `filter`, ordered by date and relay.
+    pub fn query(&self, filter: &TrafficUsageFilter) -> Vec<TrafficUsageRecord> {
+        let mut records: Vec<_> = self
+            .records
+            .iter()
+            .filter(|record| filter.matches(record))
+            .cloned()
+            .collect();
+        records.sort_by(|a, b| a.date.cmp(&b.date).then_with(|| a.relay.cmp(&b.relay)));
+        records
+    }
+
+    /// Removes all records older than `oldest`.
+    pub fn prune(&mut self, oldest: NaiveDate) {
+        self.records.retain(|record| record.date >= oldest);
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn day(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2022, 5, day).unwrap()
+    }
+
+    #[test]
+    fn test_accumulate_and_query() {
+        let mut usage = TrafficUsage::default();
+        usage.add(day(2), "se-got-wg-001", 10, 1);
+        usage.add(day(1), "se-got-wg-001", 5, 5);
+        usage.add(day(2), "se-got-wg-001", 20, 2);
+        usage.add(day(2), "de-ber-wg-002", 1, 1);
+
+        let all = usage.query(&TrafficUsageFilter::default());
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].date, day(1));
+        assert_eq!(all[1].relay, "de-ber-wg-002");
+        assert_eq!((all[2].rx_bytes, all[2].tx_bytes), (30, 3));
+
+        let filter = TrafficUsageFilter {
+            since: Some(day(2)),
+            until: None,
+            relay: Some("SE-GOT-WG-001".to_owned()),
+        };
+        let filtered = usage.query(&filter);
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].rx_bytes, 30);
+
+        usage.prune(day(2));
+        assert_eq!(usage.query(&TrafficUsageFilter::default()).len(), 2);
+    }
+}
@@ -0,0 +1,463 @@
+//! Parser for WireGuard configuration files in the format used by `wg-quick`.
+
+use crate::{
+    settings::{CustomDnsOptions, DnsOptions, DnsState},
+    ConnectionConfig, CustomTunnelEndpoint,
+};
+use ipnetwork::IpNetwork;
+use std::{
+    fmt, fs, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+    str::FromStr,
+};
+use talpid_types::net::wireguard;
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Failed to read configuration file")]
+    Read(#[error(source)] io::Error),
+
+    #[error(display = "Line {}: Expected a section header or a key-value pair", _0)]
+    InvalidLine(usize),
+
+    #[error(display = "Line {}: Key is not part of any section", _0)]
+    KeyOutsideSection(usize),
+
+    #[error(display = "Line {}: Unknown section \"{}\"", _0, _1)]
+    UnknownSection(usize, String),
+
+    #[error(display = "Line {}: Invalid value for {}", _0, _1)]
+    InvalidValue(usize, &'static str),
+
+    #[error(display = "Missing {} in [{}] section", _0, _1)]
+    MissingKey(&'static str, &'static str),
+
+    #[error(display = "Missing [Peer] section")]
+    MissingPeer,
+
+    #[error(display = "Only configurations with a single peer are supported")]
+    MultiplePeers,
+
+    #[error(
+        display = "Failed to infer the IPv4 gateway. Use an interface address with a prefix, \
+                   such as 10.0.0.2/24, where the first address in the network is the peer, \
+                   or a private DNS server address that belongs to the peer"
+    )]
+    NoIpv4Gateway,
+}
+
+/// A WireGuard configuration in the format of a `wg-quick` configuration file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WgQuickConfig {
+    /// Hostname or IP address of the peer.
+    pub host: String,
+    /// Connection config. The IP of the peer endpoint is unspecified until `host` is resolved.
+    pub connection: wireguard::ConnectionConfig,
+    /// DNS servers to use while connected.
+    pub dns_servers: Vec<IpAddr>,
+    /// MTU of the tunnel interface, if set.
+    pub mtu: Option<u16>,
+}
+
+impl WgQuickConfig {
+    /// Reads and parses a `wg-quick` configuration file.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        fs::read_to_string(path).map_err(Error::Read)?.parse()
+    }
+
+    /// Returns a custom relay that connects to the peer of the configuration.
+    pub fn custom_tunnel_endpoint(&self) -> CustomTunnelEndpoint {
+        CustomTunnelEndpoint::new(
+            self.host.clone(),
+            ConnectionConfig::Wireguard(self.connection.clone()),
+        )
+    }
+
+    /// Returns custom DNS options using the DNS servers of the configuration, if it lists any.
+    pub fn dns_options(&self) -> Option<DnsOptions> {
+        if self.dns_servers.is_empty() {
+            return None;
+        }
+        Some(DnsOptions {
+            state: DnsState::Custom,
+            custom_options: CustomDnsOptions {
+                addresses: self.dns_servers.clone(),
+            },
+            ..DnsOptions::default()
+        })
+    }
+}
+
+/// Formats the configuration as a `wg-quick` configuration file. Interface addresses are written
+/// as single host networks.
+impl fmt::Display for WgQuickConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tunnel = &self.connection.tunnel;
+        let peer = &self.connection.peer;
+
+        writeln!(f, "[Interface]")?;
+        writeln!(f, "PrivateKey = {}", tunnel.private_key.to_base64())?;
+        let addresses: Vec<_> = tunnel
+            .addresses
+            .iter()
+            .map(|address| IpNetwork::from(*address).to_string())
+            .collect();
+        writeln!(f, "Address = {}", addresses.join(", "))?;
+        if !self.dns_servers.is_empty() {
+            let servers: Vec<_> = self.dns_servers.iter().map(IpAddr::to_string).collect();
+            writeln!(f, "DNS = {}", servers.join(", "))?;
+        }
+        if let Some(mtu) = self.mtu {
+            writeln!(f, "MTU = {mtu}")?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "[Peer]")?;
+        writeln!(f, "PublicKey = {}", peer.public_key.to_base64())?;
+        if let Some(psk) = &peer.psk {
+            writeln!(f, "PresharedKey = {}", base64::encode(psk.as_bytes()))?;
+        }
+        let allowed_ips: Vec<_> = peer.allowed_ips.iter().map(IpNetwork::to_string).collect();
+        writeln!(f, "AllowedIPs = {}", allowed_ips.join(", "))?;
+        match self.host.parse::<IpAddr>() {
+            Ok(IpAddr::V6(address)) => {
+                writeln!(f, "Endpoint = [{address}]:{}", peer.endpoint.port())
+            }
+            _ => writeln!(f, "Endpoint = {}:{}", self.host, peer.endpoint.port()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Section {
+    Interface,
+    Peer,
+}
+
+impl FromStr for WgQuickConfig {
+    type Err = Error;
+
+    fn from_str(config: &str) -> Result<Self, Self::Err> {
+        let mut section = None;
+        let mut has_peer = false;
+
+        let mut private_key = None;
+        let mut addresses: Vec<IpNetwork> = vec![];
+        let mut dns_servers: Vec<IpAddr> = vec![];
+        let mut mtu = None;
+
+        let mut public_key = None;
+        let mut psk = None;
+        let mut endpoint = None;
+        let mut allowed_ips: Vec<IpNetwork> = vec![];
+
+        for (index, line) in config.lines().enumerate() {
+            let line_nr = index + 1;
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = match name.trim().to_ascii_lowercase().as_str() {
+                    "interface" => Some(Section::Interface),
+                    "peer" if has_peer => return Err(Error::MultiplePeers),
+                    "peer" => {
+                        has_peer = true;
+                        Some(Section::Peer)
+                    }
+                    _ => return Err(Error::UnknownSection(line_nr, name.to_owned())),
+                };
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or(Error::InvalidLine(line_nr))?;
+            let value = value.trim();
+            let section = section.ok_or(Error::KeyOutsideSection(line_nr))?;
+
+            // Keys are case-insensitive, like in `wg` itself
+            match (section, key.trim().to_ascii_lowercase().as_str()) {
+                (Section::Interface, "privatekey") => {
+                    let key = parse_key(value).ok_or(Error::InvalidValue(line_nr, "PrivateKey"))?;
+                    private_key = Some(wireguard::PrivateKey::from(key));
+                }
+                (Section::Interface, "address") => {
+                    for address in split_list(value) {
+                        addresses.push(
+                            address
+                                .parse()
+                                .map_err(|_| Error::InvalidValue(line_nr, "Address"))?,
+                        );
+                    }
+                }
+                (Section::Interface, "dns") => {
+                    // Entries that are not IP addresses are search domains, which are not
+                    // supported
+                    dns_servers
+                        .extend(split_list(value).filter_map(|entry| entry.parse::<IpAddr>().ok()));
+                }
+                (Section::Interface, "mtu") => {
+                    mtu = Some(
+                        value
+                            .parse()
+                            .map_err(|_| Error::InvalidValue(line_nr, "MTU"))?,
+                    );
+                }
+                (Section::Peer, "publickey") => {
+                    let key = wireguard::PublicKey::from_base64(value)
+                        .map_err(|_| Error::InvalidValue(line_nr, "PublicKey"))?;
+                    public_key = Some(key);
+                }
+                (Section::Peer, "presharedkey") => {
+                    let key =
+                        parse_key(value).ok_or(Error::InvalidValue(line_nr, "PresharedKey"))?;
+                    psk = Some(wireguard::PresharedKey::from(key));
+                }
+                (Section::Peer, "endpoint") => {
+                    endpoint = Some(
+                        parse_endpoint(value).ok_or(Error::InvalidValue(line_nr, "Endpoint"))?,
+                    );
+                }
+                (Section::Peer, "allowedips") => {
+                    for network in split_list(value) {
+                        allowed_ips.push(
+                            network
+                                .parse()
+                                .map_err(|_| Error::InvalidValue(line_nr, "AllowedIPs"))?,
+                        );
+                    }
+                }
+                (_, key) => {
+                    log::debug!("Ignoring unsupported wg-quick key \"{}\"", key);
+                }
+            }
+        }
+
+        if !has_peer {
+            return Err(Error::MissingPeer);
+        }
+        let private_key = private_key.ok_or(Error::MissingKey("PrivateKey", "Interface"))?;
+        if addresses.is_empty() {
+            return Err(Error::MissingKey("Address", "Interface"));
+        }
+        let public_key = public_key.ok_or(Error::MissingKey("PublicKey", "Peer"))?;
+        let (host, port) = endpoint.ok_or(Error::MissingKey("Endpoint", "Peer"))?;
+        if allowed_ips.is_empty() {
+            return Err(Error::MissingKey("AllowedIPs", "Peer"));
+        }
+
+        let ipv4_gateway = addresses
+            .iter()
+            .find_map(|address| match address {
+                IpNetwork::V4(network) => ipv4_gateway(network.ip(), network.prefix()),
+                IpNetwork::V6(_) => None,
+            })
+            .or_else(|| {
+                // Configurations generated by Mullvad use single host addresses, and the relay as
+                // DNS server
+                dns_servers.iter().find_map(|server| match server {
+                    IpAddr::V4(server) if server.is_private() => Some(*server),
+                    _ => None,
+                })
+            })
+            .ok_or(Error::NoIpv4Gateway)?;
+        let ipv6_gateway = addresses.iter().find_map(|address| match address {
+            IpNetwork::V4(_) => None,
+            IpNetwork::V6(network) => ipv6_gateway(network.ip(), network.prefix()),
+        });
+
+        let connection = wireguard::ConnectionConfig {
+            tunnel: wireguard::TunnelConfig {
+                private_key,
+                addresses: addresses.iter().map(|address| address.ip()).collect(),
+            },
+            peer: wireguard::PeerConfig {
+                public_key,
+                allowed_ips,
+                // The host is resolved when connecting
+                endpoint: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port),
+                psk,
+            },
+            exit_peer: None,
+            ipv4_gateway,
+            ipv6_gateway,
+            // The daemon applies its configured firewall mark when connecting
+            #[cfg(target_os = "linux")]
+            fwmark: None,
+        };
+
+        Ok(WgQuickConfig {
+            host,
+            connection,
+            dns_servers,
+            mtu,
+        })
+    }
+}
+
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+}
+
+fn parse_key(value: &str) -> Option<[u8; 32]> {
+    let bytes = base64::decode(value).ok()?;
+    <[u8; 32]>::try_from(bytes.as_slice()).ok()
+}
+
+/// Splits `host:port`, where `host` may be a domain, an IPv4 address, or a bracketed IPv6
+/// address.
+fn parse_endpoint(value: &str) -> Option<(String, u16)> {
+    let (host, port) = value.rsplit_once(':')?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    if host.is_empty() {
+        return None;
+    }
+    Some((host.to_owned(), port.parse().ok()?))
+}
+
+/// wg-quick configurations have no notion of a gateway. Assume that the peer has the first
+/// address in the network of the interface address.
+fn ipv4_gateway(address: Ipv4Addr, prefix: u8) -> Option<Ipv4Addr> {
+    if prefix > 30 {
+        return None;
+    }
+    let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+    let gateway = Ipv4Addr::from((u32::from(address) & mask) + 1);
+    Some(gateway).filter(|gateway| *gateway != address)
+}
+
+fn ipv6_gateway(address: Ipv6Addr, prefix: u8) -> Option<Ipv6Addr> {
+    if prefix > 126 {
+        return None;
+    }
+    let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+    let gateway = Ipv6Addr::from((u128::from(address) & mask) + 1);
+    Some(gateway).filter(|gateway| *gateway != address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRIVATE_KEY: &str = "oPjddLBldZWbfp1q6BnnTj6yA4VhQ52EIAEl+a8B1Fk=";
+    const PUBLIC_KEY: &str = "Lt8rk3bIPNI5ZZR6tF0TJp2TYTXKfzDDoORcjiJ3+xE=";
+    const PSK: &str = "Ba6Ua6x+qTUxmJ3l4IjZ3sFoCAvyB68rz+rVAVB4DOc=";
+
+    #[test]
+    fn test_parse_config() {
+        let config: WgQuickConfig = format!(
+            "
+            # Self-hosted server
+            [Interface]
+            PrivateKey = {PRIVATE_KEY}
+            Address = 10.8.0.2/24, fd00::2/64
+            DNS = 10.8.0.1, example.com
+            MTU = 1380
+            ListenPort = 51820
+
+            [Peer]
+            PublicKey = {PUBLIC_KEY}
+            PresharedKey = {PSK}
+            Endpoint = vpn.example.com:51820 # comment
+            AllowedIPs = 0.0.0.0/0
+            allowedips = ::/0
+            "
+        )
+        .parse()
+        .unwrap();
+
+        assert_eq!(config.host, "vpn.example.com");
+        assert_eq!(config.mtu, Some(1380));
+        assert_eq!(config.dns_servers, vec![IpAddr::from([10, 8, 0, 1])]);
+
+        let connection = config.connection;
+        assert_eq!(connection.tunnel.private_key.to_base64(), PRIVATE_KEY);
+        assert_eq!(
+            connection.tunnel.addresses,
+            vec![
+                "10.8.0.2".parse::<IpAddr>().unwrap(),
+                "fd00::2".parse().unwrap()
+            ]
+        );
+        assert_eq!(connection.peer.public_key.to_base64(), PUBLIC_KEY);
+        assert_eq!(
+            connection
+                .peer
+                .psk
+                .map(|psk| base64::encode(psk.as_bytes())),
+            Some(PSK.to_owned())
+        );
+        assert_eq!(connection.peer.endpoint.port(), 51820);
+        assert_eq!(connection.peer.allowed_ips.len(), 2);
+        assert_eq!(connection.ipv4_gateway, Ipv4Addr::new(10, 8, 0, 1));
+        assert_eq!(connection.ipv6_gateway, Some("fd00::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_format_and_parse() {
+        let config: WgQuickConfig = format!(
+            "
+            [Interface]
+            PrivateKey = {PRIVATE_KEY}
+            Address = 10.64.0.2/32, fc00:bbbb:bbbb:bb01::2/128
+            DNS = 10.64.0.1
+
+            [Peer]
+            PublicKey = {PUBLIC_KEY}
+            PresharedKey = {PSK}
+            AllowedIPs = 0.0.0.0/0, ::/0
+            Endpoint = [2001:db8::1]:51820
+            "
+        )
+        .parse()
+        .unwrap();
+
+        assert_eq!(config.connection.ipv4_gateway, Ipv4Addr::new(10, 64, 0, 1));
+        assert_eq!(config.to_string().parse::<WgQuickConfig>().unwrap(), config);
+    }
+
+    #[test]
+    fn test_parse_endpoint() {
+        assert_eq!(
+            parse_endpoint("[2001:db8::1]:51820"),
+            Some(("2001:db8::1".to_owned(), 51820))
+        );
+        assert_eq!(
+            parse_endpoint("192.0.2.1:443"),
+            Some(("192.0.2.1".to_owned(), 443))
+        );
+        assert_eq!(parse_endpoint("192.0.2.1"), None);
+        assert_eq!(parse_endpoint(":51820"), None);
+    }
+
+    #[test]
+    fn test_single_host_address_has_no_gateway() {
+        let result: Result<WgQuickConfig, _> = format!(
+            "
+            [Interface]
+            PrivateKey = {PRIVATE_KEY}
+            Address = 10.8.0.2/32
+            DNS = 192.0.2.53
+            [Peer]
+            PublicKey = {PUBLIC_KEY}
+            Endpoint = 192.0.2.1:51820
+            AllowedIPs = 0.0.0.0/0
+            "
+        )
+        .parse();
+        assert!(matches!(result, Err(Error::NoIpv4Gateway)));
+    }
+
+    #[test]
+    fn test_multiple_peers_are_rejected() {
+        let result: Result<WgQuickConfig, _> = "[Interface]\n[Peer]\n[Peer]\n".parse();
+        assert!(matches!(result, Err(Error::MultiplePeers)));
+    }
+}
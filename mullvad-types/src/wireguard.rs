@@ -10,6 +10,9 @@ pub const MIN_ROTATION_INTERVAL: Duration = Duration::from_secs(1 * 24 * 60 * 60
 pub const MAX_ROTATION_INTERVAL: Duration = Duration::from_secs(14 * 24 * 60 * 60);
 pub const DEFAULT_ROTATION_INTERVAL: Duration = MAX_ROTATION_INTERVAL;
 
+/// Shortest allowed interval between PSK exchanges in a quantum-resistant tunnel.
+pub const MIN_QUANTUM_RESISTANT_REKEY_INTERVAL: Duration = Duration::from_secs(1 * 60 * 60);
+
 /// Whether to enable or disable quantum resistant tunnels when the setting
 /// is set to `QuantumResistantState::Auto`.
 const QUANTUM_RESISTANT_AUTO_STATE: bool = false;
@@ -136,10 +139,20 @@ pub struct TunnelOptions {
         jnix(map = "|maybe_mtu| maybe_mtu.map(|mtu| mtu as i32)")
     )]
     pub mtu: Option<u16>,
+    /// Probe the path MTU to the relay and through the tunnel after connecting. Ignored if `mtu`
+    /// is set.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub mtu_discovery: bool,
+    /// Interval in seconds between keepalive packets sent to the relay
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub persistent_keepalive: Option<u16>,
     /// Temporary switch for wireguard-nt
     #[cfg(windows)]
     #[serde(rename = "wireguard_nt")]
     pub use_wireguard_nt: bool,
+    /// Use the BoringTun userspace implementation instead of the kernel module
+    #[cfg(target_os = "linux")]
+    pub use_boringtun: bool,
     /// Obtain a PSK using the relay config client.
     #[cfg_attr(
         target_os = "android",
@@ -150,6 +163,13 @@ pub struct TunnelOptions {
         }")
     )]
     pub quantum_resistant: QuantumResistantState,
+    /// How often to negotiate a new PSK in a quantum-resistant tunnel. If not set, a single PSK is
+    /// negotiated when connecting.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub quantum_resistant_rekey_interval: Option<Duration>,
+    /// Probes used to verify that the tunnel works
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub connectivity_probes: wireguard::ConnectivityProbes,
     /// Interval used for automatic key rotation
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub rotation_interval: Option<RotationInterval>,
@@ -160,9 +180,15 @@ impl Default for TunnelOptions {
     fn default() -> Self {
         TunnelOptions {
             mtu: None,
+            mtu_discovery: false,
+            persistent_keepalive: None,
             quantum_resistant: QuantumResistantState::Auto,
+            quantum_resistant_rekey_interval: None,
+            connectivity_probes: wireguard::ConnectivityProbes::default(),
             #[cfg(windows)]
             use_wireguard_nt: true,
+            #[cfg(target_os = "linux")]
+            use_boringtun: false,
             rotation_interval: None,
         }
     }
@@ -172,13 +198,19 @@ impl TunnelOptions {
     pub fn into_talpid_tunnel_options(self) -> wireguard::TunnelOptions {
         wireguard::TunnelOptions {
             mtu: self.mtu,
+            mtu_discovery: self.mtu_discovery && self.mtu.is_none(),
+            persistent_keepalive: self.persistent_keepalive,
             #[cfg(windows)]
             use_wireguard_nt: self.use_wireguard_nt,
+            #[cfg(target_os = "linux")]
+            use_boringtun: self.use_boringtun,
             quantum_resistant: match self.quantum_resistant {
                 QuantumResistantState::Auto => QUANTUM_RESISTANT_AUTO_STATE,
                 QuantumResistantState::On => true,
                 QuantumResistantState::Off => false,
             },
+            quantum_resistant_rekey_interval: self.quantum_resistant_rekey_interval,
+            connectivity_probes: self.connectivity_probes,
         }
     }
 }
@@ -1,5 +1,5 @@
-use super::{FirewallArguments, FirewallPolicy};
-use crate::{split_tunnel, tunnel};
+use super::{FirewallArguments, FirewallPolicy, ReplacementTunnel};
+use crate::{tunnel, tunnel_state_machine::LinuxNetworkingIdentifiers};
 use ipnetwork::IpNetwork;
 use lazy_static::lazy_static;
 use libc;
@@ -14,7 +14,13 @@ use std::{
     fs, io,
     net::{IpAddr, Ipv4Addr},
 };
-use talpid_types::net::{AllowedTunnelTraffic, Endpoint, TransportProtocol};
+use talpid_types::{
+    net::{
+        validate_user_routes, AllowedTunnelTraffic, Endpoint, TransportProtocol, TrustedOverlays,
+        UserRoute, UserRouteTarget,
+    },
+    net_namespace, ErrorExt,
+};
 
 /// Priority for rules that tag split tunneling packets. Equals NF_IP_PRI_MANGLE.
 const MANGLE_CHAIN_PRIORITY: i32 = libc::NF_IP_PRI_MANGLE;
@@ -54,6 +60,10 @@ pub enum Error {
         _0
     )]
     LookupIfaceIndexError(String, #[error(source)] crate::linux::IfaceIndexLookupError),
+
+    /// Network interface name contains a null byte.
+    #[error(display = "Invalid network interface name \"{}\"", _0)]
+    InvalidInterfaceName(String),
 }
 
 lazy_static! {
@@ -67,6 +77,14 @@ lazy_static! {
     static ref MANGLE_CHAIN_NAME: CString = CString::new("mangle").unwrap();
     static ref NAT_CHAIN_NAME: CString = CString::new("nat").unwrap();
 
+    /// Table restricting forwarding to the exclusion network namespace. It is kept separate
+    /// since it must remain in place regardless of the firewall policy.
+    static ref EXCLUSION_TABLE_NAME: CString =
+        CString::new(net_namespace::SPLIT_TUNNEL_NETNS_NAME).unwrap();
+
+    /// Host side of the veth pair connecting the split tunnel network namespace.
+    static ref NETNS_HOST_VETH_NAME: CString = CString::new(net_namespace::HOST_VETH_NAME).unwrap();
+
     /// Allows controlling whether firewall rules should have packet counters or not from an env
     /// variable. Useful for debugging the rules.
     static ref ADD_COUNTERS: bool = env::var("TALPID_FIREWALL_DEBUG")
@@ -92,21 +110,47 @@ enum End {
 
 /// The Linux implementation for the firewall and DNS.
 pub struct Firewall {
-    fwmark: u32,
+    linux_ids: LinuxNetworkingIdentifiers,
+    trusted_overlays: TrustedOverlays,
+    user_routes: Vec<UserRoute>,
 }
 
 impl Firewall {
     pub fn from_args(args: FirewallArguments) -> Result<Self> {
-        Firewall::new(args.fwmark)
+        let mut firewall = Firewall::new(args.linux_ids)?;
+        firewall.trusted_overlays = args.trusted_overlays;
+        firewall.user_routes = args.user_routes;
+        Ok(firewall)
+    }
+
+    pub fn new(linux_ids: LinuxNetworkingIdentifiers) -> Result<Self> {
+        Ok(Firewall {
+            linux_ids,
+            trusted_overlays: TrustedOverlays::default(),
+            user_routes: vec![],
+        })
     }
 
-    pub fn new(fwmark: u32) -> Result<Self> {
-        Ok(Firewall { fwmark })
+    /// Sets the overlay interfaces and networks to allow. This takes effect the next time a policy
+    /// is applied.
+    pub fn set_trusted_overlays(&mut self, overlays: TrustedOverlays) {
+        self.trusted_overlays = overlays;
+    }
+
+    /// Sets the user routes that affect the firewall rules. This takes effect the next time a
+    /// policy is applied.
+    pub fn set_user_routes(&mut self, user_routes: Vec<UserRoute>) {
+        self.user_routes = user_routes;
     }
 
     pub fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<()> {
         let table = Table::new(&*TABLE_NAME, ProtoFamily::Inet);
-        let batch = PolicyBatch::new(&table).finalize(&policy, self.fwmark)?;
+        let batch = PolicyBatch::new(&table).finalize(
+            &policy,
+            &self.linux_ids,
+            &self.trusted_overlays,
+            &self.user_routes,
+        )?;
         Self::send_and_process(&batch)?;
         Self::apply_kernel_config(&policy);
         self.verify_tables(&[&TABLE_NAME])
@@ -213,6 +257,48 @@ impl Firewall {
     }
 }
 
+/// Drops all forwarded traffic except to and from the exclusion network namespace, or removes
+/// this restriction if `enabled` is false.
+pub fn set_exclusion_forward_filter(enabled: bool) -> Result<()> {
+    let table = Table::new(&*EXCLUSION_TABLE_NAME, ProtoFamily::Inet);
+    let batch = exclusion_forward_filter_batch(&table, enabled);
+    Firewall::send_and_process(&batch)
+}
+
+fn exclusion_forward_filter_batch(table: &Table, enabled: bool) -> FinalizedBatch {
+    let mut batch = Batch::new();
+
+    // Adding the table first prevents an error if it does not exist
+    batch.add(table, nftnl::MsgType::Add);
+    batch.add(table, nftnl::MsgType::Del);
+
+    if enabled {
+        batch.add(table, nftnl::MsgType::Add);
+
+        let mut forward_chain = Chain::new(&*FORWARD_CHAIN_NAME, table);
+        forward_chain.set_hook(nftnl::Hook::Forward, 0);
+        forward_chain.set_policy(nftnl::Policy::Drop);
+        batch.add(&forward_chain, nftnl::MsgType::Add);
+
+        let veth_network = net_namespace::veth_network();
+        for (direction, end) in [(Direction::In, End::Src), (Direction::Out, End::Dst)] {
+            let mut rule = Rule::new(&forward_chain);
+            rule.add_expr(&match direction {
+                Direction::In => nft_expr!(meta iifname),
+                Direction::Out => nft_expr!(meta oifname),
+            });
+            rule.add_expr(&nft_expr!(
+                cmp == expr::InterfaceName::Exact(NETNS_HOST_VETH_NAME.clone())
+            ));
+            check_net(&mut rule, end, veth_network);
+            add_verdict(&mut rule, &Verdict::Accept);
+            batch.add(&rule, nftnl::MsgType::Add);
+        }
+    }
+
+    batch.finalize()
+}
+
 struct PolicyBatch<'a> {
     batch: Batch,
     in_chain: Chain<'a>,
@@ -281,17 +367,34 @@ impl<'a> PolicyBatch<'a> {
 
     /// Finalize the nftnl message batch by adding every firewall rule needed to satisfy the given
     /// policy.
-    pub fn finalize(mut self, policy: &FirewallPolicy, fwmark: u32) -> Result<FinalizedBatch> {
+    pub fn finalize(
+        mut self,
+        policy: &FirewallPolicy,
+        linux_ids: &LinuxNetworkingIdentifiers,
+        trusted_overlays: &TrustedOverlays,
+        user_routes: &[UserRoute],
+    ) -> Result<FinalizedBatch> {
+        let user_routes = safe_user_routes(user_routes);
+
         self.add_loopback_rules()?;
-        self.add_split_tunneling_rules(policy, fwmark)?;
+        self.add_trusted_overlay_rules(trusted_overlays)?;
+        self.add_split_tunneling_rules(policy, linux_ids, user_routes)?;
         self.add_dhcp_client_rules();
         self.add_ndp_rules();
-        self.add_policy_specific_rules(policy, fwmark)?;
+        self.add_policy_specific_rules(policy, linux_ids.fwmark, user_routes)?;
 
         Ok(self.batch.finalize())
     }
 
-    fn add_split_tunneling_rules(&mut self, policy: &FirewallPolicy, fwmark: u32) -> Result<()> {
+    fn add_split_tunneling_rules(
+        &mut self,
+        policy: &FirewallPolicy,
+        linux_ids: &LinuxNetworkingIdentifiers,
+        user_routes: &[UserRoute],
+    ) -> Result<()> {
+        let fwmark = linux_ids.fwmark;
+        let split_tunnel_mark = linux_ids.split_tunnel_mark;
+
         // Send select DNS requests in the tunnel
         if let FirewallPolicy::Connected {
             tunnel,
@@ -320,19 +423,47 @@ impl<'a> PolicyBatch<'a> {
             }
         }
 
+        // Do not mark excluded traffic to networks that must go through the tunnel
+        for route in user_routes
+            .iter()
+            .filter(|route| route.target == UserRouteTarget::Tunnel)
+        {
+            let mut rule = Rule::new(&self.mangle_chain);
+            rule.add_expr(&nft_expr!(meta cgroup));
+            rule.add_expr(&nft_expr!(cmp == linux_ids.net_cls_classid));
+            check_net(&mut rule, End::Dst, route.network);
+            add_verdict(&mut rule, &Verdict::Return);
+            self.batch.add(&rule, nftnl::MsgType::Add);
+        }
+
         let mut rule = Rule::new(&self.mangle_chain);
         rule.add_expr(&nft_expr!(meta cgroup));
-        rule.add_expr(&nft_expr!(cmp == split_tunnel::NET_CLS_CLASSID));
-        rule.add_expr(&nft_expr!(immediate data split_tunnel::MARK));
+        rule.add_expr(&nft_expr!(cmp == linux_ids.net_cls_classid));
+        rule.add_expr(&nft_expr!(immediate data split_tunnel_mark));
+        rule.add_expr(&nft_expr!(ct mark set));
+        rule.add_expr(&nft_expr!(immediate data fwmark));
+        rule.add_expr(&nft_expr!(meta mark set));
+        self.batch.add(&rule, nftnl::MsgType::Add);
+
+        // Mark traffic leaving the exclusion network namespace
+        let mut rule = Rule::new(&self.prerouting_chain);
+        rule.add_expr(&nft_expr!(meta iifname));
+        rule.add_expr(&nft_expr!(
+            cmp == expr::InterfaceName::Exact(NETNS_HOST_VETH_NAME.clone())
+        ));
+        rule.add_expr(&nft_expr!(immediate data split_tunnel_mark));
         rule.add_expr(&nft_expr!(ct mark set));
         rule.add_expr(&nft_expr!(immediate data fwmark));
         rule.add_expr(&nft_expr!(meta mark set));
+        if *ADD_COUNTERS {
+            rule.add_expr(&nft_expr!(counter));
+        }
         self.batch.add(&rule, nftnl::MsgType::Add);
 
-        for chain in &[&self.in_chain, &self.out_chain] {
+        for chain in &[&self.in_chain, &self.out_chain, &self.forward_chain] {
             let mut rule = Rule::new(chain);
             rule.add_expr(&nft_expr!(ct mark));
-            rule.add_expr(&nft_expr!(cmp == split_tunnel::MARK));
+            rule.add_expr(&nft_expr!(cmp == split_tunnel_mark));
             add_verdict(&mut rule, &Verdict::Accept);
             self.batch.add(&rule, nftnl::MsgType::Add);
         }
@@ -342,7 +473,7 @@ impl<'a> PolicyBatch<'a> {
             let mut block_tunnel_rule = Rule::new(&self.nat_chain);
             check_iface(&mut block_tunnel_rule, Direction::Out, &tunnel.interface)?;
             block_tunnel_rule.add_expr(&nft_expr!(ct mark));
-            block_tunnel_rule.add_expr(&nft_expr!(cmp == split_tunnel::MARK));
+            block_tunnel_rule.add_expr(&nft_expr!(cmp == split_tunnel_mark));
             add_verdict(&mut block_tunnel_rule, &Verdict::Drop);
             self.batch.add(&block_tunnel_rule, nftnl::MsgType::Add);
         }
@@ -357,7 +488,7 @@ impl<'a> PolicyBatch<'a> {
         rule.add_expr(&nft_expr!(cmp != iface_index));
 
         rule.add_expr(&nft_expr!(ct mark));
-        rule.add_expr(&nft_expr!(cmp == split_tunnel::MARK));
+        rule.add_expr(&nft_expr!(cmp == split_tunnel_mark));
 
         rule.add_expr(&nft_expr!(masquerade));
         if *ADD_COUNTERS {
@@ -371,7 +502,7 @@ impl<'a> PolicyBatch<'a> {
             let mut prerouting_rule = Rule::new(&self.prerouting_chain);
             check_not_iface(&mut prerouting_rule, Direction::In, &tunnel.interface)?;
             prerouting_rule.add_expr(&nft_expr!(ct mark));
-            prerouting_rule.add_expr(&nft_expr!(cmp == split_tunnel::MARK));
+            prerouting_rule.add_expr(&nft_expr!(cmp == split_tunnel_mark));
             prerouting_rule.add_expr(&nft_expr!(immediate data fwmark));
             prerouting_rule.add_expr(&nft_expr!(meta mark set));
             if *ADD_COUNTERS {
@@ -396,6 +527,58 @@ impl<'a> PolicyBatch<'a> {
         Ok(())
     }
 
+    /// Allow all traffic on overlay interfaces and to or from overlay networks, regardless of the
+    /// policy.
+    fn add_trusted_overlay_rules(&mut self, overlays: &TrustedOverlays) -> Result<()> {
+        // The overlays are validated when they are set. Ignore them rather than failing to
+        // block if invalid overlays were somehow stored anyway.
+        if let Err(error) = overlays.validate_networks() {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Ignoring unsafe trusted overlays")
+            );
+            return Ok(());
+        }
+
+        for interface in &overlays.interfaces {
+            // Match on the name since the interface may not exist yet
+            let name = CString::new(interface.as_bytes())
+                .map_err(|_| Error::InvalidInterfaceName(interface.to_owned()))?;
+
+            for chain in &[&self.in_chain, &self.forward_chain] {
+                let mut rule = Rule::new(chain);
+                rule.add_expr(&nft_expr!(meta iifname));
+                rule.add_expr(&nft_expr!(cmp == expr::InterfaceName::Exact(name.clone())));
+                add_verdict(&mut rule, &Verdict::Accept);
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
+            for chain in &[&self.out_chain, &self.forward_chain] {
+                let mut rule = Rule::new(chain);
+                rule.add_expr(&nft_expr!(meta oifname));
+                rule.add_expr(&nft_expr!(cmp == expr::InterfaceName::Exact(name.clone())));
+                add_verdict(&mut rule, &Verdict::Accept);
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
+        }
+
+        for network in &overlays.networks {
+            for chain in &[&self.in_chain, &self.forward_chain] {
+                let mut rule = Rule::new(chain);
+                check_net(&mut rule, End::Src, *network);
+                add_verdict(&mut rule, &Verdict::Accept);
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
+            for chain in &[&self.out_chain, &self.forward_chain] {
+                let mut rule = Rule::new(chain);
+                check_net(&mut rule, End::Dst, *network);
+                add_verdict(&mut rule, &Verdict::Accept);
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
+        }
+
+        Ok(())
+    }
+
     fn add_dhcp_client_rules(&mut self) {
         use self::TransportProtocol::Udp;
         // Outgoing DHCPv4 request
@@ -507,16 +690,29 @@ impl<'a> PolicyBatch<'a> {
         }
     }
 
-    fn add_policy_specific_rules(&mut self, policy: &FirewallPolicy, fwmark: u32) -> Result<()> {
+    fn add_policy_specific_rules(
+        &mut self,
+        policy: &FirewallPolicy,
+        fwmark: u32,
+        user_routes: &[UserRoute],
+    ) -> Result<()> {
         let allow_lan = match policy {
             FirewallPolicy::Connecting {
                 peer_endpoint,
+                peer_udp_relay,
+                probe_path_mtu,
                 tunnel,
                 allow_lan,
                 allowed_endpoint,
                 allowed_tunnel_traffic,
             } => {
                 self.add_allow_tunnel_endpoint_rules(peer_endpoint, fwmark);
+                if let Some(host) = peer_udp_relay {
+                    self.add_allow_udp_relay_rules(*host, fwmark);
+                }
+                if *probe_path_mtu {
+                    self.add_allow_relay_ping_rules(peer_endpoint.address.ip(), fwmark);
+                }
                 self.add_allow_endpoint_rules(&allowed_endpoint.endpoint);
 
                 // Important to block DNS after allow relay rule (so the relay can operate
@@ -524,41 +720,68 @@ impl<'a> PolicyBatch<'a> {
                 self.add_drop_dns_rule();
 
                 if let Some(tunnel) = tunnel {
-                    match allowed_tunnel_traffic {
-                        AllowedTunnelTraffic::All => {
-                            self.add_allow_tunnel_rules(&tunnel.interface)?;
-                        }
-                        AllowedTunnelTraffic::None => (),
-                        AllowedTunnelTraffic::One(endpoint) => {
-                            self.add_allow_in_tunnel_endpoint_rules(&tunnel.interface, endpoint)?;
-                        }
-                        AllowedTunnelTraffic::Two(endpoint1, endpoint2) => {
-                            self.add_allow_in_tunnel_endpoint_rules(&tunnel.interface, endpoint1)?;
-                            self.add_allow_in_tunnel_endpoint_rules(&tunnel.interface, endpoint2)?;
-                        }
-                    }
+                    self.add_allowed_tunnel_traffic_rules(
+                        &tunnel.interface,
+                        allowed_tunnel_traffic,
+                    )?;
                     if *allow_lan {
                         self.add_block_cve_2019_14899(tunnel);
                     }
                 }
+                self.add_allow_gateway_route_rules(user_routes);
                 *allow_lan
             }
             FirewallPolicy::Connected {
                 peer_endpoint,
+                peer_udp_relay,
                 tunnel,
                 allow_lan,
                 dns_servers,
+                replacement,
             } => {
                 self.add_allow_tunnel_endpoint_rules(peer_endpoint, fwmark);
+                if let Some(host) = peer_udp_relay {
+                    self.add_allow_udp_relay_rules(*host, fwmark);
+                }
+                if let Some(replacement) = replacement {
+                    self.add_allow_tunnel_endpoint_rules(&replacement.peer_endpoint, fwmark);
+                    if let Some(host) = replacement.peer_udp_relay {
+                        self.add_allow_udp_relay_rules(host, fwmark);
+                    }
+                    if replacement.probe_path_mtu {
+                        self.add_allow_relay_ping_rules(
+                            replacement.peer_endpoint.address.ip(),
+                            fwmark,
+                        );
+                    }
+                }
                 self.add_allow_dns_rules(tunnel, dns_servers, TransportProtocol::Udp)?;
                 self.add_allow_dns_rules(tunnel, dns_servers, TransportProtocol::Tcp)?;
                 // Important to block DNS *before* we allow the tunnel and allow LAN. So DNS
                 // can't leak to the wrong IPs in the tunnel or on the LAN.
                 self.add_drop_dns_rule();
                 self.add_allow_tunnel_rules(&tunnel.interface)?;
+                if let Some(ReplacementTunnel {
+                    tunnel: Some(replacement_tunnel),
+                    allowed_tunnel_traffic,
+                    ..
+                }) = replacement
+                {
+                    self.add_allowed_tunnel_traffic_rules(
+                        &replacement_tunnel.interface,
+                        allowed_tunnel_traffic,
+                    )?;
+                }
                 if *allow_lan {
                     self.add_block_cve_2019_14899(tunnel);
+                    if let Some(replacement_tunnel) = replacement
+                        .as_ref()
+                        .and_then(|replacement| replacement.tunnel.as_ref())
+                    {
+                        self.add_block_cve_2019_14899(replacement_tunnel);
+                    }
                 }
+                self.add_allow_gateway_route_rules(user_routes);
                 *allow_lan
             }
             FirewallPolicy::Blocked {
@@ -593,8 +816,36 @@ impl<'a> PolicyBatch<'a> {
     }
 
     fn add_allow_tunnel_endpoint_rules(&mut self, endpoint: &Endpoint, fwmark: u32) {
+        self.add_allow_peer_rules(|rule, end| check_endpoint(rule, end, endpoint), fwmark);
+    }
+
+    /// Allows pinging the relay outside the tunnel, which is how the path MTU is probed.
+    fn add_allow_relay_ping_rules(&mut self, host: IpAddr, fwmark: u32) {
+        self.add_allow_peer_rules(
+            |rule, end| {
+                check_ip(rule, end, host);
+                rule.add_expr(&nft_expr!(meta l4proto));
+                rule.add_expr(&nft_expr!(cmp == icmp_l4proto(host)));
+            },
+            fwmark,
+        );
+    }
+
+    /// Allows UDP to and from any port on `host`, for reaching the relay of a SOCKS5 proxy. The
+    /// port is assigned by the proxy, after the firewall policy has been applied.
+    fn add_allow_udp_relay_rules(&mut self, host: IpAddr, fwmark: u32) {
+        self.add_allow_peer_rules(
+            |rule, end| {
+                check_ip(rule, end, host);
+                check_l4proto(rule, TransportProtocol::Udp);
+            },
+            fwmark,
+        );
+    }
+
+    fn add_allow_peer_rules(&mut self, check_peer: impl Fn(&mut Rule<'_>, End), fwmark: u32) {
         let mut prerouting_rule = Rule::new(&self.prerouting_chain);
-        check_endpoint(&mut prerouting_rule, End::Src, endpoint);
+        check_peer(&mut prerouting_rule, End::Src);
         prerouting_rule.add_expr(&nft_expr!(immediate data fwmark));
         prerouting_rule.add_expr(&nft_expr!(meta mark set));
 
@@ -605,7 +856,7 @@ impl<'a> PolicyBatch<'a> {
         self.batch.add(&prerouting_rule, nftnl::MsgType::Add);
 
         let mut in_rule = Rule::new(&self.in_chain);
-        check_endpoint(&mut in_rule, End::Src, endpoint);
+        check_peer(&mut in_rule, End::Src);
 
         in_rule.add_expr(&nft_expr!(ct state));
         let allowed_states = nftnl::expr::ct::States::ESTABLISHED.bits();
@@ -616,7 +867,7 @@ impl<'a> PolicyBatch<'a> {
         self.batch.add(&in_rule, nftnl::MsgType::Add);
 
         let mut out_rule = Rule::new(&self.out_chain);
-        check_endpoint(&mut out_rule, End::Dst, endpoint);
+        check_peer(&mut out_rule, End::Dst);
         out_rule.add_expr(&nft_expr!(meta mark));
         out_rule.add_expr(&nft_expr!(cmp == fwmark));
         add_verdict(&mut out_rule, &Verdict::Accept);
@@ -742,6 +993,24 @@ impl<'a> PolicyBatch<'a> {
         }
     }
 
+    fn add_allowed_tunnel_traffic_rules(
+        &mut self,
+        tunnel_interface: &str,
+        allowed_tunnel_traffic: &AllowedTunnelTraffic,
+    ) -> Result<()> {
+        match allowed_tunnel_traffic {
+            AllowedTunnelTraffic::All => self.add_allow_tunnel_rules(tunnel_interface),
+            AllowedTunnelTraffic::None => Ok(()),
+            AllowedTunnelTraffic::One(endpoint) => {
+                self.add_allow_in_tunnel_endpoint_rules(tunnel_interface, endpoint)
+            }
+            AllowedTunnelTraffic::Two(endpoint1, endpoint2) => {
+                self.add_allow_in_tunnel_endpoint_rules(tunnel_interface, endpoint1)?;
+                self.add_allow_in_tunnel_endpoint_rules(tunnel_interface, endpoint2)
+            }
+        }
+    }
+
     fn add_allow_in_tunnel_endpoint_rules(
         &mut self,
         tunnel_interface: &str,
@@ -801,6 +1070,26 @@ impl<'a> PolicyBatch<'a> {
         }
     }
 
+    /// Allow traffic to and from networks that the user routes via the default gateway.
+    fn add_allow_gateway_route_rules(&mut self, user_routes: &[UserRoute]) {
+        for route in user_routes
+            .iter()
+            .filter(|route| route.target == UserRouteTarget::DefaultGateway)
+        {
+            for chain in &[&self.out_chain, &self.forward_chain] {
+                let mut out_rule = Rule::new(chain);
+                check_net(&mut out_rule, End::Dst, route.network);
+                add_verdict(&mut out_rule, &Verdict::Accept);
+                self.batch.add(&out_rule, nftnl::MsgType::Add);
+            }
+
+            let mut in_rule = Rule::new(&self.in_chain);
+            check_net(&mut in_rule, End::Src, route.network);
+            add_verdict(&mut in_rule, &Verdict::Accept);
+            self.batch.add(&in_rule, nftnl::MsgType::Add);
+        }
+    }
+
     fn add_allow_lan_rules(&mut self) {
         // Output and forward chains
         for chain in &[&self.out_chain, &self.forward_chain] {
@@ -1013,6 +1302,13 @@ fn l4proto(protocol: TransportProtocol) -> u8 {
     }
 }
 
+fn icmp_l4proto(host: IpAddr) -> u8 {
+    match host {
+        IpAddr::V4(_) => libc::IPPROTO_ICMP as u8,
+        IpAddr::V6(_) => libc::IPPROTO_ICMPV6 as u8,
+    }
+}
+
 fn add_verdict(rule: &mut Rule<'_>, verdict: &expr::Verdict) {
     if *ADD_COUNTERS {
         rule.add_expr(&nft_expr!(counter));
@@ -1020,6 +1316,22 @@ fn add_verdict(rule: &mut Rule<'_>, verdict: &expr::Verdict) {
     rule.add_expr(verdict);
 }
 
+/// Returns the user routes, or none of them if they would let all traffic bypass the tunnel.
+/// The routes are validated when they are set, so this only guards against invalid routes that
+/// were somehow stored anyway.
+fn safe_user_routes(user_routes: &[UserRoute]) -> &[UserRoute] {
+    match validate_user_routes(user_routes) {
+        Ok(()) => user_routes,
+        Err(error) => {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Ignoring unsafe user routes")
+            );
+            &[]
+        }
+    }
+}
+
 fn set_src_valid_mark_sysctl() -> io::Result<()> {
     fs::write(PROC_SYS_NET_IPV4_CONF_SRC_VALID_MARK, b"1")
 }
@@ -1040,3 +1352,158 @@ fn batch_deprecated_tables(batch: &mut Batch) {
         batch.add(table, nftnl::MsgType::Del);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const LINUX_IDS: LinuxNetworkingIdentifiers = LinuxNetworkingIdentifiers {
+        fwmark: 0x6d6f6c65,
+        table_id: 0x6d6f6c65,
+        split_tunnel_mark: 0xf41,
+        net_cls_classid: 0x4d9f41,
+    };
+
+    fn finalize_blocked(overlays: &TrustedOverlays) -> Result<FinalizedBatch> {
+        let table = Table::new(&*TABLE_NAME, ProtoFamily::Inet);
+        let policy = FirewallPolicy::Blocked {
+            allow_lan: false,
+            allowed_endpoint: None,
+        };
+        PolicyBatch::new(&table).finalize(&policy, &LINUX_IDS, overlays, &[])
+    }
+
+    fn finalize_connected(replacement: Option<ReplacementTunnel>) -> Result<FinalizedBatch> {
+        finalize_connected_with_routes(replacement, &[])
+    }
+
+    fn finalize_connected_with_routes(
+        replacement: Option<ReplacementTunnel>,
+        user_routes: &[UserRoute],
+    ) -> Result<FinalizedBatch> {
+        let table = Table::new(&*TABLE_NAME, ProtoFamily::Inet);
+        let policy = FirewallPolicy::Connected {
+            peer_endpoint: Endpoint::new(Ipv4Addr::new(1, 2, 3, 4), 51820, TransportProtocol::Udp),
+            peer_udp_relay: None,
+            tunnel: tunnel_metadata("lo"),
+            allow_lan: true,
+            dns_servers: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1))],
+            replacement,
+        };
+        PolicyBatch::new(&table).finalize(
+            &policy,
+            &LINUX_IDS,
+            &TrustedOverlays::default(),
+            user_routes,
+        )
+    }
+
+    fn batch_bytes(batch: &FinalizedBatch) -> Vec<u8> {
+        batch.iter().flatten().copied().collect()
+    }
+
+    fn tunnel_metadata(interface: &str) -> tunnel::TunnelMetadata {
+        tunnel::TunnelMetadata {
+            interface: interface.to_owned(),
+            ips: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2))],
+            ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+            ipv6_gateway: None,
+            mtu: None,
+        }
+    }
+
+    fn replacement_tunnel(
+        tunnel: Option<tunnel::TunnelMetadata>,
+        allowed_tunnel_traffic: AllowedTunnelTraffic,
+    ) -> ReplacementTunnel {
+        ReplacementTunnel {
+            peer_endpoint: Endpoint::new(Ipv4Addr::new(5, 6, 7, 8), 51820, TransportProtocol::Udp),
+            peer_udp_relay: Some(IpAddr::V4(Ipv4Addr::new(5, 6, 7, 9))),
+            probe_path_mtu: true,
+            tunnel,
+            allowed_tunnel_traffic,
+        }
+    }
+
+    #[test]
+    fn test_connected_with_replacement() {
+        assert!(finalize_connected(None).is_ok());
+
+        // Before its interface is up, only the endpoint of the new tunnel is allowed
+        let replacement = replacement_tunnel(None, AllowedTunnelTraffic::All);
+        assert!(finalize_connected(Some(replacement)).is_ok());
+
+        let relay = Endpoint::new(Ipv4Addr::new(10, 64, 0, 1), 1337, TransportProtocol::Tcp);
+        for allowed_tunnel_traffic in [
+            AllowedTunnelTraffic::None,
+            AllowedTunnelTraffic::All,
+            AllowedTunnelTraffic::One(relay),
+            AllowedTunnelTraffic::Two(relay, relay),
+        ] {
+            let replacement =
+                replacement_tunnel(Some(tunnel_metadata("lo")), allowed_tunnel_traffic);
+            assert!(finalize_connected(Some(replacement)).is_ok());
+        }
+
+        // Traffic on the interface of the new tunnel is allowed once it is up
+        let interface = "nonexistent-wg";
+        let replacement =
+            replacement_tunnel(Some(tunnel_metadata(interface)), AllowedTunnelTraffic::All);
+        assert!(matches!(
+            finalize_connected(Some(replacement)),
+            Err(Error::LookupIfaceIndexError(name, _)) if name == interface
+        ));
+    }
+
+    #[test]
+    fn test_trusted_overlay_networks() {
+        let overlays = TrustedOverlays {
+            interfaces: vec![],
+            networks: vec!["100.64.0.0/10".parse().unwrap()],
+        };
+        assert!(finalize_blocked(&overlays).is_ok());
+
+        // Overlays that would let all traffic through are ignored, but blocking still works
+        let blocked = batch_bytes(&finalize_blocked(&TrustedOverlays::default()).unwrap());
+        for networks in [&["0.0.0.0/0"][..], &["::/1", "8000::/1"][..]] {
+            let overlays = TrustedOverlays {
+                interfaces: vec!["tailscale0".to_owned()],
+                networks: networks.iter().map(|n| n.parse().unwrap()).collect(),
+            };
+            assert_eq!(batch_bytes(&finalize_blocked(&overlays).unwrap()), blocked);
+        }
+    }
+
+    #[test]
+    fn test_user_route_rules() {
+        let route = |network: &str, target| UserRoute {
+            network: network.parse().unwrap(),
+            target,
+        };
+        let connected = batch_bytes(&finalize_connected(None).unwrap());
+
+        let routes = [
+            route("192.168.1.0/24", UserRouteTarget::Tunnel),
+            route("10.0.0.0/8", UserRouteTarget::DefaultGateway),
+            route("fd00::/8", UserRouteTarget::DefaultGateway),
+        ];
+        assert_eq!(safe_user_routes(&routes), &routes);
+        for route in &routes {
+            assert!(finalize_connected_with_routes(None, &[*route]).is_ok());
+        }
+
+        // Routes that would let all traffic through are ignored
+        for routes in [
+            vec![route("0.0.0.0/0", UserRouteTarget::DefaultGateway)],
+            vec![route("::/0", UserRouteTarget::Tunnel)],
+            vec![
+                route("0.0.0.0/1", UserRouteTarget::DefaultGateway),
+                route("128.0.0.0/1", UserRouteTarget::DefaultGateway),
+            ],
+        ] {
+            assert!(safe_user_routes(&routes).is_empty());
+            let batch = finalize_connected_with_routes(None, &routes).unwrap();
+            assert_eq!(batch_bytes(&batch), connected);
+        }
+    }
+}
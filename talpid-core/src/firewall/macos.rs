@@ -116,12 +116,16 @@ impl Firewall {
         match policy {
             FirewallPolicy::Connecting {
                 peer_endpoint,
+                peer_udp_relay,
                 tunnel,
                 allow_lan,
                 allowed_endpoint,
                 allowed_tunnel_traffic,
             } => {
                 let mut rules = vec![self.get_allow_relay_rule(*peer_endpoint)?];
+                if let Some(host) = peer_udp_relay {
+                    rules.push(self.get_allow_udp_relay_rule(*host)?);
+                }
                 rules.push(self.get_allowed_endpoint_rule(allowed_endpoint.endpoint)?);
 
                 // Important to block DNS after allow relay rule (so the relay can operate
@@ -142,6 +146,7 @@ impl Firewall {
             }
             FirewallPolicy::Connected {
                 peer_endpoint,
+                peer_udp_relay,
                 tunnel,
                 allow_lan,
                 dns_servers,
@@ -153,6 +158,9 @@ impl Firewall {
                 }
 
                 rules.push(self.get_allow_relay_rule(*peer_endpoint)?);
+                if let Some(host) = peer_udp_relay {
+                    rules.push(self.get_allow_udp_relay_rule(*host)?);
+                }
 
                 // Important to block DNS *before* we allow the tunnel and allow LAN. So DNS
                 // can't leak to the wrong IPs in the tunnel or on the LAN.
@@ -292,6 +300,20 @@ impl Firewall {
             .build()?)
     }
 
+    /// Produces a rule that allows UDP to any port on `host`, for reaching the relay of a SOCKS5
+    /// proxy. The port is assigned by the proxy, after the firewall policy has been applied.
+    fn get_allow_udp_relay_rule(&self, host: IpAddr) -> Result<pfctl::FilterRule> {
+        Ok(self
+            .create_rule_builder(FilterRuleAction::Pass)
+            .direction(pfctl::Direction::Out)
+            .to(pfctl::Ip::from(host))
+            .proto(pfctl::Proto::Udp)
+            .keep_state(pfctl::StatePolicy::Keep)
+            .user(Uid::from(super::ROOT_UID))
+            .quick(true)
+            .build()?)
+    }
+
     /// Produces a rule that allows traffic to flow to the API. Allows the app to reach the API in
     /// blocked states.
     fn get_allowed_endpoint_rule(
@@ -10,6 +10,11 @@ use std::{
 };
 use talpid_types::net::{AllowedEndpoint, AllowedTunnelTraffic, Endpoint};
 
+#[cfg(target_os = "linux")]
+use crate::tunnel_state_machine::LinuxNetworkingIdentifiers;
+#[cfg(target_os = "linux")]
+use talpid_types::net::{TrustedOverlays, UserRoute};
+
 #[cfg(target_os = "macos")]
 #[path = "macos.rs"]
 mod imp;
@@ -26,6 +31,8 @@ mod imp;
 #[path = "android.rs"]
 mod imp;
 
+#[cfg(target_os = "linux")]
+pub(crate) use self::imp::set_exclusion_forward_filter;
 pub use self::imp::Error;
 
 lazy_static! {
@@ -103,6 +110,12 @@ pub enum FirewallPolicy {
     Connecting {
         /// The peer endpoint that should be allowed.
         peer_endpoint: Endpoint,
+        /// Host that a SOCKS5 proxy relays UDP traffic from. UDP to any port on it is allowed.
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        peer_udp_relay: Option<IpAddr>,
+        /// Whether pinging the relay outside the tunnel is allowed, for probing the path MTU.
+        #[cfg(target_os = "linux")]
+        probe_path_mtu: bool,
         /// Metadata about the tunnel and tunnel interface.
         tunnel: Option<crate::tunnel::TunnelMetadata>,
         /// Flag setting if communication with LAN networks should be possible.
@@ -120,6 +133,9 @@ pub enum FirewallPolicy {
     Connected {
         /// The peer endpoint that should be allowed.
         peer_endpoint: Endpoint,
+        /// Host that a SOCKS5 proxy relays UDP traffic from. UDP to any port on it is allowed.
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        peer_udp_relay: Option<IpAddr>,
         /// Metadata about the tunnel and tunnel interface.
         tunnel: crate::tunnel::TunnelMetadata,
         /// Flag setting if communication with LAN networks should be possible.
@@ -130,6 +146,9 @@ pub enum FirewallPolicy {
         /// A process that is allowed to send packets to the relay.
         #[cfg(windows)]
         relay_client: PathBuf,
+        /// A tunnel that is being set up to replace the current one.
+        #[cfg(target_os = "linux")]
+        replacement: Option<ReplacementTunnel>,
     },
 
     /// Block all network traffic in and out from the computer.
@@ -145,6 +164,23 @@ pub enum FirewallPolicy {
     },
 }
 
+/// A tunnel that is brought up while the current tunnel is still in use. Traffic to its peer and
+/// the allowed traffic on its interface are permitted in addition to that of the current tunnel.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ReplacementTunnel {
+    /// The peer endpoint that should be allowed.
+    pub peer_endpoint: Endpoint,
+    /// Host that a SOCKS5 proxy relays UDP traffic from. UDP to any port on it is allowed.
+    pub peer_udp_relay: Option<IpAddr>,
+    /// Whether pinging the relay outside the tunnel is allowed, for probing the path MTU.
+    pub probe_path_mtu: bool,
+    /// Metadata about the tunnel and tunnel interface, once it is up.
+    pub tunnel: Option<crate::tunnel::TunnelMetadata>,
+    /// Networks for which to permit in-tunnel traffic.
+    pub allowed_tunnel_traffic: AllowedTunnelTraffic,
+}
+
 impl fmt::Display for FirewallPolicy {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -234,9 +270,17 @@ pub struct FirewallArguments {
     /// This argument is required for the blocked state to configure the firewall correctly.
     pub allow_lan: bool,
     /// Specifies the firewall mark used to identify traffic that is allowed to be excluded from
-    /// the tunnel and _leaked_ during blocked states.
+    /// the tunnel and _leaked_ during blocked states, as well as the marks used for split
+    /// tunneling.
     #[cfg(target_os = "linux")]
-    pub fwmark: u32,
+    pub linux_ids: LinuxNetworkingIdentifiers,
+    /// Overlay interfaces and networks that are allowed in every policy.
+    #[cfg(target_os = "linux")]
+    pub trusted_overlays: TrustedOverlays,
+    /// Routes configured by the user. Networks routed via the default gateway are allowed while
+    /// connecting or connected.
+    #[cfg(target_os = "linux")]
+    pub user_routes: Vec<UserRoute>,
 }
 
 /// State to enter during firewall init.
@@ -256,11 +300,13 @@ impl Firewall {
     }
 
     /// Createsa new firewall instance.
-    pub fn new(#[cfg(target_os = "linux")] fwmark: u32) -> Result<Self, Error> {
+    pub fn new(
+        #[cfg(target_os = "linux")] linux_ids: LinuxNetworkingIdentifiers,
+    ) -> Result<Self, Error> {
         Ok(Firewall {
             inner: imp::Firewall::new(
                 #[cfg(target_os = "linux")]
-                fwmark,
+                linux_ids,
             )?,
         })
     }
@@ -278,4 +324,18 @@ impl Firewall {
         log::info!("Resetting firewall policy");
         self.inner.reset_policy()
     }
+
+    /// Sets the overlay interfaces and networks that are allowed in every policy. The change
+    /// takes effect when the next policy is applied.
+    #[cfg(target_os = "linux")]
+    pub fn set_trusted_overlays(&mut self, overlays: TrustedOverlays) {
+        self.inner.set_trusted_overlays(overlays)
+    }
+
+    /// Sets the routes configured by the user. The change takes effect when the next policy is
+    /// applied.
+    #[cfg(target_os = "linux")]
+    pub fn set_user_routes(&mut self, user_routes: Vec<UserRoute>) {
+        self.inner.set_user_routes(user_routes)
+    }
 }
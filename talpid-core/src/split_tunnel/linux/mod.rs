@@ -1,19 +1,23 @@
 use std::{
     env, fs,
     io::{self, BufRead, BufReader, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 use talpid_types::cgroup::{find_net_cls_mount, SPLIT_TUNNEL_CGROUP_NAME};
 
+mod netns;
+
+pub use netns::ExclusionNamespace;
+
 const DEFAULT_NET_CLS_DIR: &str = "/sys/fs/cgroup/net_cls";
 const NET_CLS_DIR_OVERRIDE_ENV_VAR: &str = "TALPID_NET_CLS_MOUNT_DIR";
 
-/// Identifies packets coming from the cgroup.
+/// Default class ID used to identify packets coming from the cgroup.
 /// This should be an arbitrary but unique integer.
 pub const NET_CLS_CLASSID: u32 = 0x4d9f41;
-/// Value used to mark packets and associated connections.
+/// Default value used to mark packets and associated connections.
 /// This should be an arbitrary but unique integer.
-pub const MARK: i32 = 0xf41;
+pub const MARK: u32 = 0xf41;
 
 /// Errors related to split tunneling.
 #[derive(err_derive::Error, Debug)]
@@ -31,6 +35,14 @@ pub enum Error {
     #[error(display = "Unable to set cgroup class ID")]
     SetCGroupClassId(#[error(source)] io::Error),
 
+    /// Unable to read the class IDs of other cgroups.
+    #[error(display = "Unable to read class IDs of other cgroups")]
+    ReadCGroupClassIds(#[error(source)] io::Error),
+
+    /// Another cgroup is already using the class ID.
+    #[error(display = "The cgroup {} already uses class ID {:#x}", _0, _1)]
+    CGroupClassIdCollision(String, u32),
+
     /// Unable to add PID to cgroup.procs.
     #[error(display = "Unable to add PID to cgroup.procs")]
     AddCGroupPid(#[error(source)] io::Error),
@@ -46,21 +58,39 @@ pub enum Error {
     /// Unable to read /proc/mounts
     #[error(display = "Failed to read /proc/mounts")]
     ListMounts(#[error(source)] io::Error),
+
+    /// Unable to create the network namespace for excluded processes.
+    #[error(display = "Unable to create network namespace for excluded processes")]
+    CreateNetNamespace(#[error(source)] io::Error),
+
+    /// Unable to read or set `net.ipv4.ip_forward`.
+    #[error(display = "Unable to configure IPv4 forwarding")]
+    SetIpForwarding(#[error(source)] io::Error),
+
+    /// Unable to restrict forwarding to the exclusion network namespace.
+    #[error(display = "Unable to restrict forwarding to the network namespace")]
+    SetForwardFilter(#[error(source)] crate::firewall::Error),
+
+    /// The network connecting the exclusion namespace is already in use on the host.
+    #[error(display = "The network namespace subnet {} collides with {}", _0, _1)]
+    VethNetworkCollision(ipnetwork::Ipv4Network, ipnetwork::Ipv4Network),
 }
 
 /// Manages PIDs in the Linux Cgroup excluded from the VPN tunnel.
 pub struct PidManager {
     net_cls_path: PathBuf,
+    net_cls_classid: u32,
 }
 
 impl PidManager {
     /// Creates a new PID Cgroup manager.
     ///
     /// Finds the corresponding Cgroup to use. Will mount a `net_cls` filesystem
-    /// if none exists.
-    pub fn new() -> Result<PidManager, Error> {
+    /// if none exists. Packets sent by processes in the cgroup are tagged with `net_cls_classid`.
+    pub fn new(net_cls_classid: u32) -> Result<PidManager, Error> {
         let manager = PidManager {
             net_cls_path: Self::create_cgroup()?,
+            net_cls_classid,
         };
         manager.setup_exclusion_group()?;
         Ok(manager)
@@ -99,8 +129,17 @@ impl PidManager {
             fs::create_dir(exclusions_dir.clone()).map_err(Error::CreateCGroup)?;
         }
 
+        if let Some(path) = find_classid_collision(&self.net_cls_path, self.net_cls_classid)
+            .map_err(Error::ReadCGroupClassIds)?
+        {
+            return Err(Error::CGroupClassIdCollision(
+                path.display().to_string(),
+                self.net_cls_classid,
+            ));
+        }
+
         let classid_path = exclusions_dir.join("net_cls.classid");
-        fs::write(classid_path, NET_CLS_CLASSID.to_string().as_bytes())
+        fs::write(classid_path, self.net_cls_classid.to_string().as_bytes())
             .map_err(Error::SetCGroupClassId)
     }
 
@@ -170,3 +209,62 @@ impl PidManager {
         Ok(())
     }
 }
+
+/// Returns the first cgroup below `net_cls_path`, other than our own, whose class ID is
+/// `classid`. Packets from such a cgroup would otherwise be treated as excluded traffic.
+fn find_classid_collision(net_cls_path: &Path, classid: u32) -> io::Result<Option<PathBuf>> {
+    let own_cgroup = net_cls_path.join(SPLIT_TUNNEL_CGROUP_NAME);
+    let mut pending = vec![net_cls_path.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        if dir != own_cgroup {
+            match fs::read_to_string(dir.join("net_cls.classid")) {
+                Ok(contents) if contents.trim().parse::<u32>().ok() == Some(classid) => {
+                    return Ok(Some(dir))
+                }
+                Ok(_) => (),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+                Err(error) => return Err(error),
+            }
+        }
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                pending.push(entry.path());
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn create_cgroup(path: &Path, classid: u32) {
+        fs::create_dir_all(path).unwrap();
+        fs::write(path.join("net_cls.classid"), classid.to_string()).unwrap();
+    }
+
+    #[test]
+    fn test_find_classid_collision() {
+        let net_cls = tempfile::tempdir().unwrap();
+        create_cgroup(net_cls.path(), 0);
+        create_cgroup(
+            &net_cls.path().join(SPLIT_TUNNEL_CGROUP_NAME),
+            NET_CLS_CLASSID,
+        );
+        create_cgroup(&net_cls.path().join("other"), 1);
+        assert_eq!(
+            find_classid_collision(net_cls.path(), NET_CLS_CLASSID).unwrap(),
+            None
+        );
+
+        let colliding = net_cls.path().join("other").join("nested");
+        create_cgroup(&colliding, NET_CLS_CLASSID);
+        assert_eq!(
+            find_classid_collision(net_cls.path(), NET_CLS_CLASSID).unwrap(),
+            Some(colliding)
+        );
+    }
+}
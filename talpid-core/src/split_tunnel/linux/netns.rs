@@ -0,0 +1,246 @@
+use super::Error;
+use crate::firewall;
+use ipnetwork::Ipv4Network;
+use std::{fs, io, net::Ipv4Addr, path::Path};
+use talpid_types::{
+    net_namespace::{
+        netns_path, veth_network, HOST_VETH_ADDRESS, HOST_VETH_NAME, NAMESPACE_VETH_ADDRESS,
+        NAMESPACE_VETH_NAME, SPLIT_TUNNEL_NETNS_NAME, VETH_PREFIX,
+    },
+    ErrorExt,
+};
+
+const PROC_SYS_NET_IPV4_IP_FORWARD: &str = "/proc/sys/net/ipv4/ip_forward";
+
+/// Exists while forwarding is enabled on behalf of the exclusion namespace. It is kept in a
+/// tmpfs, like the sysctl itself, so that forwarding can be restored after a crash.
+const IP_FORWARD_MARKER_PATH: &str = "/run/mullvad-exclusions-ip-forward";
+
+/// A network namespace that excluded processes can be launched in using `mullvad-exclude`.
+///
+/// The namespace is connected to the host using a veth pair. Traffic leaving the namespace is
+/// marked by the firewall so that it bypasses the tunnel routing table, and is then masqueraded
+/// out of the physical interface. The namespace and the veth pair are removed when this is
+/// dropped.
+pub struct ExclusionNamespace {
+    /// Value of `net.ipv4.ip_forward` before the namespace was created.
+    ip_forward_was_enabled: bool,
+}
+
+impl ExclusionNamespace {
+    /// Creates the exclusion namespace, replacing any stale namespace left behind by a previous
+    /// instance.
+    pub fn new() -> Result<Self, Error> {
+        Self::remove_stale();
+
+        if let Some(network) = find_colliding_network().map_err(Error::CreateNetNamespace)? {
+            return Err(Error::VethNetworkCollision(veth_network(), network));
+        }
+
+        let ip_forward_was_enabled = ip_forwarding_enabled().map_err(Error::SetIpForwarding)?;
+
+        let namespace = ExclusionNamespace {
+            ip_forward_was_enabled,
+        };
+        create_namespace().map_err(Error::CreateNetNamespace)?;
+        if !ip_forward_was_enabled {
+            // Replies are forwarded from the physical interface, so forwarding cannot be enabled
+            // for the veth alone. Instead, everything else is kept from being forwarded.
+            fs::write(IP_FORWARD_MARKER_PATH, b"").map_err(Error::SetIpForwarding)?;
+            firewall::set_exclusion_forward_filter(true).map_err(Error::SetForwardFilter)?;
+            set_ip_forwarding(true).map_err(Error::SetIpForwarding)?;
+        }
+
+        log::debug!("Created network namespace {}", SPLIT_TUNNEL_NETNS_NAME);
+
+        Ok(namespace)
+    }
+
+    /// Removes the namespace and restores forwarding if a previous instance did not get to do so,
+    /// e.g. because of a crash.
+    pub fn remove_stale() {
+        // Errors are expected if nothing exists
+        let _ = delete_namespace();
+        if Path::new(IP_FORWARD_MARKER_PATH).exists() {
+            log::debug!("Restoring forwarding settings left by a previous exclusion namespace");
+            restore_ip_forwarding();
+        }
+    }
+}
+
+impl Drop for ExclusionNamespace {
+    fn drop(&mut self) {
+        if let Err(error) = delete_namespace() {
+            log::error!(
+                "Failed to remove network namespace {}: {}",
+                SPLIT_TUNNEL_NETNS_NAME,
+                error
+            );
+        } else {
+            log::debug!("Removed network namespace {}", SPLIT_TUNNEL_NETNS_NAME);
+        }
+        if !self.ip_forward_was_enabled {
+            restore_ip_forwarding();
+        }
+    }
+}
+
+/// Disables forwarding that was enabled for the exclusion namespace.
+fn restore_ip_forwarding() {
+    if let Err(error) = set_ip_forwarding(false) {
+        log::error!("Failed to restore net.ipv4.ip_forward: {}", error);
+        // Keep the filter and marker, so that forwarding stays restricted until restored
+        return;
+    }
+    if let Err(error) = firewall::set_exclusion_forward_filter(false) {
+        log::error!(
+            "{}",
+            error.display_chain_with_msg("Failed to remove forwarding filter")
+        );
+    }
+    if let Err(error) = fs::remove_file(IP_FORWARD_MARKER_PATH) {
+        log::error!("Failed to remove {}: {}", IP_FORWARD_MARKER_PATH, error);
+    }
+}
+
+/// Returns an address or route on the host that overlaps with the veth network, if any.
+fn find_colliding_network() -> io::Result<Option<Ipv4Network>> {
+    let addresses = ip_output(&["-4", "-o", "addr", "show"])?;
+    let routes = ip_output(&["-4", "route", "show", "table", "all"])?;
+    Ok(colliding_network(&addresses, &routes, veth_network()))
+}
+
+fn colliding_network(addresses: &str, routes: &str, veth: Ipv4Network) -> Option<Ipv4Network> {
+    // Lines look like `2: eth0    inet 192.168.1.2/24 brd ...`
+    let address_networks = addresses.lines().filter_map(|line| {
+        let mut fields = line.split_whitespace();
+        fields.find(|field| *field == "inet")?;
+        fields.next()?.parse().ok()
+    });
+    // Lines look like `[type] 192.168.1.0/24 dev eth0 ...`, or `default via ...`
+    let route_networks = routes.lines().filter_map(|line| {
+        line.split_whitespace()
+            .take(2)
+            .find_map(|field| field.parse::<Ipv4Network>().ok())
+            .filter(|network| network.prefix() > 0)
+    });
+    address_networks
+        .chain(route_networks)
+        .find(|network: &Ipv4Network| {
+            network.contains(veth.network()) || veth.contains(network.network())
+        })
+}
+
+fn create_namespace() -> io::Result<()> {
+    ip(&["netns", "add", SPLIT_TUNNEL_NETNS_NAME])?;
+    ip(&[
+        "link",
+        "add",
+        HOST_VETH_NAME,
+        "type",
+        "veth",
+        "peer",
+        "name",
+        NAMESPACE_VETH_NAME,
+        "netns",
+        SPLIT_TUNNEL_NETNS_NAME,
+    ])?;
+
+    ip(&[
+        "addr",
+        "add",
+        &cidr(HOST_VETH_ADDRESS),
+        "dev",
+        HOST_VETH_NAME,
+    ])?;
+    ip(&["link", "set", HOST_VETH_NAME, "up"])?;
+
+    ip_netns(&["link", "set", "lo", "up"])?;
+    ip_netns(&[
+        "addr",
+        "add",
+        &cidr(NAMESPACE_VETH_ADDRESS),
+        "dev",
+        NAMESPACE_VETH_NAME,
+    ])?;
+    ip_netns(&["link", "set", NAMESPACE_VETH_NAME, "up"])?;
+    ip_netns(&[
+        "route",
+        "add",
+        "default",
+        "via",
+        &HOST_VETH_ADDRESS.to_string(),
+        "dev",
+        NAMESPACE_VETH_NAME,
+    ])
+}
+
+fn delete_namespace() -> io::Result<()> {
+    // Deleting one end of the veth pair also deletes the other end
+    let link_result = ip(&["link", "delete", HOST_VETH_NAME]);
+    if netns_path().exists() {
+        ip(&["netns", "delete", SPLIT_TUNNEL_NETNS_NAME])?;
+    }
+    link_result
+}
+
+fn ip_output(args: &[&str]) -> io::Result<String> {
+    duct::cmd("ip", args).stderr_capture().read()
+}
+
+fn ip(args: &[&str]) -> io::Result<()> {
+    duct::cmd("ip", args)
+        .stdout_null()
+        .stderr_capture()
+        .run()
+        .map(|_| ())
+}
+
+fn ip_netns(args: &[&str]) -> io::Result<()> {
+    let mut netns_args = vec!["-n", SPLIT_TUNNEL_NETNS_NAME];
+    netns_args.extend_from_slice(args);
+    ip(&netns_args)
+}
+
+fn cidr(addr: Ipv4Addr) -> String {
+    format!("{addr}/{VETH_PREFIX}")
+}
+
+fn ip_forwarding_enabled() -> io::Result<bool> {
+    Ok(fs::read_to_string(PROC_SYS_NET_IPV4_IP_FORWARD)?.trim() == "1")
+}
+
+fn set_ip_forwarding(enabled: bool) -> io::Result<()> {
+    fs::write(
+        PROC_SYS_NET_IPV4_IP_FORWARD,
+        if enabled { b"1" } else { b"0" },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_colliding_network() {
+        let veth = veth_network();
+        let addresses = "1: lo    inet 127.0.0.1/8 scope host lo\n\
+                         2: eth0    inet 192.168.1.2/24 brd 192.168.1.255 scope global eth0\n";
+        let routes = "default via 192.168.1.1 dev eth0\n\
+                      192.168.1.0/24 dev eth0 proto kernel scope link src 192.168.1.2\n\
+                      local 127.0.0.0/8 dev lo table local proto kernel scope host\n";
+        assert_eq!(colliding_network(addresses, routes, veth), None);
+
+        let addresses = "3: docker0    inet 172.16.0.1/12 scope global docker0\n";
+        assert_eq!(
+            colliding_network(addresses, routes, veth),
+            Some("172.16.0.1/12".parse().unwrap())
+        );
+
+        let routes = "172.25.254.2 via 192.168.1.1 dev eth0\n";
+        assert_eq!(
+            colliding_network("", routes, veth),
+            Some("172.25.254.2/32".parse().unwrap())
+        );
+    }
+}
@@ -1,15 +1,15 @@
 use crate::logging;
 #[cfg(not(target_os = "android"))]
-use futures::channel::oneshot;
+use futures::channel::{mpsc, oneshot};
 use std::path;
 #[cfg(not(target_os = "android"))]
 use talpid_openvpn;
 #[cfg(any(target_os = "linux", target_os = "windows"))]
 use talpid_routing::RouteManagerHandle;
 pub use talpid_tunnel::{TunnelArgs, TunnelEvent, TunnelMetadata};
-#[cfg(not(target_os = "android"))]
-use talpid_types::net::openvpn as openvpn_types;
 use talpid_types::net::{wireguard as wireguard_types, TunnelParameters};
+#[cfg(not(target_os = "android"))]
+use talpid_types::{net::openvpn as openvpn_types, tunnel::TunnelStatistics};
 
 /// A module for all WireGuard related tunnel management.
 use talpid_wireguard;
@@ -91,6 +91,7 @@ impl TunnelMonitor {
                 args.resource_dir,
                 args.on_event,
                 args.tunnel_close_rx,
+                args.stats_request_rx,
                 #[cfg(target_os = "linux")]
                 args.route_manager,
             )),
@@ -213,6 +214,7 @@ impl TunnelMonitor {
         resource_dir: &path::Path,
         on_event: L,
         tunnel_close_rx: oneshot::Receiver<()>,
+        stats_request_rx: mpsc::UnboundedReceiver<oneshot::Sender<TunnelStatistics>>,
         #[cfg(target_os = "linux")] route_manager: RouteManagerHandle,
     ) -> Result<Self>
     where
@@ -227,6 +229,7 @@ impl TunnelMonitor {
             log,
             resource_dir,
             tunnel_close_rx,
+            stats_request_rx,
             #[cfg(target_os = "linux")]
             route_manager,
         )
@@ -13,15 +13,21 @@ use futures::{
     stream::Fuse,
     StreamExt,
 };
-use std::net::IpAddr;
+use std::{net::IpAddr, time::Duration};
 use talpid_types::{
     net::TunnelParameters,
-    tunnel::{ErrorStateCause, FirewallPolicyError},
+    tunnel::{ErrorStateCause, FirewallPolicyError, TunnelStatistics},
     BoxedError, ErrorExt,
 };
 
 #[cfg(windows)]
 use crate::tunnel::TunnelMonitor;
+#[cfg(target_os = "linux")]
+use futures::FutureExt;
+#[cfg(target_os = "linux")]
+use talpid_routing::NetworkChange;
+#[cfg(target_os = "linux")]
+use talpid_types::net::AllowedTunnelTraffic;
 
 use super::connecting_state::TunnelCloseEvent;
 
@@ -34,6 +40,10 @@ pub struct ConnectedStateBootstrap {
     pub tunnel_parameters: TunnelParameters,
     pub tunnel_close_event: TunnelCloseEvent,
     pub tunnel_close_tx: oneshot::Sender<()>,
+    #[cfg(target_os = "linux")]
+    pub network_change_tx: mpsc::UnboundedSender<()>,
+    pub stats_request_tx: mpsc::UnboundedSender<oneshot::Sender<TunnelStatistics>>,
+    pub psk_rekey_interval_tx: mpsc::UnboundedSender<Option<Duration>>,
 }
 
 /// The tunnel is up and working.
@@ -43,6 +53,20 @@ pub struct ConnectedState {
     tunnel_parameters: TunnelParameters,
     tunnel_close_event: TunnelCloseEvent,
     tunnel_close_tx: oneshot::Sender<()>,
+    #[cfg(target_os = "linux")]
+    network_change_tx: mpsc::UnboundedSender<()>,
+    stats_request_tx: mpsc::UnboundedSender<oneshot::Sender<TunnelStatistics>>,
+    psk_rekey_interval_tx: mpsc::UnboundedSender<Option<Duration>>,
+    /// Tunnel that is being set up to replace this one while this one keeps carrying traffic.
+    #[cfg(target_os = "linux")]
+    replacement: Option<ConnectingState>,
+}
+
+/// Result of waiting for an event from either the current tunnel or its replacement.
+enum ConnectedEventResult {
+    Current(EventResult),
+    #[cfg(target_os = "linux")]
+    Replacement(EventResult),
 }
 
 impl ConnectedState {
@@ -53,6 +77,12 @@ impl ConnectedState {
             tunnel_parameters: bootstrap.tunnel_parameters,
             tunnel_close_event: bootstrap.tunnel_close_event,
             tunnel_close_tx: bootstrap.tunnel_close_tx,
+            #[cfg(target_os = "linux")]
+            network_change_tx: bootstrap.network_change_tx,
+            stats_request_tx: bootstrap.stats_request_tx,
+            psk_rekey_interval_tx: bootstrap.psk_rekey_interval_tx,
+            #[cfg(target_os = "linux")]
+            replacement: None,
         }
     }
 
@@ -107,6 +137,8 @@ impl ConnectedState {
     fn get_firewall_policy(&self, shared_values: &SharedTunnelStateValues) -> FirewallPolicy {
         FirewallPolicy::Connected {
             peer_endpoint: self.tunnel_parameters.get_next_hop_endpoint(),
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            peer_udp_relay: self.tunnel_parameters.get_next_hop_udp_relay(),
             tunnel: self.metadata.clone(),
             allow_lan: shared_values.allow_lan,
             #[cfg(not(target_os = "android"))]
@@ -116,6 +148,11 @@ impl ConnectedState {
                 &shared_values.resource_dir,
                 &self.tunnel_parameters,
             ),
+            #[cfg(target_os = "linux")]
+            replacement: self
+                .replacement
+                .as_ref()
+                .map(ConnectingState::replacement_firewall_policy),
         }
     }
 
@@ -163,10 +200,12 @@ impl ConnectedState {
     }
 
     fn disconnect(
-        self,
+        #[cfg_attr(not(target_os = "linux"), allow(unused_mut))] mut self,
         shared_values: &mut SharedTunnelStateValues,
         after_disconnect: AfterDisconnect,
     ) -> EventConsequence {
+        #[cfg(target_os = "linux")]
+        self.close_replacement(shared_values);
         Self::reset_dns(shared_values);
         Self::reset_routes(shared_values);
 
@@ -209,12 +248,70 @@ impl ConnectedState {
                     }
                 }
             }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::TrustedOverlays(overlays)) => {
+                if shared_values.set_trusted_overlays(overlays) {
+                    shared_values.warn_about_missing_overlay_interfaces();
+                    if let Err(error) = self.set_firewall_policy(shared_values) {
+                        return self.disconnect(
+                            shared_values,
+                            AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                        );
+                    }
+                }
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::UserRoutes(user_routes)) => {
+                if shared_values.set_user_routes(user_routes) {
+                    if let Err(error) = self.set_firewall_policy(shared_values) {
+                        return self.disconnect(
+                            shared_values,
+                            AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                        );
+                    }
+                }
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::NetworkChanged(change)) => {
+                let tunnel_interface_changed = match &change {
+                    NetworkChange::DefaultRoute => false,
+                    NetworkChange::InterfaceUp(name) | NetworkChange::InterfaceDown(name) => {
+                        *name == self.metadata.interface
+                    }
+                };
+                if !tunnel_interface_changed {
+                    log::debug!("Network changed ({:?}), probing tunnel", change);
+                    let _ = self.network_change_tx.unbounded_send(());
+                }
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetStatistics(tx)) => {
+                let _ = self.stats_request_tx.unbounded_send(tx);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::PskRekeyInterval(interval)) => {
+                let _ = self.psk_rekey_interval_tx.unbounded_send(interval);
+                SameState(self.into())
+            }
             Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
                 shared_values.allowed_endpoint = endpoint;
                 let _ = tx.send(());
                 SameState(self.into())
             }
             Some(TunnelCommand::Dns(servers)) => match shared_values.set_dns_servers(servers) {
+                #[cfg(not(target_os = "android"))]
+                Ok(true)
+                    if !super::connecting_state::blocked_dns_probes(
+                        &self.tunnel_parameters,
+                        shared_values.dns_servers.as_deref(),
+                    )
+                    .is_empty() =>
+                {
+                    log::info!("Reconnecting since the DNS servers no longer answer DNS probes");
+                    self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+                }
                 Ok(true) => {
                     if let Err(error) = self.set_firewall_policy(shared_values) {
                         return self.disconnect(
@@ -258,7 +355,13 @@ impl ConnectedState {
                 }
             }
             Some(TunnelCommand::Connect) => {
-                self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+                cfg_if! {
+                    if #[cfg(target_os = "linux")] {
+                        self.replace_tunnel(shared_values)
+                    } else {
+                        self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+                    }
+                }
             }
             Some(TunnelCommand::Disconnect) | None => {
                 self.disconnect(shared_values, AfterDisconnect::Nothing)
@@ -290,17 +393,28 @@ impl ConnectedState {
             Some((TunnelEvent::Down, _)) | None => {
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
+            Some((TunnelEvent::Renegotiated, _)) => {
+                log::debug!("Tunnel keys were renegotiated");
+                SameState(self.into())
+            }
+            Some((TunnelEvent::Closing(reason), _)) => {
+                log::info!("Tunnel is closing: {}", reason);
+                SameState(self.into())
+            }
             Some(_) => SameState(self.into()),
         }
     }
 
     fn handle_tunnel_close_event(
-        self,
+        #[cfg_attr(not(target_os = "linux"), allow(unused_mut))] mut self,
         block_reason: Option<ErrorStateCause>,
         shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence {
         use self::EventConsequence::*;
 
+        #[cfg(target_os = "linux")]
+        self.close_replacement(shared_values);
+
         if let Some(block_reason) = block_reason {
             Self::reset_dns(shared_values);
             Self::reset_routes(shared_values);
@@ -312,6 +426,159 @@ impl ConnectedState {
         Self::reset_routes(shared_values);
         NewState(ConnectingState::enter(shared_values, 0))
     }
+
+    /// Brings up a tunnel with new parameters next to the current one, so that traffic can keep
+    /// flowing until the new tunnel is known to work. Falls back on reconnecting if that is not
+    /// possible.
+    #[cfg(target_os = "linux")]
+    fn replace_tunnel(mut self, shared_values: &mut SharedTunnelStateValues) -> EventConsequence {
+        use self::EventConsequence::*;
+
+        if self.replacement.is_some() {
+            log::debug!("Already replacing the tunnel, reconnecting instead");
+            return self.disconnect(shared_values, AfterDisconnect::Reconnect(0));
+        }
+
+        let parameters = match shared_values
+            .runtime
+            .block_on(shared_values.tunnel_parameters_generator.generate(0))
+        {
+            Ok(parameters) => parameters,
+            // Let the connecting state deal with the error
+            Err(_) => return self.disconnect(shared_values, AfterDisconnect::Reconnect(0)),
+        };
+        if !can_replace(&self.tunnel_parameters, &parameters) {
+            return self.disconnect(shared_values, AfterDisconnect::Reconnect(0));
+        }
+
+        log::info!(
+            "Setting up tunnel to {} before closing the current one",
+            parameters.get_next_hop_endpoint()
+        );
+        self.replacement = Some(ConnectingState::start_replacement(
+            shared_values,
+            parameters,
+            self.metadata.interface.clone(),
+        ));
+
+        match self.set_firewall_policy(shared_values) {
+            Ok(()) => SameState(self.into()),
+            Err(error) => self.disconnect(
+                shared_values,
+                AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+            ),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn close_replacement(&mut self, shared_values: &mut SharedTunnelStateValues) {
+        if let Some(replacement) = self.replacement.take() {
+            replacement.close(&shared_values.runtime);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn handle_replacement_event(
+        mut self,
+        result: EventResult,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> EventConsequence {
+        use self::EventConsequence::*;
+
+        let replacement = match self.replacement.as_mut() {
+            Some(replacement) => replacement,
+            None => return SameState(self.into()),
+        };
+
+        match ReplacementAction::from_event(result) {
+            ReplacementAction::InterfaceUp(metadata, allowed_tunnel_traffic, _done_tx) => {
+                replacement.set_interface_up(metadata, allowed_tunnel_traffic);
+                match self.set_firewall_policy(shared_values) {
+                    Ok(()) => SameState(self.into()),
+                    Err(error) => self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    ),
+                }
+            }
+            ReplacementAction::SwitchOver(metadata, _done_tx) => {
+                // The new tunnel has taken over the routes. Move the firewall and DNS over to it
+                // and close the old tunnel.
+                log::info!("Switching over to tunnel on {}", metadata.interface);
+                let replacement = self.replacement.take().unwrap();
+                let _ = self.tunnel_close_tx.send(());
+                let tunnel_close_event = self.tunnel_close_event;
+                shared_values.runtime.spawn(async move {
+                    let _ = tunnel_close_event.await;
+                });
+
+                NewState(ConnectedState::enter(
+                    shared_values,
+                    replacement.into_connected_state_bootstrap(metadata),
+                ))
+            }
+            ReplacementAction::InterfaceDown => {
+                replacement.set_interface_down();
+                SameState(self.into())
+            }
+            ReplacementAction::Ignore => SameState(self.into()),
+            ReplacementAction::Abort => {
+                log::warn!("Failed to set up the new tunnel. Reconnecting.");
+                self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+            }
+        }
+    }
+}
+
+/// Returns whether a tunnel using `new` can be set up while the tunnel using `current` is still
+/// up.
+#[cfg(target_os = "linux")]
+fn can_replace(current: &TunnelParameters, new: &TunnelParameters) -> bool {
+    match (current, new) {
+        (TunnelParameters::Wireguard(current), TunnelParameters::Wireguard(new)) => {
+            // Multihop and PQ tunnels need routes through the new tunnel while being set up
+            new.connection.exit_peer.is_none()
+                && !new.options.quantum_resistant
+                && current.generic_options.enable_ipv6 == new.generic_options.enable_ipv6
+        }
+        _ => false,
+    }
+}
+
+/// What to do in response to an event from a replacement tunnel.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+enum ReplacementAction {
+    /// The interface of the new tunnel is up, so traffic on it must be allowed. The tunnel waits
+    /// for the sender to be dropped before it proceeds.
+    InterfaceUp(TunnelMetadata, AllowedTunnelTraffic, oneshot::Sender<()>),
+    /// The new tunnel works and has taken over the routes.
+    SwitchOver(TunnelMetadata, oneshot::Sender<()>),
+    /// The interface of the new tunnel went down again.
+    InterfaceDown,
+    Ignore,
+    /// The new tunnel could not be set up.
+    Abort,
+}
+
+#[cfg(target_os = "linux")]
+impl ReplacementAction {
+    fn from_event(result: EventResult) -> Self {
+        match result {
+            EventResult::Event(Some((
+                TunnelEvent::InterfaceUp(metadata, allowed_tunnel_traffic),
+                done_tx,
+            ))) => ReplacementAction::InterfaceUp(metadata, allowed_tunnel_traffic, done_tx),
+            EventResult::Event(Some((TunnelEvent::Up(metadata), done_tx))) => {
+                ReplacementAction::SwitchOver(metadata, done_tx)
+            }
+            EventResult::Event(Some((TunnelEvent::Down, _))) => ReplacementAction::InterfaceDown,
+            EventResult::Event(Some((TunnelEvent::Renegotiated | TunnelEvent::Closing(_), _))) => {
+                ReplacementAction::Ignore
+            }
+            _ => ReplacementAction::Abort,
+        }
+    }
 }
 
 impl TunnelState for ConnectedState {
@@ -323,7 +590,8 @@ impl TunnelState for ConnectedState {
         bootstrap: Self::Bootstrap,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
         let connected_state = ConnectedState::from(bootstrap);
-        let tunnel_endpoint = connected_state.tunnel_parameters.get_tunnel_endpoint();
+        let mut tunnel_endpoint = connected_state.tunnel_parameters.get_tunnel_endpoint();
+        tunnel_endpoint.mtu = connected_state.metadata.mtu;
 
         if let Err(error) = connected_state.set_firewall_policy(shared_values) {
             DisconnectingState::enter(
@@ -345,6 +613,9 @@ impl TunnelState for ConnectedState {
                 ),
             )
         } else {
+            #[cfg(target_os = "linux")]
+            shared_values.warn_about_missing_overlay_interfaces();
+
             (
                 TunnelStateWrapper::from(connected_state),
                 TunnelStateTransition::Connected(tunnel_endpoint),
@@ -359,23 +630,198 @@ impl TunnelState for ConnectedState {
         shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence {
         let result = runtime.block_on(async {
+            #[cfg(target_os = "linux")]
+            let replacement_event = next_replacement_event(&mut self.replacement).fuse();
+            #[cfg(not(target_os = "linux"))]
+            let replacement_event = futures::future::pending();
+            futures::pin_mut!(replacement_event);
+
             futures::select! {
-                command = commands.next() => EventResult::Command(command),
-                event = self.tunnel_events.next() => EventResult::Event(event),
-                result = &mut self.tunnel_close_event => EventResult::Close(result),
+                command = commands.next() => {
+                    ConnectedEventResult::Current(EventResult::Command(command))
+                }
+                event = self.tunnel_events.next() => {
+                    ConnectedEventResult::Current(EventResult::Event(event))
+                }
+                result = &mut self.tunnel_close_event => {
+                    ConnectedEventResult::Current(EventResult::Close(result))
+                }
+                result = replacement_event => result,
             }
         });
 
         match result {
-            EventResult::Command(command) => self.handle_commands(command, shared_values),
-            EventResult::Event(event) => self.handle_tunnel_events(event, shared_values),
-            EventResult::Close(result) => {
+            ConnectedEventResult::Current(EventResult::Command(command)) => {
+                self.handle_commands(command, shared_values)
+            }
+            ConnectedEventResult::Current(EventResult::Event(event)) => {
+                self.handle_tunnel_events(event, shared_values)
+            }
+            ConnectedEventResult::Current(EventResult::Close(result)) => {
                 if result.is_err() {
                     log::warn!("Tunnel monitor thread has stopped unexpectedly");
                 }
                 let block_reason = result.unwrap_or(None);
                 self.handle_tunnel_close_event(block_reason, shared_values)
             }
+            #[cfg(target_os = "linux")]
+            ConnectedEventResult::Replacement(result) => {
+                self.handle_replacement_event(result, shared_values)
+            }
         }
     }
 }
+
+#[cfg(target_os = "linux")]
+async fn next_replacement_event(replacement: &mut Option<ConnectingState>) -> ConnectedEventResult {
+    match replacement {
+        Some(replacement) => ConnectedEventResult::Replacement(replacement.next_event().await),
+        None => futures::future::pending().await,
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use talpid_types::net::{
+        openvpn,
+        wireguard::{
+            self, ConnectionConfig, ConnectivityProbes, PeerConfig, PrivateKey, TunnelConfig,
+        },
+        Endpoint, GenericTunnelOptions, TransportProtocol,
+    };
+
+    fn peer(endpoint: &str) -> PeerConfig {
+        PeerConfig {
+            public_key: PrivateKey::new_from_random().public_key(),
+            allowed_ips: vec!["0.0.0.0/0".parse().unwrap()],
+            endpoint: endpoint.parse().unwrap(),
+            psk: None,
+        }
+    }
+
+    fn wireguard_parameters(endpoint: &str) -> wireguard::TunnelParameters {
+        wireguard::TunnelParameters {
+            connection: ConnectionConfig {
+                tunnel: TunnelConfig {
+                    private_key: PrivateKey::new_from_random(),
+                    addresses: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2))],
+                },
+                peer: peer(endpoint),
+                exit_peer: None,
+                ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+                ipv6_gateway: None,
+                fwmark: None,
+            },
+            options: wireguard::TunnelOptions {
+                mtu: None,
+                mtu_discovery: false,
+                persistent_keepalive: None,
+                use_boringtun: false,
+                quantum_resistant: false,
+                quantum_resistant_rekey_interval: None,
+                connectivity_probes: ConnectivityProbes::default(),
+            },
+            generic_options: GenericTunnelOptions { enable_ipv6: false },
+            obfuscation: None,
+        }
+    }
+
+    fn metadata() -> TunnelMetadata {
+        TunnelMetadata {
+            interface: "wg0-mullvad".to_owned(),
+            ips: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2))],
+            ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+            ipv6_gateway: None,
+            mtu: None,
+        }
+    }
+
+    fn event(event: TunnelEvent) -> (EventResult, oneshot::Receiver<()>) {
+        let (done_tx, done_rx) = oneshot::channel();
+        (EventResult::Event(Some((event, done_tx))), done_rx)
+    }
+
+    #[test]
+    fn test_can_replace() {
+        let current = TunnelParameters::from(wireguard_parameters("1.2.3.4:51820"));
+        let new = wireguard_parameters("5.6.7.8:51820");
+        assert!(can_replace(&current, &TunnelParameters::from(new.clone())));
+
+        let mut multihop = new.clone();
+        multihop.connection.exit_peer = Some(peer("9.9.9.9:51820"));
+        assert!(!can_replace(&current, &TunnelParameters::from(multihop)));
+
+        let mut quantum_resistant = new.clone();
+        quantum_resistant.options.quantum_resistant = true;
+        assert!(!can_replace(
+            &current,
+            &TunnelParameters::from(quantum_resistant)
+        ));
+
+        let mut ipv6 = new;
+        ipv6.generic_options.enable_ipv6 = true;
+        assert!(!can_replace(&current, &TunnelParameters::from(ipv6)));
+
+        let openvpn = TunnelParameters::from(openvpn::TunnelParameters {
+            config: openvpn::ConnectionConfig::new(
+                Endpoint::new(Ipv4Addr::new(5, 6, 7, 8), 1194, TransportProtocol::Udp),
+                "username".to_owned(),
+                "password".to_owned(),
+            ),
+            options: Default::default(),
+            generic_options: GenericTunnelOptions { enable_ipv6: false },
+            proxy: None,
+            fwmark: 0,
+        });
+        assert!(!can_replace(&current, &openvpn));
+        assert!(!can_replace(&openvpn, &current));
+    }
+
+    #[test]
+    fn test_replacement_action() {
+        let (result, done_rx) = event(TunnelEvent::InterfaceUp(
+            metadata(),
+            AllowedTunnelTraffic::All,
+        ));
+        let action = ReplacementAction::from_event(result);
+        assert!(matches!(
+            &action,
+            ReplacementAction::InterfaceUp(metadata, AllowedTunnelTraffic::All, _)
+                if metadata.interface == "wg0-mullvad"
+        ));
+        // The tunnel must not proceed until the action has been carried out
+        let mut done_rx = done_rx;
+        assert_eq!(done_rx.try_recv(), Ok(None));
+        drop(action);
+        assert!(done_rx.try_recv().is_err());
+
+        let (result, _done_rx) = event(TunnelEvent::Up(metadata()));
+        assert!(matches!(
+            ReplacementAction::from_event(result),
+            ReplacementAction::SwitchOver(..)
+        ));
+
+        let (result, _done_rx) = event(TunnelEvent::Down);
+        assert!(matches!(
+            ReplacementAction::from_event(result),
+            ReplacementAction::InterfaceDown
+        ));
+
+        let (result, _done_rx) = event(TunnelEvent::Renegotiated);
+        assert!(matches!(
+            ReplacementAction::from_event(result),
+            ReplacementAction::Ignore
+        ));
+
+        assert!(matches!(
+            ReplacementAction::from_event(EventResult::Event(None)),
+            ReplacementAction::Abort
+        ));
+        assert!(matches!(
+            ReplacementAction::from_event(EventResult::Close(Ok(None))),
+            ReplacementAction::Abort
+        ));
+    }
+}
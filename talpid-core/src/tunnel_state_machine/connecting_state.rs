@@ -3,6 +3,8 @@ use super::{
     EventConsequence, EventResult, SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver,
     TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
+#[cfg(target_os = "linux")]
+use crate::firewall::ReplacementTunnel;
 use crate::{
     firewall::FirewallPolicy,
     tunnel::{self, TunnelMonitor},
@@ -14,6 +16,7 @@ use futures::{
     FutureExt, StreamExt,
 };
 use std::{
+    net::IpAddr,
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
     thread,
@@ -21,9 +24,11 @@ use std::{
 };
 use talpid_routing::RouteManager;
 use talpid_tunnel::{tun_provider::TunProvider, TunnelArgs, TunnelEvent, TunnelMetadata};
+#[cfg(not(target_os = "android"))]
+use talpid_types::net::wireguard::ConnectivityProbe;
 use talpid_types::{
     net::{AllowedTunnelTraffic, TunnelParameters},
-    tunnel::{ErrorStateCause, FirewallPolicyError},
+    tunnel::{ErrorStateCause, FirewallPolicyError, TunnelStatistics},
     ErrorExt,
 };
 
@@ -50,6 +55,10 @@ pub struct ConnectingState {
     allowed_tunnel_traffic: AllowedTunnelTraffic,
     tunnel_close_event: TunnelCloseEvent,
     tunnel_close_tx: oneshot::Sender<()>,
+    #[cfg(target_os = "linux")]
+    network_change_tx: mpsc::UnboundedSender<()>,
+    stats_request_tx: mpsc::UnboundedSender<oneshot::Sender<TunnelStatistics>>,
+    psk_rekey_interval_tx: mpsc::UnboundedSender<Option<Duration>>,
     retry_attempt: u32,
 }
 
@@ -67,6 +76,10 @@ impl ConnectingState {
 
         let policy = FirewallPolicy::Connecting {
             peer_endpoint,
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            peer_udp_relay: params.get_next_hop_udp_relay(),
+            #[cfg(target_os = "linux")]
+            probe_path_mtu: params.get_mtu_discovery(),
             tunnel: tunnel_metadata.clone(),
             allow_lan: shared_values.allow_lan,
             allowed_endpoint: shared_values.allowed_endpoint.clone(),
@@ -100,6 +113,7 @@ impl ConnectingState {
         tun_provider: Arc<Mutex<TunProvider>>,
         route_manager: &mut RouteManager,
         retry_attempt: u32,
+        #[cfg(target_os = "linux")] replaces_interface: Option<String>,
     ) -> Self {
         let (event_tx, event_rx) = mpsc::unbounded();
         let on_tunnel_event =
@@ -117,6 +131,10 @@ impl ConnectingState {
 
         let (tunnel_close_tx, tunnel_close_rx) = oneshot::channel();
         let (tunnel_close_event_tx, tunnel_close_event_rx) = oneshot::channel();
+        #[cfg(target_os = "linux")]
+        let (network_change_tx, network_change_rx) = mpsc::unbounded();
+        let (stats_request_tx, stats_request_rx) = mpsc::unbounded();
+        let (psk_rekey_interval_tx, psk_rekey_interval_rx) = mpsc::unbounded();
 
         let mut tunnel_parameters = parameters.clone();
 
@@ -150,6 +168,12 @@ impl ConnectingState {
                 tun_provider,
                 retry_attempt,
                 route_manager: route_manager_handle,
+                #[cfg(target_os = "linux")]
+                network_change_rx,
+                #[cfg(target_os = "linux")]
+                replaces_interface,
+                stats_request_rx,
+                psk_rekey_interval_rx,
             };
 
             let block_reason = match TunnelMonitor::start(&mut tunnel_parameters, &log_dir, args) {
@@ -213,6 +237,10 @@ impl ConnectingState {
             allowed_tunnel_traffic: INITIAL_ALLOWED_TUNNEL_TRAFFIC,
             tunnel_close_event: tunnel_close_event_rx.fuse(),
             tunnel_close_tx,
+            #[cfg(target_os = "linux")]
+            network_change_tx,
+            stats_request_tx,
+            psk_rekey_interval_tx,
             retry_attempt,
         }
     }
@@ -250,13 +278,20 @@ impl ConnectingState {
         }
     }
 
-    fn into_connected_state_bootstrap(self, metadata: TunnelMetadata) -> ConnectedStateBootstrap {
+    pub(super) fn into_connected_state_bootstrap(
+        self,
+        metadata: TunnelMetadata,
+    ) -> ConnectedStateBootstrap {
         ConnectedStateBootstrap {
             metadata,
             tunnel_events: self.tunnel_events,
             tunnel_parameters: self.tunnel_parameters,
             tunnel_close_event: self.tunnel_close_event,
             tunnel_close_tx: self.tunnel_close_tx,
+            #[cfg(target_os = "linux")]
+            network_change_tx: self.network_change_tx,
+            stats_request_tx: self.stats_request_tx,
+            psk_rekey_interval_tx: self.psk_rekey_interval_tx,
         }
     }
 
@@ -331,6 +366,29 @@ impl ConnectingState {
                     self.reset_firewall(shared_values)
                 }
             }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::TrustedOverlays(overlays)) => {
+                if shared_values.set_trusted_overlays(overlays) {
+                    self.reset_firewall(shared_values)
+                } else {
+                    SameState(self.into())
+                }
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::UserRoutes(user_routes)) => {
+                if shared_values.set_user_routes(user_routes) {
+                    self.reset_firewall(shared_values)
+                } else {
+                    SameState(self.into())
+                }
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::NetworkChanged(_)) => SameState(self.into()),
+            Some(TunnelCommand::GetStatistics(_)) => SameState(self.into()),
+            Some(TunnelCommand::PskRekeyInterval(interval)) => {
+                let _ = self.psk_rekey_interval_tx.unbounded_send(interval);
+                SameState(self.into())
+            }
             Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
                 if shared_values.allowed_endpoint != endpoint {
                     shared_values.allowed_endpoint = endpoint;
@@ -353,6 +411,17 @@ impl ConnectingState {
             Some(TunnelCommand::Dns(servers)) => match shared_values.set_dns_servers(servers) {
                 #[cfg(target_os = "android")]
                 Ok(true) => self.disconnect(shared_values, AfterDisconnect::Reconnect(0)),
+                #[cfg(not(target_os = "android"))]
+                Ok(true)
+                    if !blocked_dns_probes(
+                        &self.tunnel_parameters,
+                        shared_values.dns_servers.as_deref(),
+                    )
+                    .is_empty() =>
+                {
+                    log::info!("Reconnecting since the DNS servers no longer answer DNS probes");
+                    self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+                }
                 Ok(_) => SameState(self.into()),
                 Err(cause) => self.disconnect(shared_values, AfterDisconnect::Block(cause)),
             },
@@ -443,6 +512,12 @@ impl ConnectingState {
                 shared_values,
                 self.into_connected_state_bootstrap(metadata),
             )),
+            Some((TunnelEvent::Renegotiated, _)) => SameState(self.into()),
+            Some((TunnelEvent::Closing(reason), _)) => {
+                // The tunnel monitor reports the failure once the process has exited
+                log::info!("Tunnel is closing: {}", reason);
+                SameState(self.into())
+            }
             Some((TunnelEvent::Down, _)) => {
                 // It is important to reset this before the tunnel device is down,
                 // or else commands that reapply the firewall rules will fail since
@@ -485,6 +560,131 @@ impl ConnectingState {
     }
 }
 
+#[cfg(target_os = "linux")]
+impl ConnectingState {
+    /// Starts a tunnel that replaces the tunnel on `replaces_interface`. The new tunnel does not
+    /// take over the routes until connectivity through it has been established, which happens
+    /// right before it is reported as up.
+    pub(super) fn start_replacement(
+        shared_values: &mut SharedTunnelStateValues,
+        mut parameters: TunnelParameters,
+        replaces_interface: String,
+    ) -> Self {
+        remove_blocked_dns_probes(&mut parameters, shared_values.dns_servers.as_deref());
+        Self::start_tunnel(
+            shared_values.runtime.clone(),
+            parameters,
+            &shared_values.log_dir,
+            &shared_values.resource_dir,
+            shared_values.tun_provider.clone(),
+            &mut shared_values.route_manager,
+            0,
+            Some(replaces_interface),
+        )
+    }
+
+    /// Returns what the firewall must allow while this tunnel is replacing another one.
+    pub(super) fn replacement_firewall_policy(&self) -> ReplacementTunnel {
+        ReplacementTunnel {
+            peer_endpoint: self.tunnel_parameters.get_next_hop_endpoint(),
+            peer_udp_relay: self.tunnel_parameters.get_next_hop_udp_relay(),
+            probe_path_mtu: self.tunnel_parameters.get_mtu_discovery(),
+            tunnel: self.tunnel_metadata.clone(),
+            allowed_tunnel_traffic: self.allowed_tunnel_traffic.clone(),
+        }
+    }
+
+    pub(super) fn set_interface_up(
+        &mut self,
+        metadata: TunnelMetadata,
+        allowed_tunnel_traffic: AllowedTunnelTraffic,
+    ) {
+        self.tunnel_metadata = Some(metadata);
+        self.allowed_tunnel_traffic = allowed_tunnel_traffic;
+    }
+
+    pub(super) fn set_interface_down(&mut self) {
+        self.tunnel_metadata = None;
+        self.allowed_tunnel_traffic = INITIAL_ALLOWED_TUNNEL_TRAFFIC;
+    }
+
+    /// Waits for the next tunnel event, or for the tunnel to close.
+    pub(super) async fn next_event(&mut self) -> EventResult {
+        futures::select! {
+            event = self.tunnel_events.next() => EventResult::Event(event),
+            result = &mut self.tunnel_close_event => EventResult::Close(result),
+        }
+    }
+
+    /// Closes the tunnel in the background.
+    pub(super) fn close(self, runtime: &tokio::runtime::Handle) {
+        let _ = self.tunnel_close_tx.send(());
+        let tunnel_close_event = self.tunnel_close_event;
+        runtime.spawn(async move {
+            let _ = tunnel_close_event.await;
+        });
+    }
+}
+
+/// Returns the resolvers of the DNS probes in `parameters` that the firewall blocks. DNS is only
+/// allowed to `dns_servers`, or to the tunnel gateway if no DNS servers are set.
+#[cfg(not(target_os = "android"))]
+pub(super) fn blocked_dns_probes(
+    parameters: &TunnelParameters,
+    dns_servers: Option<&[IpAddr]>,
+) -> Vec<IpAddr> {
+    let parameters = match parameters {
+        TunnelParameters::Wireguard(parameters) => parameters,
+        _ => return vec![],
+    };
+    let gateways = [
+        Some(IpAddr::V4(parameters.connection.ipv4_gateway)),
+        parameters.connection.ipv6_gateway.map(IpAddr::V6),
+    ];
+    parameters
+        .options
+        .connectivity_probes
+        .probes
+        .iter()
+        .filter_map(|probe| match probe {
+            ConnectivityProbe::Dns { resolver, .. } => Some(*resolver),
+            _ => None,
+        })
+        .filter(|resolver| match dns_servers {
+            Some(dns_servers) => !dns_servers.contains(resolver),
+            None => !gateways.contains(&Some(*resolver)),
+        })
+        .collect()
+}
+
+/// Removes DNS probes that would be blocked by the firewall, since they would never be answered.
+#[cfg(not(target_os = "android"))]
+fn remove_blocked_dns_probes(parameters: &mut TunnelParameters, dns_servers: Option<&[IpAddr]>) {
+    let blocked = blocked_dns_probes(parameters, dns_servers);
+    let probe_config = match parameters {
+        TunnelParameters::Wireguard(parameters) if !blocked.is_empty() => {
+            &mut parameters.options.connectivity_probes
+        }
+        _ => return,
+    };
+    for resolver in &blocked {
+        log::warn!(
+            "Not sending DNS probes to {} since it is not one of the DNS servers in use",
+            resolver
+        );
+    }
+    probe_config.probes.retain(|probe| {
+        !matches!(probe, ConnectivityProbe::Dns { resolver, .. } if blocked.contains(resolver))
+    });
+    if probe_config.probes.is_empty() {
+        probe_config.probes.push(ConnectivityProbe::IcmpGateway);
+    }
+    // Keep the remaining probes from requiring more answers than they can provide
+    probe_config.required_successes = probe_config
+        .required_successes
+        .min(u32::try_from(probe_config.probes.len()).unwrap_or(u32::MAX));
+}
+
 #[cfg_attr(not(target_os = "windows"), allow(unused_variables))]
 fn should_retry(error: &tunnel::Error, retry_attempt: u32) -> bool {
     use talpid_wireguard::{Error, TunnelError};
@@ -552,7 +752,13 @@ impl TunnelState for ConnectingState {
             Err(err) => {
                 ErrorState::enter(shared_values, ErrorStateCause::TunnelParameterError(err))
             }
-            Ok(tunnel_parameters) => {
+            Ok(mut tunnel_parameters) => {
+                #[cfg(not(target_os = "android"))]
+                remove_blocked_dns_probes(
+                    &mut tunnel_parameters,
+                    shared_values.dns_servers.as_deref(),
+                );
+
                 #[cfg(windows)]
                 if let Err(error) = shared_values.split_tunnel.set_tunnel_addresses(None) {
                     log::error!(
@@ -598,6 +804,8 @@ impl TunnelState for ConnectingState {
                         shared_values.tun_provider.clone(),
                         &mut shared_values.route_manager,
                         retry_attempt,
+                        #[cfg(target_os = "linux")]
+                        None,
                     );
                     let params = connecting_state.tunnel_parameters.clone();
                     (
@@ -636,3 +844,111 @@ impl TunnelState for ConnectingState {
         }
     }
 }
+
+#[cfg(all(test, not(target_os = "android")))]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use talpid_types::net::{
+        wireguard::{
+            self, ConnectionConfig, ConnectivityProbes, PeerConfig, PrivateKey, TunnelConfig,
+        },
+        GenericTunnelOptions,
+    };
+
+    const GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 64, 0, 1);
+
+    fn dns_probe(resolver: &str) -> ConnectivityProbe {
+        ConnectivityProbe::Dns {
+            resolver: resolver.parse().unwrap(),
+            hostname: "mullvad.net".to_owned(),
+        }
+    }
+
+    fn tunnel_parameters(probes: ConnectivityProbes) -> TunnelParameters {
+        TunnelParameters::from(wireguard::TunnelParameters {
+            connection: ConnectionConfig {
+                tunnel: TunnelConfig {
+                    private_key: PrivateKey::new_from_random(),
+                    addresses: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2))],
+                },
+                peer: PeerConfig {
+                    public_key: PrivateKey::new_from_random().public_key(),
+                    allowed_ips: vec!["0.0.0.0/0".parse().unwrap()],
+                    endpoint: "1.2.3.4:51820".parse().unwrap(),
+                    psk: None,
+                },
+                exit_peer: None,
+                ipv4_gateway: GATEWAY,
+                ipv6_gateway: None,
+                #[cfg(target_os = "linux")]
+                fwmark: None,
+            },
+            options: wireguard::TunnelOptions {
+                mtu: None,
+                mtu_discovery: false,
+                persistent_keepalive: None,
+                #[cfg(windows)]
+                use_wireguard_nt: false,
+                #[cfg(target_os = "linux")]
+                use_boringtun: false,
+                quantum_resistant: false,
+                quantum_resistant_rekey_interval: None,
+                connectivity_probes: probes,
+            },
+            generic_options: GenericTunnelOptions { enable_ipv6: false },
+            obfuscation: None,
+        })
+    }
+
+    fn probes(parameters: &TunnelParameters) -> &ConnectivityProbes {
+        match parameters {
+            TunnelParameters::Wireguard(parameters) => &parameters.options.connectivity_probes,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_blocked_dns_probes() {
+        let parameters = tunnel_parameters(ConnectivityProbes {
+            probes: vec![dns_probe("10.64.0.1"), dns_probe("1.1.1.1")],
+            required_successes: 1,
+            timeout: None,
+        });
+
+        let gateway = IpAddr::V4(GATEWAY);
+        let resolver: IpAddr = "1.1.1.1".parse().unwrap();
+        assert_eq!(blocked_dns_probes(&parameters, None), vec![resolver]);
+        assert_eq!(
+            blocked_dns_probes(&parameters, Some(&[resolver])),
+            vec![gateway]
+        );
+        assert!(blocked_dns_probes(&parameters, Some(&[gateway, resolver])).is_empty());
+    }
+
+    #[test]
+    fn test_remove_blocked_dns_probes() {
+        let tcp_probe = ConnectivityProbe::TcpConnect("1.1.1.1:443".parse().unwrap());
+        let mut parameters = tunnel_parameters(ConnectivityProbes {
+            probes: vec![dns_probe("1.1.1.1"), tcp_probe.clone()],
+            required_successes: 2,
+            timeout: None,
+        });
+        remove_blocked_dns_probes(&mut parameters, None);
+        assert_eq!(probes(&parameters).probes, vec![tcp_probe]);
+        assert_eq!(probes(&parameters).required_successes, 1);
+
+        // Fall back on pinging the gateway if no probes are left
+        let mut parameters = tunnel_parameters(ConnectivityProbes {
+            probes: vec![dns_probe("1.1.1.1")],
+            required_successes: 1,
+            timeout: None,
+        });
+        remove_blocked_dns_probes(&mut parameters, Some(&[]));
+        assert_eq!(
+            probes(&parameters).probes,
+            vec![ConnectivityProbe::IcmpGateway]
+        );
+        assert_eq!(probes(&parameters).required_successes, 1);
+    }
+}
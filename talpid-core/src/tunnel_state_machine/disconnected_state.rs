@@ -147,6 +147,23 @@ impl TunnelState for DisconnectedState {
                 }
                 SameState(self.into())
             }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::TrustedOverlays(overlays)) => {
+                if shared_values.set_trusted_overlays(overlays) {
+                    Self::set_firewall_policy(shared_values, false);
+                }
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::UserRoutes(user_routes)) => {
+                // The routes only affect the firewall while connecting or connected
+                shared_values.set_user_routes(user_routes);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::NetworkChanged(_)) => SameState(self.into()),
+            Some(TunnelCommand::GetStatistics(_)) => SameState(self.into()),
+            Some(TunnelCommand::PskRekeyInterval(_)) => SameState(self.into()),
             Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
                 if shared_values.allowed_endpoint != endpoint {
                     shared_values.allowed_endpoint = endpoint;
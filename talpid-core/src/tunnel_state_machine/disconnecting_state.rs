@@ -27,6 +27,20 @@ impl DisconnectingState {
                     let _ = shared_values.set_allow_lan(allow_lan);
                     AfterDisconnect::Nothing
                 }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::TrustedOverlays(overlays)) => {
+                    shared_values.set_trusted_overlays(overlays);
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::UserRoutes(user_routes)) => {
+                    shared_values.set_user_routes(user_routes);
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::NetworkChanged(_)) => AfterDisconnect::Nothing,
+                Some(TunnelCommand::GetStatistics(_)) => AfterDisconnect::Nothing,
+                Some(TunnelCommand::PskRekeyInterval(_)) => AfterDisconnect::Nothing,
                 Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
                     shared_values.allowed_endpoint = endpoint;
                     let _ = tx.send(());
@@ -63,6 +77,20 @@ impl DisconnectingState {
                     let _ = shared_values.set_allow_lan(allow_lan);
                     AfterDisconnect::Block(reason)
                 }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::TrustedOverlays(overlays)) => {
+                    shared_values.set_trusted_overlays(overlays);
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::UserRoutes(user_routes)) => {
+                    shared_values.set_user_routes(user_routes);
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::NetworkChanged(_)) => AfterDisconnect::Block(reason),
+                Some(TunnelCommand::GetStatistics(_)) => AfterDisconnect::Block(reason),
+                Some(TunnelCommand::PskRekeyInterval(_)) => AfterDisconnect::Block(reason),
                 Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
                     shared_values.allowed_endpoint = endpoint;
                     let _ = tx.send(());
@@ -104,6 +132,22 @@ impl DisconnectingState {
                     let _ = shared_values.set_allow_lan(allow_lan);
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::TrustedOverlays(overlays)) => {
+                    shared_values.set_trusted_overlays(overlays);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::UserRoutes(user_routes)) => {
+                    shared_values.set_user_routes(user_routes);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::NetworkChanged(_)) => AfterDisconnect::Reconnect(retry_attempt),
+                Some(TunnelCommand::GetStatistics(_)) => AfterDisconnect::Reconnect(retry_attempt),
+                Some(TunnelCommand::PskRekeyInterval(_)) => {
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
                 Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
                     shared_values.allowed_endpoint = endpoint;
                     let _ = tx.send(());
@@ -21,6 +21,8 @@ use crate::{
 };
 #[cfg(windows)]
 use std::ffi::OsString;
+#[cfg(target_os = "linux")]
+use talpid_routing::NetworkChange;
 use talpid_routing::RouteManager;
 use talpid_tunnel::{tun_provider::TunProvider, TunnelEvent};
 
@@ -44,7 +46,12 @@ use std::{
 use talpid_types::{android::AndroidContext, ErrorExt};
 use talpid_types::{
     net::{AllowedEndpoint, TunnelParameters},
-    tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition},
+    tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition, TunnelStatistics},
+};
+#[cfg(target_os = "linux")]
+use talpid_types::{
+    net::{TrustedOverlays, UserRoute},
+    ErrorExt,
 };
 
 const TUNNEL_STATE_MACHINE_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
@@ -103,17 +110,28 @@ pub struct InitialTunnelState {
     /// Programs to exclude from the tunnel using the split tunnel driver.
     #[cfg(windows)]
     pub exclude_paths: Vec<OsString>,
+    /// Overlay interfaces and networks that should be left untouched by the tunnel.
+    #[cfg(target_os = "linux")]
+    pub trusted_overlays: TrustedOverlays,
+    /// Routes to networks that should always go through or around the tunnel.
+    #[cfg(target_os = "linux")]
+    pub user_routes: Vec<UserRoute>,
 }
 
 /// Identifiers for various network resources that should be unique to a given instance of a tunnel
 /// state machine.
 #[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct LinuxNetworkingIdentifiers {
     /// Firewall mark is used to mark traffic which should be able to bypass the tunnel
     pub fwmark: u32,
     /// The table ID will be used for the routing table that will route all traffic through the
     /// tunnel interface.
     pub table_id: u32,
+    /// Connection mark used to identify traffic belonging to excluded processes.
+    pub split_tunnel_mark: u32,
+    /// Class ID assigned to the net_cls cgroup of excluded processes.
+    pub net_cls_classid: u32,
 }
 
 /// Spawn the tunnel state machine thread, returning a channel for sending tunnel commands.
@@ -213,6 +231,21 @@ pub enum TunnelCommand {
     /// Bypass a socket, allowing traffic to flow through outside the tunnel.
     #[cfg(target_os = "android")]
     BypassSocket(RawFd, oneshot::Sender<()>),
+    /// Set overlay interfaces and networks that should be allowed and routed outside the tunnel.
+    #[cfg(target_os = "linux")]
+    TrustedOverlays(TrustedOverlays),
+    /// Set routes to networks that should always go through or around the tunnel.
+    #[cfg(target_os = "linux")]
+    UserRoutes(Vec<UserRoute>),
+    /// Notify the state machine of a change to the default route or network interfaces.
+    #[cfg(target_os = "linux")]
+    NetworkChanged(NetworkChange),
+    /// Request traffic statistics for the current tunnel. The sender is dropped without a reply
+    /// unless the tunnel is connected.
+    GetStatistics(oneshot::Sender<TunnelStatistics>),
+    /// Set how often the PSK of a quantum-resistant tunnel is renegotiated. Applies to the
+    /// current tunnel, if any, without reconnecting.
+    PskRekeyInterval(Option<Duration>),
     /// Set applications that are allowed to send and receive traffic outside of the tunnel.
     #[cfg(windows)]
     SetExcludedApps(
@@ -283,10 +316,27 @@ impl TunnelStateMachine {
             args.linux_ids.fwmark,
             #[cfg(target_os = "linux")]
             args.linux_ids.table_id,
+            #[cfg(target_os = "linux")]
+            args.linux_ids.split_tunnel_mark,
         )
         .await
         .map_err(Error::InitRouteManagerError)?;
 
+        #[cfg(target_os = "linux")]
+        route_manager
+            .handle()
+            .map_err(Error::InitRouteManagerError)?
+            .set_trusted_overlays(args.settings.trusted_overlays.clone())
+            .await
+            .map_err(Error::InitRouteManagerError)?;
+        #[cfg(target_os = "linux")]
+        route_manager
+            .handle()
+            .map_err(Error::InitRouteManagerError)?
+            .set_user_routes(args.settings.user_routes.clone())
+            .await
+            .map_err(Error::InitRouteManagerError)?;
+
         #[cfg(windows)]
         let split_tunnel = split_tunnel::SplitTunnel::new(
             runtime.clone(),
@@ -308,7 +358,11 @@ impl TunnelStateMachine {
             },
             allow_lan: args.settings.allow_lan,
             #[cfg(target_os = "linux")]
-            fwmark: args.linux_ids.fwmark,
+            linux_ids: args.linux_ids,
+            #[cfg(target_os = "linux")]
+            trusted_overlays: args.settings.trusted_overlays.clone(),
+            #[cfg(target_os = "linux")]
+            user_routes: args.settings.user_routes.clone(),
         };
 
         let firewall = Firewall::from_args(fw_args).map_err(Error::InitFirewallError)?;
@@ -325,6 +379,26 @@ impl TunnelStateMachine {
         )
         .map_err(Error::InitDnsMonitorError)?;
 
+        #[cfg(target_os = "linux")]
+        {
+            let mut network_changes = route_manager
+                .handle()
+                .map_err(Error::InitRouteManagerError)?
+                .network_change_listener()
+                .await
+                .map_err(Error::InitRouteManagerError)?;
+            let command_tx = args.command_tx.clone();
+            tokio::spawn(async move {
+                while let Some(change) = network_changes.next().await {
+                    if let Some(tx) = command_tx.upgrade() {
+                        let _ = tx.unbounded_send(TunnelCommand::NetworkChanged(change));
+                    } else {
+                        break;
+                    }
+                }
+            });
+        }
+
         let (offline_tx, mut offline_rx) = mpsc::unbounded();
         let initial_offline_state_tx = args.offline_state_tx.clone();
         tokio::spawn(async move {
@@ -373,6 +447,10 @@ impl TunnelStateMachine {
             is_offline,
             dns_servers: args.settings.dns_servers,
             allowed_endpoint: args.settings.allowed_endpoint,
+            #[cfg(target_os = "linux")]
+            trusted_overlays: args.settings.trusted_overlays,
+            #[cfg(target_os = "linux")]
+            user_routes: args.settings.user_routes,
             tunnel_parameters_generator: Box::new(args.tunnel_parameters_generator),
             tun_provider: Arc::new(Mutex::new(args.tun_provider)),
             log_dir: args.log_dir,
@@ -462,6 +540,12 @@ struct SharedTunnelStateValues {
     dns_servers: Option<Vec<IpAddr>>,
     /// Endpoint that should not be blocked by the firewall.
     allowed_endpoint: AllowedEndpoint,
+    /// Overlay interfaces and networks that are allowed and routed outside the tunnel.
+    #[cfg(target_os = "linux")]
+    trusted_overlays: TrustedOverlays,
+    /// Routes to networks that should always go through or around the tunnel.
+    #[cfg(target_os = "linux")]
+    user_routes: Vec<UserRoute>,
     /// The generator of new `TunnelParameter`s
     tunnel_parameters_generator: Box<dyn TunnelParametersGenerator>,
     /// The provider of tunnel devices.
@@ -538,6 +622,68 @@ impl SharedTunnelStateValues {
         }
     }
 
+    /// Updates the overlay interfaces and networks. Returns whether they changed, in which case
+    /// the firewall policy must be reapplied for the change to take effect.
+    #[cfg(target_os = "linux")]
+    pub fn set_trusted_overlays(&mut self, overlays: TrustedOverlays) -> bool {
+        if self.trusted_overlays == overlays {
+            return false;
+        }
+
+        self.firewall.set_trusted_overlays(overlays.clone());
+        let result = self.route_manager.handle().and_then(|handle| {
+            self.runtime
+                .block_on(handle.set_trusted_overlays(overlays.clone()))
+        });
+        if let Err(error) = result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to update routing rules for overlay networks")
+            );
+        }
+
+        self.trusted_overlays = overlays;
+        true
+    }
+
+    /// Updates the routes configured by the user. Returns whether they changed, in which case the
+    /// firewall policy must be reapplied for the change to take effect.
+    #[cfg(target_os = "linux")]
+    pub fn set_user_routes(&mut self, user_routes: Vec<UserRoute>) -> bool {
+        if self.user_routes == user_routes {
+            return false;
+        }
+
+        self.firewall.set_user_routes(user_routes.clone());
+        let result = self.route_manager.handle().and_then(|handle| {
+            self.runtime
+                .block_on(handle.set_user_routes(user_routes.clone()))
+        });
+        if let Err(error) = result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to apply user routes")
+            );
+        }
+
+        self.user_routes = user_routes;
+        true
+    }
+
+    /// Logs a warning for each trusted overlay interface that does not exist.
+    #[cfg(target_os = "linux")]
+    pub fn warn_about_missing_overlay_interfaces(&self) {
+        for interface in &self.trusted_overlays.interfaces {
+            if crate::linux::iface_index(interface).is_err() {
+                log::warn!(
+                    "Trusted overlay interface \"{}\" does not exist. Traffic to its networks will \
+                     be routed through the tunnel",
+                    interface
+                );
+            }
+        }
+    }
+
     /// NetworkManager's connectivity check can get hung when DNS requests fail, thus the TSM
     /// should always disable it before applying firewall rules. The connectivity check should be
     /// reset whenever the firewall is cleared.
@@ -39,6 +39,8 @@ pub static INTERESTING_EVENTS: &[EventType] = &[
     EventType::Up,
     EventType::RouteUp,
     EventType::RoutePredown,
+    EventType::TlsFinal,
+    EventType::Down,
 ];
 
 openvpn_plugin!(
@@ -92,6 +94,11 @@ fn openvpn_event(
 ) -> Result<EventResult, Error> {
     log::debug!("Received event: {:?}", event);
 
+    if is_notification(event) {
+        notify(event, env, handle);
+        return Ok(EventResult::Success);
+    }
+
     let parsed_env = openvpn_plugin::ffi::parse::env_utf8(&env).map_err(Error::ParseEnvFailed)?;
 
     let ctx = handle
@@ -111,3 +118,55 @@ fn openvpn_event(
         Ok(EventResult::Failure)
     }
 }
+
+/// Returns whether the event is only reported to the daemon. OpenVPN must carry on regardless of
+/// whether reporting such an event succeeds.
+fn is_notification(event: EventType) -> bool {
+    matches!(event, EventType::TlsFinal | EventType::Down)
+}
+
+/// Forwards a notification event to the daemon. Failing to do so leaves the client in place, so
+/// that later events can still be delivered.
+fn notify(
+    event: EventType,
+    env: HashMap<CString, CString>,
+    handle: &mut Mutex<Option<EventProcessor>>,
+) {
+    let parsed_env = match openvpn_plugin::ffi::parse::env_utf8(&env) {
+        Ok(parsed_env) => parsed_env,
+        Err(e) => {
+            log::warn!("{}", Error::ParseEnvFailed(e).display_chain());
+            return;
+        }
+    };
+
+    let ctx = handle
+        .get_mut()
+        .expect("failed to obtain mutex for EventProcessor");
+    match ctx.as_mut() {
+        Some(processor) => {
+            if let Err(e) = processor.process_event(event, parsed_env) {
+                log::warn!("{}", e.display_chain());
+            }
+        }
+        None => log::debug!("Client has been closed, not reporting {:?}", event),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notifications_always_succeed() {
+        let mut handle = Mutex::new(None);
+
+        for event in [EventType::TlsFinal, EventType::Down] {
+            let result = openvpn_event(event, vec![], HashMap::new(), &mut handle).unwrap();
+            assert_eq!(result, EventResult::Success);
+        }
+
+        let result = openvpn_event(EventType::Up, vec![], HashMap::new(), &mut handle).unwrap();
+        assert_eq!(result, EventResult::Failure);
+    }
+}
@@ -72,6 +72,10 @@ impl EventProcessor {
             openvpn_plugin::EventType::RoutePredown => self
                 .runtime
                 .block_on(self.ipc_client.route_predown(details)),
+            openvpn_plugin::EventType::TlsFinal => {
+                self.runtime.block_on(self.ipc_client.tls_final(details))
+            }
+            openvpn_plugin::EventType::Down => self.runtime.block_on(self.ipc_client.down(details)),
             other => return Err(Error::UnhandledEvent(other)),
         };
         response.map(|_| ()).map_err(Error::SendEvent)
@@ -4,7 +4,7 @@
 #![deny(rust_2018_idioms)]
 
 use crate::proxy::{ProxyMonitor, ProxyResourceData};
-use futures::channel::oneshot;
+use futures::{channel::oneshot, StreamExt};
 #[cfg(windows)]
 use lazy_static::lazy_static;
 use process::{
@@ -25,12 +25,12 @@ use std::{
         mpsc, Arc, Mutex,
     },
     thread,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 #[cfg(target_os = "linux")]
 use talpid_routing::{self, RequiredRoute};
 use talpid_tunnel::TunnelEvent;
-use talpid_types::{net::openvpn, ErrorExt};
+use talpid_types::{net::openvpn, tunnel::TunnelStatistics, ErrorExt};
 use tokio::task;
 
 #[cfg(windows)]
@@ -112,6 +112,10 @@ pub enum Error {
     #[error(display = "Error while writing credentials to temporary file")]
     CredentialsWriteError(#[error(source)] io::Error),
 
+    /// Error while writing the certificates and keys of a profile to temporary files.
+    #[error(display = "Error while writing OpenVPN profile to temporary files")]
+    ProfileWriteError(#[error(source)] io::Error),
+
     /// Failures related to the proxy service.
     #[error(display = "Unable to start the proxy service")]
     StartProxyError(#[error(source)] proxy::Error),
@@ -177,6 +181,11 @@ pub struct OpenVpnMonitor<C: OpenVpnBuilder = OpenVpnCommand> {
     _user_pass_file: mktemp::TempFile,
     /// Keep the 'TempFile' for the proxy user-pass file in the struct, so it's removed on drop.
     _proxy_auth_file: Option<mktemp::TempFile>,
+    /// Keep the `TempFile` for the status file in the struct, so it's removed on drop.
+    _status_file: Option<mktemp::TempFile>,
+    /// Keep the `TempFile`s for the profile certificates and keys in the struct, so they're
+    /// removed on drop.
+    _profile_files: Option<ProfileFiles>,
 
     runtime: tokio::runtime::Handle,
     event_server_abort_tx: triggered::Trigger,
@@ -252,6 +261,9 @@ impl OpenVpnMonitor<OpenVpnCommand> {
         log_path: Option<PathBuf>,
         resource_dir: &Path,
         tunnel_close_rx: oneshot::Receiver<()>,
+        stats_request_rx: futures::channel::mpsc::UnboundedReceiver<
+            oneshot::Sender<TunnelStatistics>,
+        >,
         #[cfg(target_os = "linux")] route_manager: talpid_routing::RouteManagerHandle,
     ) -> Result<Self>
     where
@@ -267,6 +279,14 @@ impl OpenVpnMonitor<OpenVpnCommand> {
             Self::create_proxy_auth_file(&params.proxy).map_err(Error::CredentialsWriteError)?;
         let user_pass_file_path = user_pass_file.to_path_buf();
         let proxy_auth_file_path = proxy_auth_file.as_ref().map(|file| file.to_path_buf());
+        let status_file = mktemp::TempFile::new();
+        let profile_files = params
+            .config
+            .profile
+            .as_ref()
+            .map(Self::create_profile_files)
+            .transpose()
+            .map_err(Error::ProfileWriteError)?;
 
         let log_dir = log_path.as_ref().map(|log_path| {
             log_path
@@ -289,6 +309,8 @@ impl OpenVpnMonitor<OpenVpnCommand> {
             params,
             user_pass_file.as_ref(),
             proxy_auth_file.as_ref().map(AsRef::as_ref),
+            status_file.as_ref(),
+            profile_files.as_ref(),
             resource_dir,
             &proxy_monitor,
             #[cfg(windows)]
@@ -302,6 +324,13 @@ impl OpenVpnMonitor<OpenVpnCommand> {
 
         let (event_server_abort_tx, event_server_abort_rx) = triggered::trigger();
 
+        let tls_negotiations = Arc::new(Mutex::new(TlsNegotiations::default()));
+        tokio::spawn(Self::serve_stats_requests(
+            status_file.to_path_buf(),
+            tls_negotiations.clone(),
+            stats_request_rx,
+        ));
+
         let openvpn_init_args = OpenVpnTunnelInitArgs {
             event_server_abort_tx: event_server_abort_tx.clone(),
             event_server_abort_rx,
@@ -309,6 +338,8 @@ impl OpenVpnMonitor<OpenVpnCommand> {
             log_path,
             user_pass_file,
             proxy_auth_file,
+            status_file: Some(status_file),
+            profile_files,
             proxy_monitor,
             tunnel_close_rx,
             #[cfg(target_os = "linux")]
@@ -322,6 +353,7 @@ impl OpenVpnMonitor<OpenVpnCommand> {
                 user_pass_file_path: user_pass_file_path.clone(),
                 proxy_auth_file_path: proxy_auth_file_path.clone(),
                 abort_server_tx: event_server_abort_tx,
+                tls_negotiations,
                 #[cfg(target_os = "linux")]
                 route_manager_handle: route_manager,
                 #[cfg(target_os = "linux")]
@@ -333,6 +365,39 @@ impl OpenVpnMonitor<OpenVpnCommand> {
         .await
     }
 
+    /// Replies to requests for traffic statistics, which are read from the OpenVPN status file
+    /// and the TLS negotiations reported by the plugin, until the request channel is closed.
+    async fn serve_stats_requests(
+        status_path: PathBuf,
+        tls_negotiations: Arc<Mutex<TlsNegotiations>>,
+        mut stats_request_rx: futures::channel::mpsc::UnboundedReceiver<
+            oneshot::Sender<TunnelStatistics>,
+        >,
+    ) {
+        while let Some(reply_tx) = stats_request_rx.next().await {
+            match tokio::fs::read_to_string(&status_path).await {
+                Ok(contents) => {
+                    if let Some(mut stats) = parse_status_file(&contents) {
+                        if let TunnelStatistics::OpenVpn {
+                            tls_renegotiations,
+                            last_tls_negotiation,
+                            ..
+                        } = &mut stats
+                        {
+                            let negotiations = tls_negotiations.lock().unwrap();
+                            *tls_renegotiations = negotiations.count.saturating_sub(1);
+                            *last_tls_negotiation = negotiations.last;
+                        }
+                        let _ = reply_tx.send(stats);
+                    }
+                }
+                Err(error) => {
+                    log::trace!("Failed to read OpenVPN status file: {}", error);
+                }
+            }
+        }
+    }
+
     #[cfg(windows)]
     fn new_wintun_context(
         params: &openvpn::TunnelParameters,
@@ -368,6 +433,44 @@ fn extract_routes(env: &HashMap<String, String>) -> Result<HashSet<RequiredRoute
     Ok(routes)
 }
 
+/// Parses the byte counters of a status file written by OpenVPN.
+fn parse_status_file(contents: &str) -> Option<TunnelStatistics> {
+    let mut rx_bytes = None;
+    let mut tx_bytes = None;
+    for line in contents.lines() {
+        if let Some((key, value)) = line.split_once(',') {
+            match key {
+                "TCP/UDP read bytes" => rx_bytes = value.trim().parse().ok(),
+                "TCP/UDP write bytes" => tx_bytes = value.trim().parse().ok(),
+                _ => (),
+            }
+        }
+    }
+    Some(TunnelStatistics::OpenVpn {
+        rx_bytes: rx_bytes?,
+        tx_bytes: tx_bytes?,
+        tls_renegotiations: 0,
+        last_tls_negotiation: None,
+    })
+}
+
+/// TLS negotiations reported by the OpenVPN plugin, including the initial one.
+#[derive(Debug, Default)]
+struct TlsNegotiations {
+    count: u32,
+    last: Option<SystemTime>,
+}
+
+/// Certificates and keys of an imported OpenVPN profile, written to temporary files that are
+/// passed to OpenVPN.
+#[derive(Debug)]
+struct ProfileFiles {
+    ca: mktemp::TempFile,
+    cert: Option<mktemp::TempFile>,
+    key: Option<mktemp::TempFile>,
+    tls_auth: Option<mktemp::TempFile>,
+}
+
 struct OpenVpnTunnelInitArgs {
     event_server_abort_tx: triggered::Trigger,
     event_server_abort_rx: triggered::Listener,
@@ -375,6 +478,8 @@ struct OpenVpnTunnelInitArgs {
     log_path: Option<PathBuf>,
     user_pass_file: mktemp::TempFile,
     proxy_auth_file: Option<mktemp::TempFile>,
+    status_file: Option<mktemp::TempFile>,
+    profile_files: Option<ProfileFiles>,
     proxy_monitor: Option<Box<dyn ProxyMonitor>>,
     tunnel_close_rx: oneshot::Receiver<()>,
     #[cfg(target_os = "linux")]
@@ -397,6 +502,8 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
         let log_path = init_args.log_path;
         let user_pass_file = init_args.user_pass_file;
         let proxy_auth_file = init_args.proxy_auth_file;
+        let status_file = init_args.status_file;
+        let profile_files = init_args.profile_files;
         let proxy_monitor = init_args.proxy_monitor;
         let tunnel_close_rx = init_args.tunnel_close_rx;
 
@@ -427,6 +534,8 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
             closed: Arc::new(AtomicBool::new(false)),
             _user_pass_file: user_pass_file,
             _proxy_auth_file: proxy_auth_file,
+            _status_file: status_file,
+            _profile_files: profile_files,
 
             runtime: tokio::runtime::Handle::current(),
             event_server_abort_tx,
@@ -617,13 +726,16 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
     fn create_proxy_auth_file(
         proxy_settings: &Option<openvpn::ProxySettings>,
     ) -> std::result::Result<Option<mktemp::TempFile>, io::Error> {
-        if let Some(openvpn::ProxySettings::Remote(ref remote_proxy)) = proxy_settings {
-            if let Some(ref proxy_auth) = remote_proxy.auth {
-                return Ok(Some(Self::create_credentials_file(
-                    &proxy_auth.username,
-                    &proxy_auth.password,
-                )?));
-            }
+        let proxy_auth = match proxy_settings {
+            Some(openvpn::ProxySettings::Remote(remote_proxy)) => remote_proxy.auth.as_ref(),
+            Some(openvpn::ProxySettings::Http(http_proxy)) => http_proxy.auth.as_ref(),
+            _ => None,
+        };
+        if let Some(proxy_auth) = proxy_auth {
+            return Ok(Some(Self::create_credentials_file(
+                &proxy_auth.username,
+                &proxy_auth.password,
+            )?));
         }
         Ok(None)
     }
@@ -651,6 +763,23 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
         Ok(temp_file)
     }
 
+    fn create_profile_files(profile: &openvpn::ProfileConfig) -> io::Result<ProfileFiles> {
+        let create_file = |contents: &str| -> io::Result<mktemp::TempFile> {
+            let temp_file = mktemp::TempFile::new();
+            let mut file = fs::File::create(&temp_file)?;
+            Self::set_user_pass_file_permissions(&file)?;
+            file.write_all(contents.as_bytes())?;
+            Ok(temp_file)
+        };
+        log::debug!("Writing OpenVPN profile certificates and keys to temporary files");
+        Ok(ProfileFiles {
+            ca: create_file(&profile.ca)?,
+            cert: profile.cert.as_deref().map(create_file).transpose()?,
+            key: profile.key.as_deref().map(create_file).transpose()?,
+            tls_auth: profile.tls_auth.as_deref().map(create_file).transpose()?,
+        })
+    }
+
     #[cfg(unix)]
     fn set_user_pass_file_permissions(file: &fs::File) -> io::Result<()> {
         use std::os::unix::fs::PermissionsExt;
@@ -677,19 +806,49 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
         params: &openvpn::TunnelParameters,
         user_pass_file: &Path,
         proxy_auth_file: Option<&Path>,
+        status_file: &Path,
+        profile_files: Option<&ProfileFiles>,
         resource_dir: &Path,
         proxy_monitor: &Option<Box<dyn ProxyMonitor>>,
         #[cfg(windows)] alias: OsString,
     ) -> Result<OpenVpnCommand> {
         let mut cmd = OpenVpnCommand::new(Self::get_openvpn_bin(resource_dir)?);
-        if let Some(config) = Self::get_config_path(resource_dir) {
-            cmd.config(config);
-        }
         cmd.remote(params.config.endpoint)
-            .user_pass(user_pass_file)
+            .status(status_file)
             .tunnel_options(&params.options)
-            .enable_ipv6(params.generic_options.enable_ipv6)
-            .ca(resource_dir.join("ca.crt"));
+            .enable_ipv6(params.generic_options.enable_ipv6);
+        match (&params.config.profile, profile_files) {
+            (Some(profile), Some(files)) => {
+                // The bundled configuration and CA only apply to Mullvad's servers
+                cmd.ca(&files.ca);
+                if let (Some(cert), Some(key)) = (&files.cert, &files.key) {
+                    cmd.cert(cert).key(key);
+                }
+                if let Some(tls_auth) = &files.tls_auth {
+                    cmd.tls_auth(tls_auth, profile.key_direction);
+                }
+                if let Some(cipher) = &profile.cipher {
+                    cmd.data_cipher_fallback(cipher.as_str());
+                }
+                if let Some(digest) = &profile.auth {
+                    cmd.auth(digest.as_str());
+                }
+                if let Some(verify_x509_name) = &profile.verify_x509_name {
+                    cmd.verify_x509_name(verify_x509_name.clone());
+                }
+                cmd.require_tls1_3(false);
+                if profile.auth_user_pass {
+                    cmd.user_pass(user_pass_file);
+                }
+            }
+            _ => {
+                if let Some(config) = Self::get_config_path(resource_dir) {
+                    cmd.config(config);
+                }
+                cmd.user_pass(user_pass_file)
+                    .ca(resource_dir.join("ca.crt"));
+            }
+        }
         #[cfg(windows)]
         cmd.tunnel_alias(Some(alias));
         if let Some(proxy_settings) = params.proxy.clone().take() {
@@ -872,6 +1031,7 @@ mod event_server {
         pub user_pass_file_path: super::PathBuf,
         pub proxy_auth_file_path: Option<super::PathBuf>,
         pub abort_server_tx: triggered::Trigger,
+        pub tls_negotiations: std::sync::Arc<std::sync::Mutex<super::TlsNegotiations>>,
         #[cfg(target_os = "linux")]
         pub route_manager_handle: talpid_routing::RouteManagerHandle,
         #[cfg(target_os = "linux")]
@@ -1005,6 +1165,7 @@ mod event_server {
                 ips,
                 ipv4_gateway,
                 ipv6_gateway,
+                mtu: None,
             })
         }
     }
@@ -1059,6 +1220,56 @@ mod event_server {
             (self.on_event)(talpid_tunnel::TunnelEvent::Down).await;
             Ok(Response::new(()))
         }
+
+        async fn tls_final(
+            &self,
+            _request: Request<EventDetails>,
+        ) -> std::result::Result<Response<()>, tonic::Status> {
+            if record_tls_negotiation(&self.tls_negotiations) {
+                log::debug!("OpenVPN TLS session renegotiated");
+                // OpenVPN waits for the plugin, so don't hold it up while the event is handled
+                tokio::spawn((self.on_event)(talpid_tunnel::TunnelEvent::Renegotiated));
+            }
+            Ok(Response::new(()))
+        }
+
+        async fn down(
+            &self,
+            request: Request<EventDetails>,
+        ) -> std::result::Result<Response<()>, tonic::Status> {
+            if let Some(event) = closing_event(&request.into_inner().env) {
+                (self.on_event)(event).await;
+            }
+            Ok(Response::new(()))
+        }
+    }
+
+    /// Counts a completed TLS negotiation. Returns whether it was a renegotiation, since the
+    /// first negotiation is part of setting up the tunnel.
+    pub(super) fn record_tls_negotiation(
+        tls_negotiations: &std::sync::Mutex<super::TlsNegotiations>,
+    ) -> bool {
+        let mut negotiations = tls_negotiations.lock().unwrap();
+        negotiations.count += 1;
+        negotiations.last = Some(std::time::SystemTime::now());
+        negotiations.count > 1
+    }
+
+    /// Returns the event to report when OpenVPN closes the connection, if any.
+    pub(super) fn closing_event(
+        env: &HashMap<String, String>,
+    ) -> Option<talpid_tunnel::TunnelEvent> {
+        let reason = env
+            .get("signal")
+            .cloned()
+            .unwrap_or_else(|| "unknown".to_owned());
+        log::info!("OpenVPN is closing the connection: {}", reason);
+        // Closing the tunnel ourselves is not worth reporting
+        if matches!(reason.as_str(), "sigterm" | "sigint") {
+            None
+        } else {
+            Some(talpid_tunnel::TunnelEvent::Closing(reason))
+        }
     }
 
     pub async fn start<L>(
@@ -1185,6 +1396,18 @@ mod tests {
         ) -> std::result::Result<tonic::Response<()>, tonic::Status> {
             Ok(tonic::Response::new(()))
         }
+        async fn tls_final(
+            &self,
+            _request: tonic::Request<event_server::EventDetails>,
+        ) -> std::result::Result<tonic::Response<()>, tonic::Status> {
+            Ok(tonic::Response::new(()))
+        }
+        async fn down(
+            &self,
+            _request: tonic::Request<event_server::EventDetails>,
+        ) -> std::result::Result<tonic::Response<()>, tonic::Status> {
+            Ok(tonic::Response::new(()))
+        }
     }
 
     #[derive(Debug, Default, Clone)]
@@ -1260,6 +1483,8 @@ mod tests {
             log_path,
             user_pass_file: TempFile::new(),
             proxy_auth_file: None,
+            status_file: None,
+            profile_files: None,
             proxy_monitor: None,
             tunnel_close_rx: close_rx,
             #[cfg(target_os = "linux")]
@@ -1388,4 +1613,56 @@ mod tests {
             _ => panic!("Wrong error"),
         }
     }
+
+    #[test]
+    fn parse_status_file_counters() {
+        let contents = "OpenVPN STATISTICS\n\
+                        Updated,2022-12-01 10:00:00\n\
+                        TUN/TAP read bytes,1234\n\
+                        TUN/TAP write bytes,5678\n\
+                        TCP/UDP read bytes,7000\n\
+                        TCP/UDP write bytes,3000\n\
+                        Auth read bytes,5678\n\
+                        END\n";
+        assert_eq!(
+            parse_status_file(contents),
+            Some(TunnelStatistics::OpenVpn {
+                rx_bytes: 7000,
+                tx_bytes: 3000,
+                tls_renegotiations: 0,
+                last_tls_negotiation: None,
+            })
+        );
+        assert_eq!(parse_status_file("OpenVPN STATISTICS\nEND\n"), None);
+    }
+
+    #[test]
+    fn record_tls_renegotiations() {
+        let negotiations = std::sync::Mutex::new(TlsNegotiations::default());
+
+        assert!(!event_server::record_tls_negotiation(&negotiations));
+        assert!(event_server::record_tls_negotiation(&negotiations));
+
+        let negotiations = negotiations.lock().unwrap();
+        assert_eq!(negotiations.count, 2);
+        assert!(negotiations.last.is_some());
+    }
+
+    #[test]
+    fn report_closing_reason() {
+        let env = |signal: &str| {
+            std::collections::HashMap::from([("signal".to_owned(), signal.to_owned())])
+        };
+
+        assert_eq!(event_server::closing_event(&env("sigterm")), None);
+        assert_eq!(event_server::closing_event(&env("sigint")), None);
+        assert_eq!(
+            event_server::closing_event(&env("ping-restart")),
+            Some(TunnelEvent::Closing("ping-restart".to_owned()))
+        );
+        assert_eq!(
+            event_server::closing_event(&std::collections::HashMap::new()),
+            Some(TunnelEvent::Closing("unknown".to_owned()))
+        );
+    }
 }
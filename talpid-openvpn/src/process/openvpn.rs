@@ -28,8 +28,6 @@ static BASE_ARGUMENTS: &[&[&str]] = &[
     &["--rcvbuf", "1048576"],
     &["--sndbuf", "1048576"],
     &["--fast-io"],
-    &["--data-ciphers-fallback", "AES-256-GCM"],
-    &["--tls-version-min", "1.3"],
     &["--verb", "3"],
     #[cfg(windows)]
     &[
@@ -50,6 +48,9 @@ static BASE_ARGUMENTS: &[&[&str]] = &[
     &["--windows-driver", "wintun"],
 ];
 
+/// Data channel cipher to use if the server does not support cipher negotiation.
+const DEFAULT_DATA_CIPHER_FALLBACK: &str = "AES-256-GCM";
+
 static ALLOWED_TLS1_3_CIPHERS: &[&str] =
     &["TLS_AES_256_GCM_SHA384", "TLS_CHACHA20_POLY1305_SHA256"];
 
@@ -64,8 +65,16 @@ pub struct OpenVpnCommand {
     proxy_auth_path: Option<PathBuf>,
     ca: Option<PathBuf>,
     crl: Option<PathBuf>,
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    tls_auth: Option<(PathBuf, Option<u8>)>,
+    data_cipher_fallback: Option<String>,
+    auth: Option<String>,
+    verify_x509_name: Option<net::openvpn::VerifyX509Name>,
+    require_tls1_3: bool,
     plugin: Option<(PathBuf, Vec<String>)>,
     log: Option<PathBuf>,
+    status: Option<PathBuf>,
     tunnel_options: net::openvpn::TunnelOptions,
     proxy_settings: Option<net::openvpn::ProxySettings>,
     tunnel_alias: Option<OsString>,
@@ -87,8 +96,16 @@ impl OpenVpnCommand {
             proxy_auth_path: None,
             ca: None,
             crl: None,
+            cert: None,
+            key: None,
+            tls_auth: None,
+            data_cipher_fallback: None,
+            auth: None,
+            verify_x509_name: None,
+            require_tls1_3: true,
             plugin: None,
             log: None,
+            status: None,
             tunnel_options: net::openvpn::TunnelOptions::default(),
             proxy_settings: None,
             tunnel_alias: None,
@@ -144,6 +161,51 @@ impl OpenVpnCommand {
         self
     }
 
+    /// Sets the path to the client certificate file.
+    pub fn cert(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.cert = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the path to the private key of the client certificate.
+    pub fn key(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.key = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the path to the static key used to authenticate the TLS control channel, and the
+    /// key direction to use it with.
+    pub fn tls_auth(&mut self, path: impl AsRef<Path>, key_direction: Option<u8>) -> &mut Self {
+        self.tls_auth = Some((path.as_ref().to_path_buf(), key_direction));
+        self
+    }
+
+    /// Sets the data channel cipher to use if the server does not support cipher negotiation.
+    /// Defaults to AES-256-GCM.
+    pub fn data_cipher_fallback(&mut self, cipher: impl Into<String>) -> &mut Self {
+        self.data_cipher_fallback = Some(cipher.into());
+        self
+    }
+
+    /// Sets the message digest used to authenticate packets.
+    pub fn auth(&mut self, digest: impl Into<String>) -> &mut Self {
+        self.auth = Some(digest.into());
+        self
+    }
+
+    /// Sets the name that the server certificate must have.
+    pub fn verify_x509_name(&mut self, name: net::openvpn::VerifyX509Name) -> &mut Self {
+        self.verify_x509_name = Some(name);
+        self
+    }
+
+    /// Sets whether to require TLS 1.3 and only allow the TLS 1.3 cipher suites that Mullvad's
+    /// servers use. Enabled by default. Other servers may only support older versions.
+    pub fn require_tls1_3(&mut self, require: bool) -> &mut Self {
+        self.require_tls1_3 = require;
+        self
+    }
+
     /// Sets a plugin and its arguments that OpenVPN will be started with.
     pub fn plugin(&mut self, path: impl AsRef<Path>, args: Vec<String>) -> &mut Self {
         self.plugin = Some((path.as_ref().to_path_buf(), args));
@@ -156,6 +218,12 @@ impl OpenVpnCommand {
         self
     }
 
+    /// Sets a file that OpenVPN writes traffic statistics to every second.
+    pub fn status(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.status = Some(path.as_ref().to_path_buf());
+        self
+    }
+
     /// Sets extra options
     pub fn tunnel_options(&mut self, tunnel_options: &net::openvpn::TunnelOptions) -> &mut Self {
         self.tunnel_options = tunnel_options.clone();
@@ -214,6 +282,48 @@ impl OpenVpnCommand {
             args.push(OsString::from("--crl-verify"));
             args.push(OsString::from(crl.as_os_str()));
         }
+        if let Some(ref cert) = self.cert {
+            args.push(OsString::from("--cert"));
+            args.push(OsString::from(cert.as_os_str()));
+        }
+        if let Some(ref key) = self.key {
+            args.push(OsString::from("--key"));
+            args.push(OsString::from(key.as_os_str()));
+        }
+        if let Some((ref path, key_direction)) = self.tls_auth {
+            args.push(OsString::from("--tls-auth"));
+            args.push(OsString::from(path.as_os_str()));
+            if let Some(key_direction) = key_direction {
+                args.push(OsString::from(key_direction.to_string()));
+            }
+        }
+        if let Some(ref digest) = self.auth {
+            args.push(OsString::from("--auth"));
+            args.push(OsString::from(digest));
+        }
+        if let Some(ref verify_x509_name) = self.verify_x509_name {
+            args.push(OsString::from("--verify-x509-name"));
+            args.push(OsString::from(&verify_x509_name.name));
+            if let Some(ref name_type) = verify_x509_name.name_type {
+                args.push(OsString::from(name_type));
+            }
+        }
+
+        // The server negotiates by the order of its own list, so a client preference would have
+        // no effect. The selected cipher is the only one allowed instead.
+        if let Some(cipher) = self.tunnel_options.data_cipher {
+            args.push(OsString::from("--data-ciphers"));
+            args.push(OsString::from(cipher.as_openvpn_name()));
+        }
+        args.push(OsString::from("--data-ciphers-fallback"));
+        args.push(OsString::from(
+            self.data_cipher_fallback.as_deref().unwrap_or_else(|| {
+                self.tunnel_options
+                    .data_cipher
+                    .map(|cipher| cipher.as_openvpn_name())
+                    .unwrap_or(DEFAULT_DATA_CIPHER_FALLBACK)
+            }),
+        ));
 
         if let Some((ref path, ref plugin_args)) = self.plugin {
             args.push(OsString::from("--plugin"));
@@ -226,10 +336,29 @@ impl OpenVpnCommand {
             args.push(OsString::from(path))
         }
 
+        if let Some(ref path) = self.status {
+            args.push(OsString::from("--status"));
+            args.push(OsString::from(path));
+            args.push(OsString::from("1"));
+        }
+
         if let Some(mssfix) = self.tunnel_options.mssfix {
             args.push(OsString::from("--mssfix"));
             args.push(OsString::from(mssfix.to_string()));
         }
+        if let Some(tun_mtu) = self.tunnel_options.tun_mtu {
+            args.push(OsString::from("--tun-mtu"));
+            args.push(OsString::from(tun_mtu.to_string()));
+        }
+        if let Some(fragment) = self.tunnel_options.fragment {
+            // OpenVPN refuses to start if fragmentation is enabled for TCP
+            if self.uses_udp() {
+                args.push(OsString::from("--fragment"));
+                args.push(OsString::from(fragment.to_string()));
+            } else {
+                log::debug!("Ignoring fragment option since the tunnel does not use UDP");
+            }
+        }
 
         if !self.enable_ipv6 {
             args.push(OsString::from("--pull-filter"));
@@ -246,7 +375,9 @@ impl OpenVpnCommand {
             args.push(tunnel_device.clone());
         }
 
-        args.extend(Self::tls_cipher_arguments().iter().map(OsString::from));
+        if self.require_tls1_3 {
+            args.extend(Self::tls_cipher_arguments().iter().map(OsString::from));
+        }
         args.extend(self.proxy_arguments().iter().map(OsString::from));
 
         #[cfg(target_os = "linux")]
@@ -269,6 +400,8 @@ impl OpenVpnCommand {
 
     fn tls_cipher_arguments() -> Vec<String> {
         vec![
+            "--tls-version-min".to_owned(),
+            "1.3".to_owned(),
             "--tls-ciphersuites".to_owned(),
             ALLOWED_TLS1_3_CIPHERS.join(":"),
         ]
@@ -289,6 +422,14 @@ impl OpenVpnCommand {
         args
     }
 
+    fn uses_udp(&self) -> bool {
+        self.proxy_settings.is_none()
+            && self
+                .remote
+                .map(|endpoint| endpoint.protocol == net::TransportProtocol::Udp)
+                .unwrap_or(false)
+    }
+
     fn authentication_arguments(&self) -> Vec<OsString> {
         let mut args = vec![];
         if let Some(ref user_pass_path) = self.user_pass_path {
@@ -328,6 +469,25 @@ impl OpenVpnCommand {
                 args.push("255.255.255.255".to_owned());
                 args.push("net_gateway".to_owned());
             }
+            Some(net::openvpn::ProxySettings::Http(ref http_proxy)) => {
+                args.push("--http-proxy".to_owned());
+                args.push(http_proxy.address.ip().to_string());
+                args.push(http_proxy.address.port().to_string());
+
+                if let Some(ref _auth) = http_proxy.auth {
+                    if let Some(ref auth_file) = self.proxy_auth_path {
+                        args.push(auth_file.to_string_lossy().to_string());
+                        args.push("basic".to_owned());
+                    } else {
+                        log::error!("Proxy credentials present but credentials file missing");
+                    }
+                }
+
+                args.push("--route".to_owned());
+                args.push(http_proxy.address.ip().to_string());
+                args.push("255.255.255.255".to_owned());
+                args.push("net_gateway".to_owned());
+            }
             Some(net::openvpn::ProxySettings::Shadowsocks(ref ss)) => {
                 args.push("--socks-proxy".to_owned());
                 args.push("127.0.0.1".to_owned());
@@ -443,7 +603,7 @@ impl Drop for OpenVpnProcHandle {
 mod tests {
     use super::OpenVpnCommand;
     use std::{ffi::OsString, net::Ipv4Addr};
-    use talpid_types::net::{Endpoint, TransportProtocol};
+    use talpid_types::net::{openvpn, Endpoint, TransportProtocol};
 
     #[test]
     fn passes_one_remote() {
@@ -470,4 +630,132 @@ mod tests {
         assert!(testee_args.contains(&OsString::from("123")));
         assert!(testee_args.contains(&OsString::from("cde")));
     }
+
+    #[test]
+    fn passes_http_proxy_with_auth() {
+        let proxy = openvpn::ProxySettings::Http(openvpn::HttpProxySettings {
+            address: "192.0.2.1:3128".parse().unwrap(),
+            auth: Some(openvpn::ProxyAuth {
+                username: "user".to_owned(),
+                password: "pass".to_owned(),
+            }),
+        });
+        let testee_args = OpenVpnCommand::new("")
+            .proxy_settings(proxy)
+            .proxy_auth("./auth")
+            .get_arguments();
+
+        let position = testee_args
+            .iter()
+            .position(|arg| arg == "--http-proxy")
+            .expect("missing --http-proxy");
+        assert_eq!(
+            testee_args[position + 1..position + 5],
+            ["192.0.2.1", "3128", "./auth", "basic"].map(OsString::from)
+        );
+    }
+
+    #[test]
+    fn passes_tls_auth_and_cipher() {
+        let testee_args = OpenVpnCommand::new("")
+            .tls_auth("./ta.key", Some(1))
+            .data_cipher_fallback("AES-256-CBC")
+            .get_arguments();
+
+        let position = testee_args
+            .iter()
+            .position(|arg| arg == "--tls-auth")
+            .expect("missing --tls-auth");
+        assert_eq!(
+            testee_args[position + 1..position + 3],
+            ["./ta.key", "1"].map(OsString::from)
+        );
+        let position = testee_args
+            .iter()
+            .position(|arg| arg == "--data-ciphers-fallback")
+            .expect("missing --data-ciphers-fallback");
+        assert_eq!(testee_args[position + 1], "AES-256-CBC");
+    }
+
+    #[test]
+    fn passes_tunnel_options() {
+        let remote = Endpoint::new(Ipv4Addr::new(127, 0, 0, 1), 1194, TransportProtocol::Udp);
+        let options = openvpn::TunnelOptions {
+            data_cipher: Some(openvpn::DataCipher::Chacha20Poly1305),
+            fragment: Some(1300),
+            tun_mtu: Some(1400),
+            ..Default::default()
+        };
+        let testee_args = OpenVpnCommand::new("")
+            .remote(remote)
+            .tunnel_options(&options)
+            .get_arguments();
+
+        let value_of = |name: &str| {
+            let position = testee_args.iter().position(|arg| arg == name)?;
+            testee_args.get(position + 1).cloned()
+        };
+        assert_eq!(
+            value_of("--data-ciphers"),
+            Some(OsString::from("CHACHA20-POLY1305"))
+        );
+        assert_eq!(
+            value_of("--data-ciphers-fallback"),
+            Some(OsString::from("CHACHA20-POLY1305"))
+        );
+        assert_eq!(value_of("--fragment"), Some(OsString::from("1300")));
+        assert_eq!(value_of("--tun-mtu"), Some(OsString::from("1400")));
+    }
+
+    #[test]
+    fn skips_fragment_over_tcp() {
+        let remote = Endpoint::new(Ipv4Addr::new(127, 0, 0, 1), 443, TransportProtocol::Tcp);
+        let options = openvpn::TunnelOptions {
+            fragment: Some(1300),
+            ..Default::default()
+        };
+        let testee_args = OpenVpnCommand::new("")
+            .remote(remote)
+            .tunnel_options(&options)
+            .get_arguments();
+        assert!(!testee_args.contains(&OsString::from("--fragment")));
+    }
+
+    #[test]
+    fn passes_auth_and_verify_x509_name() {
+        let testee_args = OpenVpnCommand::new("")
+            .auth("SHA512")
+            .verify_x509_name(openvpn::VerifyX509Name {
+                name: "C=SE, CN=server".to_owned(),
+                name_type: Some("subject".to_owned()),
+            })
+            .get_arguments();
+
+        let position = testee_args
+            .iter()
+            .position(|arg| arg == "--auth")
+            .expect("missing --auth");
+        assert_eq!(testee_args[position + 1], "SHA512");
+        let position = testee_args
+            .iter()
+            .position(|arg| arg == "--verify-x509-name")
+            .expect("missing --verify-x509-name");
+        assert_eq!(
+            testee_args[position + 1..position + 3],
+            ["C=SE, CN=server", "subject"].map(OsString::from)
+        );
+    }
+
+    #[test]
+    fn tls1_3_is_optional() {
+        let testee_args = OpenVpnCommand::new("").get_arguments();
+        assert!(testee_args.iter().any(|arg| arg == "--tls-version-min"));
+        assert!(testee_args.iter().any(|arg| arg == "--tls-ciphersuites"));
+
+        let testee_args = OpenVpnCommand::new("")
+            .require_tls1_3(false)
+            .get_arguments();
+        assert!(!testee_args.iter().any(|arg| arg == "--tls-version-min"));
+        assert!(!testee_args.iter().any(|arg| arg == "--tls-ciphersuites"));
+    }
 }
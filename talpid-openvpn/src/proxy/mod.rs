@@ -64,6 +64,12 @@ pub async fn start_proxy(
                 remote_settings.address.port(),
             )?))
         }
+        openvpn::ProxySettings::Http(http_settings) => {
+            // These are generic proxy settings with the proxy client not managed by us.
+            Ok(Box::new(noop::NoopProxyMonitor::start(
+                http_settings.address.port(),
+            )?))
+        }
         openvpn::ProxySettings::Shadowsocks(ss_settings) => Ok(Box::new(
             ShadowsocksProxyMonitor::start(ss_settings, resource_data).await?,
         )),
@@ -26,6 +26,9 @@ pub use imp::{Error, RouteManager};
 
 pub use imp::RouteManagerHandle;
 
+#[cfg(target_os = "linux")]
+pub use imp::{default_route_interfaces, NetworkChange};
+
 /// A network route with a specific network node, destination and an optional metric.
 #[derive(Debug, Hash, Eq, PartialEq, Clone)]
 pub struct Route {
@@ -1,5 +1,5 @@
 use crate::{
-    imp::{CallbackMessage, RouteManagerCommand},
+    imp::{CallbackMessage, NetworkChange, RouteManagerCommand},
     NetNode, Node, RequiredRoute, Route,
 };
 use netlink_sys::AsyncSocket;
@@ -8,7 +8,10 @@ use std::{
     io,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
 };
-use talpid_types::ErrorExt;
+use talpid_types::{
+    net::{validate_user_routes, TrustedOverlays, UserRoute, UserRouteTarget},
+    ErrorExt,
+};
 
 use futures::{
     channel::mpsc::{UnboundedReceiver, UnboundedSender},
@@ -57,13 +60,200 @@ lazy_static! {
     };
 }
 
-fn all_rules(fwmark: u32, table: u32) -> [RuleMessage; 4] {
-    [
+fn all_rules(
+    fwmark: u32,
+    table: u32,
+    overlays: &TrustedOverlays,
+    user_routes: &[UserRoute],
+) -> Vec<RuleMessage> {
+    let mut rules = vec![
         no_fwmark_rule_v4(fwmark, table),
         no_fwmark_rule_v6(fwmark, table),
         SUPPRESS_RULE_V4.clone(),
         SUPPRESS_RULE_V6.clone(),
-    ]
+    ];
+    rules.extend(overlays.interfaces.iter().flat_map(|interface| {
+        [
+            overlay_interface_rule(AF_INET, interface),
+            overlay_interface_rule(AF_INET6, interface),
+        ]
+    }));
+    rules.extend(
+        overlays
+            .networks
+            .iter()
+            .filter(|network| network.prefix() > 0)
+            .map(overlay_network_rule),
+    );
+    rules.extend(
+        user_routes
+            .iter()
+            .filter(|route| route.target == UserRouteTarget::Tunnel)
+            .map(|route| tunnel_network_rule(fwmark, table, &route.network)),
+    );
+    rules
+}
+
+/// Returns the user routes, or none of them if they would let all traffic bypass the tunnel.
+fn safe_user_routes(user_routes: Vec<UserRoute>) -> Vec<UserRoute> {
+    match validate_user_routes(&user_routes) {
+        Ok(()) => user_routes,
+        Err(error) => {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Ignoring unsafe user routes")
+            );
+            vec![]
+        }
+    }
+}
+
+/// Returns the names of the interfaces used by the IPv4 and IPv6 default routes in the main
+/// routing table.
+pub fn default_route_interfaces() -> std::io::Result<Vec<String>> {
+    let mut interfaces = parse_ipv4_default_interfaces(&std::fs::read_to_string(PROC_NET_ROUTE)?);
+    // The file is missing if IPv6 is disabled
+    if let Ok(ipv6_routes) = std::fs::read_to_string(PROC_NET_IPV6_ROUTE) {
+        for interface in parse_ipv6_default_interfaces(&ipv6_routes) {
+            if !interfaces.contains(&interface) {
+                interfaces.push(interface);
+            }
+        }
+    }
+    Ok(interfaces)
+}
+
+const PROC_NET_ROUTE: &str = "/proc/net/route";
+const PROC_NET_IPV6_ROUTE: &str = "/proc/net/ipv6_route";
+
+/// Parses `/proc/net/route`, where each route is listed as
+/// `Iface Destination Gateway Flags RefCnt Use Metric Mask ...`.
+fn parse_ipv4_default_interfaces(routes: &str) -> Vec<String> {
+    routes
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let columns: Vec<_> = line.split_whitespace().collect();
+            match columns[..] {
+                [interface, "00000000", _, _, _, _, _, "00000000", ..] => {
+                    Some(interface.to_owned())
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Parses `/proc/net/ipv6_route`, where each route is listed as
+/// `destination prefix_len source source_prefix_len next_hop metric refcnt use flags iface`.
+/// Unreachable routes are attached to the loopback interface and are ignored.
+fn parse_ipv6_default_interfaces(routes: &str) -> Vec<String> {
+    const UNSPECIFIED: &str = "00000000000000000000000000000000";
+    routes
+        .lines()
+        .filter_map(|line| {
+            let columns: Vec<_> = line.split_whitespace().collect();
+            match columns[..] {
+                [UNSPECIFIED, "00", _, _, _, _, _, _, _, interface] if interface != "lo" => {
+                    Some(interface.to_owned())
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn check_rule_collisions(
+    rules: &[RuleMessage],
+    fwmark: u32,
+    table_id: u32,
+    split_tunnel_mark: u32,
+) -> Result<()> {
+    if split_tunnel_mark == fwmark {
+        return Err(Error::SplitTunnelMarkCollision(split_tunnel_mark));
+    }
+    for rule in rules {
+        let uses_mark = |mark| {
+            rule.nlas
+                .iter()
+                .any(|nla| matches!(nla, RuleNla::FwMark(rule_mark) if *rule_mark == mark))
+        };
+        let uses_table = u32::from(rule.header.table) == table_id
+            || rule
+                .nlas
+                .iter()
+                .any(|nla| matches!(nla, RuleNla::Table(table) if *table == table_id));
+        if uses_mark(fwmark) || uses_table {
+            log::error!("Found conflicting routing rule: {:?}", rule);
+            return Err(Error::RuleCollision { fwmark, table_id });
+        }
+        if uses_mark(split_tunnel_mark) {
+            log::error!("Found conflicting routing rule: {:?}", rule);
+            return Err(Error::SplitTunnelMarkCollision(split_tunnel_mark));
+        }
+    }
+    Ok(())
+}
+
+/// Route traffic bound to an overlay interface using the main table.
+fn overlay_interface_rule(family: libc::c_int, interface: &str) -> RuleMessage {
+    RuleMessage {
+        header: RuleHeader {
+            family: family as u8,
+            action: FR_ACT_TO_TBL,
+            ..RuleHeader::default()
+        },
+        nlas: vec![
+            RuleNla::OifName(interface.to_owned()),
+            RuleNla::Table(RT_TABLE_MAIN as u32),
+        ],
+    }
+}
+
+/// Route traffic destined for an overlay network using the main table, unless the only matching
+/// route is the default route. This prevents leaks if the overlay network is down.
+fn overlay_network_rule(network: &IpNetwork) -> RuleMessage {
+    let (family, destination) = match network.ip() {
+        IpAddr::V4(addr) => (AF_INET, addr.octets().to_vec()),
+        IpAddr::V6(addr) => (AF_INET6, addr.octets().to_vec()),
+    };
+    RuleMessage {
+        header: RuleHeader {
+            family: family as u8,
+            dst_len: network.prefix(),
+            action: FR_ACT_TO_TBL,
+            ..RuleHeader::default()
+        },
+        nlas: vec![
+            RuleNla::Destination(destination),
+            RuleNla::SuppressPrefixLen(0),
+            RuleNla::Table(RT_TABLE_MAIN as u32),
+        ],
+    }
+}
+
+/// Route unmarked traffic destined for the given network using the tunnel table. Since this rule
+/// takes precedence over the suppress rules, this also applies to networks in the main table,
+/// such as the LAN.
+fn tunnel_network_rule(fwmark: u32, table: u32, network: &IpNetwork) -> RuleMessage {
+    let (family, destination) = match network.ip() {
+        IpAddr::V4(addr) => (AF_INET, addr.octets().to_vec()),
+        IpAddr::V6(addr) => (AF_INET6, addr.octets().to_vec()),
+    };
+    RuleMessage {
+        header: RuleHeader {
+            family: family as u8,
+            dst_len: network.prefix(),
+            action: FR_ACT_TO_TBL,
+            flags: FIB_RULE_INVERT,
+            ..RuleHeader::default()
+        },
+        nlas: vec![
+            RuleNla::Destination(destination),
+            RuleNla::FwMark(fwmark),
+            RuleNla::Table(table),
+        ],
+    }
 }
 
 fn no_fwmark_rule_v4(fwmark: u32, table: u32) -> RuleMessage {
@@ -127,6 +317,22 @@ pub enum Error {
     #[error(display = "Cannot find a free routing table ID")]
     NoFreeRoutingTableId,
 
+    /// Another routing rule is already using the firewall mark or routing table.
+    #[error(
+        display = "An existing routing rule uses firewall mark {:#x} or routing table {:#x}",
+        fwmark,
+        table_id
+    )]
+    RuleCollision { fwmark: u32, table_id: u32 },
+
+    /// The split tunnel mark is the same as the firewall mark, or used by an existing routing
+    /// rule.
+    #[error(
+        display = "Split tunnel mark {:#x} is already in use as a firewall mark",
+        _0
+    )]
+    SplitTunnelMarkCollision(u32),
+
     #[error(display = "Shutting down route manager")]
     Shutdown,
 }
@@ -136,6 +342,7 @@ pub struct RouteManagerImpl {
     messages: UnboundedReceiver<(NetlinkMessage<RtnlMessage>, SocketAddr)>,
     iface_map: BTreeMap<u32, NetworkInterface>,
     listeners: Vec<UnboundedSender<CallbackMessage>>,
+    network_change_listeners: Vec<UnboundedSender<NetworkChange>>,
 
     // currently added routes
     added_routes: HashSet<Route>,
@@ -145,6 +352,20 @@ pub struct RouteManagerImpl {
     /// Firewall mark identifies traffic which shouldn't be routed via the tunnel routing table. It
     /// is used to construct a routing rule.
     fwmark: u32,
+    /// Connection mark of excluded traffic. No routing rule may use it as a firewall mark.
+    split_tunnel_mark: u32,
+    /// Overlay networks that should be routed using the main table.
+    trusted_overlays: TrustedOverlays,
+    /// Whether IPv6 routing rules are enabled, or `None` if no routing rules have been created.
+    routing_rules_ipv6: Option<bool>,
+    /// Routes configured by the user.
+    user_routes: Vec<UserRoute>,
+    /// Routes in the tunnel table that send user routes via the default gateway. These are
+    /// recreated whenever the default route changes.
+    gateway_routes: HashSet<Route>,
+    /// Interfaces used by the default routes in the main table, as of the last change. Changes to
+    /// other interfaces do not affect the tunnel and are not reported to network change listeners.
+    default_interfaces: HashSet<String>,
 }
 
 impl RouteManagerImpl {
@@ -152,6 +373,7 @@ impl RouteManagerImpl {
         required_routes: HashSet<RequiredRoute>,
         table_id: u32,
         fwmark: u32,
+        split_tunnel_mark: u32,
     ) -> Result<Self> {
         let (mut connection, handle, messages) =
             rtnetlink::new_connection().map_err(Error::Connect)?;
@@ -173,12 +395,21 @@ impl RouteManagerImpl {
             messages,
             iface_map,
             listeners: vec![],
+            network_change_listeners: vec![],
             added_routes: HashSet::new(),
             table_id,
             fwmark,
+            split_tunnel_mark,
+            trusted_overlays: TrustedOverlays::default(),
+            routing_rules_ipv6: None,
+            user_routes: vec![],
+            gateway_routes: HashSet::new(),
+            default_interfaces: HashSet::new(),
         };
+        monitor.update_default_interfaces();
 
         monitor.clear_routing_rules().await?;
+        monitor.check_rule_collisions().await?;
         monitor.add_required_routes(required_routes).await?;
 
         Ok(monitor)
@@ -188,10 +419,16 @@ impl RouteManagerImpl {
         use netlink_packet_route::constants::*;
 
         self.clear_routing_rules().await?;
+        self.routing_rules_ipv6 = Some(enable_ipv6);
 
-        for rule in all_rules(self.fwmark, self.table_id)
-            .iter()
-            .filter(|rule| rule.header.family as u16 == AF_INET || enable_ipv6)
+        for rule in all_rules(
+            self.fwmark,
+            self.table_id,
+            &self.trusted_overlays,
+            &self.user_routes,
+        )
+        .iter()
+        .filter(|rule| rule.header.family as u16 == AF_INET || enable_ipv6)
         {
             let mut req = NetlinkMessage::from(RtnlMessage::NewRule((*rule).clone()));
             req.header.flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_REPLACE;
@@ -204,12 +441,22 @@ impl RouteManagerImpl {
                 }
             }
         }
+
+        self.add_gateway_routes().await;
         Ok(())
     }
 
     async fn clear_routing_rules(&mut self) -> Result<()> {
+        self.routing_rules_ipv6 = None;
+        self.remove_gateway_routes().await;
+
         let rules = self.get_rules().await?;
-        for rule in all_rules(self.fwmark, self.table_id) {
+        for rule in all_rules(
+            self.fwmark,
+            self.table_id,
+            &self.trusted_overlays,
+            &self.user_routes,
+        ) {
             let mut matching_rule = None;
 
             // `RTM_DELRULE` is way too picky about which rules are considered the same.
@@ -222,6 +469,9 @@ impl RouteManagerImpl {
                 if found_rule.header.action != rule.header.action {
                     continue;
                 }
+                if found_rule.header.dst_len != rule.header.dst_len {
+                    continue;
+                }
                 if (found_rule.header.flags & rule.header.flags) != rule.header.flags {
                     continue;
                 }
@@ -247,6 +497,122 @@ impl RouteManagerImpl {
         Ok(())
     }
 
+    async fn set_trusted_overlays(&mut self, overlays: TrustedOverlays) -> Result<()> {
+        if self.trusted_overlays == overlays {
+            return Ok(());
+        }
+        match self.routing_rules_ipv6 {
+            Some(enable_ipv6) => {
+                // Remove the rules for the old overlays before creating the new ones
+                self.clear_routing_rules().await?;
+                self.trusted_overlays = overlays;
+                self.create_routing_rules(enable_ipv6).await
+            }
+            None => {
+                self.trusted_overlays = overlays;
+                Ok(())
+            }
+        }
+    }
+
+    async fn set_user_routes(&mut self, user_routes: Vec<UserRoute>) -> Result<()> {
+        let user_routes = safe_user_routes(user_routes);
+        if self.user_routes == user_routes {
+            return Ok(());
+        }
+        match self.routing_rules_ipv6 {
+            Some(enable_ipv6) => {
+                self.clear_routing_rules().await?;
+                self.user_routes = user_routes;
+                self.create_routing_rules(enable_ipv6).await
+            }
+            None => {
+                self.user_routes = user_routes;
+                Ok(())
+            }
+        }
+    }
+
+    /// Add routes to the tunnel table that send user routes with the default gateway as their
+    /// target via whichever route would have been used outside the tunnel. Failing to add a route
+    /// is not fatal, since the traffic then simply goes through the tunnel.
+    async fn add_gateway_routes(&mut self) {
+        let enable_ipv6 = match self.routing_rules_ipv6 {
+            Some(enable_ipv6) => enable_ipv6,
+            None => return,
+        };
+        let networks: Vec<IpNetwork> = self
+            .user_routes
+            .iter()
+            .filter(|route| route.target == UserRouteTarget::DefaultGateway)
+            .filter(|route| route.network.is_ipv4() || enable_ipv6)
+            .map(|route| route.network)
+            .collect();
+
+        for network in networks {
+            let node = match self
+                .get_destination_route(&network.ip(), Some(self.fwmark))
+                .await
+            {
+                Ok(Some(route)) => route.node,
+                Ok(None) => {
+                    log::debug!("No route outside the tunnel for {}", network);
+                    continue;
+                }
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg(&format!(
+                            "Failed to find a route outside the tunnel for {network}"
+                        ))
+                    );
+                    continue;
+                }
+            };
+            let route = Route::new(node, network).table(self.table_id);
+            if let Err(error) = self.add_route_direct(route.clone()).await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(&format!("Failed to add route {route}"))
+                );
+                continue;
+            }
+            self.gateway_routes.insert(route);
+        }
+    }
+
+    /// Recreate the gateway routes after the default route has changed.
+    async fn update_gateway_routes(&mut self) {
+        let has_gateway_routes = self
+            .user_routes
+            .iter()
+            .any(|route| route.target == UserRouteTarget::DefaultGateway);
+        if self.routing_rules_ipv6.is_none() || !has_gateway_routes {
+            return;
+        }
+        log::debug!("Default route changed, updating user routes");
+        self.remove_gateway_routes().await;
+        self.add_gateway_routes().await;
+    }
+
+    async fn remove_gateway_routes(&mut self) {
+        for route in self.gateway_routes.drain().collect::<Vec<_>>() {
+            if let Err(error) = self.delete_route_if_exists(&route).await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(&format!("Failed to remove route {route}"))
+                );
+            }
+        }
+    }
+
+    /// Fails if any remaining routing rule refers to our firewall marks or routing table. Since
+    /// our own rules have been removed at this point, such rules belong to some other software.
+    async fn check_rule_collisions(&mut self) -> Result<()> {
+        let rules = self.get_rules().await?;
+        check_rule_collisions(&rules, self.fwmark, self.table_id, self.split_tunnel_mark)
+    }
+
     async fn get_rules(&mut self) -> Result<Vec<RuleMessage>> {
         use netlink_packet_route::constants::*;
 
@@ -357,8 +723,12 @@ impl RouteManagerImpl {
                     self.process_command(command).await?;
                 },
                 (route_change, _socket) = self.messages.select_next_some().fuse() => {
-                    if let Err(error) = self.process_netlink_message(route_change) {
-                        log::error!("{}", error.display_chain_with_msg("Failed to process netlink message"));
+                    match self.process_netlink_message(route_change) {
+                        Ok(true) => self.update_gateway_routes().await,
+                        Ok(false) => (),
+                        Err(error) => {
+                            log::error!("{}", error.display_chain_with_msg("Failed to process netlink message"));
+                        }
                     }
                 }
             };
@@ -384,9 +754,20 @@ impl RouteManagerImpl {
             RouteManagerCommand::ClearRoutingRules(result_tx) => {
                 let _ = result_tx.send(self.clear_routing_rules().await);
             }
+            RouteManagerCommand::SetTrustedOverlays(overlays, result_tx) => {
+                let _ = result_tx.send(self.set_trusted_overlays(overlays).await);
+            }
+            RouteManagerCommand::SetUserRoutes(user_routes, result_tx) => {
+                let _ = result_tx.send(self.set_user_routes(user_routes).await);
+            }
             RouteManagerCommand::NewChangeListener(result_tx) => {
                 let _ = result_tx.send(self.listen());
             }
+            RouteManagerCommand::NewNetworkChangeListener(result_tx) => {
+                let (tx, rx) = futures::channel::mpsc::unbounded();
+                self.network_change_listeners.push(tx);
+                let _ = result_tx.send(rx);
+            }
             RouteManagerCommand::GetDestinationRoute(destination, mark, result_tx) => {
                 let _ = result_tx.send(self.get_destination_route(&destination, mark).await);
             }
@@ -401,32 +782,73 @@ impl RouteManagerImpl {
         Ok(())
     }
 
-    fn process_netlink_message(&mut self, msg: NetlinkMessage<RtnlMessage>) -> Result<()> {
+    /// Processes a netlink notification. Returns whether a default route in the main table was
+    /// added or removed.
+    fn process_netlink_message(&mut self, msg: NetlinkMessage<RtnlMessage>) -> Result<bool> {
+        let mut default_route_changed = false;
         match msg.payload {
             NetlinkPayload::InnerMessage(RtnlMessage::NewLink(new_link)) => {
-                if let Some((idx, name)) = Self::map_interface(new_link) {
-                    self.iface_map.insert(idx, name);
+                if let Some((idx, iface)) = Self::map_interface(new_link) {
+                    let was_up = self.iface_map.get(&idx).map(|old_iface| old_iface.is_up);
+                    if self.is_default_interface(&iface) && was_up != Some(iface.is_up) {
+                        self.notify_network_change_listeners(if iface.is_up {
+                            NetworkChange::InterfaceUp(iface.name.clone())
+                        } else {
+                            NetworkChange::InterfaceDown(iface.name.clone())
+                        });
+                    }
+                    self.iface_map.insert(idx, iface);
                 }
             }
             NetlinkPayload::InnerMessage(RtnlMessage::DelLink(old_link)) => {
-                if let Some((idx, _)) = Self::map_interface(old_link) {
+                if let Some((idx, iface)) = Self::map_interface(old_link) {
                     self.iface_map.remove(&idx);
+                    if self.is_default_interface(&iface) {
+                        self.notify_network_change_listeners(NetworkChange::InterfaceDown(
+                            iface.name,
+                        ));
+                    }
+                    // IPv4 routes are removed along with the interface without any notification
+                    self.update_default_interfaces();
                 }
             }
             NetlinkPayload::InnerMessage(RtnlMessage::NewRoute(new_route)) => {
                 if let Some(addition) = self.parse_route_message(new_route)? {
+                    default_route_changed = is_main_default_route(&addition);
                     self.notify_change_listeners(CallbackMessage::NewRoute(addition));
                 }
             }
             NetlinkPayload::InnerMessage(RtnlMessage::DelRoute(old_route)) => {
                 if let Some(deletion) = self.parse_route_message(old_route)? {
+                    default_route_changed = is_main_default_route(&deletion);
                     self.process_deleted_route(&deletion)?;
                     self.notify_change_listeners(CallbackMessage::DelRoute(deletion));
                 }
             }
             _ => (),
         };
-        Ok(())
+        if default_route_changed {
+            self.update_default_interfaces();
+            self.notify_network_change_listeners(NetworkChange::DefaultRoute);
+        }
+        Ok(default_route_changed)
+    }
+
+    /// Returns whether the interface was used by a default route in the main table. Other
+    /// interfaces, such as container veths, the split tunneling namespace or overlay networks,
+    /// do not carry the tunnel traffic.
+    fn is_default_interface(&self, iface: &NetworkInterface) -> bool {
+        !iface.is_loopback() && self.default_interfaces.contains(&iface.name)
+    }
+
+    fn update_default_interfaces(&mut self) {
+        match default_route_interfaces() {
+            Ok(interfaces) => self.default_interfaces = interfaces.into_iter().collect(),
+            Err(error) => log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to read default route interfaces")
+            ),
+        }
     }
 
     fn notify_change_listeners(&mut self, message: CallbackMessage) {
@@ -434,6 +856,11 @@ impl RouteManagerImpl {
             .retain(|listener| listener.unbounded_send(message.clone()).is_ok());
     }
 
+    fn notify_network_change_listeners(&mut self, change: NetworkChange) {
+        self.network_change_listeners
+            .retain(|listener| listener.unbounded_send(change.clone()).is_ok());
+    }
+
     // Tries to coax a Route out of a RouteMessage
     fn parse_route_message(&self, msg: RouteMessage) -> Result<Option<Route>> {
         let af_spec = msg.header.address_family;
@@ -532,6 +959,7 @@ impl RouteManagerImpl {
     fn map_interface(msg: LinkMessage) -> Option<(u32, NetworkInterface)> {
         let index = msg.header.index;
         let link_layer_type = msg.header.link_layer_type;
+        let is_up = msg.header.flags & (libc::IFF_LOWER_UP as u32) != 0;
         for nla in msg.nlas {
             if let LinkNla::IfName(name) = nla {
                 return Some((
@@ -539,6 +967,7 @@ impl RouteManagerImpl {
                     NetworkInterface {
                         name,
                         link_layer_type,
+                        is_up,
                     },
                 ));
             }
@@ -839,6 +1268,10 @@ impl RouteManagerImpl {
     }
 }
 
+fn is_main_default_route(route: &Route) -> bool {
+    route.prefix.prefix() == 0 && route.table_id == u32::from(RT_TABLE_MAIN)
+}
+
 fn ip_to_bytes(addr: IpAddr) -> Vec<u8> {
     match addr {
         IpAddr::V4(addr) => addr.octets().to_vec(),
@@ -892,6 +1325,8 @@ fn execute_route_get_request(
 struct NetworkInterface {
     name: String,
     link_layer_type: u16,
+    /// Whether the interface has a carrier.
+    is_up: bool,
 }
 
 impl NetworkInterface {
@@ -910,7 +1345,7 @@ mod test {
     fn test_drop_in_executor() {
         let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
         runtime.block_on(async {
-            let manager = RouteManagerImpl::new(HashSet::new(), 0, 0)
+            let manager = RouteManagerImpl::new(HashSet::new(), 0, 0, 1)
                 .await
                 .expect("Failed to initialize route manager");
             std::mem::drop(manager);
@@ -922,10 +1357,97 @@ mod test {
     fn test_drop() {
         let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
         let manager = runtime.block_on(async {
-            RouteManagerImpl::new(HashSet::new(), 1000, 1000)
+            RouteManagerImpl::new(HashSet::new(), 1000, 1000, 1001)
                 .await
                 .expect("Failed to initialize route manager")
         });
         std::mem::drop(manager);
     }
+
+    #[test]
+    fn test_parse_ipv4_default_interfaces() {
+        let routes = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlan0\t00000000\t0102A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+wlan0\t0002A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\t0\t0\t0
+tailscale0\t00000064\t00000000\t0001\t0\t0\t0\t000000FF\t0\t0\t0
+";
+        assert_eq!(parse_ipv4_default_interfaces(routes), vec!["wlan0"]);
+    }
+
+    #[test]
+    fn test_parse_ipv6_default_interfaces() {
+        let routes = "\
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe800000000000000000000000000001 00000400 00000001 00000000 00000003     eth0
+fe800000000000000000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001     eth0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 00000000000000000000000000000000 ffffffff 00000001 00000000 00200200       lo
+";
+        assert_eq!(parse_ipv6_default_interfaces(routes), vec!["eth0"]);
+    }
+
+    #[test]
+    fn test_user_route_rules() {
+        let route = |network: &str, target| UserRoute {
+            network: network.parse().unwrap(),
+            target,
+        };
+        let overlays = TrustedOverlays::default();
+        let base_rules = all_rules(1, 100, &overlays, &[]);
+
+        // Only routes through the tunnel need rules; gateway routes are added to the table
+        let tunnel_network = "192.168.1.0/24".parse().unwrap();
+        let routes = safe_user_routes(vec![
+            route("192.168.1.0/24", UserRouteTarget::Tunnel),
+            route("10.0.0.0/8", UserRouteTarget::DefaultGateway),
+        ]);
+        assert_eq!(routes.len(), 2);
+        let mut expected_rules = base_rules.clone();
+        expected_rules.push(tunnel_network_rule(1, 100, &tunnel_network));
+        assert_eq!(all_rules(1, 100, &overlays, &routes), expected_rules);
+
+        // Routes that would send everything outside the tunnel are ignored
+        for routes in [
+            vec![route("0.0.0.0/0", UserRouteTarget::Tunnel)],
+            vec![route("::/0", UserRouteTarget::DefaultGateway)],
+            vec![
+                route("192.168.1.0/24", UserRouteTarget::Tunnel),
+                route("0.0.0.0/1", UserRouteTarget::DefaultGateway),
+                route("128.0.0.0/1", UserRouteTarget::DefaultGateway),
+            ],
+        ] {
+            let routes = safe_user_routes(routes);
+            assert!(routes.is_empty());
+            assert_eq!(all_rules(1, 100, &overlays, &routes), base_rules);
+        }
+    }
+
+    #[test]
+    fn test_check_rule_collisions() {
+        let rule = |nlas| RuleMessage {
+            header: RuleHeader::default(),
+            nlas,
+        };
+        let rules = [
+            rule(vec![RuleNla::FwMark(1), RuleNla::Table(100)]),
+            rule(vec![RuleNla::Table(RT_TABLE_MAIN as u32)]),
+        ];
+
+        assert!(check_rule_collisions(&rules, 2, 200, 3).is_ok());
+        assert!(matches!(
+            check_rule_collisions(&rules, 1, 200, 3),
+            Err(Error::RuleCollision { .. })
+        ));
+        assert!(matches!(
+            check_rule_collisions(&rules, 2, 100, 3),
+            Err(Error::RuleCollision { .. })
+        ));
+        assert!(matches!(
+            check_rule_collisions(&rules, 2, 200, 1),
+            Err(Error::SplitTunnelMarkCollision(1))
+        ));
+        assert!(matches!(
+            check_rule_collisions(&[], 2, 200, 2),
+            Err(Error::SplitTunnelMarkCollision(2))
+        ));
+    }
 }
@@ -18,6 +18,8 @@ use futures::stream::Stream;
 
 #[cfg(target_os = "linux")]
 use std::net::IpAddr;
+#[cfg(target_os = "linux")]
+use talpid_types::net::{TrustedOverlays, UserRoute};
 
 #[allow(clippy::module_inception)]
 #[cfg(target_os = "macos")]
@@ -30,6 +32,8 @@ pub use imp::listen_for_default_route_changes;
 #[cfg(target_os = "linux")]
 #[path = "linux.rs"]
 mod imp;
+#[cfg(target_os = "linux")]
+pub use imp::default_route_interfaces;
 
 #[allow(clippy::module_inception)]
 #[cfg(target_os = "android")]
@@ -106,6 +110,35 @@ impl RouteManagerHandle {
             .map_err(Error::PlatformError)
     }
 
+    /// Route traffic on the given overlay interfaces and networks using the main routing table.
+    #[cfg(target_os = "linux")]
+    pub async fn set_trusted_overlays(&self, overlays: TrustedOverlays) -> Result<(), Error> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RouteManagerCommand::SetTrustedOverlays(
+                overlays,
+                response_tx,
+            ))
+            .map_err(|_| Error::RouteManagerDown)?;
+        response_rx
+            .await
+            .map_err(|_| Error::ManagerChannelDown)?
+            .map_err(Error::PlatformError)
+    }
+
+    /// Set the routes configured by the user. These are applied while routing rules are active.
+    #[cfg(target_os = "linux")]
+    pub async fn set_user_routes(&self, user_routes: Vec<UserRoute>) -> Result<(), Error> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RouteManagerCommand::SetUserRoutes(user_routes, response_tx))
+            .map_err(|_| Error::RouteManagerDown)?;
+        response_rx
+            .await
+            .map_err(|_| Error::ManagerChannelDown)?
+            .map_err(Error::PlatformError)
+    }
+
     /// Listen for route changes.
     #[cfg(target_os = "linux")]
     pub async fn change_listener(&self) -> Result<impl Stream<Item = CallbackMessage>, Error> {
@@ -116,6 +149,18 @@ impl RouteManagerHandle {
         response_rx.await.map_err(|_| Error::ManagerChannelDown)
     }
 
+    /// Listen for changes to the default route and to network interfaces.
+    #[cfg(target_os = "linux")]
+    pub async fn network_change_listener(
+        &self,
+    ) -> Result<impl Stream<Item = NetworkChange>, Error> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RouteManagerCommand::NewNetworkChangeListener(response_tx))
+            .map_err(|_| Error::RouteManagerDown)?;
+        response_rx.await.map_err(|_| Error::ManagerChannelDown)
+    }
+
     /// Listen for route changes.
     #[cfg(target_os = "linux")]
     pub async fn get_destination_route(
@@ -169,8 +214,14 @@ pub(crate) enum RouteManagerCommand {
     #[cfg(target_os = "linux")]
     ClearRoutingRules(oneshot::Sender<Result<(), PlatformError>>),
     #[cfg(target_os = "linux")]
+    SetTrustedOverlays(TrustedOverlays, oneshot::Sender<Result<(), PlatformError>>),
+    #[cfg(target_os = "linux")]
+    SetUserRoutes(Vec<UserRoute>, oneshot::Sender<Result<(), PlatformError>>),
+    #[cfg(target_os = "linux")]
     NewChangeListener(oneshot::Sender<mpsc::UnboundedReceiver<CallbackMessage>>),
     #[cfg(target_os = "linux")]
+    NewNetworkChangeListener(oneshot::Sender<mpsc::UnboundedReceiver<NetworkChange>>),
+    #[cfg(target_os = "linux")]
     GetMtuForRoute(IpAddr, oneshot::Sender<Result<u16, PlatformError>>),
     /// Attempt to fetch a route for the given destination with an optional firewall mark.
     #[cfg(target_os = "linux")]
@@ -188,6 +239,18 @@ pub enum CallbackMessage {
     DelRoute(Route),
 }
 
+/// A change in the network environment that may break established connections.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkChange {
+    /// A default route was added to or removed from the main routing table.
+    DefaultRoute,
+    /// A network interface used by a default route gained a carrier.
+    InterfaceUp(String),
+    /// A network interface used by a default route lost its carrier or was removed.
+    InterfaceDown(String),
+}
+
 /// RouteManager applies a set of routes to the route table.
 /// If a destination has to be routed through the default node,
 /// the route will be adjusted dynamically when the default route changes.
@@ -204,6 +267,7 @@ impl RouteManager {
         required_routes: HashSet<RequiredRoute>,
         #[cfg(target_os = "linux")] fwmark: u32,
         #[cfg(target_os = "linux")] table_id: u32,
+        #[cfg(target_os = "linux")] split_tunnel_mark: u32,
     ) -> Result<Self, Error> {
         let (manage_tx, manage_rx) = mpsc::unbounded();
         let manager = imp::RouteManagerImpl::new(
@@ -212,6 +276,8 @@ impl RouteManager {
             fwmark,
             #[cfg(target_os = "linux")]
             table_id,
+            #[cfg(target_os = "linux")]
+            split_tunnel_mark,
         )
         .await?;
         tokio::spawn(manager.run(manage_rx));
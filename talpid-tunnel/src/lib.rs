@@ -2,6 +2,7 @@ use std::{
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
     path::Path,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
 #[cfg(windows)]
@@ -9,9 +10,12 @@ use std::{
 pub mod network_interface;
 
 pub mod tun_provider;
-use futures::{channel::oneshot, future::BoxFuture};
+use futures::{
+    channel::{mpsc, oneshot},
+    future::BoxFuture,
+};
 use talpid_routing::RouteManagerHandle;
-use talpid_types::net::AllowedTunnelTraffic;
+use talpid_types::{net::AllowedTunnelTraffic, tunnel::TunnelStatistics};
 use tun_provider::TunProvider;
 
 /// Arguments for creating a tunnel.
@@ -33,6 +37,20 @@ where
     pub retry_attempt: u32,
     /// Route manager handle.
     pub route_manager: RouteManagerHandle,
+    /// Receiver for network change notifications, such as a new default route. The tunnel should
+    /// try to restore connectivity right away when one is received.
+    #[cfg(target_os = "linux")]
+    pub network_change_rx: mpsc::UnboundedReceiver<()>,
+    /// Name of the interface of a tunnel that keeps carrying traffic while this tunnel is being
+    /// set up. If set, the new tunnel must use another interface, and must not touch any routes
+    /// until connectivity has been established. It then takes over the routes of the old tunnel.
+    #[cfg(target_os = "linux")]
+    pub replaces_interface: Option<String>,
+    /// Receiver for requests for traffic statistics. The sender is dropped without a reply if no
+    /// statistics are available.
+    pub stats_request_rx: mpsc::UnboundedReceiver<oneshot::Sender<TunnelStatistics>>,
+    /// Receiver for changes to how often the PSK of a quantum-resistant tunnel is renegotiated.
+    pub psk_rekey_interval_rx: mpsc::UnboundedReceiver<Option<Duration>>,
 }
 
 /// Information about a VPN tunnel.
@@ -46,6 +64,8 @@ pub struct TunnelMetadata {
     pub ipv4_gateway: Ipv4Addr,
     /// The IP to the IPv6 default gateway on the tunnel interface.
     pub ipv6_gateway: Option<Ipv6Addr>,
+    /// The MTU of the tunnel interface, if known.
+    pub mtu: Option<u16>,
 }
 
 /// Possible events from the VPN tunnel and the child process managing it.
@@ -59,4 +79,9 @@ pub enum TunnelEvent {
     Up(TunnelMetadata),
     /// Sent when the tunnel goes down, but before destroying the tunnel device.
     Down,
+    /// Sent when the keys of an established tunnel have been renegotiated.
+    Renegotiated,
+    /// Sent when the tunnel process is closing the connection on its own, with the reason it
+    /// gave, such as `ping-exit` or `connection-reset`.
+    Closing(String),
 }
@@ -9,6 +9,8 @@ pub mod tunnel;
 
 #[cfg(target_os = "linux")]
 pub mod cgroup;
+#[cfg(target_os = "linux")]
+pub mod net_namespace;
 
 /// Used to generate string representations of error chains.
 pub trait ErrorExt {
@@ -34,6 +34,7 @@ impl TunnelParameters {
                 proxy: params.proxy.as_ref().map(|proxy| proxy.get_endpoint()),
                 obfuscation: None,
                 entry_endpoint: None,
+                mtu: None,
             },
             TunnelParameters::Wireguard(params) => TunnelEndpoint {
                 tunnel_type: TunnelType::Wireguard,
@@ -48,6 +49,7 @@ impl TunnelParameters {
                     .connection
                     .get_exit_endpoint()
                     .map(|_| params.connection.get_endpoint()),
+                mtu: None,
             },
         }
     }
@@ -74,6 +76,38 @@ impl TunnelParameters {
                 address: *endpoint,
                 protocol: TransportProtocol::Tcp,
             },
+            ObfuscatorConfig::Shadowsocks { endpoint, .. } => Endpoint {
+                address: *endpoint,
+                protocol: TransportProtocol::Udp,
+            },
+            ObfuscatorConfig::Tls { endpoint, .. } => Endpoint {
+                address: *endpoint,
+                protocol: TransportProtocol::Tcp,
+            },
+            ObfuscatorConfig::Socks5 { endpoint, .. } => Endpoint {
+                address: *endpoint,
+                protocol: TransportProtocol::Tcp,
+            },
+        }
+    }
+
+    /// Returns the host that a SOCKS5 proxy relays the tunnel traffic from, if one is used. The
+    /// UDP port is assigned by the proxy when the tunnel is started, so it is not known here.
+    pub fn get_next_hop_udp_relay(&self) -> Option<IpAddr> {
+        match self {
+            TunnelParameters::Wireguard(wireguard::TunnelParameters {
+                obfuscation: Some(ObfuscatorConfig::Socks5 { endpoint, .. }),
+                ..
+            }) => Some(endpoint.ip()),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the path MTU to the relay is probed once the tunnel is up.
+    pub fn get_mtu_discovery(&self) -> bool {
+        match self {
+            TunnelParameters::OpenVpn(_) => false,
+            TunnelParameters::Wireguard(params) => params.options.mtu_discovery,
         }
     }
 
@@ -142,6 +176,10 @@ pub struct TunnelEndpoint {
     pub obfuscation: Option<ObfuscationEndpoint>,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub entry_endpoint: Option<Endpoint>,
+    /// MTU of the tunnel interface. Only known once connected.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtu: Option<u16>,
 }
 
 impl fmt::Display for TunnelEndpoint {
@@ -175,12 +213,21 @@ impl fmt::Display for TunnelEndpoint {
 pub enum ObfuscationType {
     #[serde(rename = "udp2tcp")]
     Udp2Tcp,
+    #[serde(rename = "shadowsocks")]
+    Shadowsocks,
+    #[serde(rename = "tls")]
+    Tls,
+    #[serde(rename = "socks5")]
+    Socks5,
 }
 
 impl fmt::Display for ObfuscationType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         match self {
             ObfuscationType::Udp2Tcp => "Udp2Tcp".fmt(f),
+            ObfuscationType::Shadowsocks => "Shadowsocks".fmt(f),
+            ObfuscationType::Tls => "TLS".fmt(f),
+            ObfuscationType::Socks5 => "SOCKS5".fmt(f),
         }
     }
 }
@@ -202,6 +249,27 @@ impl From<&ObfuscatorConfig> for ObfuscationEndpoint {
                 },
                 ObfuscationType::Udp2Tcp,
             ),
+            ObfuscatorConfig::Shadowsocks { endpoint, .. } => (
+                Endpoint {
+                    address: *endpoint,
+                    protocol: TransportProtocol::Udp,
+                },
+                ObfuscationType::Shadowsocks,
+            ),
+            ObfuscatorConfig::Tls { endpoint, .. } => (
+                Endpoint {
+                    address: *endpoint,
+                    protocol: TransportProtocol::Tcp,
+                },
+                ObfuscationType::Tls,
+            ),
+            ObfuscatorConfig::Socks5 { endpoint, .. } => (
+                Endpoint {
+                    address: *endpoint,
+                    protocol: TransportProtocol::Tcp,
+                },
+                ObfuscationType::Socks5,
+            ),
         };
 
         ObfuscationEndpoint {
@@ -373,6 +441,192 @@ pub struct GenericTunnelOptions {
     pub enable_ipv6: bool,
 }
 
+/// Interfaces and networks belonging to other VPNs or overlay networks, such as Tailscale or
+/// ZeroTier. Traffic on these is allowed in every tunnel state and is routed using the main
+/// routing table instead of through the tunnel.
+#[cfg(target_os = "linux")]
+#[derive(Default, Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TrustedOverlays {
+    /// Names of the overlay network interfaces.
+    pub interfaces: Vec<String>,
+    /// Networks that should be reachable via the overlay network interfaces.
+    pub networks: Vec<ipnetwork::IpNetwork>,
+}
+
+#[cfg(target_os = "linux")]
+impl TrustedOverlays {
+    pub fn is_empty(&self) -> bool {
+        self.interfaces.is_empty() && self.networks.is_empty()
+    }
+
+    /// Fails if allowing the overlays would let all traffic bypass the tunnel, i.e. if the
+    /// networks together cover the entire address space or an interface carries one of the
+    /// default routes.
+    pub fn validate(&self, default_interfaces: &[String]) -> Result<(), TrustedOverlaysError> {
+        self.validate_networks()?;
+        if let Some(interface) = self
+            .interfaces
+            .iter()
+            .find(|interface| default_interfaces.contains(interface))
+        {
+            return Err(TrustedOverlaysError::DefaultInterface(interface.clone()));
+        }
+        Ok(())
+    }
+
+    /// Fails if the networks together cover the entire IPv4 or IPv6 address space.
+    pub fn validate_networks(&self) -> Result<(), TrustedOverlaysError> {
+        match covered_default_network(&self.networks) {
+            Some(network) => Err(TrustedOverlaysError::DefaultNetwork(network)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Reasons for rejecting a set of [`TrustedOverlays`].
+#[cfg(target_os = "linux")]
+#[derive(err_derive::Error, Debug, Clone, PartialEq, Eq)]
+pub enum TrustedOverlaysError {
+    /// The networks together match every address in the given default network.
+    #[error(display = "The overlay networks match all addresses in {}", _0)]
+    DefaultNetwork(ipnetwork::IpNetwork),
+    /// The interface is used by a default route.
+    #[error(display = "The overlay interface {} is used by a default route", _0)]
+    DefaultInterface(String),
+}
+
+/// Returns `0.0.0.0/0` or `::/0` if the networks together match every IPv4 or every IPv6
+/// address, respectively.
+#[cfg(target_os = "linux")]
+pub fn covered_default_network<'a>(
+    networks: impl IntoIterator<Item = &'a ipnetwork::IpNetwork>,
+) -> Option<ipnetwork::IpNetwork> {
+    use ipnetwork::IpNetwork;
+
+    let mut ipv4_ranges = vec![];
+    let mut ipv6_ranges = vec![];
+    for network in networks {
+        match network {
+            IpNetwork::V4(network) => ipv4_ranges.push(address_range(
+                u128::from(u32::from(network.network())),
+                32 - network.prefix(),
+            )),
+            IpNetwork::V6(network) => ipv6_ranges.push(address_range(
+                u128::from(network.network()),
+                128 - network.prefix(),
+            )),
+        }
+    }
+
+    if covers_all_addresses(ipv4_ranges, u128::from(u32::MAX)) {
+        Some("0.0.0.0/0".parse().unwrap())
+    } else if covers_all_addresses(ipv6_ranges, u128::MAX) {
+        Some("::/0".parse().unwrap())
+    } else {
+        None
+    }
+}
+
+/// Returns the first and last address of a network, given its host bits.
+#[cfg(target_os = "linux")]
+fn address_range(first: u128, host_bits: u8) -> (u128, u128) {
+    let host_mask = u128::MAX
+        .checked_shr(128 - u32::from(host_bits))
+        .unwrap_or(0);
+    (first, first | host_mask)
+}
+
+/// Returns whether the address ranges together cover every address from zero up to
+/// `max_address`.
+#[cfg(target_os = "linux")]
+fn covers_all_addresses(mut ranges: Vec<(u128, u128)>, max_address: u128) -> bool {
+    ranges.sort_unstable();
+    // The first address that no range has covered yet
+    let mut uncovered = 0;
+    for (first, last) in ranges {
+        if first > uncovered {
+            return false;
+        }
+        if last >= max_address {
+            return true;
+        }
+        uncovered = uncovered.max(last + 1);
+    }
+    false
+}
+
+/// A route to a network that is configured by the user.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct UserRoute {
+    /// Destination network of the route.
+    pub network: ipnetwork::IpNetwork,
+    /// Where traffic to the network is sent.
+    pub target: UserRouteTarget,
+}
+
+#[cfg(target_os = "linux")]
+impl fmt::Display for UserRoute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(f, "{} via {}", self.network, self.target)
+    }
+}
+
+/// Fails if a route matches all addresses, or if the routes via the default gateway together
+/// cover the entire IPv4 or IPv6 address space, since that would let all traffic bypass the
+/// tunnel.
+#[cfg(target_os = "linux")]
+pub fn validate_user_routes(routes: &[UserRoute]) -> Result<(), UserRouteError> {
+    if let Some(route) = routes.iter().find(|route| route.network.prefix() == 0) {
+        return Err(UserRouteError::DefaultNetwork(route.network));
+    }
+    let gateway_networks = routes
+        .iter()
+        .filter(|route| route.target == UserRouteTarget::DefaultGateway)
+        .map(|route| &route.network);
+    match covered_default_network(gateway_networks) {
+        Some(network) => Err(UserRouteError::GatewayDefaultNetwork(network)),
+        None => Ok(()),
+    }
+}
+
+/// Reasons for rejecting a set of [`UserRoute`]s.
+#[cfg(target_os = "linux")]
+#[derive(err_derive::Error, Debug, Clone, PartialEq, Eq)]
+pub enum UserRouteError {
+    /// The route matches all addresses.
+    #[error(display = "The route {} matches all addresses", _0)]
+    DefaultNetwork(ipnetwork::IpNetwork),
+    /// The routes via the default gateway together match every address in the given network.
+    #[error(
+        display = "The routes via the default gateway match all addresses in {}",
+        _0
+    )]
+    GatewayDefaultNetwork(ipnetwork::IpNetwork),
+}
+
+/// Where traffic matching a [`UserRoute`] is sent.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserRouteTarget {
+    /// Always route the network through the tunnel, even if it is part of the LAN or the traffic
+    /// comes from an excluded process.
+    Tunnel,
+    /// Route the network outside the tunnel, via the current default gateway.
+    DefaultGateway,
+}
+
+#[cfg(target_os = "linux")]
+impl fmt::Display for UserRouteTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            UserRouteTarget::Tunnel => "tunnel".fmt(f),
+            UserRouteTarget::DefaultGateway => "default gateway".fmt(f),
+        }
+    }
+}
+
 /// Returns a vector of IP networks representing all of the internet, 0.0.0.0/0.
 /// This may be used in [`crate::net::wireguard::PeerConfig`] to route all traffic
 /// to the tunnel interface.
@@ -382,3 +636,138 @@ pub fn all_of_the_internet() -> Vec<ipnetwork::IpNetwork> {
         "::0/0".parse().expect("Failed to parse ipv6 network"),
     ]
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_covered_default_network() {
+        let networks = |networks: &[&str]| -> Vec<ipnetwork::IpNetwork> {
+            networks.iter().map(|n| n.parse().unwrap()).collect()
+        };
+
+        assert_eq!(covered_default_network(&[]), None);
+        assert_eq!(covered_default_network(&networks(&["0.0.0.0/1"])), None);
+        assert_eq!(
+            covered_default_network(&networks(&["0.0.0.0/1", "128.0.0.0/2", "::/0"])),
+            Some("::/0".parse().unwrap())
+        );
+        // Overlapping networks
+        assert_eq!(
+            covered_default_network(&networks(&[
+                "128.0.0.0/1",
+                "0.0.0.0/2",
+                "0.0.0.0/1",
+                "255.255.255.255/32"
+            ])),
+            Some("0.0.0.0/0".parse().unwrap())
+        );
+        // Halving networks that leave out only the last address
+        let mut halves: Vec<ipnetwork::IpNetwork> = (1..=32)
+            .map(|prefix| {
+                let first = !(u32::MAX >> (prefix - 1));
+                ipnetwork::Ipv4Network::new(first.into(), prefix as u8)
+                    .unwrap()
+                    .into()
+            })
+            .collect();
+        assert_eq!(covered_default_network(&halves), None);
+        halves.push("255.255.255.255/32".parse().unwrap());
+        assert_eq!(
+            covered_default_network(&halves),
+            Some("0.0.0.0/0".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_validate_trusted_overlays() {
+        let default_interfaces = vec!["eth0".to_owned()];
+        let valid = TrustedOverlays {
+            interfaces: vec!["tailscale0".to_owned()],
+            networks: vec!["100.64.0.0/10".parse().unwrap()],
+        };
+        assert_eq!(valid.validate(&default_interfaces), Ok(()));
+
+        for network in ["0.0.0.0/0", "::/0"] {
+            let overlays = TrustedOverlays {
+                networks: vec![network.parse().unwrap()],
+                ..valid.clone()
+            };
+            assert_eq!(
+                overlays.validate(&default_interfaces),
+                Err(TrustedOverlaysError::DefaultNetwork(
+                    network.parse().unwrap()
+                ))
+            );
+        }
+
+        // Networks that only cover everything together are rejected too
+        for (networks, default_network) in [
+            (&["0.0.0.0/1", "128.0.0.0/1"][..], "0.0.0.0/0"),
+            (&["::/1", "8000::/1"][..], "::/0"),
+            (&["8000::/1", "::/2", "4000::/3", "6000::/3"][..], "::/0"),
+        ] {
+            let overlays = TrustedOverlays {
+                networks: networks.iter().map(|n| n.parse().unwrap()).collect(),
+                ..valid.clone()
+            };
+            assert_eq!(
+                overlays.validate_networks(),
+                Err(TrustedOverlaysError::DefaultNetwork(
+                    default_network.parse().unwrap()
+                ))
+            );
+        }
+
+        let overlays = TrustedOverlays {
+            interfaces: vec!["tailscale0".to_owned(), "eth0".to_owned()],
+            ..valid
+        };
+        assert_eq!(
+            overlays.validate(&default_interfaces),
+            Err(TrustedOverlaysError::DefaultInterface("eth0".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_validate_user_routes() {
+        let route = |network: &str, target| UserRoute {
+            network: network.parse().unwrap(),
+            target,
+        };
+        let valid = [
+            route("192.168.1.0/24", UserRouteTarget::Tunnel),
+            route("0.0.0.0/1", UserRouteTarget::Tunnel),
+            route("128.0.0.0/1", UserRouteTarget::DefaultGateway),
+            route("10.0.0.0/8", UserRouteTarget::DefaultGateway),
+            route("::/1", UserRouteTarget::DefaultGateway),
+        ];
+        assert_eq!(validate_user_routes(&valid), Ok(()));
+
+        for target in [UserRouteTarget::Tunnel, UserRouteTarget::DefaultGateway] {
+            for network in ["0.0.0.0/0", "::/0"] {
+                assert_eq!(
+                    validate_user_routes(&[route(network, target)]),
+                    Err(UserRouteError::DefaultNetwork(network.parse().unwrap()))
+                );
+            }
+        }
+
+        let mut routes = valid.to_vec();
+        routes.push(route("8000::/1", UserRouteTarget::DefaultGateway));
+        assert_eq!(
+            validate_user_routes(&routes),
+            Err(UserRouteError::GatewayDefaultNetwork(
+                "::/0".parse().unwrap()
+            ))
+        );
+        routes.push(route("0.0.0.0/1", UserRouteTarget::DefaultGateway));
+        assert_eq!(
+            validate_user_routes(&routes),
+            Err(UserRouteError::GatewayDefaultNetwork(
+                "0.0.0.0/0".parse().unwrap()
+            ))
+        );
+    }
+}
@@ -1,7 +1,32 @@
+use super::openvpn::ProxyAuth;
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
 
 #[derive(Clone, Eq, PartialEq, Deserialize, Serialize, Debug)]
 pub enum ObfuscatorConfig {
-    Udp2Tcp { endpoint: SocketAddr },
+    Udp2Tcp {
+        endpoint: SocketAddr,
+    },
+    /// Relay WireGuard traffic through a Shadowsocks server.
+    Shadowsocks {
+        endpoint: SocketAddr,
+        password: String,
+        cipher: String,
+        /// WireGuard endpoint that the Shadowsocks server relays the traffic to.
+        wireguard_endpoint: SocketAddr,
+    },
+    /// Send WireGuard traffic over a TLS connection.
+    Tls {
+        endpoint: SocketAddr,
+        /// Server name sent in the TLS handshake.
+        server_name: String,
+    },
+    /// Relay WireGuard traffic through a SOCKS5 proxy using UDP ASSOCIATE.
+    Socks5 {
+        /// TCP endpoint of the proxy. Datagrams are relayed from the same host.
+        endpoint: SocketAddr,
+        auth: Option<ProxyAuth>,
+        /// WireGuard endpoint that the proxy relays the traffic to.
+        wireguard_endpoint: SocketAddr,
+    },
 }
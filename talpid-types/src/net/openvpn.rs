@@ -23,6 +23,10 @@ pub struct ConnectionConfig {
     pub endpoint: Endpoint,
     pub username: String,
     pub password: String,
+    /// Certificates and keys imported from an OpenVPN profile. When not set, the bundled CA
+    /// certificate and configuration are used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<ProfileConfig>,
 }
 
 impl ConnectionConfig {
@@ -31,19 +35,124 @@ impl ConnectionConfig {
             endpoint,
             username,
             password,
+            profile: None,
         }
     }
 }
 
+/// Server specific settings read from an OpenVPN profile. Keys and certificates are stored in
+/// PEM format.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct ProfileConfig {
+    /// CA certificate used to verify the server.
+    pub ca: String,
+    /// Client certificate, if the server authenticates clients by certificate.
+    pub cert: Option<String>,
+    /// Private key belonging to `cert`.
+    pub key: Option<String>,
+    /// Static key used to authenticate the TLS control channel.
+    pub tls_auth: Option<String>,
+    /// Direction argument of `tls_auth`.
+    pub key_direction: Option<u8>,
+    /// Data channel cipher to use if the server does not support cipher negotiation.
+    pub cipher: Option<String>,
+    /// Message digest used to authenticate packets, such as `SHA256`.
+    pub auth: Option<String>,
+    /// Name that the server certificate must have.
+    pub verify_x509_name: Option<VerifyX509Name>,
+    /// Whether the server expects a username and password.
+    pub auth_user_pass: bool,
+}
+
+/// Name that the server certificate must have, as given by `verify-x509-name`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct VerifyX509Name {
+    pub name: String,
+    /// What `name` is compared to: `subject`, `name` or `name-prefix`. OpenVPN compares it to
+    /// the whole subject if this is not set.
+    pub name_type: Option<String>,
+}
+
 /// `TunnelOptions` contains options for an OpenVPN tunnel that should be applied
 /// irrespective of the relay parameters - i.e. have nothing to do with the particular
 /// OpenVPN server, but do affect the connection.
 /// Stored in [`TunnelParameters`].
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct TunnelOptions {
     /// Optional argument for openvpn to try and limit TCP packet size,
     /// as discussed [here](https://openvpn.net/archive/openvpn-users/2003-11/msg00154.html)
     pub mssfix: Option<u16>,
+    /// Only use this data channel cipher, so servers that do not support it cannot be used. If
+    /// not set, the server picks one of the ciphers that both sides support.
+    pub data_cipher: Option<DataCipher>,
+    /// Fragment packets so that no UDP datagram is larger than this many bytes. Only applies to
+    /// UDP, and only works if the server uses the same setting.
+    pub fragment: Option<u16>,
+    /// MTU of the tunnel interface. OpenVPN picks the MTU if not set.
+    pub tun_mtu: Option<u16>,
+}
+
+impl TunnelOptions {
+    /// Smallest allowed `tun_mtu` and `fragment` size.
+    pub const MIN_MTU: u16 = 576;
+    /// Largest allowed `tun_mtu` and `fragment` size.
+    pub const MAX_MTU: u16 = 1500;
+
+    /// Returns an error if the options cannot be passed to OpenVPN.
+    pub fn validate(&self) -> Result<(), TunnelOptionsError> {
+        let mtu_range = Self::MIN_MTU..=Self::MAX_MTU;
+        if let Some(tun_mtu) = self.tun_mtu {
+            if !mtu_range.contains(&tun_mtu) {
+                return Err(TunnelOptionsError::InvalidTunMtu(tun_mtu));
+            }
+        }
+        if let Some(fragment) = self.fragment {
+            if !mtu_range.contains(&fragment) {
+                return Err(TunnelOptionsError::InvalidFragment(fragment));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Error returned for invalid [`TunnelOptions`].
+#[derive(err_derive::Error, Debug, Clone, PartialEq, Eq)]
+pub enum TunnelOptionsError {
+    /// The tunnel MTU is out of range.
+    #[error(
+        display = "The tun-mtu size must be between {} and {} bytes, got {}",
+        TunnelOptions::MIN_MTU,
+        TunnelOptions::MAX_MTU,
+        _0
+    )]
+    InvalidTunMtu(u16),
+    /// The fragment size is out of range.
+    #[error(
+        display = "The fragment size must be between {} and {} bytes, got {}",
+        TunnelOptions::MIN_MTU,
+        TunnelOptions::MAX_MTU,
+        _0
+    )]
+    InvalidFragment(u16),
+}
+
+/// Data channel ciphers that can be selected in [`TunnelOptions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DataCipher {
+    Aes256Gcm,
+    Chacha20Poly1305,
+}
+
+impl DataCipher {
+    /// Returns the name of the cipher as understood by OpenVPN.
+    pub fn as_openvpn_name(&self) -> &'static str {
+        match self {
+            DataCipher::Aes256Gcm => "AES-256-GCM",
+            DataCipher::Chacha20Poly1305 => "CHACHA20-POLY1305",
+        }
+    }
 }
 
 /// Proxy server options to be used by `OpenVpnMonitor` when starting a tunnel.
@@ -53,6 +162,7 @@ pub enum ProxySettings {
     Local(LocalProxySettings),
     Remote(RemoteProxySettings),
     Shadowsocks(ShadowsocksProxySettings),
+    Http(HttpProxySettings),
 }
 
 impl ProxySettings {
@@ -70,6 +180,10 @@ impl ProxySettings {
                 endpoint: settings.get_endpoint(),
                 proxy_type: ProxyType::Shadowsocks,
             },
+            ProxySettings::Http(settings) => ProxyEndpoint {
+                endpoint: settings.get_endpoint(),
+                proxy_type: ProxyType::Custom,
+            },
         }
     }
 }
@@ -106,6 +220,23 @@ impl RemoteProxySettings {
     }
 }
 
+/// Options for an HTTP proxy that supports the CONNECT method. Only basic authentication is
+/// supported.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct HttpProxySettings {
+    pub address: SocketAddr,
+    pub auth: Option<ProxyAuth>,
+}
+
+impl HttpProxySettings {
+    pub fn get_endpoint(&self) -> Endpoint {
+        Endpoint {
+            address: self.address,
+            protocol: TransportProtocol::Tcp,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 pub struct ProxyAuth {
     pub username: String,
@@ -182,6 +313,14 @@ pub fn validate_proxy_settings(proxy: &ProxySettings) -> Result<(), String> {
                 return Err(String::from("localhost is not a valid remote server"));
             }
         }
+        ProxySettings::Http(http) => {
+            if http.address.port() == 0 {
+                return Err(String::from("Invalid port number"));
+            }
+            if http.address.ip().is_loopback() {
+                return Err(String::from("localhost is not a valid remote server"));
+            }
+        }
         ProxySettings::Shadowsocks(ss) => {
             if ss.peer.ip().is_loopback() {
                 return Err(String::from(
@@ -198,3 +337,42 @@ pub fn validate_proxy_settings(proxy: &ProxySettings) -> Result<(), String> {
     };
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate_tunnel_options() {
+        assert_eq!(TunnelOptions::default().validate(), Ok(()));
+
+        let options = TunnelOptions {
+            tun_mtu: Some(TunnelOptions::MIN_MTU),
+            fragment: Some(TunnelOptions::MAX_MTU),
+            ..TunnelOptions::default()
+        };
+        assert_eq!(options.validate(), Ok(()));
+
+        let options = TunnelOptions {
+            tun_mtu: Some(TunnelOptions::MIN_MTU - 1),
+            ..TunnelOptions::default()
+        };
+        assert_eq!(
+            options.validate(),
+            Err(TunnelOptionsError::InvalidTunMtu(
+                TunnelOptions::MIN_MTU - 1
+            ))
+        );
+
+        let options = TunnelOptions {
+            fragment: Some(TunnelOptions::MAX_MTU + 1),
+            ..TunnelOptions::default()
+        };
+        assert_eq!(
+            options.validate(),
+            Err(TunnelOptionsError::InvalidFragment(
+                TunnelOptions::MAX_MTU + 1
+            ))
+        );
+    }
+}
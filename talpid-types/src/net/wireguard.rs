@@ -6,6 +6,7 @@ use std::{
     cmp, fmt,
     hash::{Hash, Hasher},
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
 };
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
@@ -56,10 +57,11 @@ pub struct PeerConfig {
     pub allowed_ips: Vec<IpNetwork>,
     /// IP address of the WireGuard server.
     pub endpoint: SocketAddr,
-    /// Preshared key (PSK). The PSK should never be persisted, so it does not serialize
-    /// or deserialize. A PSK is only used with quantum-resistant tunnels and are then
-    /// ephemeral and living in memory only.
-    #[serde(skip)]
+    /// Preshared key (PSK). PSKs negotiated for quantum-resistant tunnels are ephemeral and
+    /// never set on a persisted config. A static PSK may be configured for custom relays, in
+    /// which case it is stored in the settings like the private key of the tunnel, since the
+    /// relay cannot be used without it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub psk: Option<PresharedKey>,
 }
 
@@ -75,13 +77,148 @@ pub struct TunnelConfig {
 pub struct TunnelOptions {
     /// MTU for the wireguard tunnel
     pub mtu: Option<u16>,
+    /// Probe the largest MTU that fits in the path to the relay and through the tunnel once the
+    /// tunnel is up, and use that instead of `mtu`. Only supported on Linux.
+    pub mtu_discovery: bool,
+    /// Send a keepalive packet to each peer this often, in seconds. Keeps NAT mappings alive
+    /// while the tunnel is idle.
+    pub persistent_keepalive: Option<u16>,
     /// Temporary switch for wireguard-nt
     #[cfg(windows)]
     pub use_wireguard_nt: bool,
+    /// Prefer the BoringTun userspace implementation over the kernel module
+    #[cfg(target_os = "linux")]
+    pub use_boringtun: bool,
     /// Perform PQ-safe PSK exchange when connecting
     pub quantum_resistant: bool,
+    /// Perform a new PSK exchange through the tunnel this often. If not set, the PSK obtained when
+    /// connecting is used for the lifetime of the tunnel. Ignored unless `quantum_resistant` is
+    /// set.
+    pub quantum_resistant_rekey_interval: Option<Duration>,
+    /// How the connectivity monitor verifies that the tunnel works
+    pub connectivity_probes: ConnectivityProbes,
+}
+
+/// Method used by the connectivity monitor to check that traffic flows through the tunnel.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectivityProbe {
+    /// Send ICMP echo requests to the tunnel gateway. Any incoming tunnel traffic counts as a
+    /// response.
+    IcmpGateway,
+    /// Open a TCP connection to the given address. A refused connection also counts as a
+    /// response, since the host was reached.
+    TcpConnect(SocketAddr),
+    /// Look up `hostname` using the given DNS resolver. The resolver must be one of the DNS
+    /// servers in use, since DNS to any other server is blocked. Otherwise, the probe is skipped.
+    Dns { resolver: IpAddr, hostname: String },
+    /// Send an HTTP HEAD request for `path` to the given address.
+    HttpHead { address: SocketAddr, path: String },
+}
+
+/// Probes sent by the connectivity monitor when traffic stops flowing, and how to combine their
+/// results.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ConnectivityProbes {
+    pub probes: Vec<ConnectivityProbe>,
+    /// Number of probes that must be answered for the tunnel to be considered working.
+    pub required_successes: u32,
+    /// How long the probes may go unanswered before the tunnel is considered broken. If not set,
+    /// the default timeout of the connectivity monitor is used.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for ConnectivityProbes {
+    fn default() -> Self {
+        Self {
+            probes: vec![ConnectivityProbe::IcmpGateway],
+            required_successes: 1,
+            timeout: None,
+        }
+    }
 }
 
+impl ConnectivityProbes {
+    /// Shortest allowed probe timeout.
+    pub const MIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Returns an error if the probes cannot be used by the connectivity monitor.
+    pub fn validate(&self) -> Result<(), ConnectivityProbesError> {
+        if self.probes.is_empty() {
+            return Err(ConnectivityProbesError::NoProbes);
+        }
+        if self.required_successes == 0
+            || usize::try_from(self.required_successes).unwrap_or(usize::MAX) > self.probes.len()
+        {
+            return Err(ConnectivityProbesError::InvalidRequiredSuccesses(
+                self.required_successes,
+            ));
+        }
+        if self.timeout.map(|t| t < Self::MIN_TIMEOUT).unwrap_or(false) {
+            return Err(ConnectivityProbesError::TimeoutTooShort);
+        }
+        for probe in &self.probes {
+            if let ConnectivityProbe::Dns { hostname, .. } = probe {
+                if !is_valid_hostname(hostname) {
+                    return Err(ConnectivityProbesError::InvalidHostname(hostname.clone()));
+                }
+            }
+            if let ConnectivityProbe::HttpHead { path, .. } = probe {
+                if !path.starts_with('/') || path.contains(char::is_whitespace) {
+                    return Err(ConnectivityProbesError::InvalidPath(path.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_valid_hostname(hostname: &str) -> bool {
+    let hostname = hostname.strip_suffix('.').unwrap_or(hostname);
+    hostname.len() <= 253
+        && hostname.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        })
+}
+
+/// Error returned for invalid [`ConnectivityProbes`].
+#[derive(Debug)]
+pub enum ConnectivityProbesError {
+    NoProbes,
+    InvalidRequiredSuccesses(u32),
+    TimeoutTooShort,
+    InvalidHostname(String),
+    InvalidPath(String),
+}
+
+impl fmt::Display for ConnectivityProbesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ConnectivityProbesError::*;
+
+        match self {
+            NoProbes => write!(f, "At least one probe must be specified"),
+            InvalidRequiredSuccesses(n) => write!(
+                f,
+                "The number of required successes must be between 1 and the number of probes, got {n}"
+            ),
+            TimeoutTooShort => write!(
+                f,
+                "The probe timeout must be at least {} seconds",
+                ConnectivityProbes::MIN_TIMEOUT.as_secs()
+            ),
+            InvalidHostname(hostname) => write!(f, "Invalid hostname: {hostname}"),
+            InvalidPath(path) => write!(f, "Invalid HTTP path: {path}"),
+        }
+    }
+}
+
+impl std::error::Error for ConnectivityProbesError {}
+
 /// Wireguard x25519 private key
 #[derive(Clone)]
 pub struct PrivateKey(x25519_dalek::StaticSecret);
@@ -254,9 +391,34 @@ impl From<Box<[u8; 32]>> for PresharedKey {
     }
 }
 
+impl From<[u8; 32]> for PresharedKey {
+    fn from(key: [u8; 32]) -> PresharedKey {
+        PresharedKey(Box::new(key))
+    }
+}
+
+impl Serialize for PresharedKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_key(self.as_bytes(), serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PresharedKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_key(deserializer)
+    }
+}
+
+/// The key is redacted, since tunnel configs containing it are logged.
 impl fmt::Debug for PresharedKey {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", &base64::encode(self.as_bytes()))
+        f.write_str("PresharedKey(<redacted>)")
     }
 }
 
@@ -288,3 +450,16 @@ where
             Ok(From::from(key))
         })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_psk_debug_is_redacted() {
+        let psk = PresharedKey::from([0x42; 32]);
+        let formatted = format!("{psk:?}");
+        assert!(!formatted.contains(&base64::encode(psk.as_bytes())));
+        assert_eq!(formatted, "PresharedKey(<redacted>)");
+    }
+}
@@ -0,0 +1,69 @@
+use ipnetwork::Ipv4Network;
+use std::{net::Ipv4Addr, path::PathBuf};
+
+/// Name of the network namespace that excluded processes may be run in.
+pub const SPLIT_TUNNEL_NETNS_NAME: &str = "mullvad-exclusions";
+
+/// Directory where named network namespaces are bind-mounted. This is the same location used by
+/// iproute2, so the namespace can be inspected using `ip netns`.
+const NETNS_RUN_DIR: &str = "/run/netns";
+
+/// Directory containing per-namespace configuration files, such as `resolv.conf`. This follows
+/// the convention used by `ip netns exec`.
+const NETNS_ETC_DIR: &str = "/etc/netns";
+
+/// Name of the veth interface that lives in the host namespace.
+pub const HOST_VETH_NAME: &str = "mullvad-ex0";
+/// Name of the veth interface that lives in the exclusion namespace.
+pub const NAMESPACE_VETH_NAME: &str = "mullvad-ex1";
+
+/// Address of the host end of the veth pair. This is the default gateway of the namespace.
+pub const HOST_VETH_ADDRESS: Ipv4Addr = Ipv4Addr::new(172, 25, 254, 1);
+/// Address of the namespace end of the veth pair.
+pub const NAMESPACE_VETH_ADDRESS: Ipv4Addr = Ipv4Addr::new(172, 25, 254, 2);
+/// Prefix length of the network connecting the two ends of the veth pair.
+pub const VETH_PREFIX: u8 = 30;
+
+/// Returns the network connecting the two ends of the veth pair.
+pub fn veth_network() -> Ipv4Network {
+    let network = Ipv4Network::new(HOST_VETH_ADDRESS, VETH_PREFIX).unwrap();
+    Ipv4Network::new(network.network(), VETH_PREFIX).unwrap()
+}
+
+/// Returns the path of the bind-mounted exclusion namespace.
+pub fn netns_path() -> PathBuf {
+    PathBuf::from(NETNS_RUN_DIR).join(SPLIT_TUNNEL_NETNS_NAME)
+}
+
+/// Returns the path of the `resolv.conf` that should be used inside the exclusion namespace, if
+/// one has been provided.
+pub fn netns_resolv_conf_path() -> PathBuf {
+    PathBuf::from(NETNS_ETC_DIR)
+        .join(SPLIT_TUNNEL_NETNS_NAME)
+        .join("resolv.conf")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_veth_addresses_share_network() {
+        let network = veth_network();
+        assert_eq!(network, "172.25.254.0/30".parse().unwrap());
+        assert!(network.contains(HOST_VETH_ADDRESS));
+        assert!(network.contains(NAMESPACE_VETH_ADDRESS));
+        assert_ne!(HOST_VETH_ADDRESS, NAMESPACE_VETH_ADDRESS);
+        assert_ne!(network.network(), HOST_VETH_ADDRESS);
+        assert_ne!(network.broadcast(), NAMESPACE_VETH_ADDRESS);
+    }
+
+    #[test]
+    fn test_netns_paths() {
+        assert_eq!(netns_path(), PathBuf::from("/run/netns/mullvad-exclusions"));
+        assert_eq!(
+            netns_resolv_conf_path(),
+            PathBuf::from("/etc/netns/mullvad-exclusions/resolv.conf")
+        );
+    }
+}
@@ -1,10 +1,13 @@
-use crate::net::TunnelEndpoint;
+use crate::net::{wireguard, TunnelEndpoint};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
-use std::fmt;
 #[cfg(target_os = "android")]
 use std::net::IpAddr;
+use std::{
+    fmt,
+    time::{Duration, SystemTime},
+};
 
 /// Event emitted from the states in `talpid_core::tunnel_state_machine` when the tunnel state
 /// machine enters a new state.
@@ -211,3 +214,37 @@ impl fmt::Display for ErrorStateCause {
         write!(f, "{description}")
     }
 }
+
+/// Traffic statistics for a running tunnel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TunnelStatistics {
+    /// Statistics for each peer of a WireGuard tunnel.
+    Wireguard {
+        peers: Vec<WireguardPeerStatistics>,
+        /// Time between the last ping sent by the connectivity monitor and the first incoming
+        /// traffic after it. This is only a rough estimate of the round-trip time.
+        ping_rtt: Option<Duration>,
+        /// Time since `ping_rtt` was measured. The connectivity monitor only pings when the
+        /// tunnel is idle or appears to be broken, so the measurement may be old.
+        ping_rtt_age: Option<Duration>,
+    },
+    /// Bytes sent and received by OpenVPN.
+    OpenVpn {
+        rx_bytes: u64,
+        tx_bytes: u64,
+        /// Number of times the TLS session has been renegotiated since the initial negotiation.
+        tls_renegotiations: u32,
+        /// Time of the last completed TLS negotiation, if any.
+        last_tls_negotiation: Option<SystemTime>,
+    },
+}
+
+/// Traffic statistics for a single WireGuard peer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WireguardPeerStatistics {
+    pub public_key: wireguard::PublicKey,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    /// Time of the last completed handshake, if any.
+    pub last_handshake: Option<SystemTime>,
+}
@@ -2,6 +2,7 @@ use std::{
     borrow::Cow,
     ffi::CString,
     net::{Ipv4Addr, Ipv6Addr},
+    time::Duration,
 };
 use talpid_types::net::{obfuscation::ObfuscatorConfig, wireguard, GenericTunnelOptions};
 
@@ -18,6 +19,12 @@ pub struct Config {
     pub ipv6_gateway: Option<Ipv6Addr>,
     /// Maximum transmission unit for the tunnel
     pub mtu: u16,
+    /// Replace `mtu` with the largest MTU that fits in the path to the relay and through the
+    /// tunnel once the tunnel is up
+    #[cfg(target_os = "linux")]
+    pub mtu_discovery: bool,
+    /// Interval in seconds between keepalive packets sent to each peer
+    pub persistent_keepalive: Option<u16>,
     /// Firewall mark
     #[cfg(target_os = "linux")]
     pub fwmark: Option<u32>,
@@ -27,8 +34,15 @@ pub struct Config {
     /// Temporary switch for wireguard-nt
     #[cfg(target_os = "windows")]
     pub use_wireguard_nt: bool,
+    /// Prefer the BoringTun userspace implementation over the kernel module
+    #[cfg(target_os = "linux")]
+    pub use_boringtun: bool,
     /// Obfuscator config to be used for reaching the relay.
     pub obfuscator_config: Option<ObfuscatorConfig>,
+    /// How often to negotiate a new PSK after the initial PQ-safe PSK exchange
+    pub psk_rekey_interval: Option<Duration>,
+    /// Probes used by the connectivity monitor
+    pub connectivity_probes: wireguard::ConnectivityProbes,
 }
 
 #[cfg(not(target_os = "android"))]
@@ -118,12 +132,19 @@ impl Config {
             ipv6_gateway,
             mtu,
             #[cfg(target_os = "linux")]
+            mtu_discovery: wg_options.mtu_discovery,
+            persistent_keepalive: wg_options.persistent_keepalive,
+            #[cfg(target_os = "linux")]
             fwmark: connection_config.fwmark,
             #[cfg(target_os = "linux")]
             enable_ipv6: generic_options.enable_ipv6,
             #[cfg(target_os = "windows")]
             use_wireguard_nt: wg_options.use_wireguard_nt,
+            #[cfg(target_os = "linux")]
+            use_boringtun: wg_options.use_boringtun,
             obfuscator_config,
+            psk_rekey_interval: wg_options.quantum_resistant_rekey_interval,
+            connectivity_probes: wg_options.connectivity_probes.clone(),
         })
     }
 
@@ -151,6 +172,12 @@ impl Config {
             if let Some(ref psk) = peer.psk {
                 wg_conf.add("preshared_key", psk.as_bytes().as_ref());
             }
+            if let Some(interval) = self.persistent_keepalive {
+                wg_conf.add(
+                    "persistent_keepalive_interval",
+                    interval.to_string().as_str(),
+                );
+            }
             for addr in &peer.allowed_ips {
                 wg_conf.add("allowed_ip", addr.to_string().as_str());
             }
@@ -209,3 +236,87 @@ impl WgConfigBuffer {
         self.buf
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::net::wireguard::{PeerConfig, PrivateKey, TunnelConfig};
+
+    fn config(persistent_keepalive: Option<u16>) -> Config {
+        let peer = |endpoint: &str| PeerConfig {
+            public_key: PrivateKey::new_from_random().public_key(),
+            allowed_ips: vec!["0.0.0.0/0".parse().unwrap()],
+            endpoint: endpoint.parse().unwrap(),
+            psk: None,
+        };
+        Config {
+            tunnel: TunnelConfig {
+                private_key: PrivateKey::new_from_random(),
+                addresses: vec!["10.64.0.2".parse().unwrap()],
+            },
+            peers: vec![peer("1.2.3.4:51820"), peer("1.2.3.5:51820")],
+            ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+            ipv6_gateway: None,
+            mtu: DEFAULT_MTU,
+            #[cfg(target_os = "linux")]
+            mtu_discovery: false,
+            persistent_keepalive,
+            #[cfg(target_os = "linux")]
+            fwmark: None,
+            #[cfg(target_os = "linux")]
+            enable_ipv6: false,
+            #[cfg(target_os = "windows")]
+            use_wireguard_nt: false,
+            #[cfg(target_os = "linux")]
+            use_boringtun: false,
+            obfuscator_config: None,
+            psk_rekey_interval: None,
+            connectivity_probes: Default::default(),
+        }
+    }
+
+    /// Returns the keys of each peer section, in order.
+    fn peer_sections(userspace_config: &CString) -> Vec<Vec<String>> {
+        let mut peers: Vec<Vec<String>> = vec![];
+        for line in userspace_config.to_str().unwrap().lines() {
+            let key = match line.split_once('=') {
+                Some((key, _)) => key,
+                None => continue,
+            };
+            if key == "public_key" {
+                peers.push(vec![]);
+            }
+            if let Some(peer) = peers.last_mut() {
+                peer.push(key.to_owned());
+            }
+        }
+        peers
+    }
+
+    #[test]
+    fn test_userspace_format_keepalive() {
+        let userspace_config = config(Some(25)).to_userspace_format();
+        assert!(userspace_config
+            .to_str()
+            .unwrap()
+            .lines()
+            .any(|line| line == "persistent_keepalive_interval=25"));
+
+        let peers = peer_sections(&userspace_config);
+        assert_eq!(peers.len(), 2);
+        for peer in peers {
+            let keepalive_position = peer
+                .iter()
+                .position(|key| key == "persistent_keepalive_interval")
+                .expect("missing keepalive for peer");
+            let allowed_ip_position = peer.iter().position(|key| key == "allowed_ip").unwrap();
+            assert!(keepalive_position < allowed_ip_position);
+        }
+
+        let userspace_config = config(None).to_userspace_format();
+        assert!(!userspace_config
+            .to_str()
+            .unwrap()
+            .contains("persistent_keepalive_interval"));
+    }
+}
@@ -1,13 +1,19 @@
 use crate::{
     ping_monitor::{new_pinger, Pinger},
+    probes::{new_probes, Probe},
     stats::StatsMap,
 };
 use std::{
     cmp,
+    collections::HashSet,
     net::Ipv4Addr,
-    sync::{mpsc, Mutex, Weak},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex, Weak,
+    },
     time::{Duration, Instant},
 };
+use talpid_types::net::wireguard::{ConnectivityProbe, ConnectivityProbes};
 
 use super::{Tunnel, TunnelError};
 
@@ -69,13 +75,75 @@ pub enum Error {
 ///
 /// Once a connection established, a connection is only considered broken once the connectivity
 /// monitor has started pinging and no traffic has been received for a duration of `PING_TIMEOUT`.
+///
+/// If probes other than ICMP to the gateway are configured, they are sent alongside the pings
+/// once the tunnel is up, and incoming traffic no longer ends the countdown on its own. Instead,
+/// the connection is considered to work once enough probes have been answered since the countdown
+/// started. Pings count as answered if any traffic is received. The probes are also sent as soon
+/// as the tunnel is up, since a handshake with the relay does not show that anything beyond it is
+/// reachable. Each probe runs on a separate thread, so that slow probes do not hold up the
+/// monitor.
 pub struct ConnectivityMonitor {
     tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
     conn_state: ConnState,
     initial_ping_timestamp: Option<Instant>,
     num_pings_sent: u32,
     pinger: Box<dyn Pinger>,
+    ping_gateway: bool,
+    probes: Vec<Arc<dyn Probe>>,
+    required_successes: usize,
+    probe_round: ProbeRound,
+    /// Identifies the current countdown, so that answers to probes sent during an earlier one can
+    /// be ignored.
+    probe_check: u32,
+    /// Indices of the probes that have not finished yet. These are not resent.
+    probes_in_flight: HashSet<usize>,
+    probe_result_tx: mpsc::Sender<ProbeResult>,
+    probe_result_rx: mpsc::Receiver<ProbeResult>,
+    ping_timeout: Duration,
     close_receiver: mpsc::Receiver<()>,
+    reprobe: Arc<AtomicBool>,
+    /// Set while a check started by `start_probing` is in progress, in which case probes are
+    /// resent even though no traffic timeout has been reached.
+    forced_check: bool,
+    last_ping_timestamp: Option<Instant>,
+    ping_rtt: Arc<Mutex<Option<PingRtt>>>,
+}
+
+/// A ping round-trip time measured by the [`ConnectivityMonitor`].
+#[derive(Debug, Clone, Copy)]
+pub struct PingRtt {
+    pub rtt: Duration,
+    /// When the measurement was made. Pings are only sent while the tunnel is idle or appears
+    /// to be broken, so measurements can be old.
+    pub measured_at: Instant,
+}
+
+/// Handle used to make a running [`ConnectivityMonitor`] verify the connection right away, instead
+/// of waiting for traffic to time out.
+#[derive(Clone)]
+pub struct ReprobeHandle(Arc<AtomicBool>);
+
+impl ReprobeHandle {
+    /// Start pinging during the next iteration of the monitor loop.
+    pub fn reprobe(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Answers received to the probes sent during the current countdown.
+#[derive(Default)]
+struct ProbeRound {
+    /// Indices of the probes that have been answered.
+    answered: HashSet<usize>,
+    traffic_received: bool,
+}
+
+/// Outcome of a probe, sent back from the thread that ran it.
+struct ProbeResult {
+    check: u32,
+    probe: usize,
+    answered: bool,
 }
 
 impl ConnectivityMonitor {
@@ -84,7 +152,14 @@ impl ConnectivityMonitor {
         #[cfg(any(target_os = "macos", target_os = "linux"))] interface: String,
         tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
         close_receiver: mpsc::Receiver<()>,
+        probe_config: &ConnectivityProbes,
     ) -> Result<Self, Error> {
+        let probes = new_probes(
+            &probe_config.probes,
+            #[cfg(any(target_os = "macos", target_os = "linux"))]
+            &interface,
+        );
+        // The pinger is needed to prod WireGuard into connecting, even if its replies are ignored
         let pinger = new_pinger(
             addr,
             #[cfg(any(target_os = "macos", target_os = "linux"))]
@@ -93,6 +168,7 @@ impl ConnectivityMonitor {
         .map_err(Error::PingError)?;
 
         let now = Instant::now();
+        let (probe_result_tx, probe_result_rx) = mpsc::channel();
 
         Ok(Self {
             tunnel_handle,
@@ -100,15 +176,42 @@ impl ConnectivityMonitor {
             initial_ping_timestamp: None,
             num_pings_sent: 0,
             pinger,
+            ping_gateway: probe_config
+                .probes
+                .contains(&ConnectivityProbe::IcmpGateway),
+            probes,
+            required_successes: usize::try_from(probe_config.required_successes)
+                .unwrap_or(usize::MAX),
+            probe_round: ProbeRound::default(),
+            probe_check: 0,
+            probes_in_flight: HashSet::new(),
+            probe_result_tx,
+            probe_result_rx,
+            ping_timeout: probe_config.timeout.unwrap_or(PING_TIMEOUT),
             close_receiver,
+            reprobe: Arc::new(AtomicBool::new(false)),
+            forced_check: false,
+            last_ping_timestamp: None,
+            ping_rtt: Arc::new(Mutex::new(None)),
         })
     }
 
+    pub(super) fn reprobe_handle(&self) -> ReprobeHandle {
+        ReprobeHandle(self.reprobe.clone())
+    }
+
+    /// Returns a handle to the most recently measured ping round-trip time. The measurement is the
+    /// time from sending a ping until incoming traffic is observed, so its precision is limited by
+    /// the polling interval.
+    pub(super) fn ping_rtt_handle(&self) -> Arc<Mutex<Option<PingRtt>>> {
+        self.ping_rtt.clone()
+    }
+
     // checks if the tunnel has ever worked. Intended to check if a connection to a tunnel is
     // successful at the start of a connection.
     pub(super) fn establish_connectivity(&mut self, retry_attempt: u32) -> Result<bool, Error> {
         // Send initial ping to prod WireGuard into connecting.
-        self.pinger.send_icmp().map_err(Error::PingError)?;
+        self.send_ping()?;
         self.establish_connectivity_inner(
             retry_attempt,
             ESTABLISH_TIMEOUT,
@@ -146,6 +249,10 @@ impl ConnectivityMonitor {
     }
 
     pub(super) fn run(&mut self) -> Result<(), Error> {
+        if !self.probes.is_empty() {
+            log::debug!("Checking tunnel connectivity using the configured probes");
+            self.start_probing(Instant::now())?;
+        }
         self.wait_loop(REGULAR_LOOP_SLEEP)
     }
 
@@ -163,6 +270,10 @@ impl ConnectivityMonitor {
             let mut current_iteration = Instant::now();
             let time_slept = current_iteration - last_iteration;
             if time_slept < (iter_delay * 2) {
+                if self.reprobe.swap(false, Ordering::SeqCst) {
+                    log::debug!("Checking tunnel connectivity after a network change");
+                    self.start_probing(current_iteration)?;
+                }
                 if !self.check_connectivity(Instant::now())? {
                     return Ok(());
                 }
@@ -184,7 +295,7 @@ impl ConnectivityMonitor {
 
     /// Returns true if connection is established
     fn check_connectivity(&mut self, now: Instant) -> Result<bool, Error> {
-        self.check_connectivity_interval(now, PING_TIMEOUT)
+        self.check_connectivity_interval(now, self.ping_timeout)
     }
 
     /// Returns true if connection is established
@@ -197,8 +308,18 @@ impl ConnectivityMonitor {
             None => Ok(false),
             Some(new_stats) => {
                 let new_stats = new_stats?;
+                self.receive_probe_results();
 
                 if self.conn_state.update(now, new_stats) {
+                    if !self.is_probing() || self.probes.is_empty() {
+                        self.record_ping_rtt();
+                        self.reset_pinger();
+                        return Ok(true);
+                    }
+                    self.probe_round.traffic_received = true;
+                }
+                if self.probe_round_succeeded() {
+                    self.record_ping_rtt();
                     self.reset_pinger();
                     return Ok(true);
                 }
@@ -221,10 +342,12 @@ impl ConnectivityMonitor {
     }
 
     fn maybe_send_ping(&mut self, now: Instant) -> Result<(), Error> {
-        // Only send out a ping if we haven't received a byte in a while or no traffic has flowed
-        // in the last 2 minutes, but if a ping already has been sent out, only send one out every
-        // 3 seconds.
-        if (self.conn_state.rx_timed_out() || self.conn_state.traffic_timed_out())
+        // Only send out a ping if we haven't received a byte in a while, no traffic has flowed
+        // in the last 2 minutes or a check has been forced, but if a ping already has been sent
+        // out, only send one out every 3 seconds.
+        if (self.conn_state.rx_timed_out()
+            || self.conn_state.traffic_timed_out()
+            || self.forced_check)
             && self
                 .initial_ping_timestamp
                 .map(|initial_ping_timestamp| {
@@ -232,7 +355,7 @@ impl ConnectivityMonitor {
                 })
                 .unwrap_or(true)
         {
-            self.pinger.send_icmp().map_err(Error::PingError)?;
+            self.send_probes()?;
             if self.initial_ping_timestamp.is_none() {
                 self.initial_ping_timestamp = Some(now);
             }
@@ -241,6 +364,88 @@ impl ConnectivityMonitor {
         Ok(())
     }
 
+    /// Send probes and start the countdown to `PING_TIMEOUT` right away. Answered probes reset the
+    /// pinger as usual.
+    fn start_probing(&mut self, now: Instant) -> Result<(), Error> {
+        self.reset_pinger();
+        self.send_probes()?;
+        self.initial_ping_timestamp = Some(now);
+        self.num_pings_sent = 1;
+        self.forced_check = true;
+        Ok(())
+    }
+
+    fn is_probing(&self) -> bool {
+        self.initial_ping_timestamp.is_some()
+    }
+
+    /// Send probes that have not been answered yet. Probes other than pings are only sent once
+    /// the tunnel is up, since nothing beyond the gateway is routed through it before that.
+    fn send_probes(&mut self) -> Result<(), Error> {
+        let connected = self.conn_state.connected();
+        if self.ping_gateway || !connected {
+            self.send_ping()?;
+        }
+        if connected {
+            for (index, probe) in self.probes.iter().enumerate() {
+                if self.probe_round.answered.contains(&index)
+                    || !self.probes_in_flight.insert(index)
+                {
+                    continue;
+                }
+                let probe = probe.clone();
+                let result_tx = self.probe_result_tx.clone();
+                let check = self.probe_check;
+                std::thread::spawn(move || {
+                    let answered = probe.probe();
+                    let _ = result_tx.send(ProbeResult {
+                        check,
+                        probe: index,
+                        answered,
+                    });
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Record the results of probes that have finished since this was last called.
+    fn receive_probe_results(&mut self) {
+        while let Ok(result) = self.probe_result_rx.try_recv() {
+            self.probes_in_flight.remove(&result.probe);
+            if result.answered && result.check == self.probe_check {
+                self.probe_round.answered.insert(result.probe);
+            }
+        }
+    }
+
+    /// Returns true if enough probes sent during the current countdown have been answered.
+    fn probe_round_succeeded(&self) -> bool {
+        if self.probes.is_empty() || !self.is_probing() {
+            return false;
+        }
+        let gateway_answered = self.ping_gateway && self.probe_round.traffic_received;
+        self.probe_round.answered.len() + usize::from(gateway_answered) >= self.required_successes
+    }
+
+    fn send_ping(&mut self) -> Result<(), Error> {
+        self.pinger.send_icmp().map_err(Error::PingError)?;
+        self.last_ping_timestamp = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Store the time since the last ping was sent, if one is outstanding.
+    fn record_ping_rtt(&mut self) {
+        if let Some(timestamp) = self.last_ping_timestamp.take() {
+            if let Ok(mut ping_rtt) = self.ping_rtt.lock() {
+                *ping_rtt = Some(PingRtt {
+                    rtt: timestamp.elapsed(),
+                    measured_at: Instant::now(),
+                });
+            }
+        }
+    }
+
     fn ping_timed_out(&self, timeout: Duration) -> bool {
         self.initial_ping_timestamp
             .map(|initial_ping_timestamp| initial_ping_timestamp.elapsed() > timeout)
@@ -250,7 +455,11 @@ impl ConnectivityMonitor {
     /// Reset timeouts - assume that the last time bytes were received is now.
     fn reset_pinger(&mut self) {
         self.initial_ping_timestamp = None;
+        self.forced_check = false;
+        self.last_ping_timestamp = None;
         self.num_pings_sent = 0;
+        self.probe_round = ProbeRound::default();
+        self.probe_check = self.probe_check.wrapping_add(1);
         self.pinger.reset();
     }
 }
@@ -399,7 +608,7 @@ mod test {
     use std::{
         pin::Pin,
         sync::{
-            atomic::{AtomicBool, Ordering},
+            atomic::{AtomicBool, AtomicUsize, Ordering},
             Arc, Mutex,
         },
         time::{Duration, Instant},
@@ -440,6 +649,7 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 0,
+                last_handshake: None,
             },
         );
         conn_state.update(Instant::now(), stats);
@@ -465,6 +675,7 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 0,
+                last_handshake: None,
             },
         );
         conn_state.update(connect_time, stats);
@@ -489,6 +700,7 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 0,
+                last_handshake: None,
             },
         );
         conn_state.update(start, stats);
@@ -500,6 +712,7 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 1,
+                last_handshake: None,
             },
         );
         conn_state.update(update_time, stats);
@@ -523,6 +736,40 @@ mod test {
         }
     }
 
+    struct MockProbe {
+        answered: bool,
+    }
+
+    impl Probe for MockProbe {
+        fn probe(&self) -> bool {
+            self.answered
+        }
+    }
+
+    /// Probe that is answered once it is released.
+    struct BlockingProbe {
+        times_sent: Arc<AtomicUsize>,
+        release_rx: Mutex<mpsc::Receiver<()>>,
+    }
+
+    impl Probe for BlockingProbe {
+        fn probe(&self) -> bool {
+            self.times_sent.fetch_add(1, Ordering::SeqCst);
+            self.release_rx.lock().unwrap().recv().is_ok()
+        }
+    }
+
+    /// Wait until all probes that have been sent have finished.
+    fn wait_for_probes(monitor: &mut ConnectivityMonitor) {
+        let start = Instant::now();
+        monitor.receive_probe_results();
+        while !monitor.probes_in_flight.is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+            monitor.receive_probe_results();
+        }
+    }
+
     struct MockTunnel {
         on_get_stats: Box<dyn Fn() -> Result<stats::StatsMap, TunnelError> + Send>,
     }
@@ -543,6 +790,7 @@ mod test {
                 stats::Stats {
                     tx_bytes: 0,
                     rx_bytes: 0,
+                    last_handshake: None,
                 },
             );
             let peers = Mutex::new(map);
@@ -567,6 +815,7 @@ mod test {
                         stats::Stats {
                             tx_bytes: 0,
                             rx_bytes: 0,
+                            last_handshake: None,
                         },
                     );
                     Ok(map)
@@ -615,13 +864,27 @@ mod test {
         tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
         close_receiver: mpsc::Receiver<()>,
     ) -> ConnectivityMonitor {
+        let (probe_result_tx, probe_result_rx) = mpsc::channel();
         ConnectivityMonitor {
             conn_state: ConnState::new(now, Default::default()),
             initial_ping_timestamp: None,
             num_pings_sent: 0,
             pinger,
+            ping_gateway: true,
+            probes: vec![],
+            required_successes: 1,
+            probe_round: ProbeRound::default(),
+            probe_check: 0,
+            probes_in_flight: HashSet::new(),
+            probe_result_tx,
+            probe_result_rx,
+            ping_timeout: PING_TIMEOUT,
             close_receiver,
             tunnel_handle,
+            reprobe: Arc::new(AtomicBool::new(false)),
+            forced_check: false,
+            last_ping_timestamp: None,
+            ping_rtt: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -633,6 +896,7 @@ mod test {
             stats::Stats {
                 tx_bytes: 0,
                 rx_bytes: 0,
+                last_handshake: None,
             },
         );
         ConnState::Connected {
@@ -662,6 +926,123 @@ mod test {
         assert!(!monitor.check_connectivity(now).unwrap())
     }
 
+    #[test]
+    /// Verify that probing after a network change sends a ping right away and that
+    /// `check_connectivity()` returns `false` if nothing is received within `PING_TIMEOUT`, even
+    /// though no traffic timeout has been reached.
+    fn test_reprobe_times_out() {
+        let (_tunnel_anchor, tunnel) = MockTunnel::never_incrementing().into_locked();
+        let (_tx, rx) = mpsc::channel();
+        let ping_sent = Arc::new(AtomicBool::new(false));
+        let ping_sent_inner = ping_sent.clone();
+        let pinger = MockPinger {
+            on_send_ping: Some(Box::new(move || {
+                ping_sent_inner.store(true, Ordering::SeqCst);
+            })),
+        };
+        let now = Instant::now();
+        let start = now
+            .checked_sub(PING_TIMEOUT + Duration::from_secs(1))
+            .unwrap();
+        let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, rx);
+
+        // Mock the state - connectivity has been established and no timeout has been reached
+        monitor.conn_state = connected_state(now);
+        assert!(monitor.check_connectivity(now).unwrap());
+
+        monitor.start_probing(start).unwrap();
+        assert!(ping_sent.load(Ordering::SeqCst));
+        assert!(!monitor.check_connectivity(now).unwrap());
+    }
+
+    #[test]
+    /// Verify that pings are only resent without a traffic timeout while a forced check is in
+    /// progress.
+    fn test_resend_only_during_forced_check() {
+        let (_tunnel_anchor, tunnel) = MockTunnel::never_incrementing().into_locked();
+        let (_tx, rx) = mpsc::channel();
+        let pings_sent = Arc::new(AtomicUsize::new(0));
+        let pings_sent_inner = pings_sent.clone();
+        let pinger = MockPinger {
+            on_send_ping: Some(Box::new(move || {
+                pings_sent_inner.fetch_add(1, Ordering::SeqCst);
+            })),
+        };
+        let now = Instant::now();
+        let start = now.checked_sub(SECONDS_PER_PING * 2).unwrap();
+        let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, rx);
+        monitor.conn_state = connected_state(now);
+
+        // An ongoing ping countdown alone does not cause more pings
+        monitor.initial_ping_timestamp = Some(now);
+        monitor.num_pings_sent = 1;
+        monitor.maybe_send_ping(now).unwrap();
+        assert_eq!(pings_sent.load(Ordering::SeqCst), 0);
+
+        monitor.start_probing(now).unwrap();
+        assert_eq!(pings_sent.load(Ordering::SeqCst), 1);
+        monitor.maybe_send_ping(now).unwrap();
+        assert_eq!(pings_sent.load(Ordering::SeqCst), 2);
+
+        monitor.reset_pinger();
+        monitor.initial_ping_timestamp = Some(now);
+        monitor.num_pings_sent = 1;
+        monitor.maybe_send_ping(now).unwrap();
+        assert_eq!(pings_sent.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    /// Verify that incoming traffic does not keep the tunnel alive while probing if the probes go
+    /// unanswered, and that answered probes do.
+    fn test_probes_override_traffic() {
+        for (answered, expect_connected) in [(false, false), (true, true)] {
+            let (_tunnel_anchor, tunnel) = MockTunnel::always_incrementing().into_locked();
+            let (_tx, rx) = mpsc::channel();
+            let now = Instant::now();
+            let start = now
+                .checked_sub(PING_TIMEOUT + Duration::from_secs(1))
+                .unwrap();
+            let mut monitor = mock_monitor(start, Box::new(MockPinger::default()), tunnel, rx);
+            monitor.probes = vec![Arc::new(MockProbe { answered })];
+            monitor.required_successes = 2;
+
+            monitor.conn_state = connected_state(start);
+            monitor.start_probing(start).unwrap();
+            wait_for_probes(&mut monitor);
+            assert_eq!(monitor.check_connectivity(now).unwrap(), expect_connected);
+        }
+    }
+
+    #[test]
+    /// Verify that a slow probe does not hold up the monitor, is not resent while it is waiting
+    /// for an answer, and is counted once answered.
+    fn test_slow_probe() {
+        let (_tunnel_anchor, tunnel) = MockTunnel::never_incrementing().into_locked();
+        let (_tx, rx) = mpsc::channel();
+        let now = Instant::now();
+        let start = now.checked_sub(Duration::from_secs(1)).unwrap();
+        let mut monitor = mock_monitor(start, Box::new(MockPinger::default()), tunnel, rx);
+
+        let times_sent = Arc::new(AtomicUsize::new(0));
+        let (release_tx, release_rx) = mpsc::channel();
+        monitor.probes = vec![Arc::new(BlockingProbe {
+            times_sent: times_sent.clone(),
+            release_rx: Mutex::new(release_rx),
+        })];
+        monitor.conn_state = connected_state(start);
+
+        monitor.start_probing(now).unwrap();
+        // Still within the timeout, so the connection is considered to work
+        assert!(monitor.check_connectivity(now).unwrap());
+        monitor.send_probes().unwrap();
+        assert!(!monitor.probe_round_succeeded());
+
+        release_tx.send(()).unwrap();
+        wait_for_probes(&mut monitor);
+        assert_eq!(times_sent.load(Ordering::SeqCst), 1);
+        assert!(monitor.probe_round_succeeded());
+    }
+
     #[test]
     /// Verify that `check_connectivity()` returns `true` if the tunnel is connected and traffic is
     /// flowing constantly.
@@ -733,6 +1114,7 @@ mod test {
             stats::Stats {
                 tx_bytes: 0,
                 rx_bytes: 0,
+                last_handshake: None,
             },
         );
         let tunnel_stats = Mutex::new(map);
@@ -784,6 +1166,7 @@ mod test {
             stats::Stats {
                 tx_bytes: 0,
                 rx_bytes: 0,
+                last_handshake: None,
             },
         );
 
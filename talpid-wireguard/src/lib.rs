@@ -5,22 +5,24 @@
 
 use self::config::Config;
 use futures::future::{abortable, AbortHandle as FutureAbortHandle, BoxFuture, Future};
-#[cfg(windows)]
-use futures::{channel::mpsc, StreamExt};
+use futures::{
+    channel::{mpsc, oneshot},
+    FutureExt, StreamExt,
+};
 #[cfg(target_os = "linux")]
 use lazy_static::lazy_static;
 #[cfg(target_os = "android")]
 use std::borrow::Cow;
 #[cfg(target_os = "linux")]
 use std::env;
-#[cfg(windows)]
+#[cfg(any(windows, target_os = "linux"))]
 use std::io;
 use std::{
     convert::Infallible,
     net::IpAddr,
     path::Path,
     pin::Pin,
-    sync::{mpsc as sync_mpsc, Arc, Mutex},
+    sync::{mpsc as sync_mpsc, Arc, Mutex, Weak},
     time::Duration,
 };
 use talpid_routing as routing;
@@ -38,19 +40,26 @@ use talpid_types::{
         wireguard::{PresharedKey, PrivateKey, PublicKey},
         AllowedTunnelTraffic, Endpoint, TransportProtocol,
     },
+    tunnel::{TunnelStatistics, WireguardPeerStatistics},
     ErrorExt,
 };
 use tokio::sync::Mutex as AsyncMutex;
 use tunnel_obfuscation::{
-    create_obfuscator, Error as ObfuscationError, Settings as ObfuscationSettings, Udp2TcpSettings,
+    create_obfuscator, Error as ObfuscationError, Settings as ObfuscationSettings,
+    ShadowsocksSettings, Socks5Auth, Socks5Settings, TlsSettings, Udp2TcpSettings,
 };
 
 /// WireGuard config data-types
 pub mod config;
 mod connectivity_check;
 mod logging;
+#[cfg(target_os = "linux")]
+mod mtu_discovery;
 mod ping_monitor;
+mod probes;
 mod stats;
+#[cfg(target_os = "linux")]
+mod wireguard_boringtun;
 mod wireguard_go;
 #[cfg(target_os = "linux")]
 pub(crate) mod wireguard_kernel;
@@ -180,51 +189,102 @@ async fn maybe_create_obfuscator(
     // The first one is always the entry relay.
     let mut first_peer = config.peers.get_mut(0).expect("missing peer");
 
-    if let Some(ref obfuscator_config) = config.obfuscator_config {
-        match obfuscator_config {
-            ObfuscatorConfig::Udp2Tcp { endpoint } => {
-                log::trace!("Connecting to Udp2Tcp endpoint {:?}", *endpoint);
-                let settings = Udp2TcpSettings {
-                    peer: *endpoint,
-                    #[cfg(target_os = "linux")]
-                    fwmark: config.fwmark,
-                };
-                let obfuscator = create_obfuscator(&ObfuscationSettings::Udp2Tcp(settings))
-                    .await
-                    .map_err(Error::CreateObfuscatorError)?;
-                let endpoint = obfuscator.endpoint();
+    let obfuscator_config = match config.obfuscator_config {
+        Some(ref obfuscator_config) => obfuscator_config,
+        None => return Ok(None),
+    };
+
+    let settings = match obfuscator_config {
+        ObfuscatorConfig::Udp2Tcp { endpoint } => {
+            log::trace!("Connecting to Udp2Tcp endpoint {:?}", *endpoint);
+            ObfuscationSettings::Udp2Tcp(Udp2TcpSettings {
+                peer: *endpoint,
+                listen_addr: None,
+                #[cfg(target_os = "linux")]
+                fwmark: config.fwmark,
+            })
+        }
+        ObfuscatorConfig::Shadowsocks {
+            endpoint,
+            password,
+            cipher,
+            wireguard_endpoint,
+        } => {
+            log::trace!("Connecting to Shadowsocks endpoint {:?}", *endpoint);
+            ObfuscationSettings::Shadowsocks(ShadowsocksSettings {
+                shadowsocks_endpoint: *endpoint,
+                wireguard_endpoint: *wireguard_endpoint,
+                listen_addr: None,
+                password: password.clone(),
+                cipher: cipher.clone(),
+                #[cfg(target_os = "linux")]
+                fwmark: config.fwmark,
+            })
+        }
+        ObfuscatorConfig::Tls {
+            endpoint,
+            server_name,
+        } => {
+            log::trace!("Connecting to TLS endpoint {:?}", *endpoint);
+            ObfuscationSettings::Tls(TlsSettings {
+                peer: *endpoint,
+                listen_addr: None,
+                server_name: server_name.clone(),
+                #[cfg(target_os = "linux")]
+                fwmark: config.fwmark,
+            })
+        }
+        ObfuscatorConfig::Socks5 {
+            endpoint,
+            auth,
+            wireguard_endpoint,
+        } => {
+            log::trace!("Connecting to SOCKS5 proxy {:?}", *endpoint);
+            ObfuscationSettings::Socks5(Socks5Settings {
+                proxy: *endpoint,
+                auth: auth.as_ref().map(|auth| Socks5Auth {
+                    username: auth.username.clone(),
+                    password: auth.password.clone(),
+                }),
+                wireguard_endpoint: *wireguard_endpoint,
+                listen_addr: None,
+                #[cfg(target_os = "linux")]
+                fwmark: config.fwmark,
+            })
+        }
+    };
+    let obfuscator = create_obfuscator(&settings)
+        .await
+        .map_err(Error::CreateObfuscatorError)?;
+    let endpoint = obfuscator.endpoint();
 
-                log::trace!("Patching first WireGuard peer to become {:?}", endpoint);
-                first_peer.endpoint = endpoint;
+    log::trace!("Patching first WireGuard peer to become {:?}", endpoint);
+    first_peer.endpoint = endpoint;
 
-                #[cfg(target_os = "android")]
-                let remote_socket_fd = obfuscator.remote_socket_fd();
+    #[cfg(target_os = "android")]
+    let remote_socket_fd = obfuscator.remote_socket_fd();
 
-                let (runner, abort_handle) = abortable(async move {
-                    match obfuscator.run().await {
-                        Ok(_) => {
-                            let _ = close_msg_sender.send(CloseMsg::ObfuscatorExpired);
-                        }
-                        Err(error) => {
-                            log::error!(
-                                "{}",
-                                error.display_chain_with_msg("Obfuscation controller failed")
-                            );
-                            let _ = close_msg_sender
-                                .send(CloseMsg::ObfuscatorFailed(Error::ObfuscatorError(error)));
-                        }
-                    }
-                });
-                tokio::spawn(runner);
-                return Ok(Some(ObfuscatorHandle::new(
-                    abort_handle,
-                    #[cfg(target_os = "android")]
-                    remote_socket_fd,
-                )));
+    let (runner, abort_handle) = abortable(async move {
+        match obfuscator.run().await {
+            Ok(_) => {
+                let _ = close_msg_sender.send(CloseMsg::ObfuscatorExpired);
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Obfuscation controller failed")
+                );
+                let _ = close_msg_sender
+                    .send(CloseMsg::ObfuscatorFailed(Error::ObfuscatorError(error)));
             }
         }
-    }
-    Ok(None)
+    });
+    tokio::spawn(runner);
+    Ok(Some(ObfuscatorHandle::new(
+        abort_handle,
+        #[cfg(target_os = "android")]
+        remote_socket_fd,
+    )))
 }
 
 impl WireguardMonitor {
@@ -286,9 +346,17 @@ impl WireguardMonitor {
             args.route_manager.clone(),
             #[cfg(target_os = "windows")]
             setup_done_tx,
+            #[cfg(target_os = "linux")]
+            args.replaces_interface.as_deref(),
         )?;
         let iface_name = tunnel.get_interface_name();
 
+        // A tunnel that replaces another one must leave the routes alone until it is known to work
+        #[cfg(target_os = "linux")]
+        let replacing = args.replaces_interface.is_some();
+        #[cfg(not(target_os = "linux"))]
+        let replacing = false;
+
         #[cfg(target_os = "android")]
         if let Some(remote_socket_fd) = obfuscator.as_ref().map(|obfs| obfs.remote_socket_fd()) {
             // Exclude remote obfuscation socket or bridge
@@ -318,6 +386,7 @@ impl WireguardMonitor {
             iface_name.clone(),
             Arc::downgrade(&monitor.tunnel),
             pinger_rx,
+            &config.connectivity_probes,
         )
         .map_err(Error::ConnectivityMonitorError)?;
 
@@ -328,6 +397,13 @@ impl WireguardMonitor {
             let mut tunnel = moved_tunnel;
             let close_obfs_sender: sync_mpsc::Sender<CloseMsg> = moved_close_obfs_sender;
             let obfuscator = moved_obfuscator;
+
+            tokio::spawn(Self::serve_stats_requests(
+                Arc::downgrade(&tunnel),
+                connectivity_monitor.ping_rtt_handle(),
+                args.stats_request_rx,
+            ));
+
             #[cfg(windows)]
             Self::add_device_ip_addresses(&iface_name, &config.tunnel.addresses, setup_done_rx)
                 .await?;
@@ -345,21 +421,23 @@ impl WireguardMonitor {
             (on_event)(TunnelEvent::InterfaceUp(metadata.clone(), allowed_traffic)).await;
 
             // Add non-default routes before establishing the tunnel.
-            #[cfg(target_os = "linux")]
-            args.route_manager
-                .create_routing_rules(config.enable_ipv6)
-                .await
-                .map_err(Error::SetupRoutingError)
-                .map_err(CloseMsg::SetupError)?;
+            if !replacing {
+                #[cfg(target_os = "linux")]
+                args.route_manager
+                    .create_routing_rules(config.enable_ipv6)
+                    .await
+                    .map_err(Error::SetupRoutingError)
+                    .map_err(CloseMsg::SetupError)?;
 
-            let routes = Self::get_pre_tunnel_routes(&iface_name, &config)
-                .chain(Self::get_endpoint_routes(&endpoint_addrs))
-                .collect();
-            args.route_manager
-                .add_routes(routes)
-                .await
-                .map_err(Error::SetupRoutingError)
-                .map_err(CloseMsg::SetupError)?;
+                let routes = Self::get_pre_tunnel_routes(&iface_name, &config)
+                    .chain(Self::get_endpoint_routes(&endpoint_addrs))
+                    .collect();
+                args.route_manager
+                    .add_routes(routes)
+                    .await
+                    .map_err(Error::SetupRoutingError)
+                    .map_err(CloseMsg::SetupError)?;
+            }
 
             let psk_obfs_sender = close_obfs_sender.clone();
             if psk_negotiation {
@@ -394,9 +472,25 @@ impl WireguardMonitor {
             .await
             .unwrap()?;
 
-            // Add any default route(s) that may exist.
+            #[cfg(target_os = "linux")]
+            if config.mtu_discovery {
+                if let Some(&relay) = endpoint_addrs.first() {
+                    Self::discover_mtu(&iface_name, relay, &mut config).await;
+                }
+            }
+
+            // Add any default route(s) that may exist. Existing routes are replaced, so a tunnel
+            // that replaces another one takes over all of its routes here.
+            let routes = if replacing {
+                Self::get_pre_tunnel_routes(&iface_name, &config)
+                    .chain(Self::get_endpoint_routes(&endpoint_addrs))
+                    .chain(Self::get_post_tunnel_routes(&iface_name, &config))
+                    .collect()
+            } else {
+                Self::get_post_tunnel_routes(&iface_name, &config).collect()
+            };
             args.route_manager
-                .add_routes(Self::get_post_tunnel_routes(&iface_name, &config).collect())
+                .add_routes(routes)
                 .await
                 .map_err(Error::SetupRoutingError)
                 .map_err(CloseMsg::SetupError)?;
@@ -404,16 +498,71 @@ impl WireguardMonitor {
             let metadata = Self::tunnel_metadata(&iface_name, &config);
             (on_event)(TunnelEvent::Up(metadata)).await;
 
-            tokio::task::spawn_blocking(move || {
+            #[cfg(target_os = "linux")]
+            let reprobe = connectivity_monitor.reprobe_handle();
+            let connectivity_monitor = tokio::task::spawn_blocking(move || {
                 if let Err(error) = connectivity_monitor.run() {
                     log::error!(
                         "{}",
                         error.display_chain_with_msg("Connectivity monitor failed")
                     );
                 }
-            })
-            .await
-            .unwrap();
+            });
+
+            let psk_rekey_interval = config.psk_rekey_interval;
+            let config = Arc::new(AsyncMutex::new(config));
+            let psk_renegotiation = {
+                let tunnel = tunnel.clone();
+                let config = config.clone();
+                let obfuscator = obfuscator.clone();
+                let close_obfs_sender = close_obfs_sender.clone();
+                async move {
+                    if !psk_negotiation {
+                        return futures::future::pending().await;
+                    }
+                    Self::run_periodically(
+                        psk_rekey_interval,
+                        args.psk_rekey_interval_rx,
+                        talpid_time::sleep,
+                        || {
+                            Self::renegotiate_psk(
+                                &tunnel,
+                                &config,
+                                obfuscator.clone(),
+                                close_obfs_sender.clone(),
+                            )
+                        },
+                    )
+                    .await
+                }
+            };
+            futures::pin_mut!(psk_renegotiation);
+
+            #[cfg(target_os = "linux")]
+            let maintenance = async move {
+                let network_changes = Self::recover_on_network_change(
+                    args.network_change_rx,
+                    tunnel,
+                    config,
+                    obfuscator,
+                    close_obfs_sender,
+                    reprobe,
+                );
+                futures::pin_mut!(network_changes);
+                futures::future::select(network_changes, psk_renegotiation)
+                    .await
+                    .factor_first()
+                    .0
+            };
+            #[cfg(target_os = "linux")]
+            futures::pin_mut!(maintenance);
+            #[cfg(not(target_os = "linux"))]
+            let maintenance = psk_renegotiation;
+
+            match futures::future::select(connectivity_monitor, maintenance).await {
+                futures::future::Either::Left((result, _)) => result.unwrap(),
+                futures::future::Either::Right((never, _)) => match never {},
+            }
 
             Err::<Infallible, CloseMsg>(CloseMsg::PingErr)
         };
@@ -451,9 +600,6 @@ impl WireguardMonitor {
             + Clone
             + 'static,
     {
-        let wg_psk_privkey = PrivateKey::new_from_random();
-        let close_obfs_sender = close_obfs_sender.clone();
-
         let allowed_traffic = Endpoint::new(
             config.ipv4_gateway,
             talpid_tunnel_config_client::CONFIG_SERVICE_PORT,
@@ -473,9 +619,48 @@ impl WireguardMonitor {
         let metadata = Self::tunnel_metadata(iface_name, config);
         (on_event)(TunnelEvent::InterfaceUp(metadata, allowed_traffic.clone())).await;
 
-        let exit_psk =
-            Self::perform_psk_negotiation(retry_attempt, config, wg_psk_privkey.public_key())
-                .await?;
+        let device_pubkey = config.tunnel.private_key.public_key();
+        Self::exchange_psks(
+            tunnel,
+            config,
+            device_pubkey,
+            retry_attempt,
+            obfuscator,
+            close_obfs_sender,
+        )
+        .await?;
+
+        let metadata = Self::tunnel_metadata(iface_name, config);
+        (on_event)(TunnelEvent::InterfaceUp(
+            metadata,
+            AllowedTunnelTraffic::All,
+        ))
+        .await;
+
+        Ok(())
+    }
+
+    /// Negotiates PSKs with the exit peer and, if multihop is used, the entry peer, for a new
+    /// ephemeral private key. The tunnel is then reconfigured to use the new key and PSKs.
+    /// `current_pubkey` is the key that the relays currently know the tunnel by: the device key
+    /// for a new tunnel, or the ephemeral key of the previous exchange when rekeying.
+    async fn exchange_psks(
+        tunnel: &Arc<Mutex<Option<Box<dyn Tunnel>>>>,
+        config: &mut Config,
+        current_pubkey: PublicKey,
+        retry_attempt: u32,
+        obfuscator: Arc<AsyncMutex<Option<ObfuscatorHandle>>>,
+        close_obfs_sender: sync_mpsc::Sender<CloseMsg>,
+    ) -> std::result::Result<(), CloseMsg> {
+        let wg_psk_privkey = PrivateKey::new_from_random();
+
+        let exit_psk = Self::perform_psk_negotiation(
+            retry_attempt,
+            config,
+            current_pubkey.clone(),
+            wg_psk_privkey.public_key(),
+        )
+        .await?;
 
         log::debug!("Successfully exchanged PSK with exit peer");
 
@@ -506,6 +691,7 @@ impl WireguardMonitor {
                 Self::perform_psk_negotiation(
                     retry_attempt,
                     &entry_config,
+                    current_pubkey,
                     wg_psk_privkey.public_key(),
                 )
                 .await?,
@@ -526,16 +712,91 @@ impl WireguardMonitor {
 
         *config =
             Self::reconfigure_tunnel(tunnel, config.clone(), obfuscator, close_obfs_sender).await?;
-        let metadata = Self::tunnel_metadata(iface_name, config);
-        (on_event)(TunnelEvent::InterfaceUp(
-            metadata,
-            AllowedTunnelTraffic::All,
-        ))
-        .await;
 
         Ok(())
     }
 
+    /// Runs `task` every `interval`, or never if `interval` is `None`. A new interval may be
+    /// received on `interval_rx` at any time, which restarts the timer. The interval is awaited
+    /// using `sleep`, which should count time spent suspended.
+    async fn run_periodically<S, SleepFut, F, Fut>(
+        mut interval: Option<Duration>,
+        interval_rx: mpsc::UnboundedReceiver<Option<Duration>>,
+        sleep: S,
+        mut task: F,
+    ) -> Infallible
+    where
+        S: Fn(Duration) -> SleepFut,
+        SleepFut: Future<Output = ()>,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut interval_rx = interval_rx.fuse();
+        let sleep = &sleep;
+        loop {
+            let timer = async move {
+                match interval {
+                    Some(interval) => sleep(interval).await,
+                    None => futures::future::pending().await,
+                }
+            }
+            .fuse();
+            futures::pin_mut!(timer);
+            futures::select! {
+                () = timer => task().await,
+                new_interval = interval_rx.next() => {
+                    if let Some(new_interval) = new_interval {
+                        log::debug!("PSK rekey interval changed to {:?}", new_interval);
+                        interval = new_interval;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Performs a new PSK exchange through the tunnel. If the exchange fails, the previous key
+    /// and PSKs are kept.
+    async fn renegotiate_psk(
+        tunnel: &Arc<Mutex<Option<Box<dyn Tunnel>>>>,
+        config: &AsyncMutex<Config>,
+        obfuscator: Arc<AsyncMutex<Option<ObfuscatorHandle>>>,
+        close_obfs_sender: sync_mpsc::Sender<CloseMsg>,
+    ) {
+        let mut config = config.lock().await;
+        let mut new_config = config.clone();
+        // The relays replaced the device key with the ephemeral key of the previous exchange
+        let current_pubkey = config.tunnel.private_key.public_key();
+        match Self::exchange_psks(
+            tunnel,
+            &mut new_config,
+            current_pubkey,
+            0,
+            obfuscator.clone(),
+            close_obfs_sender.clone(),
+        )
+        .await
+        {
+            Ok(()) => {
+                log::info!("Renegotiated PSK");
+                *config = new_config;
+            }
+            Err(close_msg) => {
+                log::warn!("Failed to renegotiate PSK: {:?}", close_msg);
+                // The tunnel may have been reconfigured halfway through a multihop exchange
+                if let Err(close_msg) = Self::reconfigure_tunnel(
+                    tunnel,
+                    config.clone(),
+                    obfuscator,
+                    close_obfs_sender.clone(),
+                )
+                .await
+                {
+                    let _ = close_obfs_sender.send(close_msg);
+                }
+            }
+        }
+    }
+
     /// Reconfigures the tunnel to use the provided config while potentially modifying the config
     /// and restarting the obfuscation provider. Returns the new config used by the new tunnel.
     async fn reconfigure_tunnel(
@@ -566,6 +827,85 @@ impl WireguardMonitor {
         Ok(config)
     }
 
+    /// Reapplies the tunnel config whenever the network changes. This restarts the obfuscator, if
+    /// any, and replaces the peers, so that traffic uses the new route and new handshakes are
+    /// performed. The connectivity monitor is then told to verify the connection right away.
+    ///
+    /// Only changes to the default routes and the interfaces carrying them are reported, so other
+    /// interfaces, such as container veths or overlay networks, do not trigger this.
+    #[cfg(target_os = "linux")]
+    async fn recover_on_network_change(
+        mut network_change_rx: mpsc::UnboundedReceiver<()>,
+        tunnel: Arc<Mutex<Option<Box<dyn Tunnel>>>>,
+        config: Arc<AsyncMutex<Config>>,
+        obfuscator: Arc<AsyncMutex<Option<ObfuscatorHandle>>>,
+        close_obfs_sender: sync_mpsc::Sender<CloseMsg>,
+        reprobe: connectivity_check::ReprobeHandle,
+    ) -> Infallible {
+        while network_change_rx.next().await.is_some() {
+            // Changes tend to arrive in bursts, so handle them all at once
+            while let Ok(Some(())) = network_change_rx.try_next() {}
+
+            log::debug!("Network changed, reconfiguring tunnel");
+            let mut config = config.lock().await;
+            match Self::reconfigure_tunnel(
+                &tunnel,
+                config.clone(),
+                obfuscator.clone(),
+                close_obfs_sender.clone(),
+            )
+            .await
+            {
+                Ok(new_config) => *config = new_config,
+                Err(close_msg) => {
+                    let _ = close_obfs_sender.send(close_msg);
+                }
+            }
+            reprobe.reprobe();
+        }
+        futures::future::pending().await
+    }
+
+    /// Replies to requests for traffic statistics until the request channel is closed.
+    async fn serve_stats_requests(
+        tunnel: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
+        ping_rtt: Arc<Mutex<Option<connectivity_check::PingRtt>>>,
+        mut stats_request_rx: mpsc::UnboundedReceiver<oneshot::Sender<TunnelStatistics>>,
+    ) {
+        while let Some(reply_tx) = stats_request_rx.next().await {
+            let tunnel = tunnel.clone();
+            let stats = tokio::task::spawn_blocking(move || {
+                tunnel
+                    .upgrade()?
+                    .lock()
+                    .ok()?
+                    .as_ref()
+                    .and_then(|tunnel| tunnel.get_tunnel_stats().ok())
+            })
+            .await
+            .ok()
+            .flatten();
+
+            if let Some(stats) = stats {
+                let peers = stats
+                    .into_iter()
+                    .map(|(public_key, stats)| WireguardPeerStatistics {
+                        public_key: PublicKey::from(public_key),
+                        rx_bytes: stats.rx_bytes,
+                        tx_bytes: stats.tx_bytes,
+                        last_handshake: stats.last_handshake,
+                    })
+                    .collect();
+                let ping_rtt = ping_rtt.lock().ok().and_then(|ping_rtt| *ping_rtt);
+                let _ = reply_tx.send(TunnelStatistics::Wireguard {
+                    peers,
+                    ping_rtt: ping_rtt.map(|ping_rtt| ping_rtt.rtt),
+                    ping_rtt_age: ping_rtt.map(|ping_rtt| ping_rtt.measured_at.elapsed()),
+                });
+            }
+        }
+    }
+
     /// Replace `0.0.0.0/0`/`::/0` with the gateway IPs when `gateway_only` is true.
     /// Used to block traffic to other destinations while connecting on Android.
     #[cfg(target_os = "android")]
@@ -637,6 +977,7 @@ impl WireguardMonitor {
     async fn perform_psk_negotiation(
         retry_attempt: u32,
         config: &Config,
+        current_pubkey: PublicKey,
         wg_psk_pubkey: PublicKey,
     ) -> std::result::Result<PresharedKey, CloseMsg> {
         log::debug!("Performing PQ-safe PSK exchange");
@@ -651,7 +992,7 @@ impl WireguardMonitor {
             timeout,
             talpid_tunnel_config_client::push_pq_key(
                 IpAddr::V4(config.ipv4_gateway),
-                config.tunnel.private_key.public_key(),
+                current_pubkey,
                 wg_psk_pubkey,
             ),
         )
@@ -675,11 +1016,25 @@ impl WireguardMonitor {
         tun_provider: Arc<Mutex<TunProvider>>,
         #[cfg(windows)] route_manager_handle: crate::routing::RouteManagerHandle,
         #[cfg(windows)] setup_done_tx: mpsc::Sender<std::result::Result<(), BoxedError>>,
+        #[cfg(target_os = "linux")] replaces_interface: Option<&str>,
     ) -> Result<Box<dyn Tunnel>> {
         #[cfg(target_os = "linux")]
-        if !*FORCE_USERSPACE_WIREGUARD {
-            if will_nm_manage_dns() {
-                match wireguard_kernel::NetworkManagerTunnel::new(runtime, config) {
+        if config.use_boringtun {
+            match Self::open_boringtun_tunnel(&runtime, config, tun_provider.clone()) {
+                Ok(tunnel) => return Ok(tunnel),
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg(
+                            "Failed to start BoringTun, falling back to wireguard-go"
+                        )
+                    );
+                }
+            }
+        } else if !*FORCE_USERSPACE_WIREGUARD {
+            // NetworkManager can only manage a single Mullvad tunnel at a time
+            if will_nm_manage_dns() && replaces_interface.is_none() {
+                match wireguard_kernel::NetworkManagerTunnel::new(runtime.clone(), config) {
                     Ok(tunnel) => {
                         log::debug!("Using NetworkManager to use kernel WireGuard implementation");
                         return Ok(Box::new(tunnel));
@@ -694,7 +1049,11 @@ impl WireguardMonitor {
                     }
                 };
             } else {
-                match wireguard_kernel::NetlinkTunnel::new(runtime, config) {
+                match wireguard_kernel::NetlinkTunnel::new(
+                    runtime.clone(),
+                    config,
+                    wireguard_kernel::interface_name(replaces_interface),
+                ) {
                     Ok(tunnel) => {
                         log::debug!("Using kernel WireGuard implementation");
                         return Ok(Box::new(tunnel));
@@ -706,6 +1065,17 @@ impl WireguardMonitor {
                                 "Failed to setup kernel WireGuard device, falling back to the userspace implementation"
                             )
                         );
+                        match Self::open_boringtun_tunnel(&runtime, config, tun_provider.clone()) {
+                            Ok(tunnel) => return Ok(tunnel),
+                            Err(error) => {
+                                log::error!(
+                                    "{}",
+                                    error.display_chain_with_msg(
+                                        "Failed to start BoringTun, falling back to wireguard-go"
+                                    )
+                                );
+                            }
+                        }
                     }
                 };
             }
@@ -745,6 +1115,22 @@ impl WireguardMonitor {
         ))
     }
 
+    #[cfg(target_os = "linux")]
+    fn open_boringtun_tunnel(
+        runtime: &tokio::runtime::Handle,
+        config: &Config,
+        tun_provider: Arc<Mutex<TunProvider>>,
+    ) -> std::result::Result<Box<dyn Tunnel>, TunnelError> {
+        let tunnel = wireguard_boringtun::BoringTunnel::start_tunnel(
+            runtime,
+            config,
+            tun_provider,
+            Self::get_tunnel_destinations(config).flat_map(Self::replace_default_prefixes),
+        )?;
+        log::debug!("Using BoringTun userspace WireGuard implementation");
+        Ok(Box::new(tunnel))
+    }
+
     /// Blocks the current thread until tunnel disconnects
     pub fn wait(mut self) -> Result<()> {
         let wait_result = match self.close_msg_receiver.recv() {
@@ -896,12 +1282,42 @@ impl WireguardMonitor {
         vec![network]
     }
 
+    /// Replaces the tunnel MTU with the largest one that fits in the path to the entry relay and
+    /// through the tunnel to the gateway. `relay` must be the address of the relay itself, and
+    /// not that of a local obfuscator.
+    #[cfg(target_os = "linux")]
+    async fn discover_mtu(iface_name: &str, relay: IpAddr, config: &mut Config) {
+        // The minimum allowed MTU size for our tunnel in IPv6 is 1280 and 576 for IPv4
+        let min_mtu = if config.enable_ipv6 { 1280 } else { 576 };
+        match mtu_discovery::discover_mtu(
+            iface_name,
+            relay,
+            config.ipv4_gateway,
+            config.fwmark,
+            min_mtu,
+        )
+        .await
+        {
+            Ok(mtu) => {
+                log::info!("Discovered tunnel MTU: {mtu}");
+                config.mtu = mtu;
+            }
+            Err(error) => {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to discover tunnel MTU")
+                );
+            }
+        }
+    }
+
     fn tunnel_metadata(interface_name: &str, config: &Config) -> TunnelMetadata {
         TunnelMetadata {
             interface: interface_name.to_string(),
             ips: config.tunnel.addresses.clone(),
             ipv4_gateway: config.ipv4_gateway,
             ipv6_gateway: config.ipv6_gateway,
+            mtu: Some(config.mtu),
         }
     }
 }
@@ -985,6 +1401,21 @@ pub enum TunnelError {
     #[error(display = "Failed to configure Wireguard sockets to bypass the tunnel")]
     BypassError(#[error(source)] tun_provider::Error),
 
+    /// Failed to set up the tunnel device or socket for BoringTun.
+    #[cfg(target_os = "linux")]
+    #[error(display = "Failed to set up I/O for BoringTun")]
+    BoringtunSocketError(#[error(source)] io::Error),
+
+    /// The config cannot be used with BoringTun.
+    #[cfg(target_os = "linux")]
+    #[error(display = "Invalid BoringTun configuration: {}", _0)]
+    BoringtunConfigError(&'static str),
+
+    /// BoringTun can only be used with a single peer.
+    #[cfg(target_os = "linux")]
+    #[error(display = "Multihop is not supported by BoringTun")]
+    BoringtunMultihopUnsupported,
+
     /// Invalid tunnel interface name.
     #[error(display = "Invalid tunnel interface name")]
     InterfaceNameError(#[error(source)] std::ffi::NulError),
@@ -1014,3 +1445,242 @@ fn will_nm_manage_dns() -> bool {
         })
         .unwrap_or(false)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        net::Ipv4Addr,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+    use talpid_types::net::wireguard::{PeerConfig, TunnelConfig};
+
+    /// Tunnel that records every config that is applied to it.
+    struct MockTunnel {
+        configs: Arc<Mutex<Vec<Config>>>,
+    }
+
+    impl Tunnel for MockTunnel {
+        fn get_interface_name(&self) -> String {
+            "mock-tunnel".to_string()
+        }
+
+        fn stop(self: Box<Self>) -> std::result::Result<(), TunnelError> {
+            Ok(())
+        }
+
+        fn get_tunnel_stats(&self) -> std::result::Result<stats::StatsMap, TunnelError> {
+            Ok(stats::StatsMap::new())
+        }
+
+        fn set_config(
+            &self,
+            config: Config,
+        ) -> Pin<Box<dyn Future<Output = std::result::Result<(), TunnelError>> + Send>> {
+            self.configs.lock().unwrap().push(config);
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    /// Returns a config for a tunnel whose config service cannot be reached.
+    fn unreachable_relay_config() -> Config {
+        Config {
+            tunnel: TunnelConfig {
+                private_key: PrivateKey::new_from_random(),
+                addresses: vec!["10.64.0.2".parse().unwrap()],
+            },
+            peers: vec![PeerConfig {
+                public_key: PrivateKey::new_from_random().public_key(),
+                allowed_ips: vec!["0.0.0.0/0".parse().unwrap()],
+                endpoint: "1.2.3.4:51820".parse().unwrap(),
+                psk: None,
+            }],
+            ipv4_gateway: Ipv4Addr::LOCALHOST,
+            ipv6_gateway: None,
+            mtu: 1380,
+            #[cfg(target_os = "linux")]
+            mtu_discovery: false,
+            persistent_keepalive: None,
+            #[cfg(target_os = "linux")]
+            fwmark: None,
+            #[cfg(target_os = "linux")]
+            enable_ipv6: false,
+            #[cfg(target_os = "windows")]
+            use_wireguard_nt: false,
+            #[cfg(target_os = "linux")]
+            use_boringtun: false,
+            obfuscator_config: None,
+            psk_rekey_interval: None,
+            connectivity_probes: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_run_periodically() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap();
+
+        let runs = Arc::new(AtomicUsize::new(0));
+        let (interval_tx, interval_rx) = mpsc::unbounded();
+        let task = {
+            let runs = runs.clone();
+            move || {
+                runs.fetch_add(1, Ordering::SeqCst);
+                async {}
+            }
+        };
+
+        let check = async {
+            tokio::time::sleep(Duration::from_secs(60 * 60)).await;
+            assert_eq!(runs.load(Ordering::SeqCst), 0);
+
+            interval_tx
+                .unbounded_send(Some(Duration::from_secs(10)))
+                .unwrap();
+            tokio::time::sleep(Duration::from_secs(25)).await;
+            assert_eq!(runs.load(Ordering::SeqCst), 2);
+
+            // A new interval restarts the timer
+            interval_tx
+                .unbounded_send(Some(Duration::from_secs(20)))
+                .unwrap();
+            tokio::time::sleep(Duration::from_secs(15)).await;
+            assert_eq!(runs.load(Ordering::SeqCst), 2);
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            assert_eq!(runs.load(Ordering::SeqCst), 3);
+
+            interval_tx.unbounded_send(None).unwrap();
+            tokio::time::sleep(Duration::from_secs(60 * 60)).await;
+            assert_eq!(runs.load(Ordering::SeqCst), 3);
+        };
+
+        runtime.block_on(async {
+            let periodic =
+                WireguardMonitor::run_periodically(None, interval_rx, tokio::time::sleep, task);
+            futures::pin_mut!(periodic);
+            futures::pin_mut!(check);
+            match futures::future::select(periodic, check).await {
+                futures::future::Either::Left((never, _)) => match never {},
+                futures::future::Either::Right(((), _)) => (),
+            }
+        });
+    }
+
+    #[test]
+    fn test_failed_psk_renegotiation() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        let configs = Arc::new(Mutex::new(vec![]));
+        let tunnel: Arc<Mutex<Option<Box<dyn Tunnel>>>> =
+            Arc::new(Mutex::new(Some(Box::new(MockTunnel {
+                configs: configs.clone(),
+            }))));
+        let obfuscator = Arc::new(AsyncMutex::new(None));
+        let (close_obfs_sender, close_obfs_receiver) = sync_mpsc::channel();
+
+        let config = unreachable_relay_config();
+        let device_pubkey = config.tunnel.private_key.public_key();
+        let private_key = config.tunnel.private_key.to_bytes();
+
+        // The tunnel is left alone if the exchange fails
+        let mut new_config = config.clone();
+        let result = runtime.block_on(WireguardMonitor::exchange_psks(
+            &tunnel,
+            &mut new_config,
+            device_pubkey.clone(),
+            0,
+            obfuscator.clone(),
+            close_obfs_sender.clone(),
+        ));
+        assert!(matches!(
+            result,
+            Err(CloseMsg::SetupError(Error::PskNegotiationError(_))
+                | CloseMsg::PskNegotiationTimeout)
+        ));
+        assert!(configs.lock().unwrap().is_empty());
+
+        // A failed renegotiation restores the previous config instead of closing the tunnel
+        let config = AsyncMutex::new(config);
+        runtime.block_on(WireguardMonitor::renegotiate_psk(
+            &tunnel,
+            &config,
+            obfuscator,
+            close_obfs_sender,
+        ));
+        assert_eq!(
+            config.into_inner().tunnel.private_key.to_bytes(),
+            private_key
+        );
+        let configs = configs.lock().unwrap();
+        assert_eq!(configs.len(), 1);
+        assert_eq!(configs[0].tunnel.private_key.to_bytes(), private_key);
+        assert!(configs[0].peers[0].psk.is_none());
+        assert!(close_obfs_receiver.try_recv().is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_psk_renegotiation_key() {
+        use std::io::Read;
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        let gateway = Ipv4Addr::new(127, 0, 0, 2);
+        let listener = std::net::TcpListener::bind((
+            gateway,
+            talpid_tunnel_config_client::CONFIG_SERVICE_PORT,
+        ))
+        .unwrap();
+
+        let tunnel: Arc<Mutex<Option<Box<dyn Tunnel>>>> =
+            Arc::new(Mutex::new(Some(Box::new(MockTunnel {
+                configs: Arc::new(Mutex::new(vec![])),
+            }))));
+        let (close_obfs_sender, _close_obfs_receiver) = sync_mpsc::channel();
+
+        // After the first exchange, the tunnel uses an ephemeral key rather than the device key
+        let mut config = unreachable_relay_config();
+        config.ipv4_gateway = gateway;
+        let ephemeral_pubkey = config.tunnel.private_key.public_key();
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let expected = ephemeral_pubkey.as_bytes().to_vec();
+            let mut received = vec![];
+            let mut buf = [0u8; 4096];
+            while !received
+                .windows(expected.len())
+                .any(|window| window == expected)
+            {
+                match stream.read(&mut buf) {
+                    Ok(0) | Err(_) => return false,
+                    Ok(n) => received.extend_from_slice(&buf[..n]),
+                }
+            }
+            // Closing the connection fails the exchange
+            true
+        });
+
+        runtime.block_on(WireguardMonitor::renegotiate_psk(
+            &tunnel,
+            &AsyncMutex::new(config),
+            Arc::new(AsyncMutex::new(None)),
+            close_obfs_sender,
+        ));
+        // Unblock the server in case the client never connected
+        let _ = std::net::TcpStream::connect((
+            gateway,
+            talpid_tunnel_config_client::CONFIG_SERVICE_PORT,
+        ));
+        assert!(server.join().unwrap(), "the ephemeral key was not sent");
+    }
+}
@@ -0,0 +1,363 @@
+//! Finds the largest MTU that can be used for the tunnel, by sending ICMP echo requests of
+//! decreasing size to the relay, outside the tunnel. Each request is as large as a WireGuard
+//! packet carrying a full tunnel packet of the candidate MTU. The requests have the "don't
+//! fragment" flag set, so any request that is too large for the path to the relay is dropped on
+//! the way.
+//!
+//! The result is then verified by pinging the gateway through the tunnel with requests of
+//! decreasing size, starting at the MTU found for the path to the relay. This catches any limit
+//! on the far side of the relay.
+
+use futures::TryStreamExt;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    os::unix::io::AsRawFd,
+    time::{Duration, Instant},
+};
+use talpid_types::ErrorExt;
+
+/// The largest MTU that is probed. This is the MTU that WireGuard uses by default, and leaves room
+/// for an IPv6 header on a path with an MTU of 1500.
+const MAX_MTU: u16 = 1420;
+/// Difference in size between two probes.
+const PROBE_STEP: u16 = 20;
+/// How long to wait for replies to the probes.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+const IPV4_HEADER_SIZE: u16 = 20;
+const IPV6_HEADER_SIZE: u16 = 40;
+const UDP_HEADER_SIZE: u16 = 8;
+/// Size of the header and authentication tag that WireGuard adds to each tunnel packet.
+const WIREGUARD_HEADER_SIZE: u16 = 32;
+const ICMP_HEADER_SIZE: u16 = 8;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+/// MTU discovery errors
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to open or configure the ICMP socket
+    #[error(display = "Failed to open ICMP socket")]
+    Socket(#[error(source)] io::Error),
+
+    /// Failed to send a probe
+    #[error(display = "Failed to send MTU probe")]
+    Send(#[error(source)] io::Error),
+
+    /// Failed to read replies
+    #[error(display = "Failed to read MTU probe replies")]
+    Read(#[error(source)] io::Error),
+
+    /// None of the probes were answered
+    #[error(display = "No MTU probes were answered")]
+    NoReply,
+
+    /// Failed to connect to netlink
+    #[error(display = "Failed to connect to netlink")]
+    NetlinkConnection(#[error(source)] io::Error),
+
+    /// The tunnel interface could not be found
+    #[error(display = "Failed to find tunnel interface")]
+    LinkNotFound(#[error(source)] rtnetlink::Error),
+
+    /// Failed to change the MTU of the tunnel interface
+    #[error(display = "Failed to set tunnel interface MTU")]
+    SetMtu(#[error(source)] rtnetlink::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Probes the path to `relay` for the largest working tunnel MTU, no smaller than `min_mtu`, and
+/// applies it to the tunnel interface. The probes are marked with `fwmark`, so that they are
+/// routed outside the tunnel and let through by the firewall. The MTU is then lowered further
+/// if the largest probes do not reach `gateway` through the tunnel.
+pub async fn discover_mtu(
+    interface_name: &str,
+    relay: IpAddr,
+    gateway: Ipv4Addr,
+    fwmark: Option<u32>,
+    min_mtu: u16,
+) -> Result<u16> {
+    let candidates = candidate_mtus(min_mtu);
+    let relay_candidates = candidates.clone();
+    let path_mtu = tokio::task::spawn_blocking(move || {
+        let sock = relay_socket(relay, fwmark)?;
+        probe(&sock, relay, &relay_candidates, probe_size)
+    })
+    .await
+    .unwrap()?;
+    // The interface must allow the largest probe through the tunnel to be sent
+    set_interface_mtu(interface_name, path_mtu).await?;
+
+    let tunnel_candidates: Vec<u16> = candidates
+        .into_iter()
+        .filter(|mtu| *mtu <= path_mtu)
+        .collect();
+    let interface = interface_name.to_owned();
+    let result = tokio::task::spawn_blocking(move || {
+        let sock = tunnel_socket(&interface, gateway)?;
+        probe(&sock, gateway.into(), &tunnel_candidates, tunnel_probe_size)
+    })
+    .await
+    .unwrap();
+    let mtu = match result {
+        Ok(mtu) => mtu,
+        Err(error) => {
+            // The path to the relay is known to work, so keep its MTU
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Failed to probe the MTU through the tunnel")
+            );
+            return Ok(path_mtu);
+        }
+    };
+    if mtu != path_mtu {
+        set_interface_mtu(interface_name, mtu).await?;
+    }
+    Ok(mtu)
+}
+
+/// Returns the MTUs to probe, from largest to smallest.
+fn candidate_mtus(min_mtu: u16) -> Vec<u16> {
+    let min_mtu = min_mtu.min(MAX_MTU);
+    let mut candidates: Vec<u16> = (min_mtu..=MAX_MTU)
+        .rev()
+        .step_by(usize::from(PROBE_STEP))
+        .collect();
+    if candidates.last() != Some(&min_mtu) {
+        candidates.push(min_mtu);
+    }
+    candidates
+}
+
+/// Returns the size of the ICMP message that makes up a probe for the given tunnel MTU. The IP
+/// header is the same size for the probe as for a WireGuard packet, so only the UDP and
+/// WireGuard overhead has to be added.
+fn probe_size(mtu: u16) -> usize {
+    usize::from(mtu + UDP_HEADER_SIZE + WIREGUARD_HEADER_SIZE)
+}
+
+/// Returns the size of the ICMP message that makes up a probe through the tunnel, such that the
+/// IPv4 packet carrying it is as large as the given tunnel MTU.
+fn tunnel_probe_size(mtu: u16) -> usize {
+    usize::from(mtu - IPV4_HEADER_SIZE)
+}
+
+/// Opens a socket for pinging the relay outside the tunnel.
+fn relay_socket(relay: IpAddr, fwmark: Option<u32>) -> Result<Socket> {
+    let sock = match relay {
+        IpAddr::V4(_) => Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4)),
+        IpAddr::V6(_) => Socket::new(Domain::IPV6, Type::RAW, Some(Protocol::ICMPV6)),
+    }
+    .map_err(Error::Socket)?;
+    if let Some(fwmark) = fwmark {
+        sock.set_mark(fwmark).map_err(Error::Socket)?;
+    }
+    set_dont_fragment(&sock, relay)?;
+    Ok(sock)
+}
+
+/// Opens a socket for pinging the gateway through the tunnel.
+fn tunnel_socket(interface_name: &str, gateway: Ipv4Addr) -> Result<Socket> {
+    let sock =
+        Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4)).map_err(Error::Socket)?;
+    sock.bind_device(Some(interface_name.as_bytes()))
+        .map_err(Error::Socket)?;
+    set_dont_fragment(&sock, gateway.into())?;
+    Ok(sock)
+}
+
+/// Sends one echo request per candidate MTU to `destination` and returns the largest MTU that
+/// was answered. `probe_size` gives the size of the ICMP message to send for each MTU.
+fn probe(
+    sock: &Socket,
+    destination: IpAddr,
+    candidates: &[u16],
+    probe_size: fn(u16) -> usize,
+) -> Result<u16> {
+    let id: u16 = rand::random();
+    let address = SocketAddr::new(destination, 0).into();
+    for (seq, mtu) in candidates.iter().enumerate() {
+        let packet = echo_request(destination, id, seq as u16, probe_size(*mtu));
+        if let Err(error) = sock.send_to(&packet, &address) {
+            // Packets that are larger than the path MTU known to the kernel are rejected locally
+            if error.raw_os_error() != Some(libc::EMSGSIZE) {
+                return Err(Error::Send(error));
+            }
+        }
+    }
+
+    let deadline = Instant::now() + PROBE_TIMEOUT;
+    let mut largest: Option<u16> = None;
+    let mut buffer = vec![0u8; usize::from(IPV6_HEADER_SIZE) + probe_size(MAX_MTU)];
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        if remaining.is_zero() {
+            break;
+        }
+        sock.set_read_timeout(Some(remaining))
+            .map_err(Error::Socket)?;
+        let len = match std::io::Read::read(&mut &*sock, &mut buffer) {
+            Ok(len) => len,
+            Err(error)
+                if error.kind() == io::ErrorKind::WouldBlock
+                    || error.kind() == io::ErrorKind::TimedOut =>
+            {
+                break
+            }
+            Err(error) => return Err(Error::Read(error)),
+        };
+        if let Some(seq) = parse_echo_reply(destination, &buffer[..len], id) {
+            if let Some(&mtu) = candidates.get(usize::from(seq)) {
+                largest = largest.max(Some(mtu));
+                if mtu == candidates[0] {
+                    break;
+                }
+            }
+        }
+    }
+
+    largest.ok_or(Error::NoReply)
+}
+
+/// Sets the "don't fragment" flag on all packets, so that probes exceeding the path MTU are
+/// dropped rather than fragmented.
+fn set_dont_fragment(sock: &Socket, relay: IpAddr) -> Result<()> {
+    let (level, option, value) = match relay {
+        IpAddr::V4(_) => (
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            libc::IP_PMTUDISC_DO,
+        ),
+        IpAddr::V6(_) => (
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_DO,
+        ),
+    };
+    let value: libc::c_int = value;
+    // SAFETY: `value` outlives the call and its size is passed along with it.
+    let result = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            level,
+            option,
+            &value as *const _ as *const libc::c_void,
+            std::mem::size_of_val(&value) as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(Error::Socket(io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+/// Constructs an ICMP echo request that is `size` bytes long, including the ICMP header. The
+/// kernel computes the checksum of ICMPv6 messages, since it depends on the IPv6 header.
+fn echo_request(relay: IpAddr, id: u16, seq: u16, size: usize) -> Vec<u8> {
+    let mut packet = vec![0u8; size.max(usize::from(ICMP_HEADER_SIZE))];
+    packet[4..6].copy_from_slice(&id.to_be_bytes());
+    packet[6..8].copy_from_slice(&seq.to_be_bytes());
+    match relay {
+        IpAddr::V4(_) => {
+            packet[0] = ICMP_ECHO_REQUEST;
+            let checksum = internet_checksum::checksum(&packet);
+            packet[2..4].copy_from_slice(&checksum);
+        }
+        IpAddr::V6(_) => packet[0] = ICMPV6_ECHO_REQUEST,
+    }
+    packet
+}
+
+/// Returns the sequence number of an echo reply with the given ID. Raw IPv4 sockets include the
+/// IP header in received packets, while raw IPv6 sockets do not.
+fn parse_echo_reply(relay: IpAddr, packet: &[u8], id: u16) -> Option<u16> {
+    let (icmp, reply_type) = match relay {
+        IpAddr::V4(_) => {
+            let header_len = usize::from(packet.first()? & 0x0f) * 4;
+            (packet.get(header_len..)?, ICMP_ECHO_REPLY)
+        }
+        IpAddr::V6(_) => (packet, ICMPV6_ECHO_REPLY),
+    };
+    if icmp.len() < usize::from(ICMP_HEADER_SIZE) || icmp[0] != reply_type {
+        return None;
+    }
+    if u16::from_be_bytes([icmp[4], icmp[5]]) != id {
+        return None;
+    }
+    Some(u16::from_be_bytes([icmp[6], icmp[7]]))
+}
+
+async fn set_interface_mtu(interface_name: &str, mtu: u16) -> Result<()> {
+    let (connection, handle, _) = rtnetlink::new_connection().map_err(Error::NetlinkConnection)?;
+    tokio::spawn(connection);
+
+    let link = handle
+        .link()
+        .get()
+        .match_name(interface_name.to_owned())
+        .execute()
+        .try_next()
+        .await
+        .map_err(Error::LinkNotFound)?
+        .ok_or(Error::LinkNotFound(rtnetlink::Error::RequestFailed))?;
+
+    handle
+        .link()
+        .set(link.header.index)
+        .mtu(u32::from(mtu))
+        .execute()
+        .await
+        .map_err(Error::SetMtu)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_candidate_mtus() {
+        let candidates = candidate_mtus(1280);
+        assert_eq!(candidates.first(), Some(&MAX_MTU));
+        assert_eq!(candidates.last(), Some(&1280));
+        assert!(candidates.windows(2).all(|pair| pair[0] > pair[1]));
+        assert_eq!(candidate_mtus(1500), vec![MAX_MTU]);
+    }
+
+    #[test]
+    fn test_probe_size() {
+        // A full tunnel packet of the default MTU fits in a 1500 byte IPv6 path
+        assert_eq!(usize::from(IPV6_HEADER_SIZE) + probe_size(MAX_MTU), 1500);
+        assert_eq!(
+            usize::from(IPV4_HEADER_SIZE) + tunnel_probe_size(MAX_MTU),
+            usize::from(MAX_MTU)
+        );
+    }
+
+    #[test]
+    fn test_parse_echo_reply() {
+        let relay = IpAddr::from([10, 0, 0, 1]);
+        let mut reply = vec![0u8; 20];
+        reply[0] = 0x45;
+        let mut icmp = echo_request(relay, 0x1dcd, 3, 64);
+        icmp[0] = ICMP_ECHO_REPLY;
+        reply.extend(icmp);
+
+        assert_eq!(parse_echo_reply(relay, &reply, 0x1dcd), Some(3));
+        assert_eq!(parse_echo_reply(relay, &reply, 0x1dce), None);
+        assert_eq!(parse_echo_reply(relay, &reply[..24], 0x1dcd), None);
+    }
+
+    #[test]
+    fn test_parse_echo_reply_v6() {
+        let relay = IpAddr::from([0xfc00, 0, 0, 0, 0, 0, 0, 1]);
+        let mut reply = echo_request(relay, 0x1dcd, 3, 64);
+        assert_eq!(parse_echo_reply(relay, &reply, 0x1dcd), None);
+        reply[0] = ICMPV6_ECHO_REPLY;
+        assert_eq!(parse_echo_reply(relay, &reply, 0x1dcd), Some(3));
+    }
+}
@@ -0,0 +1,256 @@
+//! Probes that actively check that remote hosts can be reached through the tunnel. Unlike the
+//! pinger, which only generates traffic for the connectivity monitor to observe, these report
+//! whether they were answered.
+
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket},
+    sync::Arc,
+    time::Duration,
+};
+use talpid_types::{net::wireguard::ConnectivityProbe, ErrorExt};
+
+/// How long to wait for a response to a single probe.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+const DNS_PORT: u16 = 53;
+
+/// Check that a remote host answers through the tunnel.
+pub trait Probe: Send + Sync {
+    /// Sends the probe and returns whether it was answered in time. Blocks for at most
+    /// `PROBE_TIMEOUT` per network operation.
+    fn probe(&self) -> bool;
+}
+
+/// Create probes for all entries in `probes` that report results. ICMP probes are handled by the
+/// pinger and are skipped.
+pub fn new_probes(
+    probes: &[ConnectivityProbe],
+    #[cfg(any(target_os = "linux", target_os = "macos"))] interface_name: &str,
+) -> Vec<Arc<dyn Probe>> {
+    probes
+        .iter()
+        .filter_map(|probe| {
+            let target = match probe {
+                ConnectivityProbe::IcmpGateway => return None,
+                ConnectivityProbe::TcpConnect(address) => ProbeTarget::Tcp(*address),
+                ConnectivityProbe::Dns { resolver, hostname } => ProbeTarget::Dns {
+                    resolver: SocketAddr::new(*resolver, DNS_PORT),
+                    hostname: hostname.clone(),
+                },
+                ConnectivityProbe::HttpHead { address, path } => ProbeTarget::HttpHead {
+                    address: *address,
+                    path: path.clone(),
+                },
+            };
+            Some(Arc::new(SocketProbe {
+                target,
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                interface_name: interface_name.to_owned(),
+            }) as Arc<dyn Probe>)
+        })
+        .collect()
+}
+
+enum ProbeTarget {
+    Tcp(SocketAddr),
+    Dns {
+        resolver: SocketAddr,
+        hostname: String,
+    },
+    HttpHead {
+        address: SocketAddr,
+        path: String,
+    },
+}
+
+struct SocketProbe {
+    target: ProbeTarget,
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    interface_name: String,
+}
+
+impl SocketProbe {
+    fn new_socket(&self, address: &SocketAddr, ty: Type, protocol: Protocol) -> io::Result<Socket> {
+        let socket = Socket::new(Domain::for_address(*address), ty, Some(protocol))?;
+
+        #[cfg(target_os = "linux")]
+        socket.bind_device(Some(self.interface_name.as_bytes()))?;
+
+        #[cfg(target_os = "macos")]
+        {
+            let index = nix::net::if_::if_nametoindex(self.interface_name.as_str())
+                .map_err(io::Error::from)?;
+            socket.bind_device_by_index(std::num::NonZeroU32::new(index))?;
+        }
+
+        socket.set_read_timeout(Some(PROBE_TIMEOUT))?;
+        socket.set_write_timeout(Some(PROBE_TIMEOUT))?;
+        Ok(socket)
+    }
+
+    fn connect_tcp(&self, address: &SocketAddr) -> io::Result<TcpStream> {
+        let socket = self.new_socket(address, Type::STREAM, Protocol::TCP)?;
+        socket.connect_timeout(&SockAddr::from(*address), PROBE_TIMEOUT)?;
+        Ok(socket.into())
+    }
+
+    fn probe_tcp(&self, address: &SocketAddr) -> io::Result<bool> {
+        match self.connect_tcp(address) {
+            Ok(_) => Ok(true),
+            // The host responded with a reset
+            Err(error) if error.kind() == io::ErrorKind::ConnectionRefused => Ok(true),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn probe_dns(&self, resolver: &SocketAddr, hostname: &str) -> io::Result<bool> {
+        let id = rand::random();
+        let query = build_dns_query(id, hostname)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid hostname"))?;
+
+        let socket = self.new_socket(resolver, Type::DGRAM, Protocol::UDP)?;
+        let unspecified = match resolver {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        socket.bind(&SockAddr::from(SocketAddr::new(unspecified, 0)))?;
+        let socket: UdpSocket = socket.into();
+        socket.connect(resolver)?;
+        socket.send(&query)?;
+
+        let mut response = [0u8; 512];
+        loop {
+            let len = socket.recv(&mut response)?;
+            // Ignore stray datagrams. Timing out is reported as an error.
+            if is_dns_response(&response[..len], id) {
+                return Ok(true);
+            }
+        }
+    }
+
+    fn probe_http_head(&self, address: &SocketAddr, path: &str) -> io::Result<bool> {
+        let mut stream = self.connect_tcp(address)?;
+        write!(
+            stream,
+            "HEAD {path} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            http_host(address)
+        )?;
+
+        let mut status_line = [0u8; 12];
+        let mut read = 0;
+        while read < status_line.len() {
+            match stream.read(&mut status_line[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        Ok(is_http_response(&status_line[..read]))
+    }
+}
+
+impl Probe for SocketProbe {
+    fn probe(&self) -> bool {
+        let result = match &self.target {
+            ProbeTarget::Tcp(address) => self.probe_tcp(address),
+            ProbeTarget::Dns { resolver, hostname } => self.probe_dns(resolver, hostname),
+            ProbeTarget::HttpHead { address, path } => self.probe_http_head(address, path),
+        };
+        match result {
+            Ok(answered) => answered,
+            Err(error) => {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("Connectivity probe failed")
+                );
+                false
+            }
+        }
+    }
+}
+
+/// Build a recursive query for the A record of `hostname`. Returns `None` if the hostname cannot
+/// be encoded.
+fn build_dns_query(id: u16, hostname: &str) -> Option<Vec<u8>> {
+    let hostname = hostname.strip_suffix('.').unwrap_or(hostname);
+
+    let mut query = Vec::with_capacity(12 + hostname.len() + 6);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Standard query, recursion desired
+    query.extend_from_slice(&[0x01, 0x00]);
+    // One question and no other records
+    query.extend_from_slice(&[0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    for label in hostname.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return None;
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    // Type A, class IN
+    query.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
+    Some(query)
+}
+
+/// Returns whether `response` is a DNS response to the query with the given ID. Error responses
+/// are accepted too, since they show that the resolver was reached.
+fn is_dns_response(response: &[u8], id: u16) -> bool {
+    const QR_BIT: u8 = 0x80;
+    response.len() >= 12 && response[..2] == id.to_be_bytes() && response[2] & QR_BIT != 0
+}
+
+fn is_http_response(status_line: &[u8]) -> bool {
+    status_line.starts_with(b"HTTP/")
+}
+
+fn http_host(address: &SocketAddr) -> String {
+    match address {
+        SocketAddr::V4(address) => address.ip().to_string(),
+        SocketAddr::V6(address) => format!("[{}]", address.ip()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dns_query() {
+        let query = build_dns_query(0x1234, "mullvad.net.").unwrap();
+        let mut expected = vec![];
+        // ID, flags and counts
+        expected.extend_from_slice(&[0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+        // Name
+        expected.extend_from_slice(b"\x07mullvad\x03net\x00");
+        // Type and class
+        expected.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
+        assert_eq!(query, expected);
+    }
+
+    #[test]
+    fn test_dns_query_invalid_hostname() {
+        assert!(build_dns_query(0, "mullvad..net").is_none());
+        assert!(build_dns_query(0, &"a".repeat(64)).is_none());
+    }
+
+    #[test]
+    fn test_dns_response() {
+        let query = build_dns_query(0x1234, "mullvad.net").unwrap();
+        assert!(!is_dns_response(&query, 0x1234));
+
+        let mut response = query.clone();
+        response[2] |= 0x80;
+        assert!(is_dns_response(&response, 0x1234));
+        assert!(!is_dns_response(&response, 0x4321));
+        assert!(!is_dns_response(&response[..11], 0x1234));
+    }
+
+    #[test]
+    fn test_http_response() {
+        assert!(is_http_response(b"HTTP/1.1 301"));
+        assert!(!is_http_response(b"SSH-2.0-Ope"));
+        assert!(!is_http_response(b""));
+    }
+}
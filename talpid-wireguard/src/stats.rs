@@ -1,5 +1,6 @@
 #[cfg(target_os = "linux")]
 use super::wireguard_kernel::wg_message::{DeviceMessage, DeviceNla, PeerNla};
+use std::time::{Duration, SystemTime};
 
 #[derive(err_derive::Error, Debug, PartialEq)]
 pub enum Error {
@@ -21,6 +22,8 @@ pub enum Error {
 pub struct Stats {
     pub tx_bytes: u64,
     pub rx_bytes: u64,
+    /// Time of the last completed handshake, if any.
+    pub last_handshake: Option<SystemTime>,
 }
 
 /// A map from peer pubkeys to peer stats.
@@ -33,6 +36,8 @@ impl Stats {
         let mut peer = None;
         let mut tx_bytes = None;
         let mut rx_bytes = None;
+        let mut handshake_sec = 0;
+        let mut handshake_nsec = 0;
 
         // parts iterates over keys and values
         let parts = config.split('\n').filter_map(|line| {
@@ -51,6 +56,20 @@ impl Stats {
                     peer = Some(buffer);
                     tx_bytes = None;
                     rx_bytes = None;
+                    handshake_sec = 0;
+                    handshake_nsec = 0;
+                }
+                "last_handshake_time_sec" => {
+                    handshake_sec = value
+                        .trim()
+                        .parse()
+                        .map_err(|err| Error::IntParse(value.to_string(), err))?;
+                }
+                "last_handshake_time_nsec" => {
+                    handshake_nsec = value
+                        .trim()
+                        .parse()
+                        .map_err(|err| Error::IntParse(value.to_string(), err))?;
                 }
                 "rx_bytes" => {
                     rx_bytes = Some(
@@ -80,11 +99,14 @@ impl Stats {
                     Self {
                         tx_bytes: tx_bytes_val,
                         rx_bytes: rx_bytes_val,
+                        last_handshake: Self::handshake_time(handshake_sec, handshake_nsec),
                     },
                 );
                 peer = None;
                 tx_bytes = None;
                 rx_bytes = None;
+                handshake_sec = 0;
+                handshake_nsec = 0;
             }
         }
         Ok(map)
@@ -99,18 +121,32 @@ impl Stats {
                 for msg in peers {
                     let mut tx_bytes = 0;
                     let mut rx_bytes = 0;
+                    let mut last_handshake = None;
                     let mut pub_key = None;
 
                     for nla in &msg.0 {
                         match nla {
                             PeerNla::TxBytes(bytes) => tx_bytes = *bytes,
                             PeerNla::RxBytes(bytes) => rx_bytes = *bytes,
+                            PeerNla::LastHandshakeTime(time) => {
+                                last_handshake = Self::handshake_time(
+                                    u64::try_from(time.tv_sec()).unwrap_or(0),
+                                    u32::try_from(time.tv_nsec()).unwrap_or(0),
+                                )
+                            }
                             PeerNla::PublicKey(key) => pub_key = Some(*key),
                             _ => continue,
                         }
                     }
                     if let Some(key) = pub_key {
-                        map.insert(key, Stats { tx_bytes, rx_bytes });
+                        map.insert(
+                            key,
+                            Stats {
+                                tx_bytes,
+                                rx_bytes,
+                                last_handshake,
+                            },
+                        );
                     }
                 }
             }
@@ -118,6 +154,15 @@ impl Stats {
 
         map
     }
+
+    /// Returns the time of a handshake given as seconds and nanoseconds since the Unix epoch.
+    /// Zero means that no handshake has been completed.
+    pub fn handshake_time(secs: u64, nanos: u32) -> Option<SystemTime> {
+        if secs == 0 && nanos == 0 {
+            return None;
+        }
+        Some(SystemTime::UNIX_EPOCH + Duration::new(secs, nanos))
+    }
 }
 
 #[cfg(test)]
@@ -135,6 +180,10 @@ mod test {
         assert_eq!(actual_keys, [pubkey]);
         assert_eq!(stats[&pubkey].rx_bytes, 2396);
         assert_eq!(stats[&pubkey].tx_bytes, 2740);
+        assert_eq!(
+            stats[&pubkey].last_handshake,
+            Some(std::time::UNIX_EPOCH + std::time::Duration::new(1578420649, 369416131))
+        );
     }
 
     #[test]
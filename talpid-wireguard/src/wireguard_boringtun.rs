@@ -0,0 +1,449 @@
+//! Userspace WireGuard implementation based on BoringTun. Packets are moved between the tunnel
+//! device and a UDP socket by tasks on the tokio runtime.
+
+use super::{
+    stats::{Stats, StatsMap},
+    wireguard_go::WgGoTunnel,
+    Config, Tunnel, TunnelError,
+};
+use boringtun::noise::{Tunn, TunnResult};
+use ipnetwork::IpNetwork;
+use parking_lot::Mutex;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    future::Future,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    os::unix::io::{AsRawFd, RawFd},
+    pin::Pin,
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, SystemTime},
+};
+use talpid_tunnel::tun_provider::{Tun, TunProvider};
+use talpid_types::net::wireguard::{PeerConfig, PresharedKey};
+use tokio::{io::unix::AsyncFd, net::UdpSocket, task::JoinHandle};
+
+type Result<T> = std::result::Result<T, TunnelError>;
+
+/// Size of the buffers used for reading and writing packets.
+const MAX_PACKET_SIZE: usize = u16::MAX as usize;
+/// How often the WireGuard timers are updated. This drives handshakes and keepalives.
+const TIMER_INTERVAL: Duration = Duration::from_millis(250);
+
+pub struct BoringTunnel {
+    interface_name: String,
+    peer: Arc<Mutex<Peer>>,
+    tasks: Vec<JoinHandle<()>>,
+    // holding on to the tunnel device ensures that it lives as long as the tunnel
+    _tunnel_device: Tun,
+}
+
+struct Peer {
+    tunn: Box<Tunn>,
+    public_key: [u8; 32],
+    endpoint: SocketAddr,
+    /// Parameters that the WireGuard session was set up with. The session must be discarded if
+    /// any of them change.
+    local_public_key: [u8; 32],
+    psk: Option<PresharedKey>,
+    persistent_keepalive: Option<u16>,
+}
+
+impl Peer {
+    fn new(config: &Config) -> Result<Self> {
+        let peer = Self::peer_config(config)?;
+        let private_key = x25519_dalek::StaticSecret::from(config.tunnel.private_key.to_bytes());
+        let public_key = *peer.public_key.as_bytes();
+        let tunn = Tunn::new(
+            private_key,
+            x25519_dalek::PublicKey::from(public_key),
+            peer.psk.as_ref().map(|psk| *psk.as_bytes()),
+            config.persistent_keepalive,
+            rand::random::<u32>() >> 8,
+            None,
+        )
+        .map_err(TunnelError::BoringtunConfigError)?;
+
+        Ok(Peer {
+            tunn,
+            public_key,
+            endpoint: peer.endpoint,
+            local_public_key: *config.tunnel.private_key.public_key().as_bytes(),
+            psk: peer.psk.clone(),
+            persistent_keepalive: config.persistent_keepalive,
+        })
+    }
+
+    fn peer_config(config: &Config) -> Result<&PeerConfig> {
+        match config.peers.as_slice() {
+            [peer] => Ok(peer),
+            // Multihop requires routing the exit peer's traffic through the entry peer, which
+            // this implementation does not do.
+            [_, _, ..] => Err(TunnelError::BoringtunMultihopUnsupported),
+            [] => Err(TunnelError::BoringtunConfigError("no peer was specified")),
+        }
+    }
+
+    /// Applies `config` to the peer. The current session is only replaced if the keys or the
+    /// keepalive interval changed, so that changing the endpoint does not interrupt the tunnel.
+    fn update(&mut self, config: &Config) -> Result<()> {
+        let peer = Self::peer_config(config)?;
+        // The socket is bound to the address family of the initial endpoint
+        if peer.endpoint.is_ipv4() != self.endpoint.is_ipv4() {
+            return Err(TunnelError::SetConfigError);
+        }
+        if peer.public_key.as_bytes() != &self.public_key
+            || config.tunnel.private_key.public_key().as_bytes() != &self.local_public_key
+            || peer.psk != self.psk
+            || config.persistent_keepalive != self.persistent_keepalive
+        {
+            *self = Peer::new(config)?;
+        } else {
+            self.endpoint = peer.endpoint;
+        }
+        Ok(())
+    }
+}
+
+impl BoringTunnel {
+    pub fn start_tunnel(
+        runtime: &tokio::runtime::Handle,
+        config: &Config,
+        tun_provider: Arc<StdMutex<TunProvider>>,
+        routes: impl Iterator<Item = IpNetwork>,
+    ) -> Result<Self> {
+        let peer = Peer::new(config)?;
+        let (tunnel_device, tunnel_fd) = WgGoTunnel::get_tunnel(tun_provider, config, routes)?;
+        let interface_name = tunnel_device.interface_name().to_string();
+        let tun = TunFd::new(tunnel_fd).map_err(TunnelError::BoringtunSocketError)?;
+
+        let _guard = runtime.enter();
+        let tun = Arc::new(AsyncFd::new(tun).map_err(TunnelError::BoringtunSocketError)?);
+        let socket = Arc::new(
+            open_socket(peer.endpoint, config.fwmark).map_err(TunnelError::BoringtunSocketError)?,
+        );
+        let peer = Arc::new(Mutex::new(peer));
+
+        let tasks = vec![
+            runtime.spawn(tun_to_network(tun.clone(), socket.clone(), peer.clone())),
+            runtime.spawn(network_to_tun(tun, socket.clone(), peer.clone())),
+            runtime.spawn(update_timers(socket, peer.clone())),
+        ];
+
+        Ok(BoringTunnel {
+            interface_name,
+            peer,
+            tasks,
+            _tunnel_device: tunnel_device,
+        })
+    }
+
+    fn stop_tunnel(&mut self) {
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+    }
+}
+
+impl Drop for BoringTunnel {
+    fn drop(&mut self) {
+        self.stop_tunnel();
+    }
+}
+
+impl Tunnel for BoringTunnel {
+    fn get_interface_name(&self) -> String {
+        self.interface_name.clone()
+    }
+
+    fn get_tunnel_stats(&self) -> Result<StatsMap> {
+        let peer = self.peer.lock();
+        let (since_handshake, tx_bytes, rx_bytes, ..) = peer.tunn.stats();
+        let mut map = StatsMap::new();
+        map.insert(
+            peer.public_key,
+            Stats {
+                tx_bytes: tx_bytes as u64,
+                rx_bytes: rx_bytes as u64,
+                last_handshake: since_handshake
+                    .and_then(|elapsed| SystemTime::now().checked_sub(elapsed)),
+            },
+        );
+        Ok(map)
+    }
+
+    fn stop(mut self: Box<Self>) -> Result<()> {
+        self.stop_tunnel();
+        Ok(())
+    }
+
+    fn set_config(
+        &self,
+        config: Config,
+    ) -> Pin<Box<dyn Future<Output = std::result::Result<(), TunnelError>> + Send>> {
+        let peer = self.peer.clone();
+        Box::pin(async move { peer.lock().update(&config) })
+    }
+}
+
+/// Owned, non-blocking file descriptor of the tunnel device.
+struct TunFd(RawFd);
+
+impl TunFd {
+    fn new(fd: RawFd) -> io::Result<Self> {
+        let tun = TunFd(fd);
+        // SAFETY: The descriptor is owned by `tun` and valid.
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(tun)
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        // SAFETY: `buffer` is valid for writes of its length.
+        let n = unsafe { libc::read(self.0, buffer.as_mut_ptr() as *mut _, buffer.len()) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+
+    fn write(&self, packet: &[u8]) -> io::Result<usize> {
+        // SAFETY: `packet` is valid for reads of its length.
+        let n = unsafe { libc::write(self.0, packet.as_ptr() as *const _, packet.len()) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+}
+
+impl AsRawFd for TunFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl Drop for TunFd {
+    fn drop(&mut self) {
+        // SAFETY: The descriptor is owned by `self` and not used after this.
+        unsafe { libc::close(self.0) };
+    }
+}
+
+fn open_socket(endpoint: SocketAddr, fwmark: Option<u32>) -> io::Result<UdpSocket> {
+    let (domain, any_address) = match endpoint {
+        SocketAddr::V4(_) => (Domain::IPV4, IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+        SocketAddr::V6(_) => (Domain::IPV6, IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+    };
+    let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+    // The mark lets the socket's traffic bypass the tunnel
+    if let Some(fwmark) = fwmark {
+        socket.set_mark(fwmark)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::new(any_address, 0).into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+async fn tun_to_network(tun: Arc<AsyncFd<TunFd>>, socket: Arc<UdpSocket>, peer: Arc<Mutex<Peer>>) {
+    let mut packet = vec![0u8; MAX_PACKET_SIZE];
+    let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+    loop {
+        let len = match read_tun(&tun, &mut packet).await {
+            Ok(len) => len,
+            Err(error) => {
+                log::error!("Failed to read from tunnel device: {}", error);
+                return;
+            }
+        };
+
+        let datagram = {
+            let peer = peer.lock();
+            let endpoint = peer.endpoint;
+            match peer.tunn.encapsulate(&packet[..len], &mut buffer) {
+                TunnResult::WriteToNetwork(datagram) => Some((datagram.len(), endpoint)),
+                TunnResult::Err(error) => {
+                    log::trace!("Failed to encapsulate packet: {:?}", error);
+                    None
+                }
+                _ => None,
+            }
+        };
+        if let Some((len, endpoint)) = datagram {
+            send(&socket, &buffer[..len], endpoint).await;
+        }
+    }
+}
+
+async fn network_to_tun(tun: Arc<AsyncFd<TunFd>>, socket: Arc<UdpSocket>, peer: Arc<Mutex<Peer>>) {
+    let mut datagram = vec![0u8; MAX_PACKET_SIZE];
+    let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+    loop {
+        let (len, source) = match socket.recv_from(&mut datagram).await {
+            Ok(result) => result,
+            Err(error) => {
+                log::error!("Failed to receive WireGuard datagram: {}", error);
+                return;
+            }
+        };
+
+        // Handshake responses may cause queued packets to be sent. They are flushed by
+        // decapsulating empty datagrams until there is nothing left to send.
+        let mut input = &datagram[..len];
+        loop {
+            let result = {
+                let peer = peer.lock();
+                if source != peer.endpoint {
+                    break;
+                }
+                match peer.tunn.decapsulate(Some(source.ip()), input, &mut buffer) {
+                    TunnResult::WriteToNetwork(packet) => Some((true, packet.len())),
+                    TunnResult::WriteToTunnelV4(packet, _)
+                    | TunnResult::WriteToTunnelV6(packet, _) => Some((false, packet.len())),
+                    TunnResult::Err(error) => {
+                        log::trace!("Failed to decapsulate datagram: {:?}", error);
+                        None
+                    }
+                    TunnResult::Done => None,
+                }
+            };
+            match result {
+                Some((true, len)) => {
+                    send(&socket, &buffer[..len], source).await;
+                    input = &[];
+                }
+                Some((false, len)) => {
+                    if let Err(error) = write_tun(&tun, &buffer[..len]).await {
+                        log::error!("Failed to write to tunnel device: {}", error);
+                        return;
+                    }
+                    break;
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+async fn update_timers(socket: Arc<UdpSocket>, peer: Arc<Mutex<Peer>>) {
+    let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+    let mut interval = tokio::time::interval(TIMER_INTERVAL);
+    loop {
+        interval.tick().await;
+        let datagram = {
+            let peer = peer.lock();
+            let endpoint = peer.endpoint;
+            match peer.tunn.update_timers(&mut buffer) {
+                TunnResult::WriteToNetwork(datagram) => Some((datagram.len(), endpoint)),
+                TunnResult::Err(error) => {
+                    log::trace!("WireGuard timer error: {:?}", error);
+                    None
+                }
+                _ => None,
+            }
+        };
+        if let Some((len, endpoint)) = datagram {
+            send(&socket, &buffer[..len], endpoint).await;
+        }
+    }
+}
+
+async fn send(socket: &UdpSocket, datagram: &[u8], endpoint: SocketAddr) {
+    if let Err(error) = socket.send_to(datagram, endpoint).await {
+        log::trace!("Failed to send WireGuard datagram: {}", error);
+    }
+}
+
+async fn read_tun(tun: &AsyncFd<TunFd>, buffer: &mut [u8]) -> io::Result<usize> {
+    loop {
+        let mut guard = tun.readable().await?;
+        match guard.try_io(|tun| tun.get_ref().read(buffer)) {
+            Ok(result) => return result,
+            Err(_would_block) => continue,
+        }
+    }
+}
+
+async fn write_tun(tun: &AsyncFd<TunFd>, packet: &[u8]) -> io::Result<usize> {
+    loop {
+        let mut guard = tun.writable().await?;
+        match guard.try_io(|tun| tun.get_ref().write(packet)) {
+            Ok(result) => return result,
+            Err(_would_block) => continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::net::wireguard;
+
+    fn peer_config(endpoint: &str) -> wireguard::PeerConfig {
+        wireguard::PeerConfig {
+            public_key: wireguard::PrivateKey::new_from_random().public_key(),
+            allowed_ips: vec!["0.0.0.0/0".parse().unwrap()],
+            endpoint: endpoint.parse().unwrap(),
+            psk: None,
+        }
+    }
+
+    fn config(peers: Vec<wireguard::PeerConfig>) -> Config {
+        Config {
+            tunnel: wireguard::TunnelConfig {
+                private_key: wireguard::PrivateKey::new_from_random(),
+                addresses: vec!["10.64.0.2".parse().unwrap()],
+            },
+            peers,
+            ipv4_gateway: "10.64.0.1".parse().unwrap(),
+            ipv6_gateway: None,
+            mtu: 1380,
+            mtu_discovery: false,
+            persistent_keepalive: None,
+            fwmark: None,
+            enable_ipv6: false,
+            use_boringtun: true,
+            obfuscator_config: None,
+            psk_rekey_interval: None,
+            connectivity_probes: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_multihop_unsupported() {
+        let config = config(vec![
+            peer_config("1.2.3.4:51820"),
+            peer_config("1.2.3.5:51820"),
+        ]);
+        assert!(matches!(
+            Peer::new(&config),
+            Err(TunnelError::BoringtunMultihopUnsupported)
+        ));
+    }
+
+    #[test]
+    fn test_update_keeps_session() {
+        let mut config = config(vec![peer_config("1.2.3.4:51820")]);
+        let mut peer = Peer::new(&config).unwrap();
+        let tunn: *const Tunn = &*peer.tunn;
+
+        config.peers[0].endpoint = "1.2.3.5:51820".parse().unwrap();
+        peer.update(&config).unwrap();
+        assert_eq!(peer.endpoint, config.peers[0].endpoint);
+        assert!(std::ptr::eq(tunn, &*peer.tunn));
+
+        // A new PSK requires a new handshake. The new session is created before the old one is
+        // dropped, so the two cannot share an address.
+        config.peers[0].psk = Some(wireguard::PresharedKey::from([1u8; 32]));
+        peer.update(&config).unwrap();
+        assert!(!std::ptr::eq(tunn, &*peer.tunn));
+
+        config.peers[0].endpoint = "[::1]:51820".parse().unwrap();
+        assert!(matches!(
+            peer.update(&config),
+            Err(TunnelError::SetConfigError)
+        ));
+    }
+}
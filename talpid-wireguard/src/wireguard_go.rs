@@ -292,7 +292,7 @@ impl WgGoTunnel {
     }
 
     #[cfg(not(target_os = "windows"))]
-    fn get_tunnel(
+    pub(crate) fn get_tunnel(
         tun_provider: Arc<Mutex<TunProvider>>,
         config: &Config,
         routes: impl Iterator<Item = IpNetwork>,
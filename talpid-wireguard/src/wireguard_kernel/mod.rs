@@ -85,6 +85,17 @@ pub enum Error {
 }
 
 pub(crate) const MULLVAD_INTERFACE_NAME: &str = "wg-mullvad";
+/// Used instead of [`MULLVAD_INTERFACE_NAME`] by a tunnel that replaces a tunnel using that name.
+const MULLVAD_REPLACEMENT_INTERFACE_NAME: &str = "wg1-mullvad";
+
+/// Returns an interface name that differs from that of the tunnel being replaced, if any.
+pub(crate) fn interface_name(replaces_interface: Option<&str>) -> &'static str {
+    if replaces_interface == Some(MULLVAD_INTERFACE_NAME) {
+        MULLVAD_REPLACEMENT_INTERFACE_NAME
+    } else {
+        MULLVAD_INTERFACE_NAME
+    }
+}
 
 #[derive(Debug)]
 pub struct Handle {
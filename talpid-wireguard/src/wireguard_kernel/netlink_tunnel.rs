@@ -5,25 +5,31 @@ use futures::Future;
 use super::{
     super::stats::{Stats, StatsMap},
     wg_message::DeviceNla,
-    Config, Error, Handle, Tunnel, TunnelError, MULLVAD_INTERFACE_NAME,
+    Config, Error, Handle, Tunnel, TunnelError,
 };
 
 pub struct NetlinkTunnel {
     interface_index: u32,
+    interface_name: &'static str,
     netlink_connections: Handle,
     tokio_handle: tokio::runtime::Handle,
 }
 
 impl NetlinkTunnel {
-    pub fn new(tokio_handle: tokio::runtime::Handle, config: &Config) -> Result<Self, Error> {
+    pub fn new(
+        tokio_handle: tokio::runtime::Handle,
+        config: &Config,
+        interface_name: &'static str,
+    ) -> Result<Self, Error> {
         tokio_handle.clone().block_on(async {
             let mut netlink_connections = Handle::connect().await?;
             let interface_index = netlink_connections
-                .create_device(MULLVAD_INTERFACE_NAME.to_string(), config.mtu as u32)
+                .create_device(interface_name.to_string(), config.mtu as u32)
                 .await?;
 
             let mut tunnel = Self {
                 interface_index,
+                interface_name,
                 netlink_connections,
                 tokio_handle,
             };
@@ -79,7 +85,7 @@ impl Tunnel for NetlinkTunnel {
             Ok(name) => name.to_string_lossy().to_string(),
             Err(err) => {
                 log::error!("Failed to deduce interface name at runtime, will attempt to use the default name. {}", err);
-                MULLVAD_INTERFACE_NAME.to_string()
+                self.interface_name.to_string()
             }
         }
     }
@@ -89,6 +95,7 @@ impl Tunnel for NetlinkTunnel {
             mut netlink_connections,
             interface_index,
             tokio_handle,
+            ..
         } = *self;
         tokio_handle.block_on(async move {
             if let Err(err) = netlink_connections.delete_device(interface_index).await {
@@ -90,6 +90,9 @@ impl DeviceMessage {
             if let Some(psk) = peer.psk.as_ref() {
                 peer_nlas.push(PeerNla::PresharedKey(*psk.as_bytes()));
             }
+            if let Some(interval) = config.persistent_keepalive {
+                peer_nlas.push(PeerNla::PersistentKeepaliveInterval(interval));
+            }
             peers.push(PeerMessage(peer_nlas));
         }
 
@@ -904,4 +907,84 @@ mod test {
         assert_eq!(message, deserialized_device);
         assert_eq!(payload_buffer, expected_payload);
     }
+
+    #[test]
+    fn reset_config_persistent_keepalive() {
+        // 6 bytes of WGPEER_A_PERSISTENT_KEEPALIVE_INTERVAL 25 + 2 bytes of padding
+        let keepalive_nla: &[u8] = &[0x06, 0x00, 0x05, 0x00, 0x19, 0x00, 0x00, 0x00];
+
+        let message = DeviceMessage::reset_config(0, 1, &keepalive_config(Some(25)));
+        for peer in peer_messages(&message) {
+            assert!(peer.0.contains(&PeerNla::PersistentKeepaliveInterval(25)));
+        }
+
+        let mut payload_buffer = vec![0u8; message.buffer_len()];
+        message.serialize(&mut payload_buffer);
+        assert_eq!(
+            payload_buffer
+                .windows(keepalive_nla.len())
+                .filter(|window| *window == keepalive_nla)
+                .count(),
+            2
+        );
+        let header = NetlinkHeader {
+            length: payload_buffer.len() as u32,
+            message_type: 0,
+            flags: 0,
+            sequence_number: 0,
+            port_number: 0,
+        };
+        assert_eq!(
+            DeviceMessage::deserialize(&header, &payload_buffer).unwrap(),
+            message
+        );
+
+        let message = DeviceMessage::reset_config(0, 1, &keepalive_config(None));
+        for peer in peer_messages(&message) {
+            assert!(!peer
+                .0
+                .iter()
+                .any(|nla| matches!(nla, PeerNla::PersistentKeepaliveInterval(_))));
+        }
+    }
+
+    fn peer_messages(message: &DeviceMessage) -> &[PeerMessage] {
+        message
+            .nlas
+            .iter()
+            .find_map(|nla| match nla {
+                DeviceNla::Peers(peers) => Some(peers.as_slice()),
+                _ => None,
+            })
+            .expect("no peers in message")
+    }
+
+    fn keepalive_config(persistent_keepalive: Option<u16>) -> Config {
+        use talpid_types::net::wireguard::{PeerConfig, PrivateKey, TunnelConfig};
+
+        let peer = |endpoint: &str| PeerConfig {
+            public_key: PrivateKey::new_from_random().public_key(),
+            allowed_ips: vec!["0.0.0.0/0".parse().unwrap()],
+            endpoint: endpoint.parse().unwrap(),
+            psk: None,
+        };
+        Config {
+            tunnel: TunnelConfig {
+                private_key: PrivateKey::new_from_random(),
+                addresses: vec!["10.64.0.2".parse().unwrap()],
+            },
+            peers: vec![peer("192.168.40.1:9797"), peer("192.168.40.2:9797")],
+            ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+            ipv6_gateway: None,
+            mtu: 1380,
+            mtu_discovery: false,
+            persistent_keepalive,
+            fwmark: None,
+            enable_ipv6: false,
+            use_boringtun: false,
+            obfuscator_config: None,
+            psk_rekey_interval: None,
+            connectivity_probes: Default::default(),
+        }
+    }
 }
@@ -173,6 +173,9 @@ pub struct WgNtTunnel {
 
 const WIREGUARD_KEY_LENGTH: usize = 32;
 
+/// Number of 100-nanosecond intervals between 1601-01-01 and the Unix epoch.
+const FILETIME_UNIX_EPOCH_OFFSET: u64 = 116_444_736_000_000_000;
+
 /// See `WIREGUARD_ALLOWED_IP` at https://git.zx2c4.com/wireguard-nt/tree/api/wireguard.h.
 #[derive(Clone, Copy)]
 #[repr(C, align(8))]
@@ -848,11 +851,14 @@ fn serialize_config(config: &Config) -> Result<Vec<MaybeUninit<u8>>> {
     buffer.extend(as_uninit_byte_slice(&header));
 
     for peer in &config.peers {
-        let flags = if peer.psk.is_some() {
+        let mut flags = if peer.psk.is_some() {
             WgPeerFlag::HAS_PRESHARED_KEY | WgPeerFlag::HAS_PUBLIC_KEY | WgPeerFlag::HAS_ENDPOINT
         } else {
             WgPeerFlag::HAS_PUBLIC_KEY | WgPeerFlag::HAS_ENDPOINT
         };
+        if config.persistent_keepalive.is_some() {
+            flags |= WgPeerFlag::HAS_PERSISTENT_KEEPALIVE;
+        }
         let wg_peer = WgPeer {
             flags,
             reserved: 0,
@@ -862,7 +868,7 @@ fn serialize_config(config: &Config) -> Result<Vec<MaybeUninit<u8>>> {
                 .as_ref()
                 .map(|psk| psk.as_bytes().clone())
                 .unwrap_or([0u8; WIREGUARD_KEY_LENGTH]),
-            persistent_keepalive: 0,
+            persistent_keepalive: config.persistent_keepalive.unwrap_or(0),
             endpoint: net::inet_sockaddr_from_socketaddr(peer.endpoint).into(),
             tx_bytes: 0,
             rx_bytes: 0,
@@ -972,6 +978,7 @@ impl Tunnel for WgNtTunnel {
                     Stats {
                         tx_bytes: peer.tx_bytes,
                         rx_bytes: peer.rx_bytes,
+                        last_handshake: filetime_to_system_time(peer.last_handshake),
                     },
                 );
             }
@@ -1007,6 +1014,13 @@ impl Tunnel for WgNtTunnel {
     }
 }
 
+/// Converts a timestamp given in 100-nanosecond intervals since 1601-01-01 to a `SystemTime`.
+/// Zero means that no handshake has been completed.
+fn filetime_to_system_time(filetime: u64) -> Option<std::time::SystemTime> {
+    let intervals = filetime.checked_sub(FILETIME_UNIX_EPOCH_OFFSET)?;
+    Some(std::time::UNIX_EPOCH + std::time::Duration::from_nanos(intervals.saturating_mul(100)))
+}
+
 pub fn as_uninit_byte_slice<T: Copy + Sized>(value: &T) -> &[mem::MaybeUninit<u8>] {
     unsafe { std::slice::from_raw_parts(value as *const _ as *const _, mem::size_of::<T>()) }
 }
@@ -1044,8 +1058,11 @@ mod tests {
                 ipv4_gateway: "0.0.0.0".parse().unwrap(),
                 ipv6_gateway: None,
                 mtu: 0,
+                persistent_keepalive: None,
                 use_wireguard_nt: true,
                 obfuscator_config: None,
+                psk_rekey_interval: None,
+                connectivity_probes: Default::default(),
             }
         };
         static ref WG_STRUCT_CONFIG: Interface = Interface {
@@ -1,7 +1,18 @@
 use async_trait::async_trait;
-use std::net::SocketAddr;
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Mutex,
+};
+use tokio::net::UdpSocket;
 
+mod shadowsocks;
+mod socks5;
+mod tls;
 mod udp2tcp;
+pub use self::shadowsocks::ShadowsocksSettings;
+pub use socks5::{Socks5Auth, Socks5Settings};
+pub use tls::TlsSettings;
 pub use udp2tcp::Udp2TcpSettings;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -14,6 +25,24 @@ pub enum Error {
 
     #[error(display = "Failed to run Udp2Tcp obfuscator")]
     RunUdp2TcpObfuscator(#[error(source)] udp2tcp::Error),
+
+    #[error(display = "Failed to create Shadowsocks obfuscator")]
+    CreateShadowsocksObfuscator(#[error(source)] self::shadowsocks::Error),
+
+    #[error(display = "Failed to run Shadowsocks obfuscator")]
+    RunShadowsocksObfuscator(#[error(source)] self::shadowsocks::Error),
+
+    #[error(display = "Failed to create TLS obfuscator")]
+    CreateTlsObfuscator(#[error(source)] tls::Error),
+
+    #[error(display = "Failed to run TLS obfuscator")]
+    RunTlsObfuscator(#[error(source)] tls::Error),
+
+    #[error(display = "Failed to create SOCKS5 obfuscator")]
+    CreateSocks5Obfuscator(#[error(source)] socks5::Error),
+
+    #[error(display = "Failed to run SOCKS5 obfuscator")]
+    RunSocks5Obfuscator(#[error(source)] socks5::Error),
 }
 
 #[async_trait]
@@ -28,8 +57,44 @@ pub trait Obfuscator: Send {
     fn remote_socket_fd(&self) -> std::os::unix::io::RawFd;
 }
 
+/// Returns a random port on the loopback interface of the same IP version as `peer`.
+fn default_listen_addr(peer: &SocketAddr) -> SocketAddr {
+    if peer.is_ipv4() {
+        SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)
+    } else {
+        SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 0)
+    }
+}
+
+/// Locks `local_socket` to the sender of the first datagram received on it, i.e. WireGuard, by
+/// connecting the socket to it. Other local processes can then neither use the obfuscator nor
+/// redirect the traffic coming back from the server. Returns whether a datagram from `sender`
+/// should be forwarded.
+async fn lock_client(
+    local_socket: &UdpSocket,
+    client_addr: &Mutex<Option<SocketAddr>>,
+    sender: SocketAddr,
+) -> io::Result<bool> {
+    let locked_addr = *client_addr.lock().unwrap();
+    match locked_addr {
+        Some(locked_addr) => {
+            // Datagrams from other senders can still be queued from before the socket was
+            // connected
+            Ok(locked_addr == sender)
+        }
+        None => {
+            local_socket.connect(sender).await?;
+            *client_addr.lock().unwrap() = Some(sender);
+            Ok(true)
+        }
+    }
+}
+
 pub enum Settings {
     Udp2Tcp(Udp2TcpSettings),
+    Shadowsocks(ShadowsocksSettings),
+    Tls(TlsSettings),
+    Socks5(Socks5Settings),
 }
 
 pub async fn create_obfuscator(settings: &Settings) -> Result<Box<dyn Obfuscator>> {
@@ -37,5 +102,53 @@ pub async fn create_obfuscator(settings: &Settings) -> Result<Box<dyn Obfuscator
         Settings::Udp2Tcp(s) => udp2tcp::create_obfuscator(s)
             .await
             .map_err(Error::CreateUdp2TcpObfuscator),
+        Settings::Shadowsocks(s) => self::shadowsocks::create_obfuscator(s)
+            .await
+            .map_err(Error::CreateShadowsocksObfuscator),
+        Settings::Tls(s) => tls::create_obfuscator(s)
+            .await
+            .map_err(Error::CreateTlsObfuscator),
+        Settings::Socks5(s) => socks5::create_obfuscator(s)
+            .await
+            .map_err(Error::CreateSocks5Obfuscator),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_lock_client() {
+        let local_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let local_addr = local_socket.local_addr().unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let other = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client_addr = Mutex::new(None);
+        let mut buffer = [0u8; 16];
+
+        client.send_to(b"first", local_addr).await.unwrap();
+        let (_, sender) = local_socket.recv_from(&mut buffer).await.unwrap();
+        assert!(lock_client(&local_socket, &client_addr, sender)
+            .await
+            .unwrap());
+        assert_eq!(
+            *client_addr.lock().unwrap(),
+            Some(client.local_addr().unwrap())
+        );
+
+        // Other senders are ignored once the first one is known
+        other.send_to(b"other", local_addr).await.unwrap();
+        client.send_to(b"second", local_addr).await.unwrap();
+        let (len, sender) = local_socket.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..len], b"second");
+        assert!(lock_client(&local_socket, &client_addr, sender)
+            .await
+            .unwrap());
+        assert!(
+            !lock_client(&local_socket, &client_addr, other.local_addr().unwrap())
+                .await
+                .unwrap()
+        );
     }
 }
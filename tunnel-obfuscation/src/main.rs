@@ -1,36 +1,218 @@
-use std::{env::args, net::SocketAddr};
-use tunnel_obfuscation::{create_obfuscator, Obfuscator, Settings, Udp2TcpSettings};
+//! Runs an obfuscator outside of the daemon. WireGuard clients are pointed at the local address
+//! that is printed on startup, and their traffic is forwarded to the remote endpoint.
+
+use clap::{crate_authors, crate_description, crate_name, App, Arg, ArgMatches};
+use std::{error::Error, net::SocketAddr, process};
+use tunnel_obfuscation::{
+    create_obfuscator, Settings, ShadowsocksSettings, Socks5Auth, Socks5Settings, TlsSettings,
+    Udp2TcpSettings,
+};
+
+const DEFAULT_SHADOWSOCKS_CIPHER: &str = "aes-256-gcm";
+const DEFAULT_TLS_SERVER_NAME: &str = "www.cloudflare.com";
 
 #[tokio::main]
 async fn main() {
-    if args().len() != 2 {
-        println!("Missing arguments");
+    let remote_arg = Arg::new("remote")
+        .help("Address of the remote obfuscation server")
+        .long("remote")
+        .takes_value(true)
+        .required(true)
+        .validator(|value| value.parse::<SocketAddr>());
+
+    let subcommands = vec![
+        App::new("udp2tcp")
+            .about("Send the datagrams over TCP")
+            .arg(remote_arg.clone()),
+        App::new("shadowsocks")
+            .about("Relay the datagrams through a Shadowsocks server")
+            .arg(remote_arg.clone())
+            .arg(
+                Arg::new("forward")
+                    .help("Address of the WireGuard server that the Shadowsocks server relays to")
+                    .long("forward")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(|value| value.parse::<SocketAddr>()),
+            )
+            .arg(
+                Arg::new("password")
+                    .help("Shadowsocks password")
+                    .long("password")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("cipher")
+                    .help("Shadowsocks cipher")
+                    .long("cipher")
+                    .takes_value(true)
+                    .default_value(DEFAULT_SHADOWSOCKS_CIPHER),
+            ),
+        App::new("tls")
+            .about("Send the datagrams over TLS")
+            .arg(remote_arg.clone())
+            .arg(
+                Arg::new("server-name")
+                    .help("Server name to send in the TLS handshake")
+                    .long("server-name")
+                    .takes_value(true)
+                    .default_value(DEFAULT_TLS_SERVER_NAME),
+            ),
+        App::new("socks5")
+            .about("Relay the datagrams through a SOCKS5 proxy using UDP ASSOCIATE")
+            .arg(remote_arg)
+            .arg(
+                Arg::new("forward")
+                    .help("Address of the WireGuard server that the proxy relays to")
+                    .long("forward")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(|value| value.parse::<SocketAddr>()),
+            )
+            .arg(
+                Arg::new("username")
+                    .help("Username for proxies that require authentication")
+                    .long("username")
+                    .takes_value(true)
+                    .requires("password"),
+            )
+            .arg(
+                Arg::new("password")
+                    .help("Password for proxies that require authentication")
+                    .long("password")
+                    .takes_value(true)
+                    .requires("username"),
+            ),
+    ];
+
+    let app = App::new(crate_name!())
+        .author(crate_authors!())
+        .about(crate_description!())
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .global_setting(clap::AppSettings::DisableHelpSubcommand)
+        .global_setting(clap::AppSettings::DisableVersionFlag)
+        .arg(
+            Arg::new("bind")
+                .help(
+                    "Local address to receive WireGuard traffic on. Must be a loopback address. \
+                    Defaults to a random port on localhost",
+                )
+                .long("bind")
+                .takes_value(true)
+                .global(true)
+                .validator(parse_bind_addr),
+        );
+    #[cfg(target_os = "linux")]
+    let app = app.arg(
+        Arg::new("fwmark")
+            .help("Firewall mark to set on the sockets connecting to the remote server")
+            .long("fwmark")
+            .takes_value(true)
+            .global(true)
+            .validator(|value| value.parse::<u32>()),
+    );
+    let matches = app.subcommands(subcommands).get_matches();
+
+    let settings = match matches.subcommand() {
+        Some(("udp2tcp", sub_matches)) => Settings::Udp2Tcp(Udp2TcpSettings {
+            peer: sub_matches.value_of_t_or_exit("remote"),
+            listen_addr: listen_addr(sub_matches),
+            #[cfg(target_os = "linux")]
+            fwmark: fwmark(sub_matches),
+        }),
+        Some(("shadowsocks", sub_matches)) => Settings::Shadowsocks(ShadowsocksSettings {
+            shadowsocks_endpoint: sub_matches.value_of_t_or_exit("remote"),
+            wireguard_endpoint: sub_matches.value_of_t_or_exit("forward"),
+            listen_addr: listen_addr(sub_matches),
+            password: sub_matches.value_of("password").unwrap().to_owned(),
+            cipher: sub_matches.value_of("cipher").unwrap().to_owned(),
+            #[cfg(target_os = "linux")]
+            fwmark: fwmark(sub_matches),
+        }),
+        Some(("tls", sub_matches)) => Settings::Tls(TlsSettings {
+            peer: sub_matches.value_of_t_or_exit("remote"),
+            listen_addr: listen_addr(sub_matches),
+            server_name: sub_matches.value_of("server-name").unwrap().to_owned(),
+            #[cfg(target_os = "linux")]
+            fwmark: fwmark(sub_matches),
+        }),
+        Some(("socks5", sub_matches)) => Settings::Socks5(Socks5Settings {
+            proxy: sub_matches.value_of_t_or_exit("remote"),
+            auth: sub_matches.value_of("username").map(|username| Socks5Auth {
+                username: username.to_owned(),
+                password: sub_matches.value_of("password").unwrap().to_owned(),
+            }),
+            wireguard_endpoint: sub_matches.value_of_t_or_exit("forward"),
+            listen_addr: listen_addr(sub_matches),
+            #[cfg(target_os = "linux")]
+            fwmark: fwmark(sub_matches),
+        }),
+        _ => unreachable!("No command matched"),
+    };
+
+    let obfuscator = match create_obfuscator(&settings).await {
+        Ok(obfuscator) => obfuscator,
+        Err(error) => exit_with_error(&error),
+    };
+    println!("Listening on {}", obfuscator.endpoint());
+
+    if let Err(error) = obfuscator.run().await {
+        exit_with_error(&error);
     }
+}
 
-    let obfuscator = instantiate_requested(&args().last().unwrap()).await;
+fn listen_addr(matches: &ArgMatches) -> Option<SocketAddr> {
+    matches
+        .is_present("bind")
+        .then(|| matches.value_of_t_or_exit("bind"))
+}
 
-    println!("endpoint() returns {:?}", obfuscator.endpoint());
+/// Parses the local address to bind to. The obfuscators send the return traffic to whoever sent
+/// the latest datagram, so any host that can reach the local socket could take over the tunnel.
+fn parse_bind_addr(value: &str) -> Result<SocketAddr, String> {
+    let addr = value
+        .parse::<SocketAddr>()
+        .map_err(|error| error.to_string())?;
+    if !addr.ip().is_loopback() {
+        return Err(format!("{addr} is not a loopback address"));
+    }
+    Ok(addr)
+}
 
-    if let Err(err) = obfuscator.run().await {
-        println!("obfuscator.run() failed: {err:?}");
+#[cfg(target_os = "linux")]
+fn fwmark(matches: &ArgMatches) -> Option<u32> {
+    matches
+        .is_present("fwmark")
+        .then(|| matches.value_of_t_or_exit("fwmark"))
+}
+
+fn exit_with_error(error: &dyn Error) -> ! {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message.push_str(&format!("\nCaused by: {error}"));
+        source = error.source();
     }
+    eprintln!("{message}");
+    process::exit(1);
 }
 
-async fn instantiate_requested(obfuscator_type: &str) -> Box<dyn Obfuscator> {
-    match obfuscator_type {
-        "udp2tcp" => {
-            let settings = Udp2TcpSettings {
-                peer: SocketAddr::new("127.0.0.1".parse().unwrap(), 3030),
-                #[cfg(target_os = "linux")]
-                fwmark: Some(1337),
-            };
-
-            create_obfuscator(&Settings::Udp2Tcp(settings))
-                .await
-                .expect("Creating obfuscator failed")
-        }
-        _ => {
-            unimplemented!()
-        }
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_bind_addr() {
+        assert_eq!(
+            parse_bind_addr("127.0.0.1:51820"),
+            Ok("127.0.0.1:51820".parse().unwrap())
+        );
+        assert_eq!(parse_bind_addr("[::1]:0"), Ok("[::1]:0".parse().unwrap()));
+
+        assert!(parse_bind_addr("0.0.0.0:51820").is_err());
+        assert!(parse_bind_addr("[::]:51820").is_err());
+        assert!(parse_bind_addr("192.168.1.2:51820").is_err());
+        assert!(parse_bind_addr("localhost:51820").is_err());
     }
 }
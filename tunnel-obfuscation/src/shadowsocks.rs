@@ -0,0 +1,190 @@
+use crate::Obfuscator;
+use async_trait::async_trait;
+use shadowsocks::{
+    config::ServerType,
+    context::Context,
+    crypto::v1::CipherKind,
+    net::ConnectOpts,
+    relay::{socks5::Address, udprelay::ProxySocket},
+    ServerConfig,
+};
+use std::{io, net::SocketAddr, str::FromStr, sync::Mutex};
+use tokio::net::UdpSocket;
+
+/// Large enough to hold any UDP datagram.
+const MAX_DATAGRAM_SIZE: usize = u16::MAX as usize;
+
+pub struct ShadowsocksSettings {
+    /// Shadowsocks server to send the traffic through.
+    pub shadowsocks_endpoint: SocketAddr,
+    /// Address that the Shadowsocks server relays the traffic to.
+    pub wireguard_endpoint: SocketAddr,
+    /// Local address to receive WireGuard traffic on. Defaults to a random port on localhost.
+    pub listen_addr: Option<SocketAddr>,
+    pub password: String,
+    pub cipher: String,
+    #[cfg(target_os = "linux")]
+    pub fwmark: Option<u32>,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// The cipher is not supported
+    #[error(display = "Unsupported Shadowsocks cipher: {}", _0)]
+    InvalidCipher(String),
+
+    /// Failed to bind the local UDP socket
+    #[error(display = "Failed to bind local UDP socket")]
+    BindUdp(#[error(source)] io::Error),
+
+    /// Failed to determine UDP socket details
+    #[error(display = "Failed to determine UDP socket details")]
+    GetUdpSocketDetails(#[error(source)] io::Error),
+
+    /// Failed to set up the socket used to talk to the Shadowsocks server
+    #[error(display = "Failed to connect to Shadowsocks server")]
+    ConnectShadowsocks(#[error(source)] io::Error),
+
+    /// Failed to receive datagram from WireGuard
+    #[error(display = "Failed to receive datagram from local socket")]
+    RecvLocal(#[error(source)] io::Error),
+
+    /// Failed to connect the local UDP socket to WireGuard
+    #[error(display = "Failed to connect local socket to WireGuard")]
+    ConnectLocal(#[error(source)] io::Error),
+
+    /// Failed to send datagram to WireGuard
+    #[error(display = "Failed to send datagram to local socket")]
+    SendLocal(#[error(source)] io::Error),
+
+    /// Failed to send datagram to the Shadowsocks server
+    #[error(display = "Failed to send datagram to Shadowsocks server")]
+    SendShadowsocks(#[error(source)] io::Error),
+}
+
+struct Shadowsocks {
+    local_socket: UdpSocket,
+    local_addr: SocketAddr,
+    proxy_socket: ProxySocket,
+    wireguard_endpoint: Address,
+    /// Address of the WireGuard socket. Learned from the first datagram sent to `local_socket`,
+    /// which is then connected to it.
+    client_addr: Mutex<Option<SocketAddr>>,
+}
+
+impl Shadowsocks {
+    pub async fn new(settings: &ShadowsocksSettings) -> Result<Self> {
+        let cipher = CipherKind::from_str(&settings.cipher)
+            .map_err(|_| Error::InvalidCipher(settings.cipher.clone()))?;
+
+        let listen_addr = settings
+            .listen_addr
+            .unwrap_or_else(|| crate::default_listen_addr(&settings.wireguard_endpoint));
+        let local_socket = UdpSocket::bind(listen_addr).await.map_err(Error::BindUdp)?;
+        let local_addr = local_socket
+            .local_addr()
+            .map_err(Error::GetUdpSocketDetails)?;
+
+        let server_config = ServerConfig::new(
+            settings.shadowsocks_endpoint,
+            settings.password.clone(),
+            cipher,
+        );
+        #[allow(unused_mut)]
+        let mut connect_opts = ConnectOpts::default();
+        #[cfg(target_os = "linux")]
+        {
+            connect_opts.fwmark = settings.fwmark;
+        }
+        let proxy_socket = ProxySocket::connect_with_opts(
+            Context::new_shared(ServerType::Local),
+            &server_config,
+            &connect_opts,
+        )
+        .await
+        .map_err(Error::ConnectShadowsocks)?;
+
+        Ok(Self {
+            local_socket,
+            local_addr,
+            proxy_socket,
+            wireguard_endpoint: Address::SocketAddress(settings.wireguard_endpoint),
+            client_addr: Mutex::new(None),
+        })
+    }
+
+    async fn forward_to_server(&self) -> Result<()> {
+        let mut buffer = vec![0u8; MAX_DATAGRAM_SIZE];
+        loop {
+            let (len, client_addr) = self
+                .local_socket
+                .recv_from(&mut buffer)
+                .await
+                .map_err(Error::RecvLocal)?;
+            if !crate::lock_client(&self.local_socket, &self.client_addr, client_addr)
+                .await
+                .map_err(Error::ConnectLocal)?
+            {
+                continue;
+            }
+            self.proxy_socket
+                .send(&self.wireguard_endpoint, &buffer[..len])
+                .await
+                .map_err(Error::SendShadowsocks)?;
+        }
+    }
+
+    async fn forward_to_client(&self) -> Result<()> {
+        let mut buffer = vec![0u8; MAX_DATAGRAM_SIZE];
+        loop {
+            // A single bad datagram, e.g. one that fails to decrypt, must not end the obfuscator
+            let len = match self.proxy_socket.recv(&mut buffer).await {
+                Ok((len, _addr, _)) => len,
+                Err(error) => {
+                    log::warn!(
+                        "Failed to receive datagram from Shadowsocks server: {}",
+                        error
+                    );
+                    continue;
+                }
+            };
+            let client_known = self.client_addr.lock().unwrap().is_some();
+            // Nothing can be received before WireGuard has sent something, so this only drops
+            // unsolicited datagrams.
+            if client_known {
+                self.local_socket
+                    .send(&buffer[..len])
+                    .await
+                    .map_err(Error::SendLocal)?;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Obfuscator for Shadowsocks {
+    fn endpoint(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    async fn run(self: Box<Self>) -> crate::Result<()> {
+        let result = tokio::select! {
+            result = self.forward_to_server() => result,
+            result = self.forward_to_client() => result,
+        };
+        result.map_err(crate::Error::RunShadowsocksObfuscator)
+    }
+
+    #[cfg(target_os = "android")]
+    fn remote_socket_fd(&self) -> std::os::unix::io::RawFd {
+        use std::os::unix::io::AsRawFd;
+        self.proxy_socket.as_raw_fd()
+    }
+}
+
+pub async fn create_obfuscator(settings: &ShadowsocksSettings) -> Result<Box<dyn Obfuscator>> {
+    Ok(Box::new(Shadowsocks::new(settings).await?))
+}
@@ -0,0 +1,652 @@
+//! Relays WireGuard datagrams through a SOCKS5 proxy, using the UDP ASSOCIATE command from
+//! RFC 1928. The TCP connection to the proxy only carries the handshake, but it has to be kept
+//! open for as long as the association is used.
+
+use crate::Obfuscator;
+use async_trait::async_trait;
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Mutex,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpSocket, TcpStream, UdpSocket},
+};
+
+/// Large enough to hold any UDP datagram.
+const MAX_DATAGRAM_SIZE: usize = u16::MAX as usize;
+
+const SOCKS_VERSION: u8 = 5;
+const AUTH_VERSION: u8 = 1;
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+const CMD_UDP_ASSOCIATE: u8 = 0x03;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+pub struct Socks5Settings {
+    /// SOCKS5 proxy to relay the traffic through.
+    pub proxy: SocketAddr,
+    /// Credentials for proxies that require username and password authentication.
+    pub auth: Option<Socks5Auth>,
+    /// Address that the proxy relays the traffic to.
+    pub wireguard_endpoint: SocketAddr,
+    /// Local address to receive WireGuard traffic on. Defaults to a random port on localhost.
+    pub listen_addr: Option<SocketAddr>,
+    #[cfg(target_os = "linux")]
+    pub fwmark: Option<u32>,
+}
+
+pub struct Socks5Auth {
+    pub username: String,
+    pub password: String,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to bind the local UDP socket
+    #[error(display = "Failed to bind local UDP socket")]
+    BindUdp(#[error(source)] io::Error),
+
+    /// Failed to determine UDP socket details
+    #[error(display = "Failed to determine UDP socket details")]
+    GetUdpSocketDetails(#[error(source)] io::Error),
+
+    /// Failed to create or configure a socket used to talk to the proxy
+    #[error(display = "Failed to create proxy socket")]
+    CreateProxySocket(#[error(source)] io::Error),
+
+    /// Failed to connect to the proxy
+    #[error(display = "Failed to connect to SOCKS5 proxy")]
+    ConnectProxy(#[error(source)] io::Error),
+
+    /// Failed to send or receive a handshake message
+    #[error(display = "SOCKS5 handshake failed")]
+    Handshake(#[error(source)] io::Error),
+
+    /// The proxy replied with something that is not SOCKS5
+    #[error(display = "Invalid reply from SOCKS5 proxy")]
+    InvalidReply,
+
+    /// None of the offered authentication methods were accepted
+    #[error(display = "The SOCKS5 proxy requires an unsupported authentication method")]
+    UnsupportedAuthMethod,
+
+    /// Username and password must fit in 255 bytes each
+    #[error(display = "SOCKS5 username or password is too long")]
+    CredentialsTooLong,
+
+    /// The proxy rejected the username and password
+    #[error(display = "SOCKS5 authentication failed")]
+    AuthFailed,
+
+    /// The proxy refused to set up the UDP association
+    #[error(display = "SOCKS5 proxy refused UDP association: {}", _0)]
+    AssociateRejected(&'static str),
+
+    /// Only UDP relays on the proxy host are allowed through the firewall
+    #[error(display = "SOCKS5 proxy relays UDP from another host: {}", _0)]
+    UnexpectedRelayAddress(IpAddr),
+
+    /// The proxy closed the TCP connection, which ends the association
+    #[error(display = "SOCKS5 proxy closed the connection")]
+    ProxyClosed,
+
+    /// Failed to receive datagram from WireGuard
+    #[error(display = "Failed to receive datagram from local socket")]
+    RecvLocal(#[error(source)] io::Error),
+
+    /// Failed to connect the local UDP socket to WireGuard
+    #[error(display = "Failed to connect local socket to WireGuard")]
+    ConnectLocal(#[error(source)] io::Error),
+
+    /// Failed to send datagram to WireGuard
+    #[error(display = "Failed to send datagram to local socket")]
+    SendLocal(#[error(source)] io::Error),
+
+    /// Failed to receive datagram from the proxy
+    #[error(display = "Failed to receive datagram from SOCKS5 proxy")]
+    RecvProxy(#[error(source)] io::Error),
+
+    /// Failed to send datagram to the proxy
+    #[error(display = "Failed to send datagram to SOCKS5 proxy")]
+    SendProxy(#[error(source)] io::Error),
+}
+
+struct Socks5 {
+    local_socket: UdpSocket,
+    local_addr: SocketAddr,
+    /// Control connection. The association ends when it is closed.
+    control: TcpStream,
+    relay_socket: UdpSocket,
+    /// Header that is prepended to every datagram sent to the relay.
+    header: Vec<u8>,
+    /// Address of the WireGuard socket. Learned from the first datagram sent to `local_socket`,
+    /// which is then connected to it.
+    client_addr: Mutex<Option<SocketAddr>>,
+}
+
+impl Socks5 {
+    pub async fn new(settings: &Socks5Settings) -> Result<Self> {
+        let listen_addr = settings
+            .listen_addr
+            .unwrap_or_else(|| crate::default_listen_addr(&settings.wireguard_endpoint));
+        let local_socket = UdpSocket::bind(listen_addr).await.map_err(Error::BindUdp)?;
+        let local_addr = local_socket
+            .local_addr()
+            .map_err(Error::GetUdpSocketDetails)?;
+
+        let tcp_socket = if settings.proxy.is_ipv4() {
+            TcpSocket::new_v4()
+        } else {
+            TcpSocket::new_v6()
+        }
+        .map_err(Error::CreateProxySocket)?;
+        #[cfg(target_os = "linux")]
+        if let Some(fwmark) = settings.fwmark {
+            socket2::SockRef::from(&tcp_socket)
+                .set_mark(fwmark)
+                .map_err(Error::CreateProxySocket)?;
+        }
+        let mut control = tcp_socket
+            .connect(settings.proxy)
+            .await
+            .map_err(Error::ConnectProxy)?;
+        control
+            .set_nodelay(true)
+            .map_err(Error::CreateProxySocket)?;
+
+        let unspecified_addr = if settings.proxy.is_ipv4() {
+            SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)
+        } else {
+            SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0)
+        };
+        let relay_socket = UdpSocket::bind(unspecified_addr)
+            .await
+            .map_err(Error::CreateProxySocket)?;
+        #[cfg(target_os = "linux")]
+        if let Some(fwmark) = settings.fwmark {
+            socket2::SockRef::from(&relay_socket)
+                .set_mark(fwmark)
+                .map_err(Error::CreateProxySocket)?;
+        }
+        let relay_source = relay_socket
+            .local_addr()
+            .map_err(Error::GetUdpSocketDetails)?;
+
+        authenticate(&mut control, settings.auth.as_ref()).await?;
+        let relay_addr = associate(&mut control, relay_source).await?;
+
+        // Proxies commonly reply with an unspecified address, meaning the proxy's own address
+        let relay_addr = if relay_addr.ip().is_unspecified() {
+            SocketAddr::new(settings.proxy.ip(), relay_addr.port())
+        } else if relay_addr.ip() == settings.proxy.ip() {
+            relay_addr
+        } else {
+            return Err(Error::UnexpectedRelayAddress(relay_addr.ip()));
+        };
+        relay_socket
+            .connect(relay_addr)
+            .await
+            .map_err(Error::CreateProxySocket)?;
+
+        Ok(Self {
+            local_socket,
+            local_addr,
+            control,
+            relay_socket,
+            header: datagram_header(settings.wireguard_endpoint),
+            client_addr: Mutex::new(None),
+        })
+    }
+
+    async fn forward_to_proxy(&self) -> Result<()> {
+        let header_len = self.header.len();
+        let mut buffer = vec![0u8; header_len + MAX_DATAGRAM_SIZE];
+        buffer[..header_len].copy_from_slice(&self.header);
+        loop {
+            let (len, client_addr) = self
+                .local_socket
+                .recv_from(&mut buffer[header_len..])
+                .await
+                .map_err(Error::RecvLocal)?;
+            if !crate::lock_client(&self.local_socket, &self.client_addr, client_addr)
+                .await
+                .map_err(Error::ConnectLocal)?
+            {
+                continue;
+            }
+            self.relay_socket
+                .send(&buffer[..header_len + len])
+                .await
+                .map_err(Error::SendProxy)?;
+        }
+    }
+
+    async fn forward_to_client(&self) -> Result<()> {
+        let mut buffer = vec![0u8; MAX_DATAGRAM_SIZE];
+        loop {
+            let len = self
+                .relay_socket
+                .recv(&mut buffer)
+                .await
+                .map_err(Error::RecvProxy)?;
+            let client_known = self.client_addr.lock().unwrap().is_some();
+            // Fragmented and malformed datagrams are dropped. WireGuard retransmits anyway.
+            if let (true, Some(payload)) = (client_known, datagram_payload(&buffer[..len])) {
+                self.local_socket
+                    .send(payload)
+                    .await
+                    .map_err(Error::SendLocal)?;
+            }
+        }
+    }
+
+    /// Returns once the proxy has closed the control connection.
+    async fn wait_for_close(&self) -> Result<()> {
+        let mut buffer = [0u8; 64];
+        loop {
+            self.control.readable().await.map_err(Error::RecvProxy)?;
+            match self.control.try_read(&mut buffer) {
+                Ok(0) => return Err(Error::ProxyClosed),
+                // Nothing is expected on the connection after the handshake
+                Ok(_) => (),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => (),
+                Err(error) => return Err(Error::RecvProxy(error)),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Obfuscator for Socks5 {
+    fn endpoint(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    async fn run(self: Box<Self>) -> crate::Result<()> {
+        let result = tokio::select! {
+            result = self.forward_to_proxy() => result,
+            result = self.forward_to_client() => result,
+            result = self.wait_for_close() => result,
+        };
+        result.map_err(crate::Error::RunSocks5Obfuscator)
+    }
+
+    #[cfg(target_os = "android")]
+    fn remote_socket_fd(&self) -> std::os::unix::io::RawFd {
+        use std::os::unix::io::AsRawFd;
+        self.relay_socket.as_raw_fd()
+    }
+}
+
+/// Negotiates an authentication method with the proxy, and authenticates if required.
+async fn authenticate(control: &mut TcpStream, auth: Option<&Socks5Auth>) -> Result<()> {
+    let greeting: &[u8] = if auth.is_some() {
+        &[SOCKS_VERSION, 2, METHOD_NO_AUTH, METHOD_USERNAME_PASSWORD]
+    } else {
+        &[SOCKS_VERSION, 1, METHOD_NO_AUTH]
+    };
+    control
+        .write_all(greeting)
+        .await
+        .map_err(Error::Handshake)?;
+
+    let mut reply = [0u8; 2];
+    control
+        .read_exact(&mut reply)
+        .await
+        .map_err(Error::Handshake)?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(Error::InvalidReply);
+    }
+    match (reply[1], auth) {
+        (METHOD_NO_AUTH, _) => Ok(()),
+        (METHOD_USERNAME_PASSWORD, Some(auth)) => {
+            let request = auth_request(auth)?;
+            control
+                .write_all(&request)
+                .await
+                .map_err(Error::Handshake)?;
+            control
+                .read_exact(&mut reply)
+                .await
+                .map_err(Error::Handshake)?;
+            if reply[1] != 0 {
+                return Err(Error::AuthFailed);
+            }
+            Ok(())
+        }
+        _ => Err(Error::UnsupportedAuthMethod),
+    }
+}
+
+/// Sends a UDP ASSOCIATE request and returns the address that the proxy relays datagrams on.
+async fn associate(control: &mut TcpStream, relay_source: SocketAddr) -> Result<SocketAddr> {
+    let mut request = vec![SOCKS_VERSION, CMD_UDP_ASSOCIATE, 0];
+    write_address(&mut request, relay_source);
+    control
+        .write_all(&request)
+        .await
+        .map_err(Error::Handshake)?;
+
+    let mut reply = [0u8; 4];
+    control
+        .read_exact(&mut reply)
+        .await
+        .map_err(Error::Handshake)?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(Error::InvalidReply);
+    }
+    if reply[1] != 0 {
+        return Err(Error::AssociateRejected(reply_message(reply[1])));
+    }
+
+    let ip: IpAddr = match reply[3] {
+        ATYP_IPV4 => {
+            let mut octets = [0u8; 4];
+            control
+                .read_exact(&mut octets)
+                .await
+                .map_err(Error::Handshake)?;
+            Ipv4Addr::from(octets).into()
+        }
+        ATYP_IPV6 => {
+            let mut octets = [0u8; 16];
+            control
+                .read_exact(&mut octets)
+                .await
+                .map_err(Error::Handshake)?;
+            Ipv6Addr::from(octets).into()
+        }
+        // Resolving a name here would leak a DNS request outside the tunnel
+        _ => return Err(Error::InvalidReply),
+    };
+    let port = control.read_u16().await.map_err(Error::Handshake)?;
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// Builds a username/password authentication request, as described in RFC 1929.
+fn auth_request(auth: &Socks5Auth) -> Result<Vec<u8>> {
+    let username = auth.username.as_bytes();
+    let password = auth.password.as_bytes();
+    let username_len = u8::try_from(username.len()).map_err(|_| Error::CredentialsTooLong)?;
+    let password_len = u8::try_from(password.len()).map_err(|_| Error::CredentialsTooLong)?;
+
+    let mut request = Vec::with_capacity(3 + username.len() + password.len());
+    request.push(AUTH_VERSION);
+    request.push(username_len);
+    request.extend_from_slice(username);
+    request.push(password_len);
+    request.extend_from_slice(password);
+    Ok(request)
+}
+
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        0x01 => "general failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn write_address(buffer: &mut Vec<u8>, addr: SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            buffer.push(ATYP_IPV4);
+            buffer.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buffer.push(ATYP_IPV6);
+            buffer.extend_from_slice(&ip.octets());
+        }
+    }
+    buffer.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+/// Returns the header of a datagram relayed to `destination`: two reserved bytes, the fragment
+/// number and the destination address.
+fn datagram_header(destination: SocketAddr) -> Vec<u8> {
+    let mut header = vec![0, 0, 0];
+    write_address(&mut header, destination);
+    header
+}
+
+/// Strips the header from a datagram received from the relay. Returns `None` for fragments and
+/// malformed datagrams.
+fn datagram_payload(datagram: &[u8]) -> Option<&[u8]> {
+    let (header, rest) = (datagram.get(..4)?, &datagram[4..]);
+    if header[2] != 0 {
+        return None;
+    }
+    let address_len = match header[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => 1 + usize::from(*rest.first()?),
+        _ => return None,
+    };
+    rest.get(address_len + 2..)
+}
+
+pub async fn create_obfuscator(settings: &Socks5Settings) -> Result<Box<dyn Obfuscator>> {
+    Ok(Box::new(Socks5::new(settings).await?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::{net::TcpListener, task::JoinHandle};
+
+    /// Connects to a mock proxy that expects each request in `script` in turn, and answers it
+    /// with the corresponding reply. The returned task fails if a request does not match.
+    async fn mock_proxy(script: Vec<(Vec<u8>, Vec<u8>)>) -> (TcpStream, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        let proxy = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            for (request, reply) in script {
+                let mut received = vec![0u8; request.len()];
+                stream.read_exact(&mut received).await.unwrap();
+                assert_eq!(received, request);
+                stream.write_all(&reply).await.unwrap();
+            }
+        });
+        (TcpStream::connect(proxy_addr).await.unwrap(), proxy)
+    }
+
+    fn test_auth() -> Socks5Auth {
+        Socks5Auth {
+            username: "user".to_owned(),
+            password: "pw".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        let (mut control, proxy) =
+            mock_proxy(vec![(vec![SOCKS_VERSION, 1, METHOD_NO_AUTH], vec![5, 0])]).await;
+        authenticate(&mut control, None).await.unwrap();
+        proxy.await.unwrap();
+
+        // Proxies may skip authentication even if credentials are offered
+        let (mut control, proxy) = mock_proxy(vec![(
+            vec![SOCKS_VERSION, 2, METHOD_NO_AUTH, METHOD_USERNAME_PASSWORD],
+            vec![5, METHOD_NO_AUTH],
+        )])
+        .await;
+        authenticate(&mut control, Some(&test_auth()))
+            .await
+            .unwrap();
+        proxy.await.unwrap();
+
+        for (status, succeeds) in [(0, true), (1, false)] {
+            let (mut control, proxy) = mock_proxy(vec![
+                (
+                    vec![SOCKS_VERSION, 2, METHOD_NO_AUTH, METHOD_USERNAME_PASSWORD],
+                    vec![5, METHOD_USERNAME_PASSWORD],
+                ),
+                (
+                    auth_request(&test_auth()).unwrap(),
+                    vec![AUTH_VERSION, status],
+                ),
+            ])
+            .await;
+            let result = authenticate(&mut control, Some(&test_auth())).await;
+            proxy.await.unwrap();
+            if succeeds {
+                result.unwrap();
+            } else {
+                assert!(matches!(result, Err(Error::AuthFailed)));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_rejected() {
+        // Username and password cannot be used without credentials
+        let (mut control, proxy) = mock_proxy(vec![(
+            vec![SOCKS_VERSION, 1, METHOD_NO_AUTH],
+            vec![5, METHOD_USERNAME_PASSWORD],
+        )])
+        .await;
+        let result = authenticate(&mut control, None).await;
+        proxy.await.unwrap();
+        assert!(matches!(result, Err(Error::UnsupportedAuthMethod)));
+
+        let (mut control, proxy) = mock_proxy(vec![(
+            vec![SOCKS_VERSION, 1, METHOD_NO_AUTH],
+            vec![5, 0xff],
+        )])
+        .await;
+        let result = authenticate(&mut control, None).await;
+        proxy.await.unwrap();
+        assert!(matches!(result, Err(Error::UnsupportedAuthMethod)));
+
+        let (mut control, proxy) =
+            mock_proxy(vec![(vec![SOCKS_VERSION, 1, METHOD_NO_AUTH], vec![4, 0])]).await;
+        let result = authenticate(&mut control, None).await;
+        proxy.await.unwrap();
+        assert!(matches!(result, Err(Error::InvalidReply)));
+    }
+
+    #[tokio::test]
+    async fn test_associate() {
+        let relay_source: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let request = vec![
+            SOCKS_VERSION,
+            CMD_UDP_ASSOCIATE,
+            0,
+            ATYP_IPV4,
+            127,
+            0,
+            0,
+            1,
+            0x0f,
+            0xa0,
+        ];
+
+        let (mut control, proxy) = mock_proxy(vec![(
+            request.clone(),
+            vec![5, 0, 0, ATYP_IPV4, 0, 0, 0, 0, 0x04, 0x38],
+        )])
+        .await;
+        let relay_addr = associate(&mut control, relay_source).await.unwrap();
+        proxy.await.unwrap();
+        assert_eq!(relay_addr, "0.0.0.0:1080".parse().unwrap());
+
+        let mut reply = vec![5, 0, 0, ATYP_IPV6];
+        reply.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        reply.extend_from_slice(&[0x04, 0x38]);
+        let (mut control, proxy) = mock_proxy(vec![(request.clone(), reply)]).await;
+        let relay_addr = associate(&mut control, relay_source).await.unwrap();
+        proxy.await.unwrap();
+        assert_eq!(relay_addr, "[2001:db8::1]:1080".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_associate_rejected() {
+        let relay_source: SocketAddr = "[::1]:4000".parse().unwrap();
+        let mut request = vec![SOCKS_VERSION, CMD_UDP_ASSOCIATE, 0, ATYP_IPV6];
+        request.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        request.extend_from_slice(&[0x0f, 0xa0]);
+
+        let (mut control, proxy) = mock_proxy(vec![(
+            request.clone(),
+            vec![5, 0x07, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0],
+        )])
+        .await;
+        let result = associate(&mut control, relay_source).await;
+        proxy.await.unwrap();
+        assert!(matches!(
+            result,
+            Err(Error::AssociateRejected("command not supported"))
+        ));
+
+        // Relay addresses given as domain names are not resolved
+        let (mut control, proxy) = mock_proxy(vec![(
+            request,
+            vec![5, 0, 0, ATYP_DOMAIN, 4, b't', b'e', b's', b't', 0x04, 0x38],
+        )])
+        .await;
+        let result = associate(&mut control, relay_source).await;
+        proxy.await.unwrap();
+        assert!(matches!(result, Err(Error::InvalidReply)));
+    }
+
+    #[test]
+    fn test_datagram_header() {
+        let header = datagram_header("192.0.2.1:51820".parse().unwrap());
+        assert_eq!(header, [0, 0, 0, ATYP_IPV4, 192, 0, 2, 1, 0xca, 0x6c]);
+
+        let header = datagram_header("[2001:db8::1]:53".parse().unwrap());
+        assert_eq!(header.len(), 3 + 1 + 16 + 2);
+        assert_eq!(header[3], ATYP_IPV6);
+        assert_eq!(&header[20..], [0, 53]);
+    }
+
+    #[test]
+    fn test_datagram_payload() {
+        let mut datagram = datagram_header("192.0.2.1:51820".parse().unwrap());
+        datagram.extend_from_slice(b"payload");
+        assert_eq!(datagram_payload(&datagram), Some(&b"payload"[..]));
+
+        let mut domain = vec![0, 0, 0, ATYP_DOMAIN, 4];
+        domain.extend_from_slice(b"test\x00\x35payload");
+        assert_eq!(datagram_payload(&domain), Some(&b"payload"[..]));
+
+        // Fragments are not supported
+        datagram[2] = 1;
+        assert_eq!(datagram_payload(&datagram), None);
+
+        assert_eq!(datagram_payload(&[0, 0, 0, ATYP_IPV6, 1, 2]), None);
+        assert_eq!(datagram_payload(&[0, 0, 0, 0x05, 1, 2, 3, 4, 5, 6]), None);
+    }
+
+    #[test]
+    fn test_auth_request() {
+        assert_eq!(
+            auth_request(&test_auth()).unwrap(),
+            [AUTH_VERSION, 4, b'u', b's', b'e', b'r', 2, b'p', b'w']
+        );
+
+        let auth = Socks5Auth {
+            username: "a".repeat(256),
+            password: String::new(),
+        };
+        assert!(matches!(
+            auth_request(&auth),
+            Err(Error::CredentialsTooLong)
+        ));
+    }
+}
@@ -0,0 +1,254 @@
+//! Sends WireGuard datagrams over a TLS connection, to look like HTTPS traffic. Each datagram is
+//! prefixed with its length as a big-endian `u16`, like in udp-over-tcp.
+
+use crate::Obfuscator;
+use async_trait::async_trait;
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf},
+    net::{TcpSocket, TcpStream, UdpSocket},
+};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{
+        self,
+        client::{ServerCertVerified, ServerCertVerifier},
+        Certificate, ClientConfig, ServerName,
+    },
+    TlsConnector,
+};
+
+/// Large enough to hold any UDP datagram.
+const MAX_DATAGRAM_SIZE: usize = u16::MAX as usize;
+
+pub struct TlsSettings {
+    pub peer: SocketAddr,
+    /// Local address to receive WireGuard traffic on. Defaults to a random port on localhost.
+    pub listen_addr: Option<SocketAddr>,
+    /// Server name sent in the TLS handshake.
+    pub server_name: String,
+    #[cfg(target_os = "linux")]
+    pub fwmark: Option<u32>,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// The server name cannot be used for SNI
+    #[error(display = "Invalid TLS server name: {}", _0)]
+    InvalidServerName(String),
+
+    /// Failed to bind the local UDP socket
+    #[error(display = "Failed to bind local UDP socket")]
+    BindUdp(#[error(source)] io::Error),
+
+    /// Failed to determine UDP socket details
+    #[error(display = "Failed to determine UDP socket details")]
+    GetUdpSocketDetails(#[error(source)] io::Error),
+
+    /// Failed to create or configure the TCP socket
+    #[error(display = "Failed to create TCP socket")]
+    CreateTcpSocket(#[error(source)] io::Error),
+
+    /// Failed to connect to the remote endpoint
+    #[error(display = "Failed to connect to remote endpoint")]
+    ConnectTcp(#[error(source)] io::Error),
+
+    /// The TLS handshake failed
+    #[error(display = "TLS handshake failed")]
+    TlsHandshake(#[error(source)] io::Error),
+
+    /// Failed to receive datagram from WireGuard
+    #[error(display = "Failed to receive datagram from local socket")]
+    RecvLocal(#[error(source)] io::Error),
+
+    /// Failed to connect the local UDP socket to WireGuard
+    #[error(display = "Failed to connect local socket to WireGuard")]
+    ConnectLocal(#[error(source)] io::Error),
+
+    /// Failed to send datagram to WireGuard
+    #[error(display = "Failed to send datagram to local socket")]
+    SendLocal(#[error(source)] io::Error),
+
+    /// Failed to read from the TLS stream
+    #[error(display = "Failed to read from TLS stream")]
+    ReadTls(#[error(source)] io::Error),
+
+    /// Failed to write to the TLS stream
+    #[error(display = "Failed to write to TLS stream")]
+    WriteTls(#[error(source)] io::Error),
+}
+
+struct Tls {
+    local_socket: UdpSocket,
+    local_addr: SocketAddr,
+    stream: TlsStream<TcpStream>,
+    #[cfg(target_os = "android")]
+    remote_fd: std::os::unix::io::RawFd,
+}
+
+impl Tls {
+    pub async fn new(settings: &TlsSettings) -> Result<Self> {
+        let server_name = ServerName::try_from(settings.server_name.as_str())
+            .map_err(|_| Error::InvalidServerName(settings.server_name.clone()))?;
+
+        let listen_addr = settings
+            .listen_addr
+            .unwrap_or_else(|| crate::default_listen_addr(&settings.peer));
+        let local_socket = UdpSocket::bind(listen_addr).await.map_err(Error::BindUdp)?;
+        let local_addr = local_socket
+            .local_addr()
+            .map_err(Error::GetUdpSocketDetails)?;
+
+        let tcp_socket = if settings.peer.is_ipv4() {
+            TcpSocket::new_v4()
+        } else {
+            TcpSocket::new_v6()
+        }
+        .map_err(Error::CreateTcpSocket)?;
+        #[cfg(target_os = "linux")]
+        if let Some(fwmark) = settings.fwmark {
+            socket2::SockRef::from(&tcp_socket)
+                .set_mark(fwmark)
+                .map_err(Error::CreateTcpSocket)?;
+        }
+        let tcp_stream = tcp_socket
+            .connect(settings.peer)
+            .await
+            .map_err(Error::ConnectTcp)?;
+        // Disables the Nagle algorithm on the TCP socket. Improves performance
+        tcp_stream
+            .set_nodelay(true)
+            .map_err(Error::CreateTcpSocket)?;
+
+        #[cfg(target_os = "android")]
+        let remote_fd = std::os::unix::io::AsRawFd::as_raw_fd(&tcp_stream);
+
+        let stream = TlsConnector::from(tls_config())
+            .connect(server_name, tcp_stream)
+            .await
+            .map_err(Error::TlsHandshake)?;
+
+        Ok(Self {
+            local_socket,
+            local_addr,
+            stream,
+            #[cfg(target_os = "android")]
+            remote_fd,
+        })
+    }
+}
+
+/// The server certificate is not verified. It only has to look like HTTPS, and WireGuard
+/// authenticates the peer anyway.
+fn tls_config() -> Arc<ClientConfig> {
+    let mut config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate))
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Arc::new(config)
+}
+
+struct AcceptAnyCertificate;
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+async fn forward_to_server(
+    local_socket: &UdpSocket,
+    client_addr: &Mutex<Option<SocketAddr>>,
+    mut writer: WriteHalf<TlsStream<TcpStream>>,
+) -> Result<()> {
+    let mut buffer = vec![0u8; 2 + MAX_DATAGRAM_SIZE];
+    loop {
+        let (len, addr) = local_socket
+            .recv_from(&mut buffer[2..])
+            .await
+            .map_err(Error::RecvLocal)?;
+        if !crate::lock_client(local_socket, client_addr, addr)
+            .await
+            .map_err(Error::ConnectLocal)?
+        {
+            continue;
+        }
+        buffer[..2].copy_from_slice(&(len as u16).to_be_bytes());
+        writer
+            .write_all(&buffer[..2 + len])
+            .await
+            .map_err(Error::WriteTls)?;
+    }
+}
+
+async fn forward_to_client(
+    local_socket: &UdpSocket,
+    client_addr: &Mutex<Option<SocketAddr>>,
+    mut reader: ReadHalf<TlsStream<TcpStream>>,
+) -> Result<()> {
+    let mut buffer = vec![0u8; MAX_DATAGRAM_SIZE];
+    loop {
+        let len = usize::from(reader.read_u16().await.map_err(Error::ReadTls)?);
+        reader
+            .read_exact(&mut buffer[..len])
+            .await
+            .map_err(Error::ReadTls)?;
+        let client_known = client_addr.lock().unwrap().is_some();
+        // Nothing can be received before WireGuard has sent something, so this only drops
+        // unsolicited datagrams.
+        if client_known {
+            local_socket
+                .send(&buffer[..len])
+                .await
+                .map_err(Error::SendLocal)?;
+        }
+    }
+}
+
+#[async_trait]
+impl Obfuscator for Tls {
+    fn endpoint(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    async fn run(self: Box<Self>) -> crate::Result<()> {
+        let Self {
+            local_socket,
+            stream,
+            ..
+        } = *self;
+        let (reader, writer) = tokio::io::split(stream);
+        let client_addr = Mutex::new(None);
+        let result = tokio::select! {
+            result = forward_to_server(&local_socket, &client_addr, writer) => result,
+            result = forward_to_client(&local_socket, &client_addr, reader) => result,
+        };
+        result.map_err(crate::Error::RunTlsObfuscator)
+    }
+
+    #[cfg(target_os = "android")]
+    fn remote_socket_fd(&self) -> std::os::unix::io::RawFd {
+        self.remote_fd
+    }
+}
+
+pub async fn create_obfuscator(settings: &TlsSettings) -> Result<Box<dyn Obfuscator>> {
+    Ok(Box::new(Tls::new(settings).await?))
+}
@@ -8,6 +8,8 @@ use udp_over_tcp::{
 
 pub struct Udp2TcpSettings {
     pub peer: SocketAddr,
+    /// Local address to receive WireGuard traffic on. Defaults to a random port on localhost.
+    pub listen_addr: Option<SocketAddr>,
     #[cfg(target_os = "linux")]
     pub fwmark: Option<u32>,
 }
@@ -37,11 +39,9 @@ struct Udp2Tcp {
 
 impl Udp2Tcp {
     pub async fn new(settings: &Udp2TcpSettings) -> Result<Self> {
-        let listen_addr = if settings.peer.is_ipv4() {
-            SocketAddr::new("127.0.0.1".parse().unwrap(), 0)
-        } else {
-            SocketAddr::new("::1".parse().unwrap(), 0)
-        };
+        let listen_addr = settings
+            .listen_addr
+            .unwrap_or_else(|| crate::default_listen_addr(&settings.peer));
 
         let instance = Udp2TcpImpl::new(
             listen_addr,